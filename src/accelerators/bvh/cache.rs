@@ -0,0 +1,267 @@
+//! BVH Cache Files
+
+#![allow(dead_code)]
+use super::*;
+use crate::accelerators::cache::*;
+use byteorder::{LittleEndian, ReadBytesExt, WriteBytesExt};
+use std::io::{Read, Result, Write};
+
+impl BVHAccel {
+    /// Load a BVH from a cache file if it was built for the same primitives
+    /// and parameters. Otherwise build it and write the cache file.
+    ///
+    /// * `path`              - Path of the cache file.
+    /// * `primitives`        - The primitives.
+    /// * `max_prims_in_node` - Maximum number of primitives in a node.
+    /// * `split_method`      - The splitting method.
+    /// * `width`             - Number of children per node (2, 4 or 8).
+    pub fn new_cached(
+        path: &str,
+        primitives: &Vec<ArcPrimitive>,
+        max_prims_in_node: u8,
+        split_method: SplitMethod,
+        width: u8,
+    ) -> Self {
+        let hash = geometry_hash(primitives);
+        match Self::read_cache(
+            path,
+            primitives,
+            hash,
+            max_prims_in_node,
+            split_method,
+            width,
+        ) {
+            Ok(Some(bvh)) => {
+                info!("Loaded BVH from cache file {}.", path);
+                bvh.report_stats();
+                return bvh;
+            }
+            Ok(None) => {}
+            Err(err) => warn!("{} Rebuilding BVH.", err),
+        }
+
+        let bvh = Self::new(primitives, max_prims_in_node, split_method, width);
+        match bvh.write_cache(path, primitives, hash) {
+            Ok(()) => info!("Wrote BVH to cache file {}.", path),
+            Err(err) => warn!("{}", err),
+        }
+        bvh
+    }
+
+    /// Write the nodes and primitive ordering to a cache file. Primitives
+    /// are stored as indices into the input primitives.
+    ///
+    /// * `path`       - Path of the cache file.
+    /// * `primitives` - The input primitives the BVH was built from.
+    /// * `hash`       - Hash of the input primitives from `geometry_hash()`.
+    pub fn write_cache(
+        &self,
+        path: &str,
+        primitives: &[ArcPrimitive],
+        hash: u64,
+    ) -> std::result::Result<(), String> {
+        let indices = primitive_indices(primitives);
+        let ordering: Vec<u32> = self
+            .primitives
+            .iter()
+            .map(|p| primitive_index(&indices, p))
+            .collect();
+
+        write_cache_file(path, CacheKind::BVH, hash, |writer| {
+            writer.write_u8(split_method_id(self.split_method))?;
+            writer.write_u8(self.max_prims_in_node)?;
+            writer.write_u8(self.width)?;
+            write_u32s(writer, &ordering)?;
+
+            match self.wide_nodes.as_ref() {
+                Some(WideBVHNodes::Four(nodes)) => write_wide_nodes(writer, nodes),
+                Some(WideBVHNodes::Eight(nodes)) => write_wide_nodes(writer, nodes),
+                None => write_nodes(writer, &self.nodes),
+            }
+        })
+    }
+
+    /// Read a BVH from a cache file. Returns `Ok(None)` if the file doesn't
+    /// exist and an error if it doesn't match the primitives or parameters.
+    ///
+    /// * `path`              - Path of the cache file.
+    /// * `primitives`        - The input primitives.
+    /// * `hash`              - Hash of the input primitives.
+    /// * `max_prims_in_node` - Maximum number of primitives in a node.
+    /// * `split_method`      - The splitting method.
+    /// * `width`             - Number of children per node (2, 4 or 8).
+    pub fn read_cache(
+        path: &str,
+        primitives: &[ArcPrimitive],
+        hash: u64,
+        max_prims_in_node: u8,
+        split_method: SplitMethod,
+        width: u8,
+    ) -> std::result::Result<Option<Self>, String> {
+        read_cache_file(path, CacheKind::BVH, hash, |reader| {
+            if reader.read_u8()? != split_method_id(split_method)
+                || reader.read_u8()? != max_prims_in_node
+                || reader.read_u8()? != width
+            {
+                return Err(invalid_data("BVH parameters have changed."));
+            }
+
+            // Spatial splits can reference primitives more than once.
+            let max_refs = max_references(primitives.len(), split_method);
+            let ordering = read_u32s(reader, max_refs, primitives.len())?;
+            let ordered_prims: Vec<ArcPrimitive> = ordering
+                .iter()
+                .map(|&i| primitives[i as usize].clone())
+                .collect();
+
+            let n = ordered_prims.len();
+            let (nodes, wide_nodes) = match width {
+                4 => (
+                    vec![],
+                    Some(WideBVHNodes::Four(read_wide_nodes(reader, n)?)),
+                ),
+                8 => (
+                    vec![],
+                    Some(WideBVHNodes::Eight(read_wide_nodes(reader, n)?)),
+                ),
+                _ => (read_nodes(reader, n)?, None),
+            };
+
+            Ok(Self {
+                primitives: ordered_prims,
+                max_prims_in_node,
+                split_method,
+                width,
+                nodes,
+                wide_nodes,
+                motion_bounds: None,
+            })
+        })
+    }
+}
+
+/// Returns the value stored in cache files for a split method.
+///
+/// * `split_method` - The splitting method.
+fn split_method_id(split_method: SplitMethod) -> u8 {
+    match split_method {
+        SplitMethod::SAH => 0,
+        SplitMethod::HLBVH => 1,
+        SplitMethod::Middle => 2,
+        SplitMethod::EqualCounts => 3,
+        SplitMethod::SBVH => 4,
+    }
+}
+
+/// Write binary BVH nodes.
+///
+/// * `writer` - The writer.
+/// * `nodes`  - The nodes.
+fn write_nodes<W: Write>(writer: &mut W, nodes: &[LinearBVHNode]) -> Result<()> {
+    writer.write_u64::<LittleEndian>(nodes.len() as u64)?;
+    for node in nodes.iter() {
+        write_bounds(writer, &node.bounds)?;
+        writer.write_u32::<LittleEndian>(node.offset)?;
+        writer.write_u16::<LittleEndian>(node.n_primitives)?;
+        writer.write_u8(node.axis)?;
+    }
+    Ok(())
+}
+
+/// Read binary BVH nodes and check that all offsets are valid. A tree with
+/// one primitive per leaf has fewer than `2 * n_primitives` nodes.
+///
+/// * `reader`       - The reader.
+/// * `n_primitives` - Number of ordered primitives.
+fn read_nodes<R: Read>(reader: &mut R, n_primitives: usize) -> Result<Vec<LinearBVHNode>> {
+    let n = read_len(reader, 2 * n_primitives)?;
+    let mut nodes = Vec::with_capacity(n);
+    for i in 0..n {
+        let bounds = read_bounds(reader)?;
+        let offset = reader.read_u32::<LittleEndian>()?;
+        let n_prims = reader.read_u16::<LittleEndian>()?;
+        let axis = reader.read_u8()?;
+
+        // Interior nodes are followed by their first child and point after
+        // it to the second child.
+        let valid = if n_prims > 0 {
+            offset as usize + n_prims as usize <= n_primitives
+        } else {
+            axis < 3 && i + 1 < n && (offset as usize) > i + 1 && (offset as usize) < n
+        };
+        if !valid {
+            return Err(invalid_data("Invalid BVH node."));
+        }
+
+        nodes.push(if n_prims > 0 {
+            LinearBVHNode::new_leaf_node(bounds, offset, n_prims)
+        } else {
+            LinearBVHNode::new_interior_node(bounds, offset, axis)
+        });
+    }
+    Ok(nodes)
+}
+
+/// Write wide BVH nodes. All child slots are written so unused slots keep
+/// their inverted bounds.
+///
+/// * `writer` - The writer.
+/// * `nodes`  - The nodes.
+fn write_wide_nodes<W: Write, const N: usize>(
+    writer: &mut W,
+    nodes: &[WideBVHNode<N>],
+) -> Result<()> {
+    writer.write_u64::<LittleEndian>(nodes.len() as u64)?;
+    for node in nodes.iter() {
+        writer.write_u8(node.n_children)?;
+        for i in 0..N {
+            let bounds = Bounds3f {
+                p_min: Point3f::new(node.min_x[i], node.min_y[i], node.min_z[i]),
+                p_max: Point3f::new(node.max_x[i], node.max_y[i], node.max_z[i]),
+            };
+            write_bounds(writer, &bounds)?;
+            writer.write_u32::<LittleEndian>(node.offsets[i])?;
+            writer.write_u16::<LittleEndian>(node.n_primitives[i])?;
+        }
+    }
+    Ok(())
+}
+
+/// Read wide BVH nodes and check that all offsets are valid.
+///
+/// * `reader`       - The reader.
+/// * `n_primitives` - Number of ordered primitives.
+fn read_wide_nodes<R: Read, const N: usize>(
+    reader: &mut R,
+    n_primitives: usize,
+) -> Result<Vec<WideBVHNode<N>>> {
+    let n = read_len(reader, 2 * n_primitives)?;
+    let mut nodes = Vec::with_capacity(n);
+    for i in 0..n {
+        let mut node = WideBVHNode::<N>::new();
+        node.n_children = reader.read_u8()?;
+        if node.n_children as usize > N {
+            return Err(invalid_data("Invalid BVH node."));
+        }
+
+        for c in 0..N {
+            node.set_child_bounds(c, &read_bounds(reader)?);
+            node.offsets[c] = reader.read_u32::<LittleEndian>()?;
+            node.n_primitives[c] = reader.read_u16::<LittleEndian>()?;
+
+            // Children are always stored after their parent.
+            let offset = node.offsets[c] as usize;
+            let valid = c >= node.n_children as usize
+                || if node.n_primitives[c] > 0 {
+                    offset + node.n_primitives[c] as usize <= n_primitives
+                } else {
+                    offset > i && offset < n
+                };
+            if !valid {
+                return Err(invalid_data("Invalid BVH node."));
+            }
+        }
+        nodes.push(node);
+    }
+    Ok(nodes)
+}
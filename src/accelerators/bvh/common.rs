@@ -3,8 +3,12 @@
 #![allow(dead_code)]
 use crate::core::geometry::*;
 use crate::core::pbrt::*;
+use crate::core::stats::*;
 use std::sync::Arc;
 
+/// Number of BVH nodes visited and rays traced through BVHs.
+pub static BVH_NODES_VISITED: Stat = Stat::ratio("BVH/Nodes visited per ray");
+
 /// Splitting method to use to subdivide primitives.
 #[derive(Copy, Clone, Debug, PartialEq)]
 pub enum SplitMethod {
@@ -25,10 +29,16 @@ pub enum SplitMethod {
     /// of the primitives have smallest centroid coordinate values along the
     /// chosen axis, and second have have the largest centroid coordinate values.
     EqualCounts,
+
+    /// Surface Area Heuristic with spatial splits (SBVH). Primitives that
+    /// straddle a splitting plane are clipped to both sides and referenced by
+    /// both children, which reduces the overlap of nodes containing long,
+    /// thin primitives.
+    SBVH,
 }
 
 /// SAH bucket information.
-#[derive(Copy, Clone, Debug, Default)]
+#[derive(Copy, Clone, Debug)]
 pub struct BucketInfo {
     /// Count of primitives.
     pub count: usize,
@@ -37,6 +47,16 @@ pub struct BucketInfo {
     pub bounds: Bounds3f,
 }
 
+impl Default for BucketInfo {
+    /// Returns an empty bucket.
+    fn default() -> Self {
+        Self {
+            count: 0,
+            bounds: Bounds3f::empty(),
+        }
+    }
+}
+
 /// Stores information about a primitive.
 #[derive(Copy, Clone, Debug)]
 pub struct BVHPrimitiveInfo {
@@ -137,7 +137,7 @@ impl HLBVH {
             let first_prim_offset = ordered_prims_offset.fetch_add(n_primitives, Ordering::SeqCst);
 
             let prims = ordered_prims.clone();
-            let mut prims2 = prims.lock().expect("unabled to lock ordered_prims");
+            let mut prims2 = prims.lock().expect("unable to lock ordered_prims");
             for i in 0..n_primitives {
                 let primitive_index = morton_prims[i].primitive_index;
                 prims2[first_prim_offset + i] = primitives[primitive_index].clone();
@@ -5,17 +5,50 @@ use crate::core::geometry::*;
 use crate::core::light::*;
 use crate::core::material::*;
 use crate::core::paramset::*;
+use crate::core::pbrt::*;
 use crate::core::primitive::*;
+use crate::core::stats::*;
 
+mod cache;
 mod common;
 mod hlbvh;
 mod morton;
+mod motion;
+mod packet;
 mod sah;
+mod spatial;
+mod wide;
 
 pub use common::*;
 use hlbvh::*;
+pub use motion::*;
+use rayon::prelude::*;
 use sah::*;
+use std::mem::size_of;
 use std::sync::{Arc, Mutex};
+pub use wide::*;
+
+/// Time spent building BVHs.
+static BVH_BUILD_TIME: Stat = Stat::timer("Time/BVH construction");
+
+/// Memory used by BVH nodes and primitive references.
+static BVH_BYTES: Stat = Stat::memory("Memory/BVH tree");
+
+/// Number of interior nodes.
+static N_INTERIOR_NODES: Stat = Stat::counter("BVH/Interior nodes");
+
+/// Number of leaf nodes.
+static N_LEAF_NODES: Stat = Stat::counter("BVH/Leaf nodes");
+
+/// Number of primitives in leaf nodes.
+static PRIMS_PER_LEAF: Stat = Stat::distribution("BVH/Primitives per leaf node");
+
+/// Depth of leaf nodes.
+static LEAF_DEPTH: Stat = Stat::distribution("BVH/Leaf node depth");
+
+/// Number of time segments used for motion bounds when primitives move and
+/// the `motionsegments` parameter isn't given.
+const DEFAULT_MOTION_SEGMENTS: usize = 4;
 
 /// Bounding Volume Hierarchy Accelerator.
 #[derive(Clone)]
@@ -30,8 +63,17 @@ pub struct BVHAccel {
     /// Spliting method.
     pub split_method: SplitMethod,
 
-    /// The list of nodes.
+    /// Number of children per node (2, 4 or 8).
+    pub width: u8,
+
+    /// The list of nodes. Empty if `width` is 4 or 8.
     pub nodes: Vec<LinearBVHNode>,
+
+    /// The list of nodes if `width` is 4 or 8.
+    pub wide_nodes: Option<WideBVHNodes>,
+
+    /// Time-dependent bounds of `nodes` used to traverse moving primitives.
+    pub motion_bounds: Option<MotionBounds>,
 }
 
 impl BVHAccel {
@@ -40,18 +82,25 @@ impl BVHAccel {
     /// * `primitives`        - The primitives.
     /// * `max_prims_in_node` - Maximum number of primitives in a node.
     /// * `split_method`      - The splitting method.
+    /// * `width`             - Number of children per node (2, 4 or 8).
     pub fn new(
         primitives: &Vec<ArcPrimitive>,
         max_prims_in_node: u8,
         split_method: SplitMethod,
+        width: u8,
     ) -> Self {
+        let _timer = BVH_BUILD_TIME.start();
+
         let n_primitives = primitives.len();
         if n_primitives == 0 {
             Self {
                 primitives: primitives.clone(),
                 max_prims_in_node,
                 split_method,
+                width,
                 nodes: vec![],
+                wide_nodes: None,
+                motion_bounds: None,
             }
         } else {
             // Build BVH from primitives.
@@ -64,45 +113,186 @@ impl BVHAccel {
                 .collect();
 
             // Build BVH tree for primitives using primitive_info.
-            let mut total_nodes = 0;
-            let ordered_prims =
-                Arc::new(Mutex::new(Vec::<ArcPrimitive>::with_capacity(n_primitives)));
-
-            let root = match split_method {
-                SplitMethod::HLBVH => HLBVH::build(
-                    &primitives,
-                    max_prims_in_node,
-                    &mut primitive_info,
-                    &mut total_nodes,
-                    ordered_prims.clone(),
-                ),
-                _ => SAH::recursive_build(
+            let (root, ordered_prims, total_nodes) = match split_method {
+                SplitMethod::HLBVH => {
+                    let mut total_nodes = 0;
+                    let ordered_prims =
+                        Arc::new(Mutex::new(Vec::<ArcPrimitive>::with_capacity(n_primitives)));
+                    let root = HLBVH::build(
+                        &primitives,
+                        max_prims_in_node,
+                        &mut primitive_info,
+                        &mut total_nodes,
+                        ordered_prims.clone(),
+                    );
+                    let prims = ordered_prims.lock().expect("unable to lock ordered_prims");
+                    (root, prims.to_vec(), total_nodes)
+                }
+                _ => SAH::build(
                     &primitives,
                     split_method,
                     max_prims_in_node,
                     &mut primitive_info,
-                    0,
-                    n_primitives,
-                    &mut total_nodes,
-                    ordered_prims.clone(),
                 ),
             };
 
             // Compute representation of depth-first traversal of BVH tree.
-            let mut nodes = vec![LinearBVHNode::default(); total_nodes];
-            let mut offset = 0_u32;
-            Self::flatten_bvh_tree(root, &mut nodes, &mut offset);
-
-            debug_assert!(total_nodes == offset as usize);
+            // Wide BVHs collapse the binary tree instead.
+            let wide_nodes = WideBVHNodes::new(&root, width);
+            let nodes = if wide_nodes.is_none() {
+                let mut nodes = vec![LinearBVHNode::default(); total_nodes];
+                let mut offset = 0_u32;
+                Self::flatten_bvh_tree(root, &mut nodes, &mut offset);
+
+                debug_assert!(total_nodes == offset as usize);
+                nodes
+            } else {
+                vec![]
+            };
 
-            let prims = ordered_prims.clone();
-            let prims2 = prims.lock().expect("unabled to lock ordered_prims");
-            BVHAccel {
-                primitives: prims2.to_vec(),
+            let bvh = BVHAccel {
+                primitives: ordered_prims,
                 max_prims_in_node,
                 split_method,
+                width,
                 nodes,
+                wide_nodes,
+                motion_bounds: None,
+            };
+            bvh.report_stats();
+            bvh
+        }
+    }
+
+    /// Record statistics about the size and shape of the tree.
+    pub fn report_stats(&self) {
+        let report_leaf = |depth: usize, n_primitives: usize| {
+            N_LEAF_NODES.inc();
+            PRIMS_PER_LEAF.report(n_primitives as i64);
+            LEAF_DEPTH.report(depth as i64);
+        };
+
+        let node_bytes = if let Some(wide_nodes) = self.wide_nodes.as_ref() {
+            N_INTERIOR_NODES.add(wide_nodes.len() as i64);
+            wide_nodes.for_each_leaf(report_leaf);
+            wide_nodes.memory_size()
+        } else {
+            let mut to_visit = if self.nodes.is_empty() {
+                vec![]
+            } else {
+                vec![(0_usize, 0_usize)]
+            };
+            while let Some((i, depth)) = to_visit.pop() {
+                let node = &self.nodes[i];
+                if node.n_primitives > 0 {
+                    report_leaf(depth, node.n_primitives as usize);
+                } else {
+                    N_INTERIOR_NODES.inc();
+                    to_visit.push((i + 1, depth + 1));
+                    to_visit.push((node.offset as usize, depth + 1));
+                }
             }
+            self.nodes.len() * size_of::<LinearBVHNode>()
+        };
+
+        BVH_BYTES.add((node_bytes + self.primitives.len() * size_of::<ArcPrimitive>()) as i64);
+    }
+
+    /// Compute time-dependent node bounds so rays only visit nodes that
+    /// contain primitives at the ray's time. Only supported for a width of 2.
+    ///
+    /// * `n_segments` - Number of time segments.
+    /// * `start_time` - Start of the time period.
+    /// * `end_time`   - End of the time period.
+    pub fn compute_motion_bounds(&mut self, n_segments: usize, start_time: Float, end_time: Float) {
+        assert!(n_segments == 0 || self.wide_nodes.is_none());
+
+        self.motion_bounds = if n_segments > 0 && self.nodes.len() > 0 {
+            Some(MotionBounds::new(
+                &self.nodes,
+                &self.primitives,
+                n_segments,
+                start_time,
+                end_time,
+            ))
+        } else {
+            None
+        };
+    }
+
+    /// Returns `true` if the bounds of any primitive change over a time
+    /// period.
+    ///
+    /// * `primitives` - The primitives.
+    /// * `start_time` - Start of the time period.
+    /// * `end_time`   - End of the time period.
+    fn any_moving(primitives: &[ArcPrimitive], start_time: Float, end_time: Float) -> bool {
+        end_time > start_time
+            && primitives.par_iter().any(|p| {
+                let bounds = p.world_bound();
+                let (b0, b1) = p.motion_world_bounds(start_time, end_time);
+                b0 != bounds || b1 != bounds
+            })
+    }
+
+    /// Recompute the node bounds bottom-up from the current bounds of the
+    /// primitives without changing the tree. This is much faster than a
+    /// rebuild when primitives move, but the tree gets less efficient the
+    /// more they move from where they were when it was built.
+    ///
+    /// *NOTE*: Spatial splits clip primitive references to their nodes.
+    /// Refitting uses the full bounds of primitives so nodes may overlap
+    /// more than after a rebuild.
+    pub fn refit(&mut self) {
+        let prim_bounds: Vec<Bounds3f> = self
+            .primitives
+            .par_iter()
+            .map(|p| p.world_bound())
+            .collect();
+
+        if let Some(wide_nodes) = self.wide_nodes.as_mut() {
+            wide_nodes.refit(&prim_bounds);
+        }
+
+        // Children are always stored after their parent.
+        for i in (0..self.nodes.len()).rev() {
+            let node = self.nodes[i];
+            self.nodes[i].bounds = if node.n_primitives > 0 {
+                let first = node.offset as usize;
+                prim_bounds[first..first + node.n_primitives as usize]
+                    .iter()
+                    .fold(Bounds3f::empty(), |b, pb| b.union(pb))
+            } else {
+                self.nodes[i + 1]
+                    .bounds
+                    .union(&self.nodes[node.offset as usize].bounds)
+            };
+        }
+
+        if let Some(motion_bounds) = self.motion_bounds.take() {
+            self.compute_motion_bounds(
+                motion_bounds.n_segments,
+                motion_bounds.start_time,
+                motion_bounds.end_time,
+            );
+        }
+    }
+
+    /// Returns the bounding box of a node for a ray.
+    ///
+    /// * `node`    - Index of the node.
+    /// * `segment` - The ray's time segment and position within it if there
+    ///               are motion bounds.
+    fn node_bounds(&self, node: usize, segment: Option<(usize, Float)>) -> Bounds3f {
+        match (self.motion_bounds.as_ref(), segment) {
+            (Some(motion_bounds), Some(segment)) => {
+                // Interpolated bounds can be larger than the static bounds
+                // for primitives with non-linear motion.
+                motion_bounds
+                    .node_bounds(node, segment)
+                    .intersect(&self.nodes[node].bounds)
+            }
+            _ => self.nodes[node].bounds,
         }
     }
 
@@ -119,7 +309,7 @@ impl BVHAccel {
         *offset += 1;
 
         if node.n_primitives > 0 {
-            debug_assert!(!node.children[0].is_none() && !node.children[1].is_none());
+            debug_assert!(node.children[0].is_none() && node.children[1].is_none());
             debug_assert!(node.n_primitives < 65536);
 
             nodes[my_offset as usize] = LinearBVHNode::new_leaf_node(
@@ -154,7 +344,13 @@ impl Aggregate for BVHAccel {}
 impl Primitive for BVHAccel {
     /// Returns a bounding box in the world space.
     fn world_bound(&self) -> Bounds3f {
-        Bounds3f::default()
+        if let Some(wide_nodes) = self.wide_nodes.as_ref() {
+            wide_nodes.bounds()
+        } else if self.nodes.len() > 0 {
+            self.nodes[0].bounds
+        } else {
+            Bounds3f::empty()
+        }
     }
 
     /// Returns geometric details if a ray intersects the primitive and updates
@@ -163,6 +359,10 @@ impl Primitive for BVHAccel {
     ///
     /// * `r`                  - The ray.
     fn intersect(&self, r: &mut Ray) -> Option<SurfaceInteraction> {
+        if let Some(wide_nodes) = self.wide_nodes.as_ref() {
+            return wide_nodes.intersect(&self.primitives, r);
+        }
+
         let mut si: Option<SurfaceInteraction> = None;
         if self.nodes.len() > 0 {
             let inv_dir = Vector3f::new(1.0 / r.d.x, 1.0 / r.d.y, 1.0 / r.d.z);
@@ -172,14 +372,20 @@ impl Primitive for BVHAccel {
                 if inv_dir.z < 0.0 { 1_u8 } else { 0_u8 },
             ];
 
+            let segment = self.motion_bounds.as_ref().map(|m| m.segment(r.time));
+
             // Follow ray through BVH nodes to find primitive intersections.
-            let (mut to_visit_offset, mut current_node_index) = (0, 0);
-            let mut nodes_to_visit = [0_usize; 64];
+            // The stack grows with the tree so deep trees from spatial splits
+            // or cache files can't overflow it.
+            let mut nodes_to_visit = NodeStack::new(0_usize);
+            let mut n_visited = 0;
 
-            loop {
+            while let Some(current_node_index) = nodes_to_visit.pop() {
                 // Check ray against BVH node
+                n_visited += 1;
                 let node = &self.nodes[current_node_index];
-                if node.bounds.intersect_p_inv(r, &inv_dir, dir_is_neg) {
+                let bounds = self.node_bounds(current_node_index, segment);
+                if bounds.intersect_p_inv(r, &inv_dir, dir_is_neg) {
                     if node.n_primitives > 0 {
                         // Intersect ray with primitives in leaf BVH node.
                         for i in 0..node.n_primitives {
@@ -188,32 +394,20 @@ impl Primitive for BVHAccel {
                                 si = Some(hit);
                             }
                         }
-                        if to_visit_offset == 0 {
-                            break;
-                        }
-                        to_visit_offset -= 1;
-                        current_node_index = nodes_to_visit[to_visit_offset];
                     } else {
-                        // Put far BVH node on nodes_to_visit stack, advance to near
-                        // node.
+                        // Put far BVH node on nodes_to_visit stack, then the
+                        // near node so it is visited next.
                         if dir_is_neg[node.axis as usize] == 1 {
-                            nodes_to_visit[to_visit_offset] = current_node_index + 1;
-                            to_visit_offset += 1;
-                            current_node_index = node.offset as usize;
+                            nodes_to_visit.push(current_node_index + 1);
+                            nodes_to_visit.push(node.offset as usize);
                         } else {
-                            nodes_to_visit[to_visit_offset] = node.offset as usize;
-                            to_visit_offset += 1;
-                            current_node_index = current_node_index + 1;
+                            nodes_to_visit.push(node.offset as usize);
+                            nodes_to_visit.push(current_node_index + 1);
                         }
                     }
-                } else {
-                    if to_visit_offset == 0 {
-                        break;
-                    }
-                    to_visit_offset -= 1;
-                    current_node_index = nodes_to_visit[to_visit_offset];
                 }
             }
+            BVH_NODES_VISITED.add_fraction(n_visited, 1);
         }
         si
     }
@@ -222,6 +416,10 @@ impl Primitive for BVHAccel {
     ///
     /// * `r`                  - The ray.
     fn intersect_p(&self, r: &Ray) -> bool {
+        if let Some(wide_nodes) = self.wide_nodes.as_ref() {
+            return wide_nodes.intersect_p(&self.primitives, r);
+        }
+
         if self.nodes.len() > 0 {
             let inv_dir = Vector3f::new(1.0 / r.d.x, 1.0 / r.d.y, 1.0 / r.d.z);
             let dir_is_neg = [
@@ -230,52 +428,71 @@ impl Primitive for BVHAccel {
                 if inv_dir.z < 0.0 { 1_u8 } else { 0_u8 },
             ];
 
+            let segment = self.motion_bounds.as_ref().map(|m| m.segment(r.time));
+
             // Follow ray through BVH nodes to find primitive intersections.
-            let (mut to_visit_offset, mut current_node_index) = (0, 0);
-            let mut nodes_to_visit = [0_usize; 64];
+            let mut nodes_to_visit = NodeStack::new(0_usize);
+            let mut n_visited = 0;
 
-            loop {
+            while let Some(current_node_index) = nodes_to_visit.pop() {
                 // Check ray against BVH node
+                n_visited += 1;
                 let node = &self.nodes[current_node_index];
-                if node.bounds.intersect_p_inv(r, &inv_dir, dir_is_neg) {
+                let bounds = self.node_bounds(current_node_index, segment);
+                if bounds.intersect_p_inv(r, &inv_dir, dir_is_neg) {
                     if node.n_primitives > 0 {
                         // Intersect ray with primitives in leaf BVH node.
                         for i in 0..node.n_primitives {
                             let idx = node.offset as usize + i as usize;
                             if self.primitives[idx].intersect_p(r) {
+                                BVH_NODES_VISITED.add_fraction(n_visited, 1);
                                 return true;
                             }
                         }
-                        if to_visit_offset == 0 {
-                            break;
-                        }
-                        to_visit_offset -= 1;
-                        current_node_index = nodes_to_visit[to_visit_offset];
                     } else {
-                        // Put far BVH node on nodes_to_visit stack, advance to near
-                        // node.
+                        // Put far BVH node on nodes_to_visit stack, then the
+                        // near node so it is visited next.
                         if dir_is_neg[node.axis as usize] == 1 {
-                            nodes_to_visit[to_visit_offset] = current_node_index + 1;
-                            to_visit_offset += 1;
-                            current_node_index = node.offset as usize;
+                            nodes_to_visit.push(current_node_index + 1);
+                            nodes_to_visit.push(node.offset as usize);
                         } else {
-                            nodes_to_visit[to_visit_offset] = node.offset as usize;
-                            to_visit_offset += 1;
-                            current_node_index = current_node_index + 1;
+                            nodes_to_visit.push(node.offset as usize);
+                            nodes_to_visit.push(current_node_index + 1);
                         }
                     }
-                } else {
-                    if to_visit_offset == 0 {
-                        break;
-                    }
-                    to_visit_offset -= 1;
-                    current_node_index = nodes_to_visit[to_visit_offset];
                 }
             }
+            BVH_NODES_VISITED.add_fraction(n_visited, 1);
         }
         false
     }
 
+    /// Returns geometric details for the closest intersection of each ray in
+    /// a packet and updates the t_max parameter of the rays. The rays are
+    /// traversed through the BVH together so each node is visited once for
+    /// all rays that reach it.
+    ///
+    /// * `rays`               - The rays.
+    fn intersect_n(&self, rays: &mut [Ray]) -> Vec<Option<SurfaceInteraction>> {
+        if let Some(wide_nodes) = self.wide_nodes.as_ref() {
+            wide_nodes.intersect_n(&self.primitives, rays)
+        } else {
+            self.intersect_stream(rays)
+        }
+    }
+
+    /// Returns whether each ray in a packet intersects a primitive. The rays
+    /// are traversed through the BVH together.
+    ///
+    /// * `rays`               - The rays.
+    fn occluded_n(&self, rays: &[Ray]) -> Vec<bool> {
+        if let Some(wide_nodes) = self.wide_nodes.as_ref() {
+            wide_nodes.occluded_n(&self.primitives, rays)
+        } else {
+            self.occluded_stream(rays)
+        }
+    }
+
     /// Returns a reference to the AreaLight that describes the primitive’s
     /// emission distribution, if the primitive is itself a light source.
     /// If the primitive is not emissive, this method should return `None`.  
@@ -329,18 +546,21 @@ impl Primitive for BVHAccel {
     }
 }
 
-impl From<(&ParamSet, &Vec<ArcPrimitive>)> for BVHAccel {
-    /// Create a `BVHAccel` from given parameter set and primitives.
+impl From<(&ParamSet, &Vec<ArcPrimitive>, Float, Float)> for BVHAccel {
+    /// Create a `BVHAccel` from given parameter set, primitives and the
+    /// transform start and end times.
     ///
-    /// * `p` - Tuple containing the parameter set and primitives.
-    fn from(p: (&ParamSet, &Vec<ArcPrimitive>)) -> Self {
-        let (params, prims) = p;
+    /// * `p` - Tuple containing the parameter set, primitives, transform start
+    ///         time and transform end time.
+    fn from(p: (&ParamSet, &Vec<ArcPrimitive>, Float, Float)) -> Self {
+        let (params, prims, transform_start, transform_end) = p;
         let split_method_name = params.find_one_string("splitmethod", String::from("sah"));
         let split_method = match &split_method_name[..] {
             "sah" => SplitMethod::SAH,
             "hlbvh" => SplitMethod::HLBVH,
             "middle" => SplitMethod::Middle,
             "equal" => SplitMethod::EqualCounts,
+            "sbvh" => SplitMethod::SBVH,
             sm => {
                 warn!("BVH split method '{}' unknown.  Using 'sah'.", sm);
                 SplitMethod::SAH
@@ -348,6 +568,41 @@ impl From<(&ParamSet, &Vec<ArcPrimitive>)> for BVHAccel {
         };
 
         let max_prims_in_node = params.find_one_int("maxnodeprims", 4) as u8;
-        Self::new(prims, max_prims_in_node, split_method)
+
+        let mut width = match params.find_one_int("width", 2) {
+            w @ 2 | w @ 4 | w @ 8 => w as u8,
+            w => {
+                warn!("BVH width {} unsupported. Using 2.", w);
+                2
+            }
+        };
+
+        // Use motion bounds by default if any primitive moves over the
+        // shutter interval. Explicit motion segments force a width of 2.
+        let motion_segments = match params.find_one_int("motionsegments", -1) {
+            n if n < 0 => {
+                if width == 2 && Self::any_moving(prims, transform_start, transform_end) {
+                    DEFAULT_MOTION_SEGMENTS
+                } else {
+                    0
+                }
+            }
+            n => n as usize,
+        };
+        if motion_segments > 0 && width != 2 {
+            warn!("BVH motion segments require width 2. Using 2.");
+            width = 2;
+        }
+
+        // The cache file doesn't exist before the first build so it can't be
+        // looked up with `find_one_filename()`.
+        let cache_file = params.find_one_string("cachefile", String::from(""));
+        let mut bvh = if cache_file.is_empty() {
+            Self::new(prims, max_prims_in_node, split_method, width)
+        } else {
+            Self::new_cached(&cache_file, prims, max_prims_in_node, split_method, width)
+        };
+        bvh.compute_motion_bounds(motion_segments, transform_start, transform_end);
+        bvh
     }
 }
@@ -0,0 +1,195 @@
+//! Motion Blur Node Bounds
+
+#![allow(dead_code)]
+use super::common::*;
+use crate::core::geometry::*;
+use crate::core::pbrt::*;
+use crate::core::primitive::*;
+use rayon::prelude::*;
+
+/// Time-dependent bounding boxes for the nodes of a BVH. The time period is
+/// divided into equal segments and each node stores a pair of bounding boxes
+/// per segment such that linearly interpolating them bounds everything below
+/// the node at any time in the segment. This is much tighter than the static
+/// node bounds, which enclose the full motion of primitives.
+#[derive(Clone)]
+pub struct MotionBounds {
+    /// Start of the time period.
+    pub start_time: Float,
+
+    /// End of the time period.
+    pub end_time: Float,
+
+    /// Number of time segments.
+    pub n_segments: usize,
+
+    /// Bounding boxes at the start and end of each segment. The bounds for
+    /// segment `s` of node `i` are at `i * n_segments + s`.
+    pub bounds: Vec<[Bounds3f; 2]>,
+}
+
+impl MotionBounds {
+    /// Compute motion bounds for the nodes of a BVH.
+    ///
+    /// * `nodes`      - The depth-first ordered BVH nodes.
+    /// * `primitives` - The ordered primitives.
+    /// * `n_segments` - Number of time segments.
+    /// * `start_time` - Start of the time period.
+    /// * `end_time`   - End of the time period.
+    pub fn new(
+        nodes: &[LinearBVHNode],
+        primitives: &[ArcPrimitive],
+        n_segments: usize,
+        start_time: Float,
+        end_time: Float,
+    ) -> Self {
+        assert!(n_segments > 0);
+
+        // Compute the bounds of every primitive for each segment.
+        let segment_time = |s: usize| lerp(s as Float / n_segments as Float, start_time, end_time);
+        let prim_bounds: Vec<[Bounds3f; 2]> = primitives
+            .par_iter()
+            .flat_map_iter(|p| {
+                (0..n_segments).map(move |s| {
+                    let (b0, b1) = p.motion_world_bounds(segment_time(s), segment_time(s + 1));
+                    [b0, b1]
+                })
+            })
+            .collect();
+
+        // Children are always stored after their parent, so nodes can be
+        // processed bottom-up in reverse order.
+        let mut bounds = vec![[Bounds3f::empty(); 2]; nodes.len() * n_segments];
+        for (i, node) in nodes.iter().enumerate().rev() {
+            for s in 0..n_segments {
+                let b = if node.n_primitives > 0 {
+                    let first = node.offset as usize;
+                    (first..first + node.n_primitives as usize)
+                        .map(|p| prim_bounds[p * n_segments + s])
+                        .fold([Bounds3f::empty(); 2], union_pair)
+                } else {
+                    union_pair(
+                        bounds[(i + 1) * n_segments + s],
+                        bounds[node.offset as usize * n_segments + s],
+                    )
+                };
+                bounds[i * n_segments + s] = b;
+            }
+        }
+
+        Self {
+            start_time,
+            end_time,
+            n_segments,
+            bounds,
+        }
+    }
+
+    /// Returns the time segment containing the given time and the position
+    /// within the segment. Times outside the time period are clamped to it.
+    ///
+    /// * `time` - The time.
+    pub fn segment(&self, time: Float) -> (usize, Float) {
+        let duration = self.end_time - self.start_time;
+        let u = if duration > 0.0 {
+            clamp((time - self.start_time) / duration, 0.0, 1.0) * self.n_segments as Float
+        } else {
+            0.0
+        };
+        let s = min(u as usize, self.n_segments - 1);
+        (s, u - s as Float)
+    }
+
+    /// Returns the bounding box of a node at a position within a segment.
+    ///
+    /// * `node`    - Index of the node.
+    /// * `segment` - The segment and position within it.
+    pub fn node_bounds(&self, node: usize, segment: (usize, Float)) -> Bounds3f {
+        let (s, t) = segment;
+        let [b0, b1] = self.bounds[node * self.n_segments + s];
+        Bounds3f {
+            p_min: lerp(t, b0.p_min, b1.p_min),
+            p_max: lerp(t, b0.p_max, b1.p_max),
+        }
+    }
+}
+
+/// Returns the union of two pairs of start and end bounding boxes.
+///
+/// * `a` - First pair.
+/// * `b` - Second pair.
+fn union_pair(a: [Bounds3f; 2], b: [Bounds3f; 2]) -> [Bounds3f; 2] {
+    [a[0].union(&b[0]), a[1].union(&b[1])]
+}
+
+// ----------------------------------------------------------------------------
+// Tests
+// ----------------------------------------------------------------------------
+
+#[cfg(test)]
+mod tests {
+    use super::super::DEFAULT_MOTION_SEGMENTS;
+    use super::*;
+    use crate::accelerators::testing::*;
+    use crate::accelerators::BVHAccel;
+    use crate::core::paramset::*;
+
+    fn bvh(params: &ParamSet, prims: &Vec<ArcPrimitive>) -> BVHAccel {
+        BVHAccel::from((params, prims, 0.0, 1.0))
+    }
+
+    #[test]
+    fn motion_bounds_are_used_for_moving_primitives() {
+        let moving = moving_boxes(500, 1);
+        let motion_bounds = bvh(&ParamSet::new(), &moving).motion_bounds;
+        assert_eq!(
+            motion_bounds.map(|m| m.n_segments),
+            Some(DEFAULT_MOTION_SEGMENTS)
+        );
+
+        let mut params = ParamSet::new();
+        params.add_int("motionsegments", &[2]);
+        let motion_bounds = bvh(&params, &random_boxes(500, 1)).motion_bounds;
+        assert_eq!(motion_bounds.map(|m| m.n_segments), Some(2));
+    }
+
+    #[test]
+    fn motion_bounds_are_not_used_for_static_primitives() {
+        assert!(bvh(&ParamSet::new(), &random_boxes(500, 2))
+            .motion_bounds
+            .is_none());
+
+        let moving = moving_boxes(500, 2);
+        let mut params = ParamSet::new();
+        params.add_int("motionsegments", &[0]);
+        assert!(bvh(&params, &moving).motion_bounds.is_none());
+
+        // Wide BVHs don't support motion bounds unless they're requested.
+        let mut params = ParamSet::new();
+        params.add_int("width", &[4]);
+        let wide = bvh(&params, &moving);
+        assert!(wide.motion_bounds.is_none());
+        assert!(wide.wide_nodes.is_some());
+    }
+
+    #[test]
+    fn motion_bounds_contain_moving_primitives() {
+        let prims = moving_boxes(2000, 3);
+        let rays = random_rays(2000, 4);
+        let accel = bvh(&ParamSet::new(), &prims);
+        assert!(accel.motion_bounds.is_some());
+        assert!(assert_matches_brute_force(&accel, &prims, &rays) > 0);
+
+        // Node bounds at any time contain the primitives at that time.
+        let motion_bounds = accel.motion_bounds.as_ref().unwrap();
+        for &time in &[0.0, 0.1, 0.5, 0.99, 1.0] {
+            let segment = motion_bounds.segment(time);
+            let root = motion_bounds.node_bounds(0, segment);
+            for prim in prims.iter() {
+                let (b, _) = prim.motion_world_bounds(time, time);
+                let root = root.expand(1e-3);
+                assert!(root.contains(&b.p_min) && root.contains(&b.p_max));
+            }
+        }
+    }
+}
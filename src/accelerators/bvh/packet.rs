@@ -0,0 +1,509 @@
+//! Ray Packet Traversal
+//!
+//! Packets of rays are traversed through the BVH together as a ray stream.
+//! Each node is tested against all rays that reached it and only the rays
+//! that hit its bounds continue to its children. The indices of the active
+//! rays at each level are appended to a single buffer; every entry on the
+//! traversal stack refers to a range of it. Since ranges are created in
+//! stack order, popping an entry discards everything after its range.
+
+#![allow(dead_code)]
+use super::*;
+
+/// Per-ray values used to test a ray against node bounds.
+struct PacketRay {
+    /// Reciprocal of the ray direction.
+    inv_dir: Vector3f,
+
+    /// Indicates negative direction components.
+    dir_is_neg: [u8; 3],
+
+    /// The ray's time segment and position within it if there are motion
+    /// bounds.
+    segment: Option<(usize, Float)>,
+}
+
+impl BVHAccel {
+    /// Returns the per-ray values used for traversal.
+    ///
+    /// * `r` - The ray.
+    fn packet_ray(&self, r: &Ray) -> PacketRay {
+        let (inv_dir, dir_is_neg) = ray_inv_dir(r);
+        PacketRay {
+            inv_dir,
+            dir_is_neg,
+            segment: self.motion_bounds.as_ref().map(|m| m.segment(r.time)),
+        }
+    }
+
+    /// Filters the rays in `indices[start..end]` that hit the bounds of a node
+    /// and appends them to `indices`. Returns the range of the appended rays.
+    ///
+    /// * `node_index` - Index of the node.
+    /// * `rays`       - The rays.
+    /// * `info`       - Per-ray traversal values.
+    /// * `indices`    - Buffer of ray indices.
+    /// * `start`      - Start of the range of rays that reached the node.
+    /// * `end`        - End of the range of rays that reached the node.
+    /// * `active`     - Returns `true` for rays that still need traversal.
+    fn filter_rays<F: Fn(usize) -> bool>(
+        &self,
+        node_index: usize,
+        rays: &[Ray],
+        info: &[PacketRay],
+        indices: &mut Vec<u32>,
+        start: usize,
+        end: usize,
+        active: F,
+    ) -> (usize, usize) {
+        indices.truncate(end);
+        for k in start..end {
+            let i = indices[k] as usize;
+            if active(i)
+                && self
+                    .node_bounds(node_index, info[i].segment)
+                    .intersect_p_inv(&rays[i], &info[i].inv_dir, info[i].dir_is_neg)
+            {
+                indices.push(i as u32);
+            }
+        }
+        (end, indices.len())
+    }
+
+    /// Returns the children of an interior node ordered near to far for the
+    /// majority of the rays in `indices`.
+    ///
+    /// * `node_index` - Index of the node.
+    /// * `info`       - Per-ray traversal values.
+    /// * `indices`    - Indices of the rays.
+    fn ordered_children(
+        &self,
+        node_index: usize,
+        info: &[PacketRay],
+        indices: &[u32],
+    ) -> (usize, usize) {
+        let node = &self.nodes[node_index];
+        let n_neg = indices
+            .iter()
+            .filter(|&&i| info[i as usize].dir_is_neg[node.axis as usize] == 1)
+            .count();
+        if 2 * n_neg > indices.len() {
+            (node.offset as usize, node_index + 1)
+        } else {
+            (node_index + 1, node.offset as usize)
+        }
+    }
+
+    /// Returns geometric details for the closest intersection of each ray in
+    /// a packet using the binary nodes and updates the t_max parameter of the
+    /// rays.
+    ///
+    /// * `rays` - The rays.
+    pub fn intersect_stream(&self, rays: &mut [Ray]) -> Vec<Option<SurfaceInteraction>> {
+        let mut hits: Vec<Option<SurfaceInteraction>> = (0..rays.len()).map(|_| None).collect();
+        if self.nodes.is_empty() || rays.is_empty() {
+            return hits;
+        }
+
+        let info: Vec<PacketRay> = rays.iter().map(|r| self.packet_ray(r)).collect();
+        let mut indices: Vec<u32> = (0..rays.len() as u32).collect();
+        let mut nodes_to_visit = vec![(0_usize, 0_usize, rays.len())];
+        let mut n_visited = 0;
+
+        while let Some((node_index, start, end)) = nodes_to_visit.pop() {
+            n_visited += (end - start) as i64;
+            let (first, last) =
+                self.filter_rays(node_index, rays, &info, &mut indices, start, end, |_| true);
+            if first == last {
+                continue;
+            }
+
+            let node = &self.nodes[node_index];
+            if node.n_primitives > 0 {
+                // Intersect each primitive with all rays that reached the leaf.
+                let offset = node.offset as usize;
+                for prim in self.primitives[offset..offset + node.n_primitives as usize].iter() {
+                    for &i in indices[first..last].iter() {
+                        if let Some(hit) = prim.intersect(&mut rays[i as usize]) {
+                            hits[i as usize] = Some(hit);
+                        }
+                    }
+                }
+            } else {
+                let (near, far) = self.ordered_children(node_index, &info, &indices[first..last]);
+                nodes_to_visit.push((far, first, last));
+                nodes_to_visit.push((near, first, last));
+            }
+        }
+
+        BVH_NODES_VISITED.add_fraction(n_visited, rays.len() as i64);
+        hits
+    }
+
+    /// Returns whether each ray in a packet intersects a primitive using the
+    /// binary nodes.
+    ///
+    /// * `rays` - The rays.
+    pub fn occluded_stream(&self, rays: &[Ray]) -> Vec<bool> {
+        let mut occluded = vec![false; rays.len()];
+        if self.nodes.is_empty() || rays.is_empty() {
+            return occluded;
+        }
+
+        let info: Vec<PacketRay> = rays.iter().map(|r| self.packet_ray(r)).collect();
+        let mut indices: Vec<u32> = (0..rays.len() as u32).collect();
+        let mut nodes_to_visit = vec![(0_usize, 0_usize, rays.len())];
+        let mut n_visited = 0;
+        let mut n_occluded = 0;
+
+        while let Some((node_index, start, end)) = nodes_to_visit.pop() {
+            n_visited += (end - start) as i64;
+            let (first, last) = {
+                let occluded = &occluded;
+                self.filter_rays(node_index, rays, &info, &mut indices, start, end, |i| {
+                    !occluded[i]
+                })
+            };
+            if first == last {
+                continue;
+            }
+
+            let node = &self.nodes[node_index];
+            if node.n_primitives > 0 {
+                let offset = node.offset as usize;
+                for prim in self.primitives[offset..offset + node.n_primitives as usize].iter() {
+                    for &i in indices[first..last].iter() {
+                        let i = i as usize;
+                        if !occluded[i] && prim.intersect_p(&rays[i]) {
+                            occluded[i] = true;
+                            n_occluded += 1;
+                        }
+                    }
+                }
+                if n_occluded == rays.len() {
+                    break;
+                }
+            } else {
+                let (near, far) = self.ordered_children(node_index, &info, &indices[first..last]);
+                nodes_to_visit.push((far, first, last));
+                nodes_to_visit.push((near, first, last));
+            }
+        }
+
+        BVH_NODES_VISITED.add_fraction(n_visited, rays.len() as i64);
+        occluded
+    }
+}
+
+impl WideBVHNodes {
+    /// Returns geometric details for the closest intersection of each ray in
+    /// a packet and updates the t_max parameter of the rays.
+    ///
+    /// * `primitives` - The primitives referenced by leaf children.
+    /// * `rays`       - The rays.
+    pub fn intersect_n<'a>(
+        &self,
+        primitives: &'a [ArcPrimitive],
+        rays: &mut [Ray],
+    ) -> Vec<Option<SurfaceInteraction<'a>>> {
+        match self {
+            WideBVHNodes::Four(nodes) => intersect_n(nodes, primitives, rays),
+            WideBVHNodes::Eight(nodes) => intersect_n(nodes, primitives, rays),
+        }
+    }
+
+    /// Returns whether each ray in a packet intersects a primitive.
+    ///
+    /// * `primitives` - The primitives referenced by leaf children.
+    /// * `rays`       - The rays.
+    pub fn occluded_n(&self, primitives: &[ArcPrimitive], rays: &[Ray]) -> Vec<bool> {
+        match self {
+            WideBVHNodes::Four(nodes) => occluded_n(nodes, primitives, rays),
+            WideBVHNodes::Eight(nodes) => occluded_n(nodes, primitives, rays),
+        }
+    }
+}
+
+/// Returns geometric details for the closest intersection of each ray in a
+/// packet with an `N`-wide BVH and updates the t_max parameter of the rays.
+///
+/// * `nodes`      - The wide nodes.
+/// * `primitives` - The primitives referenced by leaf children.
+/// * `rays`       - The rays.
+fn intersect_n<'a, const N: usize>(
+    nodes: &[WideBVHNode<N>],
+    primitives: &'a [ArcPrimitive],
+    rays: &mut [Ray],
+) -> Vec<Option<SurfaceInteraction<'a>>> {
+    let mut hits: Vec<Option<SurfaceInteraction>> = (0..rays.len()).map(|_| None).collect();
+    if nodes.is_empty() || rays.is_empty() {
+        return hits;
+    }
+
+    let info: Vec<(Vector3f, [u8; 3])> = rays.iter().map(ray_inv_dir).collect();
+    let mut t_hits = vec![[INFINITY; N]; rays.len()];
+    let mut indices: Vec<u32> = (0..rays.len() as u32).collect();
+    let mut nodes_to_visit = vec![(0_u32, 0_usize, rays.len())];
+    let mut n_visited = 0;
+
+    while let Some((node_index, start, end)) = nodes_to_visit.pop() {
+        indices.truncate(end);
+        n_visited += (end - start) as i64;
+        let node = &nodes[node_index as usize];
+        let n_children = node.n_children as usize;
+
+        // Test all rays against the children and order the children by their
+        // mean entry distance.
+        let mut t_mean = [0.0 as Float; N];
+        let mut n_hits = [0_usize; N];
+        for &i in indices[start..end].iter() {
+            let i = i as usize;
+            let (inv_dir, dir_is_neg) = info[i];
+            t_hits[i] = node.intersect_children(&rays[i], &inv_dir, dir_is_neg);
+            for c in 0..n_children {
+                if t_hits[i][c] < INFINITY {
+                    t_mean[c] += t_hits[i][c];
+                    n_hits[c] += 1;
+                }
+            }
+        }
+        for c in 0..N {
+            t_mean[c] = if n_hits[c] > 0 {
+                t_mean[c] / n_hits[c] as Float
+            } else {
+                INFINITY
+            };
+        }
+        let (order, n_order) = sorted_hits(&t_mean, n_children);
+
+        // Intersect leaf children from near to far with the rays that hit
+        // them.
+        for &c in order[..n_order].iter() {
+            if node.n_primitives[c] > 0 {
+                let offset = node.offsets[c] as usize;
+                for prim in primitives[offset..offset + node.n_primitives[c] as usize].iter() {
+                    for &i in indices[start..end].iter() {
+                        let i = i as usize;
+                        if t_hits[i][c] < rays[i].t_max {
+                            if let Some(hit) = prim.intersect(&mut rays[i]) {
+                                hits[i] = Some(hit);
+                            }
+                        }
+                    }
+                }
+            }
+        }
+
+        // Push interior children far to near with the rays that still reach
+        // them so the nearest is visited next.
+        for &c in order[..n_order].iter().rev() {
+            if node.n_primitives[c] == 0 {
+                let first = indices.len();
+                for k in start..end {
+                    let i = indices[k] as usize;
+                    if t_hits[i][c] < rays[i].t_max {
+                        indices.push(i as u32);
+                    }
+                }
+                if indices.len() > first {
+                    nodes_to_visit.push((node.offsets[c], first, indices.len()));
+                }
+            }
+        }
+    }
+
+    BVH_NODES_VISITED.add_fraction(n_visited, rays.len() as i64);
+    hits
+}
+
+/// Returns whether each ray in a packet intersects a primitive in an
+/// `N`-wide BVH.
+///
+/// * `nodes`      - The wide nodes.
+/// * `primitives` - The primitives referenced by leaf children.
+/// * `rays`       - The rays.
+fn occluded_n<const N: usize>(
+    nodes: &[WideBVHNode<N>],
+    primitives: &[ArcPrimitive],
+    rays: &[Ray],
+) -> Vec<bool> {
+    let mut occluded = vec![false; rays.len()];
+    if nodes.is_empty() || rays.is_empty() {
+        return occluded;
+    }
+
+    let info: Vec<(Vector3f, [u8; 3])> = rays.iter().map(ray_inv_dir).collect();
+    let mut t_hits = vec![[INFINITY; N]; rays.len()];
+    let mut indices: Vec<u32> = (0..rays.len() as u32).collect();
+    let mut nodes_to_visit = vec![(0_u32, 0_usize, rays.len())];
+    let mut n_visited = 0;
+    let mut n_occluded = 0;
+
+    while let Some((node_index, start, end)) = nodes_to_visit.pop() {
+        indices.truncate(end);
+        n_visited += (end - start) as i64;
+        let node = &nodes[node_index as usize];
+        let n_children = node.n_children as usize;
+
+        for &i in indices[start..end].iter() {
+            let i = i as usize;
+            if !occluded[i] {
+                let (inv_dir, dir_is_neg) = info[i];
+                t_hits[i] = node.intersect_children(&rays[i], &inv_dir, dir_is_neg);
+            }
+        }
+
+        // The order of traversal doesn't matter for shadow rays.
+        for c in 0..n_children {
+            if node.n_primitives[c] > 0 {
+                let offset = node.offsets[c] as usize;
+                for prim in primitives[offset..offset + node.n_primitives[c] as usize].iter() {
+                    for &i in indices[start..end].iter() {
+                        let i = i as usize;
+                        if !occluded[i] && t_hits[i][c] < INFINITY && prim.intersect_p(&rays[i]) {
+                            occluded[i] = true;
+                            n_occluded += 1;
+                        }
+                    }
+                }
+            } else {
+                let first = indices.len();
+                for k in start..end {
+                    let i = indices[k] as usize;
+                    if !occluded[i] && t_hits[i][c] < INFINITY {
+                        indices.push(i as u32);
+                    }
+                }
+                if indices.len() > first {
+                    nodes_to_visit.push((node.offsets[c], first, indices.len()));
+                }
+            }
+        }
+
+        if n_occluded == rays.len() {
+            break;
+        }
+    }
+
+    BVH_NODES_VISITED.add_fraction(n_visited, rays.len() as i64);
+    occluded
+}
+
+// ----------------------------------------------------------------------------
+// Tests
+// ----------------------------------------------------------------------------
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::accelerators::testing::*;
+    use rand::{Rng, SeedableRng};
+    use rand_pcg::Pcg32;
+
+    /// Returns `n` rays from a common origin towards a small region, like
+    /// camera rays or shadow rays of a point. Every 3rd ray ends after a
+    /// random distance like a shadow ray.
+    ///
+    /// * `n`    - Number of rays.
+    /// * `seed` - Random number seed.
+    fn coherent_rays(n: usize, seed: u64) -> Vec<Ray> {
+        let mut rng = Pcg32::seed_from_u64(seed);
+        let o = Point3f::new(-15.0, 1.0, -2.0);
+        (0..n)
+            .map(|i| {
+                let d = Vector3f::new(15.0, rng.gen_range(-3.0..3.0), rng.gen_range(-3.0..3.0));
+                let t_max = if i % 3 == 0 {
+                    rng.gen_range(0.5..1.5)
+                } else {
+                    INFINITY
+                };
+                Ray::new(o, d, t_max, rng.gen_range(0.0..1.0), None)
+            })
+            .collect()
+    }
+
+    /// Asserts that tracing rays as a packet finds the same closest hits and
+    /// occlusion as tracing them one at a time.
+    ///
+    /// * `bvh`  - The BVH.
+    /// * `rays` - The rays.
+    fn assert_packets_match(bvh: &BVHAccel, rays: &[Ray]) {
+        let mut packet = rays.to_vec();
+        let hits = bvh.intersect_n(&mut packet);
+        let occluded = bvh.occluded_n(rays);
+        assert_eq!(hits.len(), rays.len());
+        assert_eq!(occluded.len(), rays.len());
+
+        let mut n_hits = 0;
+        for (i, r) in rays.iter().enumerate() {
+            let mut ray = r.clone();
+            let hit = bvh.intersect(&mut ray);
+            assert_eq!(
+                hits[i].as_ref().map(|si| si.uv.x as usize),
+                hit.map(|si| si.uv.x as usize),
+                "ray {}",
+                i
+            );
+            assert_eq!(packet[i].t_max, ray.t_max, "ray {}", i);
+            assert_eq!(occluded[i], bvh.intersect_p(r), "ray {}", i);
+            if occluded[i] {
+                n_hits += 1;
+            }
+        }
+        assert!(n_hits > 0 && n_hits < rays.len());
+    }
+
+    /// Returns the rays used to compare packets with single rays.
+    fn rays() -> Vec<Ray> {
+        let mut rays = random_rays(1000, 2);
+        rays.extend(coherent_rays(1000, 3));
+        rays
+    }
+
+    #[test]
+    fn binary_packets_match_single_rays() {
+        let prims = random_boxes(2000, 1);
+        for &split_method in &[
+            SplitMethod::SAH,
+            SplitMethod::SBVH,
+            SplitMethod::Middle,
+            SplitMethod::EqualCounts,
+        ] {
+            let bvh = BVHAccel::new(&prims, 4, split_method, 2);
+            assert!(bvh.wide_nodes.is_none());
+            assert_packets_match(&bvh, &rays());
+        }
+    }
+
+    #[test]
+    fn wide_packets_match_single_rays() {
+        let prims = random_boxes(2000, 1);
+        for &width in &[4, 8] {
+            for &split_method in &[SplitMethod::SAH, SplitMethod::SBVH] {
+                let bvh = BVHAccel::new(&prims, 4, split_method, width);
+                assert!(bvh.wide_nodes.is_some());
+                assert_packets_match(&bvh, &rays());
+            }
+        }
+    }
+
+    #[test]
+    fn motion_packets_match_single_rays() {
+        let prims = moving_boxes(1000, 4);
+        let bvh = BVHAccel::from((&ParamSet::new(), &prims, 0.0, 1.0));
+        assert!(bvh.motion_bounds.is_some());
+        assert_packets_match(&bvh, &rays());
+    }
+
+    #[test]
+    fn empty_packets_and_bvhs() {
+        let bvh = BVHAccel::new(&random_boxes(100, 5), 4, SplitMethod::SAH, 4);
+        assert!(bvh.intersect_n(&mut []).is_empty());
+        assert!(bvh.occluded_n(&[]).is_empty());
+
+        let empty = BVHAccel::new(&vec![], 4, SplitMethod::SAH, 2);
+        let mut rays = random_rays(10, 6);
+        assert!(empty.intersect_n(&mut rays).iter().all(|hit| hit.is_none()));
+        assert!(empty.occluded_n(&rays).iter().all(|&occluded| !occluded));
+    }
+}
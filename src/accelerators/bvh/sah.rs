@@ -2,162 +2,315 @@
 
 #![allow(dead_code)]
 use super::common::*;
+use super::spatial::*;
 use crate::core::geometry::*;
 use crate::core::pbrt::*;
 use crate::core::primitive::*;
 use order_stat::kth_by;
+use rayon::prelude::*;
 use std::cmp::Ordering;
-use std::sync::{Arc, Mutex};
+use std::sync::atomic::{self, AtomicUsize};
+use std::sync::Arc;
 
 const N_BUCKETS: usize = 12;
 
+/// Minimum number of primitives in a node to build its children in parallel.
+const PARALLEL_BUILD_THRESHOLD: usize = 1024;
+
+/// Minimum number of primitives in a node to compute bounds and SAH buckets
+/// in parallel.
+const PARALLEL_BUCKET_THRESHOLD: usize = 16384;
+
+/// Number of primitives processed by a task when computing bounds and SAH
+/// buckets in parallel.
+const PARALLEL_CHUNK_SIZE: usize = 4096;
+
+/// Spatial splits are only considered for nodes where the children of the
+/// best object split overlap by more than this fraction of the root's
+/// surface area.
+const SPATIAL_SPLIT_ALPHA: Float = 1e-5;
+
+/// Maximum number of additional primitive references created by spatial
+/// splits as a fraction of the number of primitives.
+const SPATIAL_SPLIT_BUDGET: Float = 1.0;
+
+/// Returns the maximum number of primitive references in the leaves of a BVH.
+/// Only spatial splits can reference a primitive more than once.
+///
+/// * `n_primitives` - Number of primitives.
+/// * `split_method` - The splitting method.
+pub fn max_references(n_primitives: usize, split_method: SplitMethod) -> usize {
+    if split_method == SplitMethod::SBVH {
+        n_primitives + (SPATIAL_SPLIT_BUDGET * n_primitives as Float) as usize
+    } else {
+        n_primitives
+    }
+}
+
+/// Splitting of primitives between two child nodes.
+enum Split {
+    /// Primitives are partitioned in place at the given index.
+    Object(usize),
+
+    /// Primitives are clipped to either side of a plane along an axis and
+    /// may be referenced by both children.
+    Spatial(Axis, Vec<BVHPrimitiveInfo>, Vec<BVHPrimitiveInfo>),
+}
+
 /// Implements Surface Area Heuristic Algorithm
-pub struct SAH {}
+pub struct SAH<'a> {
+    /// The primitives.
+    primitives: &'a [ArcPrimitive],
+
+    /// Middle|EqualCounts|SAH|SBVH
+    split_method: SplitMethod,
+
+    /// Maximum number of primitives in the node.
+    max_prims_in_node: usize,
+
+    /// Primitive numbers of leaf nodes. Leaf nodes reserve their range from
+    /// `n_ordered_prims` as they are created, so the order depends on how
+    /// subtrees were scheduled; `order_leaves()` makes it depth-first.
+    ordered_prims: Vec<AtomicUsize>,
+
+    /// Number of entries reserved in `ordered_prims`.
+    n_ordered_prims: AtomicUsize,
 
-impl SAH {
-    /// Recursively build the BVH structure for either Middle, EqualCounts or SAH
-    /// algorithm.
+    /// Minimum overlap surface area to consider a spatial split.
+    min_overlap_area: Float,
+
+    /// Total number of nodes created.
+    total_nodes: AtomicUsize,
+}
+
+impl<'a> SAH<'a> {
+    /// Build the BVH structure for either Middle, EqualCounts, SAH or SBVH
+    /// algorithm. Subtrees are built in parallel.
+    ///
+    /// Returns the root node, the primitives ordered such that primitives in
+    /// leaf nodes occupy contiguous ranges in the vector and the total number
+    /// of nodes.
     ///
-    /// * `primitives`        - The primitives in the node.
-    /// * `split_method`      - Middle|EqualCounts|SAH
+    /// * `primitives`        - The primitives.
+    /// * `split_method`      - Middle|EqualCounts|SAH|SBVH
     /// * `max_prims_in_node` - Maximum number of primitives in the node.
     /// * `primitive_info`    - Primitive information.
-    /// * `start`             - Starting index. For first call it should be 0.
-    /// * `end`               - Ending index + 1. For first call it should be number
-    ///                         of primitives.
-    /// * `total_nodes`       - Used to return total number of nodes.
-    /// * `ordered_prims`     - Used to return a list of primitives ordered such that
-    ///                         primitives in leaf nodes occupy contiguous ranges in
-    ///                         the vector.
-    pub fn recursive_build(
-        primitives: &Vec<ArcPrimitive>,
+    pub fn build(
+        primitives: &'a [ArcPrimitive],
         split_method: SplitMethod,
         max_prims_in_node: u8,
         primitive_info: &mut Vec<BVHPrimitiveInfo>,
-        start: usize,
-        end: usize,
-        total_nodes: &mut usize,
-        ordered_prims: Arc<Mutex<Vec<ArcPrimitive>>>,
-    ) -> Arc<BVHBuildNode> {
-        // Compute bounds of all primitives in BVH node.
-        let bounds = (start..end).fold(Bounds3f::default(), |b, i| {
-            b.union(&primitive_info[i].bounds)
-        });
+    ) -> (Arc<BVHBuildNode>, Vec<ArcPrimitive>, usize) {
+        let n_primitives = primitive_info.len();
+        let max_refs = max_references(n_primitives, split_method);
+
+        let (bounds, _) = Self::compute_bounds(primitive_info);
+        let builder = Self {
+            primitives,
+            split_method,
+            max_prims_in_node: max_prims_in_node as usize,
+            ordered_prims: (0..max_refs).map(|_| AtomicUsize::new(0)).collect(),
+            n_ordered_prims: AtomicUsize::new(0),
+            min_overlap_area: SPATIAL_SPLIT_ALPHA * bounds.surface_area(),
+            total_nodes: AtomicUsize::new(0),
+        };
 
-        let mut dim = Axis::default(); // Will be set if we need to make interior node.
+        let mut root = builder.recursive_build(primitive_info, max_refs - n_primitives);
 
-        let n_primitives = end - start;
+        let mut ordered_prims =
+            Vec::with_capacity(builder.n_ordered_prims.load(atomic::Ordering::Relaxed));
+        builder.order_leaves(&mut root, &mut ordered_prims);
 
-        let interior_midpoint = if n_primitives == 1 {
-            // Create leaf BVHBuildNode.
-            None
+        (root, ordered_prims, builder.total_nodes.into_inner())
+    }
+
+    /// Copies the primitives of leaf nodes to `ordered_prims` in depth-first
+    /// order and updates the offsets of the leaves, so the result doesn't
+    /// depend on the order in which leaves were created.
+    ///
+    /// * `node`          - The node.
+    /// * `ordered_prims` - Used to return the ordered primitives.
+    fn order_leaves(&self, node: &mut Arc<BVHBuildNode>, ordered_prims: &mut Vec<ArcPrimitive>) {
+        let node = Arc::get_mut(node).expect("BVH build nodes are not shared");
+        if node.n_primitives > 0 {
+            let start = node.first_prim_offset;
+            node.first_prim_offset = ordered_prims.len();
+            ordered_prims.extend(
+                self.ordered_prims[start..start + node.n_primitives]
+                    .iter()
+                    .map(|prim_num| {
+                        self.primitives[prim_num.load(atomic::Ordering::Relaxed)].clone()
+                    }),
+            );
         } else {
-            // Compute bound of primitive centroids, choose split dimension dim.
-            let centroid_bounds = (start..end).fold(Bounds3f::default(), |b, i| {
-                b.union(&primitive_info[i].centroid)
-            });
-            dim = centroid_bounds.maximum_extent();
-
-            // Partition primitives into two sets and build children.
-            if centroid_bounds.p_max[dim] == centroid_bounds.p_min[dim] {
-                // Create leaf BVHBuildNode.
-                None
-            } else {
-                // Partition primitives based on split_method.
-                match split_method {
-                    SplitMethod::Middle => Some(Self::split_middle(
-                        primitive_info,
-                        start,
-                        end,
-                        dim,
-                        &centroid_bounds,
-                    )),
-
-                    SplitMethod::EqualCounts => {
-                        Some(Self::split_equal_counts(primitive_info, start, end, dim))
-                    }
-
-                    SplitMethod::SAH => Self::split_sah(
-                        primitive_info,
-                        start,
-                        end,
-                        n_primitives,
-                        dim,
-                        &centroid_bounds,
-                        &bounds,
-                        max_prims_in_node,
-                    ),
-
-                    _ => panic!("recursive_build(): Invalid split_method={:?}", split_method),
-                }
+            for child in node.children.iter_mut().flatten() {
+                self.order_leaves(child, ordered_prims);
             }
-        };
+        }
+    }
 
-        *total_nodes += 1;
-        if let Some(mid) = interior_midpoint {
-            // Create interior BVHBuildNode.
-            let c0 = Self::recursive_build(
-                primitives,
-                split_method,
-                max_prims_in_node,
-                primitive_info,
-                start,
-                mid,
-                total_nodes,
-                ordered_prims.clone(),
-            );
-            let c1 = Self::recursive_build(
-                primitives,
-                split_method,
-                max_prims_in_node,
+    /// Recursively build the BVH structure.
+    ///
+    /// * `primitive_info` - Information for primitives in the node.
+    /// * `budget`         - Number of primitive references spatial splits may
+    ///                      duplicate in the subtree.
+    fn recursive_build(
+        &self,
+        primitive_info: &mut [BVHPrimitiveInfo],
+        budget: usize,
+    ) -> Arc<BVHBuildNode> {
+        self.total_nodes.fetch_add(1, atomic::Ordering::Relaxed);
+
+        // Compute bounds of all primitives and their centroids in BVH node.
+        let (bounds, centroid_bounds) = Self::compute_bounds(primitive_info);
+
+        let n_primitives = primitive_info.len();
+        if n_primitives == 1 {
+            return self.create_leaf(primitive_info, bounds);
+        }
+
+        // Choose split dimension dim.
+        let dim = centroid_bounds.maximum_extent();
+        if centroid_bounds.p_max[dim] == centroid_bounds.p_min[dim] {
+            return self.create_leaf(primitive_info, bounds);
+        }
+
+        // Partition primitives based on split_method.
+        let split = match self.split_method {
+            SplitMethod::Middle => Some(Split::Object(Self::split_middle(
                 primitive_info,
-                mid,
-                end,
-                total_nodes,
-                ordered_prims.clone(),
-            );
-            BVHBuildNode::new_interior_node(dim, c0, c1)
-        } else {
-            // Create leaf BVHBuildNode.
-            let prims = ordered_prims.clone();
-            let mut prims2 = prims.lock().expect("unable to lock ordered_prims");
-            let first_prim_offset = prims2.len();
-            for i in start..end {
-                let prim_num = primitive_info[i].primitive_number;
-                prims2.push(primitives[prim_num].clone());
+                dim,
+                &centroid_bounds,
+            ))),
+
+            SplitMethod::EqualCounts => {
+                Some(Split::Object(Self::split_equal_counts(primitive_info, dim)))
+            }
+
+            SplitMethod::SAH | SplitMethod::SBVH => {
+                self.split_sah(primitive_info, dim, &centroid_bounds, &bounds, budget)
+            }
+
+            _ => panic!(
+                "recursive_build(): Invalid split_method={:?}",
+                self.split_method
+            ),
+        };
+
+        // Create interior BVHBuildNode or leaf if no split occurred.
+        match split {
+            Some(Split::Object(mid)) => {
+                let (left, right) = primitive_info.split_at_mut(mid);
+                let (c0, c1) = self.build_children(left, right, budget);
+                BVHBuildNode::new_interior_node(dim, c0, c1)
+            }
+            Some(Split::Spatial(axis, mut left, mut right)) => {
+                let n_extra = left.len() + right.len() - n_primitives;
+                let (c0, c1) = self.build_children(&mut left, &mut right, budget - n_extra);
+                BVHBuildNode::new_interior_node(axis, c0, c1)
             }
-            BVHBuildNode::new_leaf_node(first_prim_offset, n_primitives, bounds)
+            None => self.create_leaf(primitive_info, bounds),
+        }
+    }
+
+    /// Build the children of an interior node. Large children are built in
+    /// parallel. The budget for duplicated primitive references is shared
+    /// in proportion to the number of primitives in each child so the tree
+    /// doesn't depend on the order in which subtrees are built.
+    ///
+    /// * `left`   - Information for primitives in the first child.
+    /// * `right`  - Information for primitives in the second child.
+    /// * `budget` - Number of primitive references spatial splits may
+    ///              duplicate in both children.
+    fn build_children(
+        &self,
+        left: &mut [BVHPrimitiveInfo],
+        right: &mut [BVHPrimitiveInfo],
+        budget: usize,
+    ) -> (Arc<BVHBuildNode>, Arc<BVHBuildNode>) {
+        let left_budget = budget * left.len() / (left.len() + right.len());
+        let right_budget = budget - left_budget;
+        if left.len() + right.len() >= PARALLEL_BUILD_THRESHOLD {
+            rayon::join(
+                || self.recursive_build(left, left_budget),
+                || self.recursive_build(right, right_budget),
+            )
+        } else {
+            (
+                self.recursive_build(left, left_budget),
+                self.recursive_build(right, right_budget),
+            )
+        }
+    }
+
+    /// Create a leaf node and reserve a range in the ordered primitives for
+    /// its primitives. The range is final once `order_leaves()` runs.
+    ///
+    /// * `primitive_info` - Information for primitives in the node.
+    /// * `bounds`         - Bounding box of primitives in the node.
+    fn create_leaf(
+        &self,
+        primitive_info: &[BVHPrimitiveInfo],
+        bounds: Bounds3f,
+    ) -> Arc<BVHBuildNode> {
+        let n_primitives = primitive_info.len();
+        let first_prim_offset = self
+            .n_ordered_prims
+            .fetch_add(n_primitives, atomic::Ordering::Relaxed);
+        debug_assert!(first_prim_offset + n_primitives <= self.ordered_prims.len());
+
+        for (i, pi) in primitive_info.iter().enumerate() {
+            self.ordered_prims[first_prim_offset + i]
+                .store(pi.primitive_number, atomic::Ordering::Relaxed);
+        }
+        BVHBuildNode::new_leaf_node(first_prim_offset, n_primitives, bounds)
+    }
+
+    /// Returns the bounding box of primitives and the bounding box of their
+    /// centroids. Large nodes are processed in parallel.
+    ///
+    /// * `primitive_info` - Information for primitives in the node.
+    fn compute_bounds(primitive_info: &[BVHPrimitiveInfo]) -> (Bounds3f, Bounds3f) {
+        let fold = |(b, cb): (Bounds3f, Bounds3f), pi: &BVHPrimitiveInfo| {
+            (b.union(&pi.bounds), cb.union(&pi.centroid))
+        };
+        let empty = (Bounds3f::empty(), Bounds3f::empty());
+
+        if primitive_info.len() >= PARALLEL_BUCKET_THRESHOLD {
+            primitive_info
+                .par_chunks(PARALLEL_CHUNK_SIZE)
+                .map(|chunk| chunk.iter().fold(empty, fold))
+                .reduce(
+                    || empty,
+                    |(b0, cb0), (b1, cb1)| (b0.union(&b1), cb0.union(&cb1)),
+                )
+        } else {
+            primitive_info.iter().fold(empty, fold)
         }
     }
 
     /// Linear Bounding Volume Hierarchy using splitting planes that are
     /// midpoint of each region of space.
     ///
-    /// * `primitive_info`  - Vector containing all primitive info.
-    /// * `start`           - Starting index in primitive_info.
-    /// * `end`             - Ending index + 1 in primitive_info.
+    /// * `primitive_info`  - Information for primitives in the node.
     /// * `dim`             - Axis used to partition primitives.
-    /// * `centroid_bounds` - Bounding box of primtive centroids in primtive_info
-    ///                       from start to end.
+    /// * `centroid_bounds` - Bounding box of primtive centroids in primtive_info.
     fn split_middle(
-        primitive_info: &mut Vec<BVHPrimitiveInfo>,
-        start: usize,
-        end: usize,
+        primitive_info: &mut [BVHPrimitiveInfo],
         dim: Axis,
         centroid_bounds: &Bounds3f,
     ) -> usize {
         let pmid = (centroid_bounds.p_min[dim] + centroid_bounds.p_max[dim]) / 2.0;
-        let split = primitive_info[start..end]
+        let mid = primitive_info
             .iter_mut()
             .partition_in_place(|pi| pi.centroid[dim] < pmid);
-        let mid = start + split;
 
-        if mid != start && mid != end {
+        if mid != 0 && mid != primitive_info.len() {
             mid
         } else {
             // Lots of prims with large overlapping bounding boxes, this may fail
             // to partition; in that case don't use EqualCounts.
-            Self::split_equal_counts(primitive_info, start, end, dim)
+            Self::split_equal_counts(primitive_info, dim)
         }
     }
 
@@ -165,19 +318,12 @@ impl SAH {
     /// of the primitives have smallest centroid coordinate values along the
     /// chosen axis, and second have have the largest centroid coordinate values.
     ///
-    /// * `primitive_info`  - Vector containing all primitive info.
-    /// * `start`           - Starting index in primitive_info.
-    /// * `end`             - Ending index + 1 in primitive_info.
+    /// * `primitive_info`  - Information for primitives in the node.
     /// * `dim`             - Axis used to partition primitives.
-    fn split_equal_counts(
-        primitive_info: &mut Vec<BVHPrimitiveInfo>,
-        start: usize,
-        end: usize,
-        dim: Axis,
-    ) -> usize {
-        let mid = (start + end) / 2;
+    fn split_equal_counts(primitive_info: &mut [BVHPrimitiveInfo], dim: Axis) -> usize {
+        let mid = primitive_info.len() / 2;
 
-        Self::kth_element_by(primitive_info, start, mid, end, |&a, &b| {
+        kth_by(primitive_info, mid, |a, b| {
             if a.centroid[dim] < b.centroid[dim] {
                 Ordering::Less
             } else if a.centroid[dim] == b.centroid[dim] {
@@ -190,128 +336,227 @@ impl SAH {
         mid
     }
 
-    /// Partition a subset of items between start and end inclusive such that:
-    /// - k^th element will be in its sorted order
-    /// - elements e in v[start, k - 1] will satisfy f(e, ek) == Ordering::Less
-    /// - elements e in v[k + 1, end] will satisfy f(e, ek) == Ordering::Greater
-    /// and the k^th element is returned.
+    /// Returns the SAH bucket of a centroid.
     ///
-    /// * `v`     - Vector to partition.
-    /// * `start` - Starting index.
-    /// * `end`   - Ending index + 1.
-    /// * `f`     - Predicate used for partitioning.
-    fn kth_element_by<F, T>(v: &mut Vec<T>, start: usize, k: usize, end: usize, f: F) -> Option<T>
-    where
-        F: Fn(&T, &T) -> Ordering,
-        T: Copy,
-    {
-        if start >= end || end > v.len() || k < start || k >= end {
-            return None;
-        }
+    /// * `centroid`        - The centroid.
+    /// * `dim`             - Axis used to partition primitives.
+    /// * `centroid_bounds` - Bounding box of primtive centroids in the node.
+    fn bucket(centroid: &Point3f, dim: Axis, centroid_bounds: &Bounds3f) -> usize {
+        let b = (N_BUCKETS as Float * centroid_bounds.offset(centroid)[dim]) as usize;
+        let b = min(b, N_BUCKETS - 1);
+        debug_assert!(b < N_BUCKETS);
+        b
+    }
 
-        let w = v[start..end].as_mut();
-        let i = *kth_by(w, k - start, |&x, &y| f(&x, &y));
+    /// Initialize BucketInfo for SAH partition buckets. Large nodes are
+    /// processed in parallel.
+    ///
+    /// * `primitive_info`  - Information for primitives in the node.
+    /// * `dim`             - Axis used to partition primitives.
+    /// * `centroid_bounds` - Bounding box of primtive centroids in the node.
+    fn compute_buckets(
+        primitive_info: &[BVHPrimitiveInfo],
+        dim: Axis,
+        centroid_bounds: &Bounds3f,
+    ) -> [BucketInfo; N_BUCKETS] {
+        let fold = |mut buckets: [BucketInfo; N_BUCKETS], pi: &BVHPrimitiveInfo| {
+            let b = Self::bucket(&pi.centroid, dim, centroid_bounds);
+            buckets[b].count += 1;
+            buckets[b].bounds = buckets[b].bounds.union(&pi.bounds);
+            buckets
+        };
+        let empty = [BucketInfo::default(); N_BUCKETS];
 
-        Some(i)
+        if primitive_info.len() >= PARALLEL_BUCKET_THRESHOLD {
+            primitive_info
+                .par_chunks(PARALLEL_CHUNK_SIZE)
+                .map(|chunk| chunk.iter().fold(empty, fold))
+                .reduce(
+                    || empty,
+                    |mut b0, b1| {
+                        for (a, b) in b0.iter_mut().zip(b1.iter()) {
+                            a.count += b.count;
+                            a.bounds = a.bounds.union(&b.bounds);
+                        }
+                        b0
+                    },
+                )
+        } else {
+            primitive_info.iter().fold(empty, fold)
+        }
     }
 
-    /// Partition primitives using Surface Area Heuristic.
+    /// Partition primitives using binned Surface Area Heuristic. The SBVH
+    /// split method also considers spatial splits.
     ///
-    /// If the algorithm is able to partition primitives it will return the pivot
-    /// index (mid) for interior node creation; otherwise None is returned to
-    /// indicate leaf node creation.
+    /// Returns the split for interior node creation or `None` to indicate
+    /// leaf node creation.
     ///
-    /// * `primitive_info`    - Vector containing all primitive info.
-    /// * `start`             - Start index in primitive_info.
-    /// * `end`               - End index in primitive_info.
-    /// * `n_primitives`      - Number of primitives between start and end.
-    /// * `dim`               - Axis used to partition primitives.
-    /// * `centroid_bounds`   - Bounding box of primtive centroids in primtive_info
-    ///                         from start to end.
-    /// * `bounds`            - Bound box of all primitives in BVH node.
-    /// * `max_prims_in_node` - Maximum primitives allowed in node.
+    /// * `primitive_info`  - Information for primitives in the node.
+    /// * `dim`             - Axis used to partition primitives.
+    /// * `centroid_bounds` - Bounding box of primtive centroids in the node.
+    /// * `bounds`          - Bound box of all primitives in BVH node.
+    /// * `budget`          - Number of primitive references spatial splits
+    ///                       may duplicate in the subtree.
     fn split_sah(
-        primitive_info: &mut Vec<BVHPrimitiveInfo>,
-        start: usize,
-        end: usize,
-        n_primitives: usize,
+        &self,
+        primitive_info: &mut [BVHPrimitiveInfo],
         dim: Axis,
         centroid_bounds: &Bounds3f,
         bounds: &Bounds3f,
-        max_prims_in_node: u8,
-    ) -> Option<usize> {
+        budget: usize,
+    ) -> Option<Split> {
+        let n_primitives = primitive_info.len();
+
         // Partition primitives using approximate SAH.
         if n_primitives <= 2 {
             // Partition primitives into equally-sized subsets.
-            Some(Self::split_equal_counts(primitive_info, start, end, dim))
-        } else {
-            // Allocate BucketInfo for SAH partition buckets.
-            let mut buckets = [BucketInfo::default(); N_BUCKETS];
-
-            // Initialize BucketInfo for SAH partition buckets.
-            for i in start..end {
-                let mut b = (N_BUCKETS as Float
-                    * centroid_bounds.offset(&primitive_info[i].centroid)[dim])
-                    as usize;
-                if b == N_BUCKETS {
-                    b = N_BUCKETS - 1;
-                }
-                debug_assert!(b > 0);
-                debug_assert!(b < N_BUCKETS);
-                buckets[b].count += 1;
-                buckets[b].bounds = buckets[b].bounds.union(&primitive_info[i].bounds);
+            return Some(Split::Object(Self::split_equal_counts(primitive_info, dim)));
+        }
+
+        let buckets = Self::compute_buckets(primitive_info, dim, centroid_bounds);
+
+        // Compute costs for splitting after each bucket from prefix and
+        // suffix sums of the buckets.
+        let mut below = [BucketInfo::default(); N_BUCKETS];
+        let mut above = [BucketInfo::default(); N_BUCKETS];
+        below[0] = buckets[0];
+        above[N_BUCKETS - 1] = buckets[N_BUCKETS - 1];
+        for i in 1..N_BUCKETS {
+            below[i].count = below[i - 1].count + buckets[i].count;
+            below[i].bounds = below[i - 1].bounds.union(&buckets[i].bounds);
+
+            let j = N_BUCKETS - 1 - i;
+            above[j].count = above[j + 1].count + buckets[j].count;
+            above[j].bounds = above[j + 1].bounds.union(&buckets[j].bounds);
+        }
+
+        // Find bucket to split at that minimizes SAH metric.
+        let surface_area = bounds.surface_area();
+        let mut min_cost = INFINITY;
+        let mut min_cost_split_bucket = 0;
+        for i in 0..N_BUCKETS - 1 {
+            let cost = 1.0
+                + (below[i].count as Float * below[i].bounds.surface_area()
+                    + above[i + 1].count as Float * above[i + 1].bounds.surface_area())
+                    / surface_area;
+            if cost < min_cost {
+                min_cost = cost;
+                min_cost_split_bucket = i;
             }
+        }
 
-            // Compute costs for splitting after each bucket
-            let mut cost = [0.0 as Float; N_BUCKETS - 1];
-            for i in 0..N_BUCKETS - 1 {
-                let (mut b0, mut b1) = (Bounds3f::default(), Bounds3f::default());
-                let (mut count0, mut count1) = (0, 0);
-                for j in 0..i + 1 {
-                    b0 = b0.union(&buckets[j].bounds);
-                    count0 += buckets[j].count;
-                }
-                for j in i + 1..N_BUCKETS {
-                    b1 = b1.union(&buckets[j].bounds);
-                    count1 += buckets[j].count;
-                }
-                cost[i] = 1.0
-                    + (count0 as Float * b0.surface_area() + count1 as Float * b1.surface_area())
-                        / bounds.surface_area();
+        // Consider splitting primitives along a plane if the children of the
+        // object split overlap.
+        let mut spatial_split: Option<SpatialSplit> = None;
+        if self.split_method == SplitMethod::SBVH && budget > 0 {
+            let overlap = below[min_cost_split_bucket]
+                .bounds
+                .intersect(&above[min_cost_split_bucket + 1].bounds);
+            if !overlap.is_empty() && overlap.surface_area() > self.min_overlap_area {
+                spatial_split = find_spatial_split(self.primitives, primitive_info, bounds)
+                    .filter(|split| split.cost < min_cost);
             }
+        }
+        if let Some(split) = spatial_split.as_ref() {
+            min_cost = split.cost;
+        }
+
+        let leaf_cost = n_primitives as Float;
+        if n_primitives <= self.max_prims_in_node && min_cost >= leaf_cost {
+            // No split occurred. Indicate creation of leaf node.
+            return None;
+        }
 
-            // Find bucket to split at that minimizes SAH metric.
-            let mut min_cost = cost[0];
-            let mut min_cost_split_bucket = 0;
-            for i in 1..N_BUCKETS - 1 {
-                if cost[i] < min_cost {
-                    min_cost = cost[i];
-                    min_cost_split_bucket = i;
-                }
+        // Split primitives along the plane if the budget for duplicated
+        // primitive references allows it.
+        if let Some(split) = spatial_split {
+            let (left, right) = split.perform(self.primitives, primitive_info);
+            if !left.is_empty()
+                && !right.is_empty()
+                && left.len() + right.len() - n_primitives <= budget
+            {
+                return Some(Split::Spatial(split.axis, left, right));
             }
+        }
 
-            let leaf_cost = n_primitives as Float;
-            if n_primitives > max_prims_in_node as usize || min_cost < leaf_cost {
-                // Partition primitives at selected SAH bucket and return the
-                // pivot point as mid.
-                let split = primitive_info[start..end + 1]
-                    .iter_mut()
-                    .partition_in_place(|pi| {
-                        let mut b = (N_BUCKETS as Float * centroid_bounds.offset(&pi.centroid)[dim])
-                            as usize;
-                        if b == N_BUCKETS {
-                            b = N_BUCKETS - 1;
-                        }
-                        debug_assert!(b > 0);
-                        debug_assert!(b < N_BUCKETS);
-                        b <= min_cost_split_bucket
-                    });
-                let pmid = start + split;
-                Some(pmid)
-            } else {
-                // No split occurred. Indicate creation of leaf node.
-                None
+        // Partition primitives at selected SAH bucket and return the pivot
+        // point as mid.
+        let mid = primitive_info.iter_mut().partition_in_place(|pi| {
+            Self::bucket(&pi.centroid, dim, centroid_bounds) <= min_cost_split_bucket
+        });
+        if mid != 0 && mid != n_primitives {
+            Some(Split::Object(mid))
+        } else {
+            // All centroids fell on one side of the split; fall back
+            // to equal counts so the recursion makes progress.
+            Some(Split::Object(Self::split_equal_counts(primitive_info, dim)))
+        }
+    }
+}
+
+// ----------------------------------------------------------------------------
+// Tests
+// ----------------------------------------------------------------------------
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::accelerators::testing::*;
+    use crate::accelerators::BVHAccel;
+
+    #[test]
+    fn leaves_are_ordered_depth_first() {
+        let prims = random_boxes(5000, 1);
+        for &split_method in &[SplitMethod::SAH, SplitMethod::SBVH] {
+            let bvh = BVHAccel::new(&prims, 4, split_method, 2);
+            let mut offset = 0;
+            for node in bvh.nodes.iter().filter(|node| node.n_primitives > 0) {
+                assert_eq!(node.offset as usize, offset);
+                offset += node.n_primitives as usize;
             }
+            assert_eq!(offset, bvh.primitives.len());
         }
     }
+
+    #[test]
+    fn builds_are_deterministic() {
+        // Large enough for subtrees to be built in parallel, with enough
+        // threads for them to be scheduled differently on every build.
+        let prims = random_boxes(20000, 2);
+        let pool = rayon::ThreadPoolBuilder::new()
+            .num_threads(8)
+            .build()
+            .unwrap();
+        for &split_method in &[SplitMethod::SAH, SplitMethod::SBVH] {
+            let a = BVHAccel::new(&prims, 4, split_method, 2);
+            for _ in 0..3 {
+                let b = pool.install(|| BVHAccel::new(&prims, 4, split_method, 2));
+                assert_eq!(a.primitives.len(), b.primitives.len());
+                assert!(a
+                    .primitives
+                    .iter()
+                    .zip(b.primitives.iter())
+                    .all(|(p, q)| Arc::ptr_eq(p, q)));
+                assert_eq!(a.nodes.len(), b.nodes.len());
+                assert!(a
+                    .nodes
+                    .iter()
+                    .zip(b.nodes.iter())
+                    .all(|(m, n)| m.offset == n.offset && m.n_primitives == n.n_primitives));
+            }
+        }
+    }
+
+    #[test]
+    fn sbvh_matches_brute_force() {
+        let prims = random_boxes(3000, 3);
+        let rays = random_rays(2000, 4);
+        let bvh = BVHAccel::new(&prims, 4, SplitMethod::SBVH, 2);
+
+        // The elongated boxes are split, but no more than the budget allows.
+        assert!(bvh.primitives.len() > prims.len());
+        assert!(bvh.primitives.len() <= 2 * prims.len());
+        assert!(assert_matches_brute_force(&bvh, &prims, &rays) > 0);
+    }
 }
@@ -0,0 +1,328 @@
+//! Spatial Splits
+
+#![allow(dead_code)]
+use super::common::*;
+use crate::core::geometry::*;
+use crate::core::pbrt::*;
+use crate::core::primitive::*;
+use rayon::prelude::*;
+
+/// Number of bins along each axis used to find spatial splits.
+const N_SPATIAL_BINS: usize = 16;
+
+/// Minimum number of primitives in a node to fill spatial bins in parallel.
+const PARALLEL_BIN_THRESHOLD: usize = 4096;
+
+/// Number of primitives processed by a task when filling spatial bins in
+/// parallel.
+const PARALLEL_CHUNK_SIZE: usize = 1024;
+
+/// Spatial split bin information.
+#[derive(Copy, Clone, Debug)]
+struct SpatialBin {
+    /// Bounding box of the parts of primitives inside the bin.
+    bounds: Bounds3f,
+
+    /// Number of primitives that start in the bin.
+    entries: usize,
+
+    /// Number of primitives that end in the bin.
+    exits: usize,
+}
+
+impl Default for SpatialBin {
+    /// Returns an empty bin.
+    fn default() -> Self {
+        Self {
+            bounds: Bounds3f::empty(),
+            entries: 0,
+            exits: 0,
+        }
+    }
+}
+
+/// A plane splitting primitives between two child nodes. Primitives that
+/// straddle the plane are clipped and referenced by both children.
+#[derive(Copy, Clone, Debug)]
+pub struct SpatialSplit {
+    /// The axis perpendicular to the plane.
+    pub axis: Axis,
+
+    /// Position of the plane along the axis.
+    pub position: Float,
+
+    /// SAH cost of the split.
+    pub cost: Float,
+}
+
+impl SpatialSplit {
+    /// Split primitives between both sides of the plane.
+    ///
+    /// Returns the information for primitives in the first and second child.
+    ///
+    /// * `primitives`     - The primitives.
+    /// * `primitive_info` - Information for primitives in the node.
+    pub fn perform(
+        &self,
+        primitives: &[ArcPrimitive],
+        primitive_info: &[BVHPrimitiveInfo],
+    ) -> (Vec<BVHPrimitiveInfo>, Vec<BVHPrimitiveInfo>) {
+        let axis = self.axis;
+        let mut left = Vec::with_capacity(primitive_info.len());
+        let mut right = Vec::with_capacity(primitive_info.len());
+
+        for pi in primitive_info.iter() {
+            if pi.bounds.p_max[axis] <= self.position {
+                left.push(*pi);
+            } else if pi.bounds.p_min[axis] >= self.position {
+                right.push(*pi);
+            } else {
+                // Clip primitive to both sides of the plane.
+                let mut left_bounds = pi.bounds;
+                left_bounds.p_max[axis] = self.position;
+                let mut right_bounds = pi.bounds;
+                right_bounds.p_min[axis] = self.position;
+
+                let primitive = &primitives[pi.primitive_number];
+                let (n_left, n_right) = (left.len(), right.len());
+                for (bounds, side) in [(left_bounds, &mut left), (right_bounds, &mut right)] {
+                    let clipped = primitive.clipped_world_bound(&bounds);
+                    if !clipped.is_empty() {
+                        side.push(BVHPrimitiveInfo::new(pi.primitive_number, clipped));
+                    }
+                }
+
+                // Keep the primitive if clipping lost it due to precision.
+                if left.len() == n_left && right.len() == n_right {
+                    left.push(*pi);
+                }
+            }
+        }
+
+        (left, right)
+    }
+}
+
+/// Returns the spatial split with the lowest SAH cost for a node or `None` if
+/// the node can't be split.
+///
+/// * `primitives`     - The primitives.
+/// * `primitive_info` - Information for primitives in the node.
+/// * `bounds`         - Bound box of all primitives in the node.
+pub fn find_spatial_split(
+    primitives: &[ArcPrimitive],
+    primitive_info: &[BVHPrimitiveInfo],
+    bounds: &Bounds3f,
+) -> Option<SpatialSplit> {
+    let surface_area = bounds.surface_area();
+    let mut best: Option<SpatialSplit> = None;
+
+    for axis in [Axis::X, Axis::Y, Axis::Z] {
+        let extent = bounds.p_max[axis] - bounds.p_min[axis];
+        if extent <= 0.0 {
+            continue;
+        }
+
+        let bins = compute_bins(primitives, primitive_info, bounds, axis);
+
+        // Compute costs for splitting after each bin from prefix and suffix
+        // sums of the bins.
+        let mut above = [SpatialBin::default(); N_SPATIAL_BINS];
+        above[N_SPATIAL_BINS - 1] = bins[N_SPATIAL_BINS - 1];
+        for i in (0..N_SPATIAL_BINS - 1).rev() {
+            above[i].exits = above[i + 1].exits + bins[i].exits;
+            above[i].bounds = above[i + 1].bounds.union(&bins[i].bounds);
+        }
+
+        let mut below = SpatialBin::default();
+        for i in 0..N_SPATIAL_BINS - 1 {
+            below.entries += bins[i].entries;
+            below.bounds = below.bounds.union(&bins[i].bounds);
+
+            let (n_left, n_right) = (below.entries, above[i + 1].exits);
+            if n_left == 0 || n_right == 0 {
+                continue;
+            }
+
+            let cost = 1.0
+                + (n_left as Float * below.bounds.surface_area()
+                    + n_right as Float * above[i + 1].bounds.surface_area())
+                    / surface_area;
+            if best.map_or(true, |b| cost < b.cost) {
+                best = Some(SpatialSplit {
+                    axis,
+                    position: bin_position(bounds, axis, i + 1),
+                    cost,
+                });
+            }
+        }
+    }
+
+    best
+}
+
+/// Returns the position of the boundary before a bin.
+///
+/// * `bounds` - Bounding box of the node.
+/// * `axis`   - The axis.
+/// * `bin`    - The bin.
+fn bin_position(bounds: &Bounds3f, axis: Axis, bin: usize) -> Float {
+    lerp(
+        bin as Float / N_SPATIAL_BINS as Float,
+        bounds.p_min[axis],
+        bounds.p_max[axis],
+    )
+}
+
+/// Returns the spatial bin of a position along an axis.
+///
+/// * `bounds`   - Bounding box of the node.
+/// * `axis`     - The axis.
+/// * `position` - The position.
+fn bin_index(bounds: &Bounds3f, axis: Axis, position: Float) -> usize {
+    let extent = bounds.p_max[axis] - bounds.p_min[axis];
+    let b = (N_SPATIAL_BINS as Float * (position - bounds.p_min[axis]) / extent) as usize;
+    min(b, N_SPATIAL_BINS - 1)
+}
+
+/// Fill the spatial bins along an axis with the parts of primitives inside
+/// each bin. Large nodes are processed in parallel.
+///
+/// * `primitives`     - The primitives.
+/// * `primitive_info` - Information for primitives in the node.
+/// * `bounds`         - Bound box of all primitives in the node.
+/// * `axis`           - The axis.
+fn compute_bins(
+    primitives: &[ArcPrimitive],
+    primitive_info: &[BVHPrimitiveInfo],
+    bounds: &Bounds3f,
+    axis: Axis,
+) -> [SpatialBin; N_SPATIAL_BINS] {
+    let fold = |mut bins: [SpatialBin; N_SPATIAL_BINS], pi: &BVHPrimitiveInfo| {
+        let first = bin_index(bounds, axis, pi.bounds.p_min[axis]);
+        let last = bin_index(bounds, axis, pi.bounds.p_max[axis]);
+        bins[first].entries += 1;
+        bins[last].exits += 1;
+
+        if first == last {
+            bins[first].bounds = bins[first].bounds.union(&pi.bounds);
+        } else {
+            let primitive = &primitives[pi.primitive_number];
+            for b in first..=last {
+                let mut bin_bounds = pi.bounds;
+                bin_bounds.p_min[axis] = max(bin_bounds.p_min[axis], bin_position(bounds, axis, b));
+                bin_bounds.p_max[axis] =
+                    min(bin_bounds.p_max[axis], bin_position(bounds, axis, b + 1));
+                let clipped = primitive.clipped_world_bound(&bin_bounds);
+                if !clipped.is_empty() {
+                    bins[b].bounds = bins[b].bounds.union(&clipped);
+                }
+            }
+        }
+        bins
+    };
+    let empty = [SpatialBin::default(); N_SPATIAL_BINS];
+
+    if primitive_info.len() >= PARALLEL_BIN_THRESHOLD {
+        primitive_info
+            .par_chunks(PARALLEL_CHUNK_SIZE)
+            .map(|chunk| chunk.iter().fold(empty, fold))
+            .reduce(
+                || empty,
+                |mut b0, b1| {
+                    for (a, b) in b0.iter_mut().zip(b1.iter()) {
+                        a.bounds = a.bounds.union(&b.bounds);
+                        a.entries += b.entries;
+                        a.exits += b.exits;
+                    }
+                    b0
+                },
+            )
+    } else {
+        primitive_info.iter().fold(empty, fold)
+    }
+}
+
+// ----------------------------------------------------------------------------
+// Tests
+// ----------------------------------------------------------------------------
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::accelerators::testing::*;
+    use std::sync::Arc;
+
+    fn boxes(bounds: &[Bounds3f]) -> (Vec<ArcPrimitive>, Vec<BVHPrimitiveInfo>) {
+        let prims: Vec<ArcPrimitive> = bounds
+            .iter()
+            .enumerate()
+            .map(|(index, &bounds)| Arc::new(BoxPrimitive { bounds, index }) as ArcPrimitive)
+            .collect();
+        let info = bounds
+            .iter()
+            .enumerate()
+            .map(|(i, &b)| BVHPrimitiveInfo::new(i, b))
+            .collect();
+        (prims, info)
+    }
+
+    fn bounds(p_min: (Float, Float, Float), p_max: (Float, Float, Float)) -> Bounds3f {
+        Bounds3f::new(
+            Point3f::new(p_min.0, p_min.1, p_min.2),
+            Point3f::new(p_max.0, p_max.1, p_max.2),
+        )
+    }
+
+    #[test]
+    fn perform_clips_straddling_primitives() {
+        let (prims, info) = boxes(&[
+            bounds((0.0, 0.0, 0.0), (1.0, 1.0, 1.0)),
+            bounds((3.0, 0.0, 0.0), (4.0, 1.0, 1.0)),
+            bounds((0.0, 2.0, 0.0), (4.0, 3.0, 1.0)),
+        ]);
+        let split = SpatialSplit {
+            axis: Axis::X,
+            position: 2.0,
+            cost: 0.0,
+        };
+        let (left, right) = split.perform(&prims, &info);
+
+        let numbers =
+            |v: &[BVHPrimitiveInfo]| v.iter().map(|pi| pi.primitive_number).collect::<Vec<_>>();
+        assert_eq!(numbers(&left), vec![0, 2]);
+        assert_eq!(numbers(&right), vec![1, 2]);
+        assert!(left[1].bounds == bounds((0.0, 2.0, 0.0), (2.0, 3.0, 1.0)));
+        assert!(right[1].bounds == bounds((2.0, 2.0, 0.0), (4.0, 3.0, 1.0)));
+    }
+
+    #[test]
+    fn spatial_split_separates_overlapping_primitives() {
+        // Two long boxes crossing each other; no object split avoids the
+        // overlap but a plane through the crossing does.
+        let (prims, info) = boxes(&[
+            bounds((0.0, 0.0, 0.0), (16.0, 1.0, 1.0)),
+            bounds((0.0, 0.0, 0.0), (1.0, 16.0, 1.0)),
+        ]);
+        let node_bounds = bounds((0.0, 0.0, 0.0), (16.0, 16.0, 1.0));
+
+        let split = find_spatial_split(&prims, &info, &node_bounds).unwrap();
+        assert!(split.axis == Axis::X || split.axis == Axis::Y);
+        assert!(split.position > 0.0 && split.position < 16.0);
+
+        let (left, right) = split.perform(&prims, &info);
+        assert!(!left.is_empty() && !right.is_empty());
+        for pi in left.iter() {
+            assert!(pi.bounds.p_max[split.axis] <= split.position);
+        }
+        for pi in right.iter() {
+            assert!(pi.bounds.p_min[split.axis] >= split.position);
+        }
+    }
+
+    #[test]
+    fn flat_nodes_have_no_spatial_split() {
+        let (prims, info) = boxes(&[bounds((0.0, 0.0, 0.0), (0.0, 0.0, 0.0)); 2]);
+        assert!(find_spatial_split(&prims, &info, &info[0].bounds).is_none());
+    }
+}
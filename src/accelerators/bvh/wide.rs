@@ -0,0 +1,783 @@
+//! Wide BVH
+
+#![allow(dead_code)]
+use super::common::*;
+use crate::core::geometry::*;
+use crate::core::pbrt::*;
+use crate::core::primitive::*;
+use std::cmp::Ordering;
+use std::mem::size_of;
+use std::simd::prelude::*;
+use std::sync::Arc;
+
+/// Size of the fixed part of the traversal stack. A node pushes at most
+/// `N - 1` children so this covers 64 levels of an 8-wide BVH. Deeper
+/// trees spill onto the heap.
+const STACK_SIZE: usize = 512;
+
+/// Number of children tested at once by the slab test. Node widths must be
+/// a multiple of this.
+const LANES: usize = 4;
+
+/// Vector of `LANES` floats.
+type FloatV = Simd<Float, LANES>;
+
+/// Stores information needed to traverse an `N`-wide BVH. The child bounds
+/// are stored as a structure of arrays so all children can be tested
+/// against a ray at once.
+#[derive(Copy, Clone, Debug)]
+pub struct WideBVHNode<const N: usize> {
+    /// Minimum x-coordinates of child bounding boxes.
+    pub min_x: [Float; N],
+
+    /// Maximum x-coordinates of child bounding boxes.
+    pub max_x: [Float; N],
+
+    /// Minimum y-coordinates of child bounding boxes.
+    pub min_y: [Float; N],
+
+    /// Maximum y-coordinates of child bounding boxes.
+    pub max_y: [Float; N],
+
+    /// Minimum z-coordinates of child bounding boxes.
+    pub min_z: [Float; N],
+
+    /// Maximum z-coordinates of child bounding boxes.
+    pub max_z: [Float; N],
+
+    /// For leaf children, offset for the primitives in the child.
+    /// For interior children, index of the child node.
+    pub offsets: [u32; N],
+
+    /// For leaf children, the number of primitives in the child.
+    /// For interior children, 0.
+    pub n_primitives: [u16; N],
+
+    /// Number of children used.
+    pub n_children: u8,
+}
+
+impl<const N: usize> WideBVHNode<N> {
+    /// Creates a node without children. Unused child slots have inverted
+    /// bounds that no ray intersects.
+    pub fn new() -> Self {
+        Self {
+            min_x: [INFINITY; N],
+            max_x: [-INFINITY; N],
+            min_y: [INFINITY; N],
+            max_y: [-INFINITY; N],
+            min_z: [INFINITY; N],
+            max_z: [-INFINITY; N],
+            offsets: [0; N],
+            n_primitives: [0; N],
+            n_children: 0,
+        }
+    }
+
+    /// Adds a child to the node.
+    ///
+    /// * `bounds`       - Bounding box of the child.
+    /// * `offset`       - Offset for primitives of a leaf child or index of
+    ///                    an interior child.
+    /// * `n_primitives` - Number of primitives of a leaf child or 0.
+    pub fn add_child(&mut self, bounds: &Bounds3f, offset: u32, n_primitives: u16) {
+        let i = self.n_children as usize;
+        debug_assert!(i < N);
+
+        self.set_child_bounds(i, bounds);
+        self.offsets[i] = offset;
+        self.n_primitives[i] = n_primitives;
+        self.n_children += 1;
+    }
+
+    /// Sets the bounding box of a child.
+    ///
+    /// * `i`      - The child.
+    /// * `bounds` - Bounding box of the child.
+    pub fn set_child_bounds(&mut self, i: usize, bounds: &Bounds3f) {
+        self.min_x[i] = bounds.p_min.x;
+        self.max_x[i] = bounds.p_max.x;
+        self.min_y[i] = bounds.p_min.y;
+        self.max_y[i] = bounds.p_max.y;
+        self.min_z[i] = bounds.p_min.z;
+        self.max_z[i] = bounds.p_max.z;
+    }
+
+    /// Returns the bounding box of a child.
+    ///
+    /// * `i` - The child.
+    pub fn child_bounds(&self, i: usize) -> Bounds3f {
+        Bounds3f::new(
+            Point3f::new(self.min_x[i], self.min_y[i], self.min_z[i]),
+            Point3f::new(self.max_x[i], self.max_y[i], self.max_z[i]),
+        )
+    }
+
+    /// Returns the bounding box of all children.
+    pub fn bounds(&self) -> Bounds3f {
+        (0..self.n_children as usize).fold(Bounds3f::empty(), |b, i| b.union(&self.child_bounds(i)))
+    }
+
+    /// Returns the parametric distances at which a ray enters each child's
+    /// bounding box. Children that are missed have a distance of infinity.
+    /// `N` must be a multiple of `LANES`.
+    ///
+    /// * `r`          - The ray.
+    /// * `inv_dir`    - Reciprocal of the ray direction.
+    /// * `dir_is_neg` - Indicates negative direction components.
+    pub fn intersect_children(
+        &self,
+        r: &Ray,
+        inv_dir: &Vector3f,
+        dir_is_neg: [u8; 3],
+    ) -> [Float; N] {
+        let (near_x, far_x) = if dir_is_neg[0] == 1 {
+            (&self.max_x, &self.min_x)
+        } else {
+            (&self.min_x, &self.max_x)
+        };
+        let (near_y, far_y) = if dir_is_neg[1] == 1 {
+            (&self.max_y, &self.min_y)
+        } else {
+            (&self.min_y, &self.max_y)
+        };
+        let (near_z, far_z) = if dir_is_neg[2] == 1 {
+            (&self.max_z, &self.min_z)
+        } else {
+            (&self.min_z, &self.max_z)
+        };
+
+        // Slab test for `LANES` children at a time. Far distances are scaled
+        // to ensure robust bounds intersection.
+        let scale = FloatV::splat(1.0 + 2.0 * gamma(3));
+        let (o_x, o_y, o_z) = (
+            FloatV::splat(r.o.x),
+            FloatV::splat(r.o.y),
+            FloatV::splat(r.o.z),
+        );
+        let (inv_x, inv_y, inv_z) = (
+            FloatV::splat(inv_dir.x),
+            FloatV::splat(inv_dir.y),
+            FloatV::splat(inv_dir.z),
+        );
+        let ray_t_max = FloatV::splat(r.t_max);
+
+        let mut t_hit = [INFINITY; N];
+        for c in (0..N).step_by(LANES) {
+            let lanes = c..c + LANES;
+            let t_min_x = (FloatV::from_slice(&near_x[lanes.clone()]) - o_x) * inv_x;
+            let t_min_y = (FloatV::from_slice(&near_y[lanes.clone()]) - o_y) * inv_y;
+            let t_min_z = (FloatV::from_slice(&near_z[lanes.clone()]) - o_z) * inv_z;
+            let t_max_x = (FloatV::from_slice(&far_x[lanes.clone()]) - o_x) * inv_x * scale;
+            let t_max_y = (FloatV::from_slice(&far_y[lanes.clone()]) - o_y) * inv_y * scale;
+            let t_max_z = (FloatV::from_slice(&far_z[lanes.clone()]) - o_z) * inv_z * scale;
+
+            let t_min = t_min_x
+                .simd_max(t_min_y)
+                .simd_max(t_min_z)
+                .simd_max(FloatV::splat(0.0));
+            let t_max = t_max_x
+                .simd_min(t_max_y)
+                .simd_min(t_max_z)
+                .simd_min(ray_t_max);
+            let hit = t_min.simd_le(t_max) & t_min.simd_lt(ray_t_max);
+            hit.select(t_min, FloatV::splat(INFINITY))
+                .copy_to_slice(&mut t_hit[lanes]);
+        }
+        t_hit
+    }
+
+    /// Collapses a binary BVH into `N`-wide nodes in depth-first order. The
+    /// largest interior children of a node are replaced with their own
+    /// children until the node is full.
+    ///
+    /// * `root` - Root of the binary BVH.
+    pub fn collapse(root: &Arc<BVHBuildNode>) -> Vec<Self> {
+        let mut nodes = vec![];
+        Self::collapse_node(root, &mut nodes);
+        nodes
+    }
+
+    /// Collapses a binary BVH node and returns its index.
+    ///
+    /// * `node`  - The binary BVH node.
+    /// * `nodes` - The wide nodes.
+    fn collapse_node(node: &Arc<BVHBuildNode>, nodes: &mut Vec<Self>) -> u32 {
+        let mut children: Vec<Arc<BVHBuildNode>> = if node.n_primitives > 0 {
+            vec![node.clone()]
+        } else {
+            node.children.iter().flatten().cloned().collect()
+        };
+
+        while children.len() < N {
+            // Open the interior child with the largest surface area.
+            let largest = children
+                .iter()
+                .enumerate()
+                .filter(|(_, c)| c.n_primitives == 0)
+                .max_by(|(_, a), (_, b)| {
+                    a.bounds
+                        .surface_area()
+                        .partial_cmp(&b.bounds.surface_area())
+                        .unwrap_or(Ordering::Equal)
+                })
+                .map(|(i, _)| i);
+
+            match largest {
+                Some(i) => {
+                    let child = children.remove(i);
+                    children.extend(child.children.iter().flatten().cloned());
+                }
+                None => break,
+            }
+        }
+
+        let my_offset = nodes.len();
+        nodes.push(Self::new());
+
+        let mut wide_node = Self::new();
+        for child in children.iter() {
+            if child.n_primitives > 0 {
+                debug_assert!(child.n_primitives < 65536);
+                wide_node.add_child(
+                    &child.bounds,
+                    child.first_prim_offset as u32,
+                    child.n_primitives as u16,
+                );
+            } else {
+                let offset = Self::collapse_node(child, nodes);
+                wide_node.add_child(&child.bounds, offset, 0);
+            }
+        }
+        nodes[my_offset] = wide_node;
+
+        my_offset as u32
+    }
+}
+
+/// Wide BVH nodes for the supported widths.
+#[derive(Clone)]
+pub enum WideBVHNodes {
+    /// 4-wide nodes.
+    Four(Vec<WideBVHNode<4>>),
+
+    /// 8-wide nodes.
+    Eight(Vec<WideBVHNode<8>>),
+}
+
+impl WideBVHNodes {
+    /// Collapses a binary BVH into wide nodes. Returns `None` for widths
+    /// other than 4 or 8.
+    ///
+    /// * `root`  - Root of the binary BVH.
+    /// * `width` - Number of children per node.
+    pub fn new(root: &Arc<BVHBuildNode>, width: u8) -> Option<Self> {
+        match width {
+            4 => Some(WideBVHNodes::Four(WideBVHNode::collapse(root))),
+            8 => Some(WideBVHNodes::Eight(WideBVHNode::collapse(root))),
+            _ => None,
+        }
+    }
+
+    /// Returns the number of nodes.
+    pub fn len(&self) -> usize {
+        match self {
+            WideBVHNodes::Four(nodes) => nodes.len(),
+            WideBVHNodes::Eight(nodes) => nodes.len(),
+        }
+    }
+
+    /// Returns the bounding box of all nodes.
+    pub fn bounds(&self) -> Bounds3f {
+        match self {
+            WideBVHNodes::Four(nodes) => nodes.first().map_or(Bounds3f::empty(), |n| n.bounds()),
+            WideBVHNodes::Eight(nodes) => nodes.first().map_or(Bounds3f::empty(), |n| n.bounds()),
+        }
+    }
+
+    /// Recompute the bounds of all children from the bounds of primitives.
+    ///
+    /// * `prim_bounds` - Bounding boxes of the primitives referenced by leaf
+    ///                   children.
+    pub fn refit(&mut self, prim_bounds: &[Bounds3f]) {
+        match self {
+            WideBVHNodes::Four(nodes) => refit(nodes, prim_bounds),
+            WideBVHNodes::Eight(nodes) => refit(nodes, prim_bounds),
+        }
+    }
+
+    /// Returns geometric details if a ray intersects a primitive and updates
+    /// the t_max parameter of the ray.
+    ///
+    /// * `primitives` - The primitives referenced by leaf children.
+    /// * `r`          - The ray.
+    pub fn intersect<'a>(
+        &self,
+        primitives: &'a [ArcPrimitive],
+        r: &mut Ray,
+    ) -> Option<SurfaceInteraction<'a>> {
+        match self {
+            WideBVHNodes::Four(nodes) => intersect(nodes, primitives, r),
+            WideBVHNodes::Eight(nodes) => intersect(nodes, primitives, r),
+        }
+    }
+
+    /// Returns `true` if a ray intersects a primitive.
+    ///
+    /// * `primitives` - The primitives referenced by leaf children.
+    /// * `r`          - The ray.
+    pub fn intersect_p(&self, primitives: &[ArcPrimitive], r: &Ray) -> bool {
+        match self {
+            WideBVHNodes::Four(nodes) => intersect_p(nodes, primitives, r),
+            WideBVHNodes::Eight(nodes) => intersect_p(nodes, primitives, r),
+        }
+    }
+
+    /// Returns the number of bytes used by the nodes.
+    pub fn memory_size(&self) -> usize {
+        match self {
+            WideBVHNodes::Four(nodes) => nodes.len() * size_of::<WideBVHNode<4>>(),
+            WideBVHNodes::Eight(nodes) => nodes.len() * size_of::<WideBVHNode<8>>(),
+        }
+    }
+
+    /// Calls a function with the depth and number of primitives of each
+    /// leaf child. Children of the root have a depth of 1.
+    ///
+    /// * `f` - The function.
+    pub fn for_each_leaf<F: FnMut(usize, usize)>(&self, f: F) {
+        match self {
+            WideBVHNodes::Four(nodes) => for_each_leaf(nodes, f),
+            WideBVHNodes::Eight(nodes) => for_each_leaf(nodes, f),
+        }
+    }
+}
+
+/// Calls a function with the depth and number of primitives of each leaf
+/// child of an `N`-wide BVH.
+///
+/// * `nodes` - The wide nodes.
+/// * `f`     - The function.
+fn for_each_leaf<F: FnMut(usize, usize), const N: usize>(nodes: &[WideBVHNode<N>], mut f: F) {
+    if nodes.is_empty() {
+        return;
+    }
+
+    let mut to_visit = vec![(0_usize, 0_usize)];
+    while let Some((i, depth)) = to_visit.pop() {
+        let node = &nodes[i];
+        for c in 0..node.n_children as usize {
+            match node.n_primitives[c] as usize {
+                0 => to_visit.push((node.offsets[c] as usize, depth + 1)),
+                n => f(depth + 1, n),
+            }
+        }
+    }
+}
+
+/// Recompute the bounds of all children of an `N`-wide BVH bottom-up.
+///
+/// * `nodes`       - The wide nodes.
+/// * `prim_bounds` - Bounding boxes of the primitives referenced by leaf
+///                   children.
+fn refit<const N: usize>(nodes: &mut [WideBVHNode<N>], prim_bounds: &[Bounds3f]) {
+    // Interior children are always stored after their parent.
+    for i in (0..nodes.len()).rev() {
+        for c in 0..nodes[i].n_children as usize {
+            let offset = nodes[i].offsets[c] as usize;
+            let bounds = match nodes[i].n_primitives[c] as usize {
+                0 => nodes[offset].bounds(),
+                n => prim_bounds[offset..offset + n]
+                    .iter()
+                    .fold(Bounds3f::empty(), |b, pb| b.union(pb)),
+            };
+            nodes[i].set_child_bounds(c, &bounds);
+        }
+    }
+}
+
+/// Traversal stack. Entries are kept in a fixed array and only spill onto
+/// the heap when the array is full.
+pub(super) struct NodeStack<T: Copy + Default> {
+    /// The fixed part of the stack.
+    entries: [T; STACK_SIZE],
+
+    /// Number of entries used in `entries`.
+    len: usize,
+
+    /// Entries pushed after `entries` is full.
+    overflow: Vec<T>,
+}
+
+impl<T: Copy + Default> NodeStack<T> {
+    /// Creates a stack with a single entry.
+    ///
+    /// * `entry` - The entry.
+    pub(super) fn new(entry: T) -> Self {
+        let mut entries = [T::default(); STACK_SIZE];
+        entries[0] = entry;
+        Self {
+            entries,
+            len: 1,
+            overflow: vec![],
+        }
+    }
+
+    /// Pushes an entry.
+    ///
+    /// * `entry` - The entry.
+    pub(super) fn push(&mut self, entry: T) {
+        if self.len < STACK_SIZE {
+            self.entries[self.len] = entry;
+            self.len += 1;
+        } else {
+            self.overflow.push(entry);
+        }
+    }
+
+    /// Removes the most recently pushed entry and returns it.
+    pub(super) fn pop(&mut self) -> Option<T> {
+        if let Some(entry) = self.overflow.pop() {
+            Some(entry)
+        } else if self.len > 0 {
+            self.len -= 1;
+            Some(self.entries[self.len])
+        } else {
+            None
+        }
+    }
+}
+
+/// Returns the reciprocal of the ray direction and the signs of its
+/// components.
+///
+/// * `r` - The ray.
+pub(crate) fn ray_inv_dir(r: &Ray) -> (Vector3f, [u8; 3]) {
+    let inv_dir = Vector3f::new(1.0 / r.d.x, 1.0 / r.d.y, 1.0 / r.d.z);
+    let dir_is_neg = [
+        if inv_dir.x < 0.0 { 1_u8 } else { 0_u8 },
+        if inv_dir.y < 0.0 { 1_u8 } else { 0_u8 },
+        if inv_dir.z < 0.0 { 1_u8 } else { 0_u8 },
+    ];
+    (inv_dir, dir_is_neg)
+}
+
+/// Returns the children of a node intersected by a ray sorted from near to
+/// far with their entry distances.
+///
+/// * `t_hit`      - Entry distances from `WideBVHNode::intersect_children()`.
+/// * `n_children` - Number of children used.
+pub(crate) fn sorted_hits<const N: usize>(
+    t_hit: &[Float; N],
+    n_children: usize,
+) -> ([usize; N], usize) {
+    let mut hits = [0_usize; N];
+    let mut n_hits = 0;
+    for i in 0..n_children {
+        if t_hit[i] < INFINITY {
+            // Insertion sort; nodes have at most 8 children.
+            let mut j = n_hits;
+            while j > 0 && t_hit[hits[j - 1]] > t_hit[i] {
+                hits[j] = hits[j - 1];
+                j -= 1;
+            }
+            hits[j] = i;
+            n_hits += 1;
+        }
+    }
+    (hits, n_hits)
+}
+
+/// Returns geometric details if a ray intersects a primitive in an `N`-wide
+/// BVH and updates the t_max parameter of the ray.
+///
+/// * `nodes`      - The wide nodes.
+/// * `primitives` - The primitives referenced by leaf children.
+/// * `r`          - The ray.
+fn intersect<'a, const N: usize>(
+    nodes: &[WideBVHNode<N>],
+    primitives: &'a [ArcPrimitive],
+    r: &mut Ray,
+) -> Option<SurfaceInteraction<'a>> {
+    let mut si: Option<SurfaceInteraction> = None;
+    if nodes.is_empty() {
+        return si;
+    }
+
+    let (inv_dir, dir_is_neg) = ray_inv_dir(r);
+
+    // Follow ray through BVH nodes to find primitive intersections.
+    let mut nodes_to_visit = NodeStack::new((0_u32, 0.0 as Float));
+    let mut n_visited = 0;
+
+    while let Some((node_index, t_enter)) = nodes_to_visit.pop() {
+        if t_enter >= r.t_max {
+            // A closer intersection was found after the node was pushed.
+            continue;
+        }
+
+        n_visited += 1;
+        let node = &nodes[node_index as usize];
+        let t_hit = node.intersect_children(r, &inv_dir, dir_is_neg);
+        let (hits, n_hits) = sorted_hits(&t_hit, node.n_children as usize);
+
+        // Intersect leaf children from near to far and collect interior
+        // children.
+        let mut interior = [0_usize; N];
+        let mut n_interior = 0;
+        for &c in hits[..n_hits].iter() {
+            if t_hit[c] >= r.t_max {
+                break;
+            }
+            if node.n_primitives[c] > 0 {
+                let offset = node.offsets[c] as usize;
+                for prim in primitives[offset..offset + node.n_primitives[c] as usize].iter() {
+                    if let Some(hit) = prim.intersect(r) {
+                        si = Some(hit);
+                    }
+                }
+            } else {
+                interior[n_interior] = c;
+                n_interior += 1;
+            }
+        }
+
+        // Push interior children far to near so the nearest is visited next.
+        for &c in interior[..n_interior].iter().rev() {
+            if t_hit[c] < r.t_max {
+                nodes_to_visit.push((node.offsets[c], t_hit[c]));
+            }
+        }
+    }
+
+    BVH_NODES_VISITED.add_fraction(n_visited, 1);
+    si
+}
+
+/// Returns `true` if a ray intersects a primitive in an `N`-wide BVH.
+///
+/// * `nodes`      - The wide nodes.
+/// * `primitives` - The primitives referenced by leaf children.
+/// * `r`          - The ray.
+fn intersect_p<const N: usize>(
+    nodes: &[WideBVHNode<N>],
+    primitives: &[ArcPrimitive],
+    r: &Ray,
+) -> bool {
+    if nodes.is_empty() {
+        return false;
+    }
+
+    let (inv_dir, dir_is_neg) = ray_inv_dir(r);
+
+    // Follow ray through BVH nodes to find primitive intersections. The
+    // order of traversal doesn't matter for shadow rays.
+    let mut nodes_to_visit = NodeStack::new(0_u32);
+    let mut n_visited = 0;
+
+    while let Some(node_index) = nodes_to_visit.pop() {
+        n_visited += 1;
+        let node = &nodes[node_index as usize];
+        let t_hit = node.intersect_children(r, &inv_dir, dir_is_neg);
+
+        for c in 0..node.n_children as usize {
+            if t_hit[c] == INFINITY {
+                continue;
+            }
+            if node.n_primitives[c] > 0 {
+                let offset = node.offsets[c] as usize;
+                for prim in primitives[offset..offset + node.n_primitives[c] as usize].iter() {
+                    if prim.intersect_p(r) {
+                        BVH_NODES_VISITED.add_fraction(n_visited, 1);
+                        return true;
+                    }
+                }
+            } else {
+                nodes_to_visit.push(node.offsets[c]);
+            }
+        }
+    }
+
+    BVH_NODES_VISITED.add_fraction(n_visited, 1);
+    false
+}
+
+// ----------------------------------------------------------------------------
+// Tests
+// ----------------------------------------------------------------------------
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::accelerators::testing::*;
+    use crate::accelerators::BVHAccel;
+    use rand::{Rng, SeedableRng};
+    use rand_pcg::Pcg32;
+
+    /// Returns the entry distances of the children of a node one child at a
+    /// time.
+    fn intersect_children_scalar<const N: usize>(
+        node: &WideBVHNode<N>,
+        r: &Ray,
+        inv_dir: &Vector3f,
+        dir_is_neg: [u8; 3],
+    ) -> [Float; N] {
+        let scale = 1.0 + 2.0 * gamma(3);
+        let mut t_hit = [INFINITY; N];
+        for i in 0..N {
+            // Read the arrays directly; `child_bounds()` would fix up the
+            // inverted bounds of unused slots.
+            let min = [node.min_x[i], node.min_y[i], node.min_z[i]];
+            let max = [node.max_x[i], node.max_y[i], node.max_z[i]];
+            let near = |axis: usize| {
+                if dir_is_neg[axis] == 1 {
+                    max[axis]
+                } else {
+                    min[axis]
+                }
+            };
+            let far = |axis: usize| {
+                if dir_is_neg[axis] == 1 {
+                    min[axis]
+                } else {
+                    max[axis]
+                }
+            };
+            let t_min = ((near(0) - r.o.x) * inv_dir.x)
+                .max((near(1) - r.o.y) * inv_dir.y)
+                .max((near(2) - r.o.z) * inv_dir.z)
+                .max(0.0);
+            let t_max = ((far(0) - r.o.x) * inv_dir.x * scale)
+                .min((far(1) - r.o.y) * inv_dir.y * scale)
+                .min((far(2) - r.o.z) * inv_dir.z * scale)
+                .min(r.t_max);
+            if t_min <= t_max && t_min < r.t_max {
+                t_hit[i] = t_min;
+            }
+        }
+        t_hit
+    }
+
+    fn check_intersect_children<const N: usize>(seed: u64) {
+        let mut rng = Pcg32::seed_from_u64(seed);
+        let prims = random_boxes(N * 50, seed);
+        let rays = random_rays(500, seed + 1);
+        for (k, chunk) in prims.chunks(N).enumerate() {
+            // Leave some slots unused.
+            let n_children = if k % 3 == 0 { rng.gen_range(1..N) } else { N };
+            let mut node = WideBVHNode::<N>::new();
+            for prim in chunk[..n_children].iter() {
+                node.add_child(&prim.world_bound(), 0, 1);
+            }
+            for r in rays.iter() {
+                let mut r = r.clone();
+                if k % 2 == 0 {
+                    r.t_max = rng.gen_range(1.0..30.0);
+                }
+                let (inv_dir, dir_is_neg) = ray_inv_dir(&r);
+                let t_hit = node.intersect_children(&r, &inv_dir, dir_is_neg);
+                assert_eq!(
+                    t_hit,
+                    intersect_children_scalar(&node, &r, &inv_dir, dir_is_neg)
+                );
+                assert!(t_hit[n_children..].iter().all(|&t| t == INFINITY));
+            }
+        }
+    }
+
+    #[test]
+    fn intersect_children_matches_scalar_slab_test() {
+        check_intersect_children::<4>(1);
+        check_intersect_children::<8>(2);
+    }
+
+    #[test]
+    fn wide_matches_binary_on_random_rays() {
+        let prims = random_boxes(2000, 3);
+        let rays = random_rays(2000, 4);
+        for &split_method in &[
+            SplitMethod::SAH,
+            SplitMethod::Middle,
+            SplitMethod::EqualCounts,
+        ] {
+            let binary = BVHAccel::new(&prims, 4, split_method, 2);
+            for &width in &[4, 8] {
+                let wide = BVHAccel::new(&prims, 4, split_method, width);
+                assert!(wide.wide_nodes.is_some());
+                for r in rays.iter() {
+                    let mut binary_ray = r.clone();
+                    let mut wide_ray = r.clone();
+                    let binary_hit = binary.intersect(&mut binary_ray).map(|si| si.uv.x);
+                    let wide_hit = wide.intersect(&mut wide_ray).map(|si| si.uv.x);
+                    assert_eq!(wide_hit, binary_hit);
+                    assert_eq!(wide_ray.t_max, binary_ray.t_max);
+                    assert_eq!(wide.intersect_p(r), binary.intersect_p(r));
+                }
+                assert!(assert_matches_brute_force(&wide, &prims, &rays) > 0);
+            }
+        }
+    }
+
+    #[test]
+    fn node_stack_spills_onto_heap() {
+        let mut stack = NodeStack::new(0_u32);
+        for i in 1..2 * STACK_SIZE as u32 {
+            stack.push(i);
+        }
+        assert_eq!(stack.overflow.len(), STACK_SIZE);
+        for i in (0..2 * STACK_SIZE as u32).rev() {
+            assert_eq!(stack.pop(), Some(i));
+        }
+        assert_eq!(stack.pop(), None);
+    }
+
+    #[test]
+    fn binary_traversal_handles_deep_trees() {
+        // Every level of the chain pushes a sibling so the traversal stack
+        // outgrows its fixed part.
+        let bvh = chain_bvh(2 * STACK_SIZE, 6);
+        let rays = random_rays(200, 7);
+        assert!(assert_matches_brute_force(&bvh, &bvh.primitives, &rays) > 0);
+    }
+
+    #[test]
+    fn traversal_handles_deep_trees() {
+        // A chain of nodes whose children all overlap. Every level pushes
+        // 7 siblings so the traversal stack outgrows its fixed part before
+        // reaching the primitive at the bottom.
+        let prims = random_boxes(1, 5);
+        let bounds = Bounds3f::new(
+            Point3f::new(-20.0, -20.0, -20.0),
+            Point3f::new(20.0, 20.0, 20.0),
+        );
+        let depth = 2 * STACK_SIZE / 7;
+        let empty = depth as u32 + 1;
+        let mut nodes: Vec<WideBVHNode<8>> = (0..depth)
+            .map(|i| {
+                let mut node = WideBVHNode::new();
+                node.add_child(&bounds, i as u32 + 1, 0);
+                for _ in 1..8 {
+                    node.add_child(&bounds, empty, 0);
+                }
+                node
+            })
+            .collect();
+        let mut leaf = WideBVHNode::new();
+        leaf.add_child(&prims[0].world_bound(), 0, 1);
+        nodes.push(leaf);
+        nodes.push(WideBVHNode::new());
+
+        let b = prims[0].world_bound();
+        let c = b.p_min + (b.p_max - b.p_min) * 0.5;
+        let mut r = Ray::new(
+            Point3f::new(c.x, c.y, -30.0),
+            Vector3f::new(0.0, 0.0, 1.0),
+            INFINITY,
+            0.0,
+            None,
+        );
+        assert!(intersect_p(&nodes, &prims, &r));
+        assert!(intersect(&nodes, &prims, &mut r).is_some());
+        assert!(r.t_max < INFINITY);
+    }
+}
@@ -0,0 +1,566 @@
+//! Acceleration Structure Cache Files
+
+#![allow(dead_code)]
+use crate::core::geometry::*;
+use crate::core::primitive::*;
+use byteorder::{LittleEndian, ReadBytesExt, WriteBytesExt};
+use rayon::prelude::*;
+use std::collections::HashMap;
+use std::fs::{rename, File};
+use std::io::{BufReader, BufWriter, Error, ErrorKind, Read, Result, Write};
+use std::path::Path;
+use std::sync::Arc;
+
+/// The first 8 bytes of a cache file are the header `PBRACCL` terminated
+/// with the format version.
+const CACHE_HEADER: [u8; 8] = [b'P', b'B', b'R', b'A', b'C', b'C', b'L', b'\x01'];
+
+/// Identifies the type of acceleration structure stored in a cache file.
+#[derive(Copy, Clone, Debug, PartialEq)]
+#[repr(u8)]
+pub enum CacheKind {
+    /// `BVHAccel`.
+    BVH = 0,
+
+    /// `KDTreeAccel`.
+    KDTree = 1,
+}
+
+/// Returns a hash of the input geometry of an acceleration structure. The
+/// hash covers the number of primitives and the data that determines their
+/// bounds, such as triangle vertices and instance transforms, since spatial
+/// splits and motion bounds depend on more than the world bounds.
+///
+/// * `primitives` - The primitives.
+pub fn geometry_hash(primitives: &[ArcPrimitive]) -> u64 {
+    let hashes: Vec<u64> = primitives
+        .par_iter()
+        .map(|p| {
+            let mut hasher = GeometryHasher::new();
+            p.hash_geometry(&mut hasher);
+            hasher.finish()
+        })
+        .collect();
+
+    let mut hasher = GeometryHasher::new();
+    hasher.write_u64(primitives.len() as u64);
+    for hash in hashes.iter() {
+        hasher.write_u64(*hash);
+    }
+    hasher.finish()
+}
+
+/// Returns a map from the address of each primitive to its index. This is
+/// used to store reordered primitives as indices into the input.
+///
+/// * `primitives` - The primitives.
+pub fn primitive_indices(primitives: &[ArcPrimitive]) -> HashMap<*const (), u32> {
+    primitives
+        .iter()
+        .enumerate()
+        .map(|(i, p)| (Arc::as_ptr(p) as *const (), i as u32))
+        .collect()
+}
+
+/// Returns the index of a primitive using the map from `primitive_indices()`.
+///
+/// * `indices`   - Map from primitive address to index.
+/// * `primitive` - The primitive.
+pub fn primitive_index(indices: &HashMap<*const (), u32>, primitive: &ArcPrimitive) -> u32 {
+    indices[&(Arc::as_ptr(primitive) as *const ())]
+}
+
+/// Write an acceleration structure cache file. The file is written to a
+/// temporary path first and then renamed so that an interrupted write does
+/// not leave a truncated cache behind.
+///
+/// * `path`  - Path of the cache file.
+/// * `kind`  - Type of acceleration structure.
+/// * `hash`  - Hash of the input geometry.
+/// * `write` - Writes the acceleration structure data.
+pub fn write_cache_file<F>(
+    path: &str,
+    kind: CacheKind,
+    hash: u64,
+    write: F,
+) -> std::result::Result<(), String>
+where
+    F: FnOnce(&mut BufWriter<File>) -> Result<()>,
+{
+    let tmp_path = format!("{}.tmp", path);
+    let file = match File::create(&tmp_path) {
+        Ok(file) => file,
+        Err(err) => return Err(format!("Could not create {}. {}", tmp_path, err)),
+    };
+
+    let mut writer = BufWriter::new(file);
+    let result = writer
+        .write_all(&CACHE_HEADER)
+        .and_then(|_| writer.write_u8(kind as u8))
+        .and_then(|_| writer.write_u64::<LittleEndian>(hash))
+        .and_then(|_| write(&mut writer))
+        .and_then(|_| writer.flush());
+    if let Err(err) = result {
+        return Err(format!("Error writing cache file {}. {}", tmp_path, err));
+    }
+
+    rename(&tmp_path, path)
+        .map_err(|err| format!("Could not rename {} to {}. {}", tmp_path, path, err))
+}
+
+/// Read an acceleration structure cache file. Returns `Ok(None)` if the file
+/// doesn't exist and an error if it can't be read or was written for other
+/// geometry.
+///
+/// * `path` - Path of the cache file.
+/// * `kind` - Type of acceleration structure.
+/// * `hash` - Hash of the input geometry.
+/// * `read` - Reads the acceleration structure data.
+pub fn read_cache_file<T, F>(
+    path: &str,
+    kind: CacheKind,
+    hash: u64,
+    read: F,
+) -> std::result::Result<Option<T>, String>
+where
+    F: FnOnce(&mut BufReader<File>) -> Result<T>,
+{
+    if !Path::new(path).exists() {
+        return Ok(None);
+    }
+
+    let file = match File::open(path) {
+        Ok(file) => file,
+        Err(err) => return Err(format!("Could not open {}. {}", path, err)),
+    };
+
+    let mut reader = BufReader::new(file);
+    let mut header = [0_u8; 8];
+    let result = reader.read_exact(&mut header).and_then(|_| {
+        if header != CACHE_HEADER {
+            return Err(invalid_data("Invalid header."));
+        }
+
+        if reader.read_u8()? != kind as u8 {
+            return Err(invalid_data(&format!("Not a {:?} cache.", kind)));
+        }
+
+        // Geometry that changed since the cache was written needs a new
+        // acceleration structure.
+        if reader.read_u64::<LittleEndian>()? != hash {
+            return Err(invalid_data("Geometry has changed."));
+        }
+
+        read(&mut reader)
+    });
+
+    match result {
+        Ok(accel) => Ok(Some(accel)),
+        Err(err) => Err(format!("Error reading cache file {}. {}", path, err)),
+    }
+}
+
+/// Returns an error for invalid cache file data.
+///
+/// * `msg` - Error message.
+pub fn invalid_data(msg: &str) -> Error {
+    Error::new(ErrorKind::InvalidData, msg)
+}
+
+/// Write a bounding box.
+///
+/// * `writer` - The writer.
+/// * `b`      - The bounding box.
+pub fn write_bounds<W: Write>(writer: &mut W, b: &Bounds3f) -> Result<()> {
+    writer.write_f32::<LittleEndian>(b.p_min.x)?;
+    writer.write_f32::<LittleEndian>(b.p_min.y)?;
+    writer.write_f32::<LittleEndian>(b.p_min.z)?;
+    writer.write_f32::<LittleEndian>(b.p_max.x)?;
+    writer.write_f32::<LittleEndian>(b.p_max.y)?;
+    writer.write_f32::<LittleEndian>(b.p_max.z)
+}
+
+/// Read a bounding box.
+///
+/// * `reader` - The reader.
+pub fn read_bounds<R: Read>(reader: &mut R) -> Result<Bounds3f> {
+    let mut v = [0.0_f32; 6];
+    reader.read_f32_into::<LittleEndian>(&mut v)?;
+    Ok(Bounds3f {
+        p_min: Point3f::new(v[0], v[1], v[2]),
+        p_max: Point3f::new(v[3], v[4], v[5]),
+    })
+}
+
+/// Write a list of `u32` values preceded by its length.
+///
+/// * `writer` - The writer.
+/// * `values` - The values.
+pub fn write_u32s<W: Write>(writer: &mut W, values: &[u32]) -> Result<()> {
+    writer.write_u64::<LittleEndian>(values.len() as u64)?;
+    for v in values.iter() {
+        writer.write_u32::<LittleEndian>(*v)?;
+    }
+    Ok(())
+}
+
+/// Read a list of `u32` values written by `write_u32s()`. The list can have at
+/// most `max_len` values and each value must be less than `bound`.
+///
+/// * `reader`  - The reader.
+/// * `max_len` - Maximum length of the list.
+/// * `bound`   - Upper bound of the values.
+pub fn read_u32s<R: Read>(reader: &mut R, max_len: usize, bound: usize) -> Result<Vec<u32>> {
+    let n = read_len(reader, max_len)?;
+    let mut values = vec![0_u32; n];
+    reader.read_u32_into::<LittleEndian>(&mut values)?;
+    if values.iter().any(|&v| v as usize >= bound) {
+        return Err(invalid_data("Index out of range."));
+    }
+    Ok(values)
+}
+
+/// Read a length written as `u64`. Lengths greater than `max` are rejected so
+/// that a corrupt file can't make the caller allocate huge buffers.
+///
+/// * `reader` - The reader.
+/// * `max`    - Maximum valid length.
+pub fn read_len<R: Read>(reader: &mut R, max: usize) -> Result<usize> {
+    let n = reader.read_u64::<LittleEndian>()?;
+    if n > max as u64 {
+        return Err(invalid_data("Invalid length."));
+    }
+    Ok(n as usize)
+}
+
+/// Returns the number of elements of the given size that fit in the file.
+/// This bounds lengths that don't depend on the number of primitives.
+///
+/// * `reader`       - The reader.
+/// * `element_size` - Size of an element in bytes.
+pub fn max_elements(reader: &BufReader<File>, element_size: usize) -> Result<usize> {
+    Ok(reader.get_ref().metadata()?.len() as usize / element_size)
+}
+
+// ----------------------------------------------------------------------------
+// Tests
+// ----------------------------------------------------------------------------
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::accelerators::testing::*;
+    use crate::accelerators::*;
+    use crate::core::material::*;
+    use crate::core::medium::*;
+    use crate::core::pbrt::*;
+    use crate::core::primitives::*;
+    use crate::core::spectrum::*;
+    use crate::core::texture::*;
+    use crate::materials::*;
+    use crate::shapes::*;
+    use crate::textures::*;
+    use rand::{Rng, SeedableRng};
+    use rand_pcg::Pcg32;
+    use std::fs::remove_file;
+
+    /// Returns matte triangle primitives with the given vertices.
+    ///
+    /// * `triangles` - The vertices of each triangle.
+    fn triangles(triangles: &[[Point3f; 3]]) -> Vec<ArcPrimitive> {
+        let identity = Arc::new(Transform::default());
+        let kd: ArcTexture<Spectrum> = Arc::new(ConstantTexture::new(Spectrum::new(0.5)));
+        let sigma: ArcTexture<Float> = Arc::new(ConstantTexture::new(0.0));
+        let material: ArcMaterial = Arc::new(MatteMaterial::new(kd, sigma, None));
+        let p: Vec<Point3f> = triangles.iter().flat_map(|t| t.iter().copied()).collect();
+        TriangleMesh::create(
+            identity.clone(),
+            identity,
+            false,
+            (0..p.len()).collect(),
+            p,
+            vec![],
+            vec![],
+            vec![],
+            None,
+            None,
+            vec![],
+        )
+        .into_iter()
+        .map(|shape| {
+            Arc::new(GeometricPrimitive::new(
+                shape,
+                material.clone(),
+                None,
+                MediumInterface::new(None, None),
+            )) as ArcPrimitive
+        })
+        .collect()
+    }
+
+    /// Returns the vertices of `n` random triangles in [-10, 10]^3.
+    ///
+    /// * `n`    - Number of triangles.
+    /// * `seed` - Random number seed.
+    fn random_triangles(n: usize, seed: u64) -> Vec<[Point3f; 3]> {
+        let mut rng = Pcg32::seed_from_u64(seed);
+        let mut point = |c: Point3f, r: Float| {
+            c + Vector3f::new(
+                rng.gen_range(-r..r),
+                rng.gen_range(-r..r),
+                rng.gen_range(-r..r),
+            )
+        };
+        (0..n)
+            .map(|_| {
+                let c = point(Point3f::default(), 10.0);
+                [point(c, 1.0), point(c, 1.0), point(c, 1.0)]
+            })
+            .collect()
+    }
+
+    /// Returns a cache file path unique to the test and process.
+    ///
+    /// * `name` - Name of the test.
+    fn cache_path(name: &str) -> String {
+        std::env::temp_dir()
+            .join(format!("pbr-rust-{}-{}.cache", name, std::process::id()))
+            .to_string_lossy()
+            .into_owned()
+    }
+
+    /// Check that two acceleration structures find the same hits.
+    ///
+    /// * `a` - The first acceleration structure.
+    /// * `b` - The second acceleration structure.
+    fn assert_same_hits(a: &dyn Primitive, b: &dyn Primitive) {
+        for r in random_rays(2000, 3).iter() {
+            let (mut ra, mut rb) = (r.clone(), r.clone());
+            assert_eq!(
+                a.intersect(&mut ra).is_some(),
+                b.intersect(&mut rb).is_some()
+            );
+            assert_eq!(ra.t_max, rb.t_max);
+            assert_eq!(a.intersect_p(r), b.intersect_p(r));
+        }
+    }
+
+    #[test]
+    fn hash_covers_vertices_inside_the_bounds() {
+        let a = [
+            Point3f::new(0.0, 0.0, 0.0),
+            Point3f::new(1.0, 0.0, 0.0),
+            Point3f::new(0.0, 1.0, 1.0),
+        ];
+        let b = [
+            Point3f::new(0.0, 0.0, 0.0),
+            Point3f::new(1.0, 0.0, 1.0),
+            Point3f::new(0.0, 1.0, 0.0),
+        ];
+        let (prims_a, prims_b) = (triangles(&[a]), triangles(&[b]));
+        assert_eq!(prims_a[0].world_bound(), prims_b[0].world_bound());
+        assert_eq!(geometry_hash(&prims_a), geometry_hash(&triangles(&[a])));
+        assert_ne!(geometry_hash(&prims_a), geometry_hash(&prims_b));
+    }
+
+    #[test]
+    fn hash_covers_instance_transforms() {
+        // A cube rotated a quarter turn about its center has the same bounds.
+        let c = Vector3f::new(2.0, 3.0, 4.0);
+        let e = Vector3f::new(1.0, 1.0, 1.0);
+        let cube: ArcPrimitive = Arc::new(BoxPrimitive {
+            bounds: Bounds3f::new(Point3f::from(c - e), Point3f::from(c + e)),
+            index: 0,
+        });
+
+        let instance = |angle: Float| -> Vec<ArcPrimitive> {
+            let t = Arc::new(
+                Transform::translate(&c) * Transform::rotate_z(angle) * Transform::translate(&-c),
+            );
+            vec![Arc::new(TransformedPrimitive::new(
+                cube.clone(),
+                AnimatedTransform::new(t.clone(), t, 0.0, 1.0),
+            ))]
+        };
+        let (a, b) = (instance(0.0), instance(90.0));
+        let (ba, bb) = (a[0].world_bound(), b[0].world_bound());
+        for axis in [Axis::X, Axis::Y, Axis::Z] {
+            assert!((ba.p_min[axis] - bb.p_min[axis]).abs() < 1e-4);
+            assert!((ba.p_max[axis] - bb.p_max[axis]).abs() < 1e-4);
+        }
+        assert_ne!(geometry_hash(&a), geometry_hash(&b));
+    }
+
+    #[test]
+    fn bvh_cache_round_trip() {
+        let prims = triangles(&random_triangles(500, 1));
+        let hash = geometry_hash(&prims);
+        for &width in &[2, 4] {
+            let path = cache_path(&format!("bvh-round-trip-{}", width));
+            let _ = remove_file(&path);
+
+            let built = BVHAccel::new_cached(&path, &prims, 4, SplitMethod::SBVH, width);
+            let loaded = BVHAccel::read_cache(&path, &prims, hash, 4, SplitMethod::SBVH, width)
+                .unwrap()
+                .unwrap();
+            remove_file(&path).unwrap();
+
+            assert_eq!(built.primitives.len(), loaded.primitives.len());
+            for (a, b) in built.primitives.iter().zip(loaded.primitives.iter()) {
+                assert!(Arc::ptr_eq(a, b));
+            }
+            assert_eq!(built.nodes.len(), loaded.nodes.len());
+            for (a, b) in built.nodes.iter().zip(loaded.nodes.iter()) {
+                assert_eq!(a.bounds, b.bounds);
+                assert_eq!(a.offset, b.offset);
+                assert_eq!(a.n_primitives, b.n_primitives);
+                assert_eq!(a.axis, b.axis);
+            }
+            assert_same_hits(&built, &loaded);
+        }
+    }
+
+    #[test]
+    fn deep_bvh_caches_load() {
+        let bvh = chain_bvh(200, 8);
+        let prims = bvh.primitives.clone();
+        let hash = geometry_hash(&prims);
+        let path = cache_path("deep-bvh");
+        bvh.write_cache(&path, &prims, hash).unwrap();
+        let loaded = BVHAccel::read_cache(&path, &prims, hash, 1, SplitMethod::SAH, 2)
+            .unwrap()
+            .unwrap();
+        remove_file(&path).unwrap();
+
+        assert_eq!(loaded.nodes.len(), 401);
+        assert!(assert_matches_brute_force(&loaded, &prims, &random_rays(200, 9)) > 0);
+    }
+
+    #[test]
+    fn kd_tree_cache_round_trip() {
+        let prims = triangles(&random_triangles(500, 2));
+        let hash = geometry_hash(&prims);
+        let path = cache_path("kd-tree-round-trip");
+        let _ = remove_file(&path);
+
+        let built = KDTreeAccel::new_cached(&path, &prims, 80, 1, 0.5, 1, -1);
+        let loaded = KDTreeAccel::read_cache(&path, &prims, hash, (80, 1, 0.5, 1, -1))
+            .unwrap()
+            .unwrap();
+        remove_file(&path).unwrap();
+
+        assert_eq!(built.primitive_indices, loaded.primitive_indices);
+        assert_eq!(built.next_free_node, loaded.next_free_node);
+        assert_same_hits(&built, &loaded);
+    }
+
+    #[test]
+    fn stale_cache_is_rejected() {
+        let mut vertices = random_triangles(200, 3);
+        let prims = triangles(&vertices);
+        let path = cache_path("stale");
+        let _ = remove_file(&path);
+        BVHAccel::new_cached(&path, &prims, 4, SplitMethod::SBVH, 2);
+
+        // Move a vertex to the centroid of the triangle, which keeps the
+        // bounds the same but changes the clipped bounds of spatial splits.
+        let [p0, p1, p2] = vertices[7];
+        vertices[7][0] =
+            p0 + (Vector3f::from(p1) + Vector3f::from(p2) - Vector3f::from(p0) * 2.0) / 3.0;
+        let moved = triangles(&vertices);
+        let hash = geometry_hash(&moved);
+        let err = BVHAccel::read_cache(&path, &moved, hash, 4, SplitMethod::SBVH, 2)
+            .err()
+            .unwrap();
+        assert!(err.contains("Geometry has changed."), "{}", err);
+
+        // Rebuilding overwrites the stale cache.
+        let rebuilt = BVHAccel::new_cached(&path, &moved, 4, SplitMethod::SBVH, 2);
+        let loaded = BVHAccel::read_cache(&path, &moved, hash, 4, SplitMethod::SBVH, 2)
+            .unwrap()
+            .unwrap();
+        remove_file(&path).unwrap();
+        assert_same_hits(&rebuilt, &loaded);
+    }
+
+    #[test]
+    fn other_files_are_rejected() {
+        let prims = triangles(&random_triangles(50, 4));
+        let hash = geometry_hash(&prims);
+        let path = cache_path("other-files");
+        let _ = remove_file(&path);
+
+        BVHAccel::new_cached(&path, &prims, 4, SplitMethod::SAH, 2);
+        let err = KDTreeAccel::read_cache(&path, &prims, hash, (80, 1, 0.5, 1, -1))
+            .err()
+            .unwrap();
+        assert!(err.contains("Not a KDTree cache."), "{}", err);
+
+        let err = BVHAccel::read_cache(&path, &prims, hash, 2, SplitMethod::SAH, 2)
+            .err()
+            .unwrap();
+        assert!(err.contains("BVH parameters have changed."), "{}", err);
+
+        std::fs::write(&path, b"not a cache file").unwrap();
+        let err = BVHAccel::read_cache(&path, &prims, hash, 4, SplitMethod::SAH, 2)
+            .err()
+            .unwrap();
+        remove_file(&path).unwrap();
+        assert!(err.contains("Invalid header."), "{}", err);
+
+        let missing = BVHAccel::read_cache(&path, &prims, hash, 4, SplitMethod::SAH, 2);
+        assert!(matches!(missing, Ok(None)));
+    }
+
+    #[test]
+    fn huge_lengths_are_rejected() {
+        let prims = triangles(&random_triangles(50, 5));
+        let hash = geometry_hash(&prims);
+        let path = cache_path("huge-lengths");
+        let huge = 1_u64 << 31;
+
+        // BVH with SAH splits, 4 primitives per node and width 2, followed by
+        // a huge primitive ordering.
+        let write_bvh = |ordering: Option<&[u32]>| {
+            write_cache_file(&path, CacheKind::BVH, hash, |writer| {
+                writer.write_all(&[0, 4, 2])?;
+                match ordering {
+                    Some(ordering) => write_u32s(writer, ordering)?,
+                    None => writer.write_u64::<LittleEndian>(huge)?,
+                }
+                writer.write_u64::<LittleEndian>(huge)
+            })
+            .unwrap()
+        };
+        write_bvh(None);
+        let err = BVHAccel::read_cache(&path, &prims, hash, 4, SplitMethod::SAH, 2)
+            .err()
+            .unwrap();
+        assert!(err.contains("Invalid length."), "{}", err);
+
+        // A valid ordering followed by a huge node count.
+        let ordering: Vec<u32> = (0..prims.len() as u32).collect();
+        write_bvh(Some(&ordering));
+        let err = BVHAccel::read_cache(&path, &prims, hash, 4, SplitMethod::SAH, 2)
+            .err()
+            .unwrap();
+        assert!(err.contains("Invalid length."), "{}", err);
+
+        // kd-tree with a huge list of primitive indices.
+        write_cache_file(&path, CacheKind::KDTree, hash, |writer| {
+            writer.write_i32::<LittleEndian>(80)?;
+            writer.write_i32::<LittleEndian>(1)?;
+            writer.write_f32::<LittleEndian>(0.5)?;
+            writer.write_u32::<LittleEndian>(1)?;
+            writer.write_i32::<LittleEndian>(-1)?;
+            write_bounds(writer, &Bounds3f::default())?;
+            writer.write_u64::<LittleEndian>(huge)
+        })
+        .unwrap();
+        let err = KDTreeAccel::read_cache(&path, &prims, hash, (80, 1, 0.5, 1, -1))
+            .err()
+            .unwrap();
+        remove_file(&path).unwrap();
+        assert!(err.contains("Invalid length."), "{}", err);
+    }
+}
@@ -0,0 +1,310 @@
+//! Instance Acceleration Structure.
+
+#![allow(dead_code)]
+use super::bvh::*;
+use super::cache::geometry_hash;
+use crate::core::geometry::*;
+use crate::core::light::*;
+use crate::core::material::*;
+use crate::core::paramset::*;
+use crate::core::pbrt::*;
+use crate::core::primitive::*;
+use crate::core::primitives::*;
+use std::collections::HashMap;
+use std::sync::Arc;
+
+/// Identifies the geometry of an object instance. A top-level acceleration
+/// structure can be refit for new instances if their keys match.
+#[derive(Clone, Debug, PartialEq)]
+pub struct InstanceKey {
+    /// Name of the object instance.
+    pub name: String,
+
+    /// Hash of the primitives of the object instance.
+    pub geometry_hash: u64,
+}
+
+impl InstanceKey {
+    /// Create a new `InstanceKey`.
+    ///
+    /// * `name`       - Name of the object instance.
+    /// * `primitives` - Primitives of the object instance.
+    pub fn new(name: &str, primitives: &[ArcPrimitive]) -> Self {
+        Self {
+            name: String::from(name),
+            geometry_hash: geometry_hash(primitives),
+        }
+    }
+}
+
+/// Top-level acceleration structure (TLAS) over object instances. Each
+/// instance references a bottom-level acceleration structure (BLAS) in
+/// object space. Moving instances only requires refitting the top-level BVH;
+/// the bottom-level structures are shared and never rebuilt.
+#[derive(Clone)]
+pub struct InstanceAccel {
+    /// The instances.
+    pub instances: Vec<Arc<TransformedPrimitive>>,
+
+    /// Keys of the object instances referenced by `instances`.
+    pub keys: Vec<InstanceKey>,
+
+    /// The BVH over the instances.
+    pub bvh: BVHAccel,
+
+    /// Index in `instances` of each primitive in `bvh`.
+    pub instance_indices: Vec<usize>,
+}
+
+impl InstanceAccel {
+    /// Create a new top-level acceleration structure.
+    ///
+    /// * `instances`       - The instances and keys of the object instances
+    ///                       they reference.
+    /// * `params`          - BVH parameters.
+    /// * `transform_start` - Transform start time.
+    /// * `transform_end`   - Transform end time.
+    pub fn new(
+        instances: Vec<(InstanceKey, Arc<TransformedPrimitive>)>,
+        params: &ParamSet,
+        transform_start: Float,
+        transform_end: Float,
+    ) -> Self {
+        let (keys, instances): (Vec<InstanceKey>, Vec<Arc<TransformedPrimitive>>) =
+            instances.into_iter().unzip();
+        let prims: Vec<ArcPrimitive> = instances
+            .iter()
+            .map(|instance| instance.clone() as ArcPrimitive)
+            .collect();
+        let bvh = BVHAccel::from((params, &prims, transform_start, transform_end));
+
+        // The BVH reorders primitives and may reference them more than once
+        // so find the instance of each by its address.
+        let indices: HashMap<*const (), usize> = prims
+            .iter()
+            .enumerate()
+            .map(|(i, p)| (Arc::as_ptr(p) as *const (), i))
+            .collect();
+        let instance_indices = bvh
+            .primitives
+            .iter()
+            .map(|p| indices[&(Arc::as_ptr(p) as *const ())])
+            .collect();
+
+        Self {
+            instances,
+            keys,
+            bvh,
+            instance_indices,
+        }
+    }
+
+    /// Returns `true` if the instances reference object instances with the
+    /// same names and geometry in the same order so they can replace the
+    /// current instances with `update()`. Object instances that were
+    /// defined again with the same geometry match.
+    ///
+    /// * `instances` - The instances and keys of the object instances they
+    ///                 reference.
+    pub fn can_update(&self, instances: &[(InstanceKey, Arc<TransformedPrimitive>)]) -> bool {
+        self.keys.len() == instances.len()
+            && self
+                .keys
+                .iter()
+                .zip(instances.iter())
+                .all(|(a, (b, _))| a == b)
+    }
+
+    /// Replace the instances and refit the top-level BVH.
+    ///
+    /// * `instances` - The instances and keys of the object instances they
+    ///                 reference. See `can_update()`.
+    pub fn update(&mut self, instances: Vec<(InstanceKey, Arc<TransformedPrimitive>)>) {
+        assert!(self.can_update(&instances));
+
+        self.instances = instances
+            .into_iter()
+            .map(|(_, instance)| instance)
+            .collect();
+        self.refit();
+    }
+
+    /// Refit the top-level BVH to the current instances.
+    pub fn refit(&mut self) {
+        for (p, &i) in self
+            .bvh
+            .primitives
+            .iter_mut()
+            .zip(self.instance_indices.iter())
+        {
+            *p = self.instances[i].clone();
+        }
+        self.bvh.refit();
+    }
+}
+
+/// Tag `InstanceAccel` as an `Aggregate`.
+impl Aggregate for InstanceAccel {}
+
+impl Primitive for InstanceAccel {
+    /// Returns a bounding box in the world space.
+    fn world_bound(&self) -> Bounds3f {
+        self.bvh.world_bound()
+    }
+
+    /// Returns geometric details if a ray intersects the primitive and updates
+    /// the t_max parameter of the ray. If there is no intersection, `None` is
+    /// returned.
+    ///
+    /// * `r`                  - The ray.
+    fn intersect(&self, r: &mut Ray) -> Option<SurfaceInteraction> {
+        self.bvh.intersect(r)
+    }
+
+    /// Returns `true` if a ray-primitive intersection succeeds; otherwise `false`.
+    ///
+    /// * `r`                  - The ray.
+    fn intersect_p(&self, r: &Ray) -> bool {
+        self.bvh.intersect_p(r)
+    }
+
+    /// Returns geometric details for the closest intersection of each ray in
+    /// a packet and updates the t_max parameter of the rays.
+    ///
+    /// * `rays`               - The rays.
+    fn intersect_n(&self, rays: &mut [Ray]) -> Vec<Option<SurfaceInteraction>> {
+        self.bvh.intersect_n(rays)
+    }
+
+    /// Returns whether each ray in a packet intersects a primitive.
+    ///
+    /// * `rays`               - The rays.
+    fn occluded_n(&self, rays: &[Ray]) -> Vec<bool> {
+        self.bvh.occluded_n(rays)
+    }
+
+    /// Returns a reference to the AreaLight that describes the primitive’s
+    /// emission distribution, if the primitive is itself a light source.
+    /// If the primitive is not emissive, this method should return `None`.
+    ///
+    /// *NOTE*: This should never be called. Calling code should directly call
+    /// get_area_light() on the primitive from the ray-primitive intersection.
+    fn get_area_light(&self) -> Option<ArcAreaLight> {
+        error!(
+            "InstanceAccel::get_area_light() shouldn't be called; \
+            should've gone to GeometricPrimitive."
+        );
+        None
+    }
+
+    /// Returns a reference to the material instance assigned to the primitive.
+    /// If `None` is returned, ray intersections with the primitive should be
+    /// ignored; the primitive only serves to delineate a volume of space for
+    /// participating media. This method is also used to check if two rays have
+    /// intersected the same object by comparing their Material pointers.
+    ///
+    /// *NOTE*: This should never be called. Calling code should directly call
+    /// get_material() on the primitive from the ray-primitive intersection.
+    fn get_material(&self) -> Option<ArcMaterial> {
+        error!(
+            "InstanceAccel::get_material() shouldn't be called; \
+            should've gone to GeometricPrimitive."
+        );
+        None
+    }
+
+    /// Initializes representations of the light-scattering properties of the
+    /// material at the intersection point on the surface.
+    ///
+    /// *NOTE*: This should never be called. Calling code should directly call
+    /// compute_scattering_functions() on the primitive from the ray-primitive
+    /// intersection.
+    ///
+    /// * `_si`                   - The surface interaction at the intersection.
+    /// * `_mode`                 - Transport mode.
+    /// * `_allow_multiple_lobes` - Allow multiple lobes.
+    fn compute_scattering_functions(
+        &self,
+        _si: &mut SurfaceInteraction,
+        _mode: TransportMode,
+        _allow_multiple_lobes: bool,
+    ) {
+        error!(
+            "InstanceAccel::compute_scattering_functions() shouldn't be \
+            called; should've gone to GeometricPrimitive."
+        );
+    }
+}
+
+// ----------------------------------------------------------------------------
+// Tests
+// ----------------------------------------------------------------------------
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::accelerators::testing::*;
+
+    /// Returns instances of random boxes, one object instance per box,
+    /// translated by `offset`.
+    fn instances(
+        boxes: &[ArcPrimitive],
+        offset: Float,
+    ) -> Vec<(InstanceKey, Arc<TransformedPrimitive>)> {
+        let transform = Arc::new(Transform::translate(&Vector3f::new(offset, 0.0, 0.0)));
+        boxes
+            .iter()
+            .enumerate()
+            .map(|(i, prim)| {
+                let key = InstanceKey::new(&format!("box{}", i), &[prim.clone()]);
+                let animated =
+                    AnimatedTransform::new(transform.clone(), transform.clone(), 0.0, 1.0);
+                (
+                    key,
+                    Arc::new(TransformedPrimitive::new(prim.clone(), animated)),
+                )
+            })
+            .collect()
+    }
+
+    fn prims(instances: &[(InstanceKey, Arc<TransformedPrimitive>)]) -> Vec<ArcPrimitive> {
+        instances
+            .iter()
+            .map(|(_, instance)| instance.clone() as ArcPrimitive)
+            .collect()
+    }
+
+    #[test]
+    fn update_refits_moved_instances() {
+        let boxes = random_boxes(500, 1);
+        let rays = random_rays(1000, 2);
+        let mut accel = InstanceAccel::new(instances(&boxes, 0.0), &ParamSet::new(), 0.0, 1.0);
+        assert!(assert_matches_brute_force(&accel, &prims(&instances(&boxes, 0.0)), &rays) > 0);
+
+        // Instances of the same objects defined again for the next frame.
+        let moved = instances(&boxes, 3.0);
+        assert!(accel.can_update(&moved));
+        accel.update(moved.clone());
+        assert!(assert_matches_brute_force(&accel, &prims(&moved), &rays) > 0);
+    }
+
+    #[test]
+    fn updates_require_the_same_objects() {
+        let boxes = random_boxes(50, 3);
+        let accel = InstanceAccel::new(instances(&boxes, 0.0), &ParamSet::new(), 0.0, 1.0);
+
+        // Fewer instances.
+        assert!(!accel.can_update(&instances(&boxes[1..], 0.0)));
+
+        // A different name.
+        let mut renamed = instances(&boxes, 0.0);
+        renamed[7].0.name = String::from("other");
+        assert!(!accel.can_update(&renamed));
+
+        // Different geometry under the same name.
+        let other_boxes = random_boxes(50, 4);
+        let mut changed = instances(&boxes, 0.0);
+        changed[7] = instances(&other_boxes, 0.0).swap_remove(7);
+        assert!(!accel.can_update(&changed));
+    }
+}
@@ -0,0 +1,167 @@
+//! KD Tree Cache Files
+
+#![allow(dead_code)]
+use super::*;
+use crate::accelerators::cache::*;
+use byteorder::{LittleEndian, ReadBytesExt, WriteBytesExt};
+
+impl KDTreeAccel {
+    /// Load a kd-tree from a cache file if it was built for the same
+    /// primitives and parameters. Otherwise build it and write the cache file.
+    ///
+    /// * `path`           - Path of the cache file.
+    /// * `primitives`     - Pimitives.
+    /// * `isect_cost`     - Intersection cost.
+    /// * `traversal_cost` - Traversal cost.
+    /// * `empty_bonus`    - Bonus value used when one of the 2 regions along
+    ///                      split are empty.
+    /// * `max_prims`      - Maximum number of primitives in leaf.
+    /// * `max_depth`      - Maximum depth of tree.
+    pub fn new_cached(
+        path: &str,
+        primitives: &Vec<ArcPrimitive>,
+        isect_cost: i32,
+        traversal_cost: i32,
+        empty_bonus: Float,
+        max_prims: u32,
+        max_depth: i32,
+    ) -> Self {
+        let hash = geometry_hash(primitives);
+        let params = (
+            isect_cost,
+            traversal_cost,
+            empty_bonus,
+            max_prims,
+            max_depth,
+        );
+        match Self::read_cache(path, primitives, hash, params) {
+            Ok(Some(kd_tree)) => {
+                info!("Loaded kd-tree from cache file {}.", path);
+                kd_tree.report_stats();
+                return kd_tree;
+            }
+            Ok(None) => {}
+            Err(err) => warn!("{} Rebuilding kd-tree.", err),
+        }
+
+        let kd_tree = Self::new(
+            primitives,
+            isect_cost,
+            traversal_cost,
+            empty_bonus,
+            max_prims,
+            max_depth,
+        );
+        match kd_tree.write_cache(path, hash, max_depth) {
+            Ok(()) => info!("Wrote kd-tree to cache file {}.", path),
+            Err(err) => warn!("{}", err),
+        }
+        kd_tree
+    }
+
+    /// Write the nodes and primitive indices to a cache file. The kd-tree
+    /// doesn't reorder its primitives so they aren't stored.
+    ///
+    /// * `path`      - Path of the cache file.
+    /// * `hash`      - Hash of the primitives from `geometry_hash()`.
+    /// * `max_depth` - Maximum depth the tree was built with.
+    pub fn write_cache(&self, path: &str, hash: u64, max_depth: i32) -> Result<(), String> {
+        write_cache_file(path, CacheKind::KDTree, hash, |writer| {
+            writer.write_i32::<LittleEndian>(self.isect_cost)?;
+            writer.write_i32::<LittleEndian>(self.traversal_cost)?;
+            writer.write_f32::<LittleEndian>(self.empty_bonus)?;
+            writer.write_u32::<LittleEndian>(self.max_prims)?;
+            writer.write_i32::<LittleEndian>(max_depth)?;
+
+            write_bounds(writer, &self.bounds)?;
+            write_u32s(writer, &self.primitive_indices)?;
+
+            // Nodes past `next_free_node` are allocated but unused.
+            let nodes = &self.nodes[..self.next_free_node as usize];
+            writer.write_u64::<LittleEndian>(nodes.len() as u64)?;
+            for node in nodes.iter() {
+                let (split, data) = node.to_raw();
+                writer.write_u32::<LittleEndian>(split)?;
+                writer.write_u32::<LittleEndian>(data)?;
+            }
+            Ok(())
+        })
+    }
+
+    /// Read a kd-tree from a cache file. Returns `Ok(None)` if the file
+    /// doesn't exist and an error if it doesn't match the primitives or
+    /// parameters.
+    ///
+    /// * `path`       - Path of the cache file.
+    /// * `primitives` - The primitives.
+    /// * `hash`       - Hash of the primitives.
+    /// * `params`     - Intersection cost, traversal cost, empty bonus,
+    ///                  maximum number of primitives in leaf and maximum
+    ///                  depth.
+    pub fn read_cache(
+        path: &str,
+        primitives: &Vec<ArcPrimitive>,
+        hash: u64,
+        params: (i32, i32, Float, u32, i32),
+    ) -> Result<Option<Self>, String> {
+        let (isect_cost, traversal_cost, empty_bonus, max_prims, max_depth) = params;
+        read_cache_file(path, CacheKind::KDTree, hash, |reader| {
+            if reader.read_i32::<LittleEndian>()? != isect_cost
+                || reader.read_i32::<LittleEndian>()? != traversal_cost
+                || reader.read_f32::<LittleEndian>()? != empty_bonus
+                || reader.read_u32::<LittleEndian>()? != max_prims
+                || reader.read_i32::<LittleEndian>()? != max_depth
+            {
+                return Err(invalid_data("kd-tree parameters have changed."));
+            }
+
+            let bounds = read_bounds(reader)?;
+            // Primitives can overlap any number of leaves so the lengths
+            // are only bounded by the file size.
+            let max_indices = max_elements(reader, 4)?;
+            let primitive_indices = read_u32s(reader, max_indices, primitives.len())?;
+
+            let max_nodes = max_elements(reader, 8)?;
+            let n = read_len(reader, max_nodes)?;
+            let mut nodes = Vec::with_capacity(n);
+            for i in 0..n {
+                let split = reader.read_u32::<LittleEndian>()?;
+                let data = reader.read_u32::<LittleEndian>()?;
+                let node = KdAccelNode::from_raw(split, data);
+
+                // Interior nodes are followed by the child below the split
+                // and point after it to the child above.
+                let valid = if node.is_leaf() {
+                    match node.n_primitives() {
+                        0 => true,
+                        1 => (node.one_primitive() as usize) < primitives.len(),
+                        np => {
+                            node.primitive_indices_offset() as usize + np as usize
+                                <= primitive_indices.len()
+                        }
+                    }
+                } else {
+                    let above_child = node.above_child() as usize;
+                    i + 1 < n && above_child > i + 1 && above_child < n
+                };
+                if !valid {
+                    return Err(invalid_data("Invalid kd-tree node."));
+                }
+                nodes.push(node);
+            }
+
+            Ok(Self {
+                isect_cost,
+                traversal_cost,
+                max_prims,
+                empty_bonus,
+                primitives: primitives.clone(),
+                primitive_indices,
+                n_alloced_nodes: nodes.len() as u32,
+                next_free_node: nodes.len() as u32,
+                nodes,
+                bounds,
+            })
+        })
+    }
+}
@@ -118,6 +118,26 @@ impl KdAccelNode {
         unsafe { offset = self.split.primitive_indices_offset }
         offset
     }
+
+    /// Returns the raw split and node data for serialization.
+    pub fn to_raw(&self) -> (u32, u32) {
+        let raw: (u32, u32);
+        unsafe { raw = (self.split.one_primitive, self.node.flags) }
+        raw
+    }
+
+    /// Create a node from raw split and node data returned by `to_raw()`.
+    ///
+    /// * `split` - Split data.
+    /// * `node`  - Node data.
+    pub fn from_raw(split: u32, node: u32) -> Self {
+        Self {
+            split: SplitData {
+                one_primitive: split,
+            },
+            node: NodeData { flags: node },
+        }
+    }
 }
 
 impl Default for KdAccelNode {
@@ -7,10 +7,34 @@ use crate::core::material::*;
 use crate::core::paramset::*;
 use crate::core::pbrt::*;
 use crate::core::primitive::*;
+use crate::core::stats::*;
+use std::mem::size_of;
 
+mod cache;
 mod common;
 use common::*;
 
+/// Time spent building kd-trees.
+static KD_BUILD_TIME: Stat = Stat::timer("Time/Kd-tree construction");
+
+/// Memory used by kd-tree nodes and primitive indices.
+static KD_BYTES: Stat = Stat::memory("Memory/Kd-tree");
+
+/// Number of interior nodes.
+static N_INTERIOR_NODES: Stat = Stat::counter("Kd-Tree/Interior nodes");
+
+/// Number of leaf nodes.
+static N_LEAF_NODES: Stat = Stat::counter("Kd-Tree/Leaf nodes");
+
+/// Number of primitives in leaf nodes.
+static PRIMS_PER_LEAF: Stat = Stat::distribution("Kd-Tree/Primitives per leaf node");
+
+/// Depth of leaf nodes.
+static LEAF_DEPTH: Stat = Stat::distribution("Kd-Tree/Leaf node depth");
+
+/// Number of kd-tree nodes visited and rays traced through kd-trees.
+static KD_NODES_VISITED: Stat = Stat::ratio("Kd-Tree/Nodes visited per ray");
+
 /// KD Tree Accelerator.
 #[derive(Clone)]
 pub struct KDTreeAccel {
@@ -63,6 +87,8 @@ impl KDTreeAccel {
         max_prims: u32,
         max_depth: i32,
     ) -> Self {
+        let _timer = KD_BUILD_TIME.start();
+
         // Build kd-tree for accelerator.
         let count = primitives.len();
         let next_free_node = 0;
@@ -85,12 +111,12 @@ impl KDTreeAccel {
 
         // Allocate working memory for kd-tree construction.
         let mut edges: [Vec<BoundEdge>; 3] = [
-            Vec::<BoundEdge>::with_capacity(2 * count),
-            Vec::<BoundEdge>::with_capacity(2 * count),
-            Vec::<BoundEdge>::with_capacity(2 * count),
+            vec![BoundEdge::default(); 2 * count],
+            vec![BoundEdge::default(); 2 * count],
+            vec![BoundEdge::default(); 2 * count],
         ];
 
-        let mut prims0 = Vec::<u32>::with_capacity(count);
+        let mut prims0: Vec<u32> = vec![0_u32; count];
         let mut prims1: Vec<u32> = vec![0_u32; (max_depth + 1) as usize * count];
 
         // Initialize prim_nums for kd-tree construction.
@@ -123,9 +149,37 @@ impl KDTreeAccel {
             0,
         );
 
+        kd_tree.report_stats();
         kd_tree
     }
 
+    /// Record statistics about the size and shape of the tree.
+    pub fn report_stats(&self) {
+        let n_nodes = self.next_free_node as usize;
+        KD_BYTES.add(
+            (n_nodes * size_of::<KdAccelNode>() + self.primitive_indices.len() * size_of::<u32>())
+                as i64,
+        );
+
+        let mut to_visit = if n_nodes > 0 {
+            vec![(0_usize, 0_usize)]
+        } else {
+            vec![]
+        };
+        while let Some((i, depth)) = to_visit.pop() {
+            let node = &self.nodes[i];
+            if node.is_leaf() {
+                N_LEAF_NODES.inc();
+                PRIMS_PER_LEAF.report(node.n_primitives() as i64);
+                LEAF_DEPTH.report(depth as i64);
+            } else {
+                N_INTERIOR_NODES.inc();
+                to_visit.push((i + 1, depth + 1));
+                to_visit.push((node.above_child() as usize, depth + 1));
+            }
+        }
+    }
+
     fn build_tree(
         &mut self,
         node_num: u32,
@@ -157,6 +211,7 @@ impl KDTreeAccel {
                 n_primitives,
                 &mut self.primitive_indices,
             );
+            return;
         }
 
         // Initialize interior node and continue recursion.
@@ -184,7 +239,7 @@ impl KDTreeAccel {
             }
 
             // Sort `edges` for `axis`.
-            edges[axis].sort_by(|e0, e1| {
+            edges[axis][..2 * n_primitives as usize].sort_by(|e0, e1| {
                 if e0.t == e1.t {
                     e0.edge_type.partial_cmp(&e1.edge_type).unwrap()
                 } else {
@@ -291,12 +346,17 @@ impl KDTreeAccel {
             bounds0.p_max[best_axis] = t_split;
             bounds1.p_min[best_axis] = t_split;
 
+            // The children reuse `prims0` and `prims1` so copy their
+            // primitives first.
+            let below_prims = prims0[..n0 as usize].to_vec();
+            let above_prims = prims1[..n1 as usize].to_vec();
+
             let n = n_primitives as usize;
             self.build_tree(
                 node_num + 1,
                 &bounds0,
                 all_prim_bounds,
-                &prim_nums[..],
+                &below_prims[..],
                 n0,
                 depth - 1,
                 edges,
@@ -312,7 +372,7 @@ impl KDTreeAccel {
                 above_child,
                 &bounds1,
                 all_prim_bounds,
-                &prim_nums[..],
+                &above_prims[..],
                 n1,
                 depth - 1,
                 edges,
@@ -320,6 +380,7 @@ impl KDTreeAccel {
                 &mut prims1[n..],
                 new_bad_refines,
             );
+            break;
         }
     }
 }
@@ -340,6 +401,7 @@ impl Primitive for KDTreeAccel {
     /// * `r`                  - The ray.
     fn intersect(&self, r: &mut Ray) -> Option<SurfaceInteraction> {
         let mut si: Option<SurfaceInteraction> = None;
+        let mut n_visited = 0;
 
         // Compute initial parametric range of ray inside kd-tree extent.
         if let Some((mut t_min, mut t_max)) = self.bounds.intersect_p(r) {
@@ -356,6 +418,7 @@ impl Primitive for KDTreeAccel {
                 if r.t_max < t_min {
                     break;
                 }
+                n_visited += 1;
 
                 if !node.is_leaf() {
                     // Process kd-tree interior node.
@@ -401,13 +464,17 @@ impl Primitive for KDTreeAccel {
                     if n_primitives == 1 {
                         // Check one primitive inside leaf node.
                         let one_primitive = node.one_primitive() as usize;
-                        si = self.primitives[one_primitive].intersect(r);
+                        if let Some(hit) = self.primitives[one_primitive].intersect(r) {
+                            si = Some(hit);
+                        }
                     } else {
                         for i in 0..n_primitives as usize {
                             // Check one primitive inside leaf node.
                             let offset = node.primitive_indices_offset() as usize;
                             let index = self.primitive_indices[offset + i] as usize;
-                            si = self.primitives[index].intersect(r);
+                            if let Some(hit) = self.primitives[index].intersect(r) {
+                                si = Some(hit);
+                            }
                         }
                     }
 
@@ -425,6 +492,7 @@ impl Primitive for KDTreeAccel {
             }
         }
 
+        KD_NODES_VISITED.add_fraction(n_visited, 1);
         si
     }
 
@@ -432,6 +500,8 @@ impl Primitive for KDTreeAccel {
     ///
     /// * `r`                  - The ray.
     fn intersect_p(&self, r: &Ray) -> bool {
+        let mut n_visited = 0;
+
         // Compute initial parametric range of ray inside kd-tree extent.
         if let Some((mut t_min, mut t_max)) = self.bounds.intersect_p(r) {
             // Prepaer to traverse kd-tree for ray.
@@ -447,6 +517,7 @@ impl Primitive for KDTreeAccel {
                 if r.t_max < t_min {
                     break;
                 }
+                n_visited += 1;
 
                 if !node.is_leaf() {
                     // Process kd-tree interior node.
@@ -494,6 +565,7 @@ impl Primitive for KDTreeAccel {
 
                         // Check one primitive inside leaf node.
                         if p.intersect_p(r) {
+                            KD_NODES_VISITED.add_fraction(n_visited, 1);
                             return true;
                         }
                     } else {
@@ -504,6 +576,7 @@ impl Primitive for KDTreeAccel {
 
                             // Check one primitive inside leaf node.
                             if p.intersect_p(r) {
+                                KD_NODES_VISITED.add_fraction(n_visited, 1);
                                 return true;
                             }
                         }
@@ -523,6 +596,7 @@ impl Primitive for KDTreeAccel {
             }
         }
 
+        KD_NODES_VISITED.add_fraction(n_visited, 1);
         false
     }
 
@@ -591,13 +665,79 @@ impl From<(&ParamSet, &Vec<ArcPrimitive>)> for KDTreeAccel {
         let max_prims = params.find_one_int("maxprims", 1) as u32;
         let max_depth = params.find_one_int("maxdepth", -1);
 
-        Self::new(
-            prims,
-            isect_cost,
-            trav_cost,
-            empty_bonus,
-            max_prims,
-            max_depth,
-        )
+        // The cache file doesn't exist before the first build so it can't be
+        // looked up with `find_one_filename()`.
+        let cache_file = params.find_one_string("cachefile", String::from(""));
+        if cache_file.is_empty() {
+            Self::new(
+                prims,
+                isect_cost,
+                trav_cost,
+                empty_bonus,
+                max_prims,
+                max_depth,
+            )
+        } else {
+            Self::new_cached(
+                &cache_file,
+                prims,
+                isect_cost,
+                trav_cost,
+                empty_bonus,
+                max_prims,
+                max_depth,
+            )
+        }
+    }
+}
+
+// ----------------------------------------------------------------------------
+// Tests
+// ----------------------------------------------------------------------------
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::accelerators::testing::*;
+
+    #[test]
+    fn matches_brute_force() {
+        let prims = random_boxes(2000, 1);
+        let rays = random_rays(2000, 2);
+        for &max_prims in &[1, 4] {
+            let kd_tree = KDTreeAccel::new(&prims, 80, 1, 0.5, max_prims, -1);
+            assert!(assert_matches_brute_force(&kd_tree, &prims, &rays) > 0);
+        }
+    }
+
+    #[test]
+    fn leaves_reference_all_primitives() {
+        let prims = random_boxes(500, 3);
+        let kd_tree = KDTreeAccel::new(&prims, 80, 1, 0.5, 1, -1);
+
+        let mut seen = vec![false; prims.len()];
+        for node in kd_tree.nodes[..kd_tree.next_free_node as usize].iter() {
+            if node.is_leaf() {
+                let n = node.n_primitives() as usize;
+                if n == 1 {
+                    seen[node.one_primitive() as usize] = true;
+                } else {
+                    let offset = node.primitive_indices_offset() as usize;
+                    for &i in kd_tree.primitive_indices[offset..offset + n].iter() {
+                        seen[i as usize] = true;
+                    }
+                }
+            }
+        }
+        assert!(seen.iter().all(|&s| s));
+    }
+
+    #[test]
+    fn single_leaf() {
+        let prims = random_boxes(3, 4);
+        let kd_tree = KDTreeAccel::new(&prims, 80, 1, 0.5, 4, -1);
+        assert_eq!(kd_tree.next_free_node, 1);
+        assert!(kd_tree.nodes[0].is_leaf());
+        assert_eq!(kd_tree.nodes[0].n_primitives(), 3);
     }
 }
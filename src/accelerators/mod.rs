@@ -2,8 +2,14 @@
 
 #![allow(dead_code)]
 mod bvh;
+mod cache;
+mod instance;
 mod kd_tree;
 
+#[cfg(test)]
+mod testing;
+
 // Re-export
 pub use bvh::*;
+pub use instance::*;
 pub use kd_tree::*;
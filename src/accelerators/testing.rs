@@ -0,0 +1,217 @@
+//! Helpers for testing acceleration structures against brute force.
+
+use super::bvh::*;
+use crate::core::geometry::*;
+use crate::core::light::*;
+use crate::core::material::*;
+use crate::core::pbrt::*;
+use crate::core::primitive::*;
+use crate::core::primitives::*;
+use rand::{Rng, SeedableRng};
+use rand_pcg::Pcg32;
+use std::sync::Arc;
+
+/// An axis-aligned box primitive. Intersections record the box index in the
+/// u-coordinate so tests can tell which primitive was hit.
+pub struct BoxPrimitive {
+    /// The box.
+    pub bounds: Bounds3f,
+
+    /// Index of the box.
+    pub index: usize,
+}
+
+impl Primitive for BoxPrimitive {
+    fn world_bound(&self) -> Bounds3f {
+        self.bounds
+    }
+
+    fn intersect(&self, r: &mut Ray) -> Option<SurfaceInteraction> {
+        match self.bounds.intersect_p(r) {
+            Some((t0, _)) if t0 < r.t_max => {
+                r.t_max = t0;
+                Some(SurfaceInteraction::new(
+                    r.at(t0),
+                    Vector3f::default(),
+                    Point2f::new(self.index as Float, 0.0),
+                    -r.d,
+                    Vector3f::new(1.0, 0.0, 0.0),
+                    Vector3f::new(0.0, 1.0, 0.0),
+                    Normal3f::default(),
+                    Normal3f::default(),
+                    r.time,
+                    None,
+                ))
+            }
+            _ => None,
+        }
+    }
+
+    fn intersect_p(&self, r: &Ray) -> bool {
+        self.bounds.intersect_p(r).is_some()
+    }
+
+    fn get_area_light(&self) -> Option<ArcAreaLight> {
+        None
+    }
+
+    fn get_material(&self) -> Option<ArcMaterial> {
+        None
+    }
+
+    fn compute_scattering_functions(
+        &self,
+        _si: &mut SurfaceInteraction,
+        _mode: TransportMode,
+        _allow_multiple_lobes: bool,
+    ) {
+    }
+}
+
+/// Returns `n` random boxes in [-10, 10]^3. Every 7th box is elongated along
+/// z so that primitives overlap split planes.
+///
+/// * `n`    - Number of boxes.
+/// * `seed` - Random number seed.
+pub fn random_boxes(n: usize, seed: u64) -> Vec<ArcPrimitive> {
+    let mut rng = Pcg32::seed_from_u64(seed);
+    (0..n)
+        .map(|index| {
+            let c = Point3f::new(
+                rng.gen_range(-10.0..10.0),
+                rng.gen_range(-10.0..10.0),
+                rng.gen_range(-10.0..10.0),
+            );
+            let e = Vector3f::new(
+                rng.gen_range(0.01..0.3),
+                rng.gen_range(0.01..0.3),
+                if index % 7 == 0 {
+                    5.0
+                } else {
+                    rng.gen_range(0.01..0.3)
+                },
+            );
+            let bounds = Bounds3f::new(c - e, c + e);
+            Arc::new(BoxPrimitive { bounds, index }) as ArcPrimitive
+        })
+        .collect()
+}
+
+/// Returns `n` random boxes from `random_boxes()` that move 5 units along x
+/// over the time interval [0, 1].
+///
+/// * `n`    - Number of boxes.
+/// * `seed` - Random number seed.
+pub fn moving_boxes(n: usize, seed: u64) -> Vec<ArcPrimitive> {
+    let start = Arc::new(Transform::default());
+    let end = Arc::new(Transform::translate(&Vector3f::new(5.0, 0.0, 0.0)));
+    random_boxes(n, seed)
+        .into_iter()
+        .map(|prim| {
+            let transform = AnimatedTransform::new(start.clone(), end.clone(), 0.0, 1.0);
+            Arc::new(TransformedPrimitive::new(prim, transform)) as ArcPrimitive
+        })
+        .collect()
+}
+
+/// Returns a binary BVH over `random_boxes(depth + 1, seed)` that is a chain
+/// of `depth` interior nodes. Each interior node has the next one as its
+/// first child and a leaf with one box as its second child, and the bounds
+/// of all interior nodes contain every box.
+///
+/// * `depth` - Number of interior nodes.
+/// * `seed`  - Random number seed.
+pub fn chain_bvh(depth: usize, seed: u64) -> BVHAccel {
+    let primitives = random_boxes(depth + 1, seed);
+    let bounds = primitives
+        .iter()
+        .fold(Bounds3f::empty(), |b, p| b.union(&p.world_bound()));
+
+    // Interior nodes come first, then the leaf below the deepest one and the
+    // second children from the bottom up.
+    let leaf = |i: usize| LinearBVHNode::new_leaf_node(primitives[i].world_bound(), i as u32, 1);
+    let mut nodes: Vec<LinearBVHNode> = (0..depth)
+        .map(|i| LinearBVHNode::new_interior_node(bounds, (2 * depth - i) as u32, (i % 3) as u8))
+        .collect();
+    nodes.extend((0..=depth).map(leaf));
+
+    BVHAccel {
+        primitives,
+        max_prims_in_node: 1,
+        split_method: SplitMethod::SAH,
+        width: 2,
+        nodes,
+        wide_nodes: None,
+        motion_bounds: None,
+    }
+}
+
+/// Returns `n` random rays starting in [-15, 15]^3. Every 5th ray has a zero
+/// x-direction.
+///
+/// * `n`    - Number of rays.
+/// * `seed` - Random number seed.
+pub fn random_rays(n: usize, seed: u64) -> Vec<Ray> {
+    let mut rng = Pcg32::seed_from_u64(seed);
+    (0..n)
+        .map(|i| {
+            let o = Point3f::new(
+                rng.gen_range(-15.0..15.0),
+                rng.gen_range(-15.0..15.0),
+                rng.gen_range(-15.0..15.0),
+            );
+            let mut d = Vector3f::new(
+                rng.gen_range(-1.0..1.0),
+                rng.gen_range(-1.0..1.0),
+                rng.gen_range(-1.0..1.0),
+            );
+            if i % 5 == 0 {
+                d.x = 0.0;
+            }
+            Ray::new(o, d, INFINITY, rng.gen_range(0.0..1.0), None)
+        })
+        .collect()
+}
+
+/// Returns the index of the closest box hit by a ray with its ray parameter
+/// and whether any primitive is hit, by testing every primitive.
+///
+/// * `prims` - The primitives.
+/// * `r`     - The ray.
+pub fn brute_force(prims: &[ArcPrimitive], r: &Ray) -> (Option<usize>, Float, bool) {
+    let mut ray = r.clone();
+    let mut index = None;
+    for prim in prims.iter() {
+        if let Some(si) = prim.intersect(&mut ray) {
+            index = Some(si.uv.x as usize);
+        }
+    }
+    let any = prims.iter().any(|prim| prim.intersect_p(r));
+    (index, ray.t_max, any)
+}
+
+/// Asserts that an acceleration structure finds the same closest hits and
+/// occlusion as brute force. Returns the number of rays that hit something.
+///
+/// * `accel` - The acceleration structure.
+/// * `prims` - The primitives in `accel`.
+/// * `rays`  - The rays.
+pub fn assert_matches_brute_force(
+    accel: &dyn Primitive,
+    prims: &[ArcPrimitive],
+    rays: &[Ray],
+) -> usize {
+    let mut hits = 0;
+    for r in rays.iter() {
+        let (index, t, any) = brute_force(prims, r);
+        let mut ray = r.clone();
+        let si = accel.intersect(&mut ray);
+        assert_eq!(si.map(|si| si.uv.x as usize), index);
+        assert_eq!(ray.t_max, t);
+        assert_eq!(accel.intersect_p(r), any);
+        if any {
+            hits += 1;
+        }
+    }
+    hits
+}
@@ -46,6 +46,11 @@ impl EnvironmentCamera {
 }
 
 impl Camera for EnvironmentCamera {
+    /// Returns the common camera data.
+    fn get_data(&self) -> &CameraData {
+        &self.data
+    }
+
     /// Returns a ray corresponding to a given sample. It also returns, a floating
     /// point value that affects how much the radiance arriving at the film plane
     /// will contribute to final image.
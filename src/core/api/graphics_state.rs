@@ -26,6 +26,7 @@ use crate::materials::*;
 use crate::samplers::*;
 use crate::shapes::*;
 use crate::textures::*;
+use std::borrow::Cow;
 use std::result::Result;
 use std::sync::{Arc, Mutex};
 
@@ -70,6 +71,21 @@ pub struct GraphicsState {
 
     /// Reverse surface normal direction for current shape/material.
     pub reverse_orientation: bool,
+
+    /// Default shape parameters set by pbrt-v4 `Attribute "shape"`.
+    pub shape_attributes: ParamSet,
+
+    /// Default light parameters set by pbrt-v4 `Attribute "light"`.
+    pub light_attributes: ParamSet,
+
+    /// Default material parameters set by pbrt-v4 `Attribute "material"`.
+    pub material_attributes: ParamSet,
+
+    /// Default medium parameters set by pbrt-v4 `Attribute "medium"`.
+    pub medium_attributes: ParamSet,
+
+    /// Default texture parameters set by pbrt-v4 `Attribute "texture"`.
+    pub texture_attributes: ParamSet,
 }
 
 impl GraphicsState {
@@ -96,6 +112,27 @@ impl GraphicsState {
             area_light_params: ParamSet::new(),
             area_light: None,
             reverse_orientation: false,
+            shape_attributes: ParamSet::new(),
+            light_attributes: ParamSet::new(),
+            material_attributes: ParamSet::new(),
+            medium_attributes: ParamSet::new(),
+            texture_attributes: ParamSet::new(),
+        }
+    }
+
+    /// Returns the parameters with the given attributes added as defaults.
+    /// The parameters are borrowed when there are no attributes to avoid
+    /// copying large parameter lists like triangle meshes.
+    ///
+    /// * `params`     - Parameter set.
+    /// * `attributes` - Default parameters set by `Attribute`.
+    pub fn with_attributes<'a>(params: &'a ParamSet, attributes: &ParamSet) -> Cow<'a, ParamSet> {
+        if attributes.is_empty() {
+            Cow::Borrowed(params)
+        } else {
+            let mut params = params.clone();
+            params.add_defaults(attributes);
+            Cow::Owned(params)
         }
     }
 
@@ -118,7 +155,11 @@ impl GraphicsState {
                 self.float_textures.clone(),
                 self.spectrum_textures.clone(),
             );
-            self.make_material(&current_material.name, &mp)
+            let material = self.make_material(&current_material.name, &mp, false);
+
+            // Material parameters provided by the shape are used.
+            geom_params.merge_lookups(&mp.geom_params);
+            material
         } else {
             Ok(current_material.material.clone())
         }
@@ -216,6 +257,8 @@ impl GraphicsState {
     /// * `world2object`        - Transformation from world space to object space.
     /// * `reverse_orientation` - Indicates whether their surface normal directions.
     /// * `paramset`            - Parameter set.
+    /// * `transform_start`     - Transform start time.
+    /// * `transform_end`       - Transform end time.
     pub fn make_shape(
         &self,
         name: &str,
@@ -223,6 +266,8 @@ impl GraphicsState {
         world2object: ArcTransform,
         reverse_orientation: bool,
         paramset: &ParamSet,
+        transform_start: Float,
+        transform_end: Float,
     ) -> Result<Vec<ArcShape>, String> {
         let p = (paramset, object2world, world2object, reverse_orientation);
 
@@ -231,20 +276,36 @@ impl GraphicsState {
             "curve" => Ok(Curve::from_props(p)),
             "cylinder" => Ok(vec![Arc::new(Cylinder::from(p))]),
             "disk" => Ok(vec![Arc::new(Disk::from(p))]),
+            "heightfield" => Ok(Heightfield::from_props(p)),
             "hyperboloid" => Ok(vec![Arc::new(Hyperboloid::from(p))]),
             "loopsubdiv" => Ok(LoopSubDiv::from_props(p)),
+            "nurbs" => Ok(Nurbs::from_props(p, &self.float_textures)),
             "paraboloid" => Ok(vec![Arc::new(Paraboloid::from(p))]),
+            "plymesh" => Ok(TriangleMesh::from_ply_props(p, &self.float_textures)),
             "sphere" => Ok(vec![Arc::new(Sphere::from(p))]),
-            "trianglemesh" => Ok(TriangleMesh::from_props(p, &self.float_textures)),
+            "trianglemesh" => Ok(TriangleMesh::from_props(
+                p,
+                &self.float_textures,
+                transform_start,
+                transform_end,
+            )),
+            "bilinearmesh" => Ok(BilinearPatchMesh::from_props(p, &self.float_textures)),
             _ => Err(format!("Shape '{}' unknown.", name)),
         }
     }
 
-    /// Creates the given type of material from parameter set.
+    /// Creates the given type of material from parameter set. Unknown
+    /// materials are replaced by a matte material unless in strict mode.
     ///
-    /// * `name` - Name.
-    /// * `mp`   - Parameter set.
-    pub fn make_material(&self, name: &str, mp: &TextureParams) -> Result<ArcMaterial, String> {
+    /// * `name`   - Name.
+    /// * `mp`     - Parameter set.
+    /// * `strict` - Report unknown materials as errors.
+    pub fn make_material(
+        &self,
+        name: &str,
+        mp: &TextureParams,
+        strict: bool,
+    ) -> Result<ArcMaterial, String> {
         match name {
             "matte" => Ok(Arc::new(MatteMaterial::from(mp))),
             "plastic" => Ok(Arc::new(PlasticMaterial::from(mp))),
@@ -255,7 +316,7 @@ impl GraphicsState {
                     Some(mat) => mat.material.clone(),
                     None => {
                         warn!("Named material '{}' undefined. Using 'matte'.", m1);
-                        self.make_material("matte", mp).unwrap()
+                        self.make_material("matte", mp, strict).unwrap()
                     }
                 };
 
@@ -264,7 +325,7 @@ impl GraphicsState {
                     Some(mat) => mat.material.clone(),
                     None => {
                         warn!("Named material '{}' undefined. Using 'matte'.", m2);
-                        self.make_material("matte", mp).unwrap()
+                        self.make_material("matte", mp, strict).unwrap()
                     }
                 };
 
@@ -272,6 +333,7 @@ impl GraphicsState {
             }
             "" => Err(format!("Unable to create material with no name")),
             "none" => Err(String::from("Unable to create material 'none'.")),
+            _ if strict => Err(format!("Material '{}' unknown.", name)),
             _ => {
                 warn!("Material '{}' unknown. Using 'matte'.", name);
                 Ok(Arc::new(MatteMaterial::from(mp)))
@@ -361,11 +423,11 @@ impl GraphicsState {
     /// * `medium2world` - Medium to world space transform.
     /// * `paramset`     - Parameter set.
     pub fn make_medium(
-        _name: &str,
+        name: &str,
         _medium2world: ArcTransform,
         _paramset: &ParamSet,
     ) -> Result<ArcMedium, String> {
-        todo!()
+        Err(format!("Medium '{}' unknown.", name))
     }
 
     /// Creates a light.
@@ -405,18 +467,26 @@ impl GraphicsState {
 
     /// Creates an accelerator.
     ///
-    /// * `name`     - Name.
-    /// * `prims`    - Primitives.
-    /// * `paramset` - Parameter set.
+    /// * `name`            - Name.
+    /// * `prims`           - Primitives.
+    /// * `paramset`        - Parameter set.
+    /// * `transform_start` - Transform start time.
+    /// * `transform_end`   - Transform end time.
     pub fn make_accelerator(
         name: &str,
         prims: &Vec<ArcPrimitive>,
         paramset: &ParamSet,
+        transform_start: Float,
+        transform_end: Float,
     ) -> Result<ArcPrimitive, String> {
-        let p = (paramset, prims);
         match name {
-            "bvh" => Ok(Arc::new(BVHAccel::from(p))),
-            "kdtree" => Ok(Arc::new(KDTreeAccel::from(p))),
+            "bvh" => Ok(Arc::new(BVHAccel::from((
+                paramset,
+                prims,
+                transform_start,
+                transform_end,
+            )))),
+            "kdtree" => Ok(Arc::new(KDTreeAccel::from((paramset, prims)))),
             _ => Err(format!("Accelerator '{}' unknown.", name)),
         }
     }
@@ -465,13 +535,18 @@ impl GraphicsState {
             medium_interface.outside.clone(),
         );
 
-        match name {
+        let camera: Result<ArcCamera, String> = match name {
             "environment" => Ok(Arc::new(EnvironmentCamera::from(p))),
             "orthographic" => Ok(Arc::new(OrthographicCamera::from(p))),
             "perspective" => Ok(Arc::new(PerspectiveCamera::from(p))),
             "realistic" => Ok(Arc::new(RealisticCamera::from(p))),
             _ => Err(format!("Camera '{}' unknown.", name)),
+        };
+
+        if camera.is_ok() {
+            paramset.report_unused(&format!("Camera \"{}\"", name));
         }
+        camera
     }
 
     /// Creates a sampler.
@@ -4,27 +4,45 @@
 mod graphics_state;
 mod material_instance;
 mod render_options;
+mod scene_error;
+mod scene_writer;
 mod transform_cache;
 mod transform_set;
 
 use crate::accelerators::*;
+use crate::core::app::OPTIONS;
+use crate::core::fileutil::*;
 use crate::core::geometry::*;
 use crate::core::light::*;
 use crate::core::medium::*;
+use crate::core::obj::*;
 use crate::core::paramset::*;
 use crate::core::pbrt::*;
 use crate::core::primitive::*;
 use crate::core::primitives::*;
 use crate::core::spectrum::*;
+use crate::core::stats::*;
 use crate::core::texture::*;
 use graphics_state::*;
 use material_instance::*;
 use render_options::*;
+use scene_writer::*;
+use std::cell::RefCell;
 use std::collections::HashMap;
+use std::path::PathBuf;
 use std::sync::{Arc, Mutex};
 use transform_cache::*;
 use transform_set::*;
 
+// Re-export.
+pub use scene_error::*;
+
+/// Time spent creating the scene from the render options.
+static SCENE_CONSTRUCTION_TIME: Stat = Stat::timer("Time/Scene construction");
+
+/// Time spent rendering.
+static RENDERING_TIME: Stat = Stat::timer("Time/Rendering");
+
 /// Map of floating point textures.
 pub type FloatTextureMap = HashMap<String, ArcTexture<Float>>;
 
@@ -77,6 +95,19 @@ pub struct Api {
 
     /// Caches the transforms.
     transform_cache: Arc<Mutex<TransformCache>>,
+
+    /// Location of the statement being processed used to report errors.
+    location: Option<SourceLocation>,
+
+    /// Abort on the first error in the scene description.
+    strict: bool,
+
+    /// The first error reported in strict mode that hasn't been taken by the
+    /// parser.
+    first_error: RefCell<Option<SceneError>>,
+
+    /// Writes the scene description instead of rendering it when set.
+    writer: Option<SceneWriter>,
 }
 
 impl Api {
@@ -94,15 +125,44 @@ impl Api {
             pushed_transforms: vec![],
             pushed_active_transform_bits: vec![],
             transform_cache: transform_cache.clone(),
+            location: None,
+            strict: OPTIONS.strict,
+            first_error: RefCell::new(None),
+            writer: if OPTIONS.cat || OPTIONS.to_ply {
+                match SceneWriter::create(&OPTIONS.image_file, OPTIONS.to_ply) {
+                    Ok(writer) => Some(writer),
+                    Err(err) => panic!("{}", err),
+                }
+            } else {
+                None
+            },
         }
     }
 
+    /// Sets the location of the statement being processed so that errors
+    /// can be reported with it.
+    ///
+    /// * `location` - The statement location.
+    pub fn set_location(&mut self, location: Option<SourceLocation>) {
+        self.location = location;
+    }
+
+    /// Returns `true` if parsing should abort on the first error.
+    pub fn is_strict(&self) -> bool {
+        self.strict
+    }
+
+    /// Returns the first error reported in strict mode since the last call.
+    pub fn take_error(&self) -> Option<SceneError> {
+        self.first_error.borrow_mut().take()
+    }
+
     /* API Methods */
 
     /// API Initialization.
     pub fn pbrt_init(&mut self) {
         if self.current_api_state != ApiState::Uninitialized {
-            error!("pbrt_init() has already been called.");
+            self.report_error("pbrt_init() has already been called.");
         }
         self.current_api_state = ApiState::OptionsBlock;
     }
@@ -110,13 +170,37 @@ impl Api {
     /// API Cleanup.
     pub fn pbrt_cleanup(&mut self) {
         if self.current_api_state == ApiState::Uninitialized {
-            error!("pbrt_cleanup() called without pbrt_init().");
+            self.report_error("pbrt_cleanup() called without pbrt_init().");
         } else if self.current_api_state == ApiState::WorldBlock {
-            error!("pbrt_cleanup() called while inside world block.");
+            self.report_error("pbrt_cleanup() called while inside world block.");
         }
+
+        // Statistics go to standard error so they don't mix with a scene
+        // description written to standard output.
+        if !OPTIONS.quiet {
+            if let Err(err) = print_stats(&mut std::io::stderr()) {
+                error!("Unable to print statistics. {}", err);
+            }
+        }
+        clear_stats();
+
+        if let Some(writer) = &self.writer {
+            writer.flush();
+        }
+
         self.current_api_state = ApiState::Uninitialized;
     }
 
+    /// Called after the entire scene description has been parsed. pbrt-v4
+    /// scenes have no `WorldEnd` statement, so a world block that is still
+    /// open is ended here and rendered.
+    pub fn pbrt_end_of_input(&mut self) {
+        if self.current_api_state == ApiState::WorldBlock {
+            warn!("WorldEnd missing at end of scene description. Assuming a pbrt-v4 scene.");
+            self.pbrt_world_end();
+        }
+    }
+
     /// Set current tranformation matrix to the identity matrix.
     pub fn pbrt_identity(&mut self) {
         if self.verify_initialized("Identity") {
@@ -155,11 +239,13 @@ impl Api {
     pub fn pbrt_transform(&mut self, tr: &[Float; 16]) {
         if self.verify_initialized("Transform") {
             for i in 0..MAX_TRANSFORMS {
-                let t = Transform::from(Matrix4x4::new(
-                    tr[0], tr[4], tr[8], tr[12], tr[1], tr[5], tr[9], tr[13], tr[2], tr[6], tr[10],
-                    tr[14], tr[3], tr[7], tr[11], tr[15],
-                ));
-                self.current_transforms[i] = Arc::new(t);
+                if self.active_transform_bits & (1 << i) > 0 {
+                    let t = Transform::from(Matrix4x4::new(
+                        tr[0], tr[4], tr[8], tr[12], tr[1], tr[5], tr[9], tr[13], tr[2], tr[6],
+                        tr[10], tr[14], tr[3], tr[7], tr[11], tr[15],
+                    ));
+                    self.current_transforms[i] = Arc::new(t);
+                }
             }
         }
     }
@@ -178,8 +264,10 @@ impl Api {
                 tr[14], tr[3], tr[7], tr[11], tr[15],
             ));
             for i in 0..MAX_TRANSFORMS {
-                let t = *self.current_transforms[i] * transform;
-                self.current_transforms[i] = Arc::new(t);
+                if self.active_transform_bits & (1 << i) > 0 {
+                    let t = *self.current_transforms[i] * transform;
+                    self.current_transforms[i] = Arc::new(t);
+                }
             }
         }
     }
@@ -302,6 +390,9 @@ impl Api {
     /// * `end`   - Ending time.
     pub fn pbrt_transform_times(&mut self, start: Float, end: Float) {
         if self.verify_options("TransformTimes") {
+            if let Some(writer) = &self.writer {
+                writer.directive(&format!("TransformTimes {} {}", start, end));
+            }
             self.render_options.transform_start_time = start;
             self.render_options.transform_end_time = end;
         }
@@ -313,8 +404,12 @@ impl Api {
     /// * `params` - Filter parameters.
     pub fn pbrt_pixel_filter(&mut self, name: String, params: &ParamSet) {
         if self.verify_options("PixelFilter") {
+            if let Some(writer) = &self.writer {
+                writer.named("PixelFilter", &name, params);
+            }
             self.render_options.filter_name = name;
             self.render_options.filter_params = params.clone();
+            self.render_options.filter_location = self.location.clone();
         }
     }
 
@@ -324,8 +419,22 @@ impl Api {
     /// * `params` - Film parameters.
     pub fn pbrt_film(&mut self, film_type: String, params: &ParamSet) {
         if self.verify_options("Film") {
+            let film_type = match film_type.as_str() {
+                "rgb" | "gbuffer" | "spectral" => {
+                    self.report_warning(&format!(
+                        "pbrt-v4 film '{}' not supported. Using 'image'.",
+                        film_type
+                    ));
+                    String::from("image")
+                }
+                _ => film_type,
+            };
+            if let Some(writer) = &self.writer {
+                writer.named("Film", &film_type, params);
+            }
             self.render_options.film_name = film_type;
-            self.render_options.filter_params = params.clone();
+            self.render_options.film_params = params.clone();
+            self.render_options.film_location = self.location.clone();
         }
     }
 
@@ -335,8 +444,23 @@ impl Api {
     /// * `params` - Sampler parameters.
     pub fn pbrt_sampler(&mut self, name: String, params: &ParamSet) {
         if self.verify_options("Sampler") {
+            let name = match name.as_str() {
+                "independent" => String::from("random"),
+                "zsobol" | "paddedsobol" | "pmj02bn" => {
+                    self.report_warning(&format!(
+                        "pbrt-v4 sampler '{}' not supported. Using 'sobol'.",
+                        name
+                    ));
+                    String::from("sobol")
+                }
+                _ => name,
+            };
+            if let Some(writer) = &self.writer {
+                writer.named("Sampler", &name, params);
+            }
             self.render_options.sampler_name = name;
             self.render_options.sampler_params = params.clone();
+            self.render_options.sampler_location = self.location.clone();
         }
     }
 
@@ -346,8 +470,12 @@ impl Api {
     /// * `params` - Accelerator parameters.
     pub fn pbrt_accelerator(&mut self, name: String, params: &ParamSet) {
         if self.verify_options("Accelerator") {
+            if let Some(writer) = &self.writer {
+                writer.named("Accelerator", &name, params);
+            }
             self.render_options.accelerator_name = name;
             self.render_options.accelerator_params = params.clone();
+            self.render_options.accelerator_location = self.location.clone();
         }
     }
 
@@ -357,8 +485,12 @@ impl Api {
     /// * `params` - Integrator parameters.
     pub fn pbrt_integrator(&mut self, name: String, params: &ParamSet) {
         if self.verify_options("Integrator") {
+            if let Some(writer) = &self.writer {
+                writer.named("Integrator", &name, params);
+            }
             self.render_options.integrator_name = name;
             self.render_options.integrator_params = params.clone();
+            self.render_options.integrator_location = self.location.clone();
         }
     }
 
@@ -369,9 +501,14 @@ impl Api {
     /// * `params` - Camera parameters.
     pub fn pbrt_camera(&mut self, name: String, params: &ParamSet) {
         if self.verify_options("Camera") {
+            if let Some(writer) = self.writer.as_mut() {
+                writer.transform(&self.current_transforms);
+                writer.named("Camera", &name, params);
+            }
             self.render_options.camera_name = name;
             self.render_options.camera_params = params.clone();
             self.render_options.camera_to_world = self.current_transforms.inverse();
+            self.render_options.camera_location = self.location.clone();
         }
     }
 
@@ -381,16 +518,27 @@ impl Api {
     /// * `params` - Medium parameters.
     pub fn pbrt_make_named_medium(&mut self, name: String, params: &ParamSet) {
         if self.verify_initialized("MakeNamedMedium") {
+            if let Some(writer) = self.writer.as_mut() {
+                writer.transform(&self.current_transforms);
+                writer.named("MakeNamedMedium", &name, params);
+                return;
+            }
+
             self.warn_if_animated_transform("MakeNamedMedium");
 
+            let params =
+                GraphicsState::with_attributes(params, &self.graphics_state.medium_attributes);
+            let params = &*params;
             let medium_type = params.find_one_string("type", String::new());
             if medium_type.is_empty() {
-                error!("No parameter string 'type' found in MakeNamedMedium.");
+                self.report_error("No parameter string 'type' found in MakeNamedMedium.");
             } else {
-                if let Ok(medium) =
-                    GraphicsState::make_medium(&name, self.current_transforms[0].clone(), params)
-                {
-                    self.render_options.named_media.insert(name, medium);
+                let medium2world = self.current_transforms[0].clone();
+                match GraphicsState::make_medium(&medium_type, medium2world, params) {
+                    Ok(medium) => {
+                        self.render_options.named_media.insert(name, medium);
+                    }
+                    Err(err) => self.report_error(&err),
                 }
             }
         }
@@ -402,15 +550,91 @@ impl Api {
     /// * `outside_name` - Outside medium name.
     pub fn pbrt_medium_interface(&mut self, inside_name: String, outside_name: String) {
         if self.verify_initialized("MediumInterface") {
+            if let Some(writer) = &self.writer {
+                writer.directive(&format!(
+                    "MediumInterface \"{}\" \"{}\"",
+                    inside_name, outside_name
+                ));
+            }
             self.graphics_state.current_inside_medium = Some(inside_name);
             self.graphics_state.current_outside_medium = Some(outside_name);
             self.render_options.have_scattering_media = true;
         }
     }
 
+    /// Set the color space of RGB values that follow. Only sRGB is supported
+    /// so other color spaces are ignored with a warning.
+    ///
+    /// * `name` - Color space name.
+    pub fn pbrt_color_space(&mut self, name: String) {
+        if let Some(writer) = &self.writer {
+            if self.verify_initialized("ColorSpace") {
+                writer.directive(&format!("ColorSpace \"{}\"", name));
+            }
+        } else if self.verify_initialized("ColorSpace") && name != "srgb" {
+            self.report_warning(&format!(
+                "ColorSpace '{}' not supported. RGB values are interpreted as sRGB.",
+                name
+            ));
+        }
+    }
+
+    /// Set pbrt-v4 rendering options. These have no equivalent and are
+    /// reported as unused parameters.
+    ///
+    /// * `params` - Option parameters.
+    pub fn pbrt_option(&mut self, params: &ParamSet) {
+        if self.verify_initialized("Option") {
+            if let Some(writer) = &self.writer {
+                writer.directive("Option");
+                writer.params(params);
+                return;
+            }
+            let directive = match &self.location {
+                Some(location) => format!("{}: Option", location),
+                None => String::from("Option"),
+            };
+            params.report_unused(&directive);
+        }
+    }
+
+    /// Set default parameters for shapes, lights, materials, media or
+    /// textures that follow. The defaults are part of the graphics state.
+    ///
+    /// * `target` - Directive type the parameters apply to (shape, light,
+    ///              material, medium or texture).
+    /// * `params` - Default parameters.
+    pub fn pbrt_attribute(&mut self, target: String, params: &ParamSet) {
+        if self.verify_initialized("Attribute") {
+            if let Some(writer) = &self.writer {
+                writer.named("Attribute", &target, params);
+                return;
+            }
+            let attributes = match target.as_str() {
+                "shape" => &mut self.graphics_state.shape_attributes,
+                "light" => &mut self.graphics_state.light_attributes,
+                "material" => &mut self.graphics_state.material_attributes,
+                "medium" => &mut self.graphics_state.medium_attributes,
+                "texture" => &mut self.graphics_state.texture_attributes,
+                _ => {
+                    self.report_error(&format!("Attribute target '{}' unknown.", target));
+                    return;
+                }
+            };
+
+            // Later attributes override earlier ones.
+            let mut params = params.clone();
+            params.add_defaults(attributes);
+            *attributes = params;
+        }
+    }
+
     /// Begin world description.
     pub fn pbrt_world_begin(&mut self) {
         if self.verify_options("WorldBegin") {
+            if let Some(writer) = self.writer.as_mut() {
+                writer.world_begin();
+            }
             self.current_api_state = ApiState::WorldBlock;
             for i in 0..MAX_TRANSFORMS {
                 self.current_transforms[i] = Arc::new(Transform::default());
@@ -436,10 +660,26 @@ impl Api {
                 self.pushed_transforms.pop();
             }
 
-            // Create scene and render.
-            let integrator = self.render_options.make_integrator();
-            let scene = self.render_options.make_scene();
-            integrator.render(scene);
+            if let Some(writer) = self.writer.as_mut() {
+                writer.world_end();
+            } else {
+                // Create scene and render.
+                let scene_timer = SCENE_CONSTRUCTION_TIME.start();
+                let integrator = self.render_options.make_integrator(&self.graphics_state);
+                let (scene, accelerator_error) = self.render_options.make_scene();
+                drop(scene_timer);
+
+                if let Some(err) = accelerator_error {
+                    self.report_scene_error(err);
+                }
+                match integrator {
+                    Ok(integrator) => {
+                        let _render_timer = RENDERING_TIME.start();
+                        integrator.render(scene);
+                    }
+                    Err(err) => self.report_scene_error(err),
+                }
+            }
 
             // Clean up after rendering.
             let mut transform_cache = self.transform_cache.lock().unwrap();
@@ -461,14 +701,10 @@ impl Api {
     /// pushed onto the stack.
     pub fn pbrt_attribute_begin(&mut self) {
         if self.verify_world("AttributeBegin") {
-            self.pushed_graphics_states
-                .push(self.graphics_state.clone());
-            self.graphics_state.float_textures_shared = true;
-            self.graphics_state.spectrum_textures_shared = true;
-            self.graphics_state.named_materials_shared = true;
-            self.pushed_transforms.push(self.current_transforms.clone());
-            self.pushed_active_transform_bits
-                .push(self.active_transform_bits);
+            self.push_graphics_state();
+            if let Some(writer) = self.writer.as_mut() {
+                writer.begin_block("AttributeBegin");
+            }
         }
     }
 
@@ -476,16 +712,12 @@ impl Api {
     /// popped off the stack and restored.
     pub fn pbrt_attribute_end(&mut self) {
         if self.verify_world("AttributeEnd") {
-            if let Some(graphics_state) = self.pushed_graphics_states.pop() {
-                self.graphics_state = graphics_state;
+            if self.pop_graphics_state() {
+                if let Some(writer) = self.writer.as_mut() {
+                    writer.end_block("AttributeEnd");
+                }
             } else {
-                error!("Unmatched pbrtAttributeEnd() encountered. Ignoring it.");
-            }
-            if let Some(transforms) = self.pushed_transforms.pop() {
-                self.current_transforms = transforms;
-            }
-            if let Some(active_transform_bits) = self.pushed_active_transform_bits.pop() {
-                self.active_transform_bits = active_transform_bits;
+                self.report_error("Unmatched pbrtAttributeEnd() encountered. Ignoring it.");
             }
         }
     }
@@ -506,7 +738,7 @@ impl Api {
             if let Some(transforms) = self.pushed_transforms.pop() {
                 self.current_transforms = transforms;
             } else {
-                error!("Unmatched pbrtTransformEnd() encountered. Ignoring it.");
+                self.report_error("Unmatched pbrtTransformEnd() encountered. Ignoring it.");
             }
             if let Some(active_transform_bits) = self.pushed_active_transform_bits.pop() {
                 self.active_transform_bits = active_transform_bits;
@@ -528,6 +760,15 @@ impl Api {
         params: &ParamSet,
     ) {
         if self.verify_world("Texture") {
+            if let Some(writer) = self.writer.as_mut() {
+                writer.transform(&self.current_transforms);
+                writer.texture(&name, &texture_type, &tex_name, params);
+                return;
+            }
+
+            let params =
+                GraphicsState::with_attributes(params, &self.graphics_state.texture_attributes);
+            let params = &*params;
             let mut tp = TextureParams::new(
                 params.clone(),
                 params.clone(),
@@ -543,17 +784,20 @@ impl Api {
 
                 self.warn_if_animated_transform("Texture");
 
-                if let Ok(ft) = GraphicsState::make_float_texture(
+                match GraphicsState::make_float_texture(
                     &tex_name,
                     &*self.current_transforms[0],
                     &mut tp,
                 ) {
-                    if self.graphics_state.float_textures_shared {
-                        let ftm = self.graphics_state.float_textures.clone();
-                        self.graphics_state.float_textures = ftm;
-                        self.graphics_state.float_textures_shared = false;
+                    Ok(ft) => {
+                        if self.graphics_state.float_textures_shared {
+                            let ftm = self.graphics_state.float_textures.clone();
+                            self.graphics_state.float_textures = ftm;
+                            self.graphics_state.float_textures_shared = false;
+                        }
+                        self.graphics_state.float_textures.insert(name, ft);
                     }
-                    self.graphics_state.float_textures.insert(name, ft);
+                    Err(err) => self.report_error(&err),
                 }
             } else if texture_type == "color" || texture_type == "spectrum" {
                 // Create `colour` texture and store in `spectrum_textures`.
@@ -563,20 +807,23 @@ impl Api {
 
                 self.warn_if_animated_transform("Texture");
 
-                if let Ok(st) = GraphicsState::make_spectrum_texture(
+                match GraphicsState::make_spectrum_texture(
                     &tex_name,
                     &*self.current_transforms[0],
                     &mut tp,
                 ) {
-                    if self.graphics_state.spectrum_textures_shared {
-                        let stm = self.graphics_state.spectrum_textures.clone();
-                        self.graphics_state.spectrum_textures = stm;
-                        self.graphics_state.spectrum_textures_shared = false;
+                    Ok(st) => {
+                        if self.graphics_state.spectrum_textures_shared {
+                            let stm = self.graphics_state.spectrum_textures.clone();
+                            self.graphics_state.spectrum_textures = stm;
+                            self.graphics_state.spectrum_textures_shared = false;
+                        }
+                        self.graphics_state.spectrum_textures.insert(name, st);
                     }
-                    self.graphics_state.spectrum_textures.insert(name, st);
+                    Err(err) => self.report_error(&err),
                 }
             } else {
-                error!("Texture type '{}' unknown.", texture_type);
+                self.report_error(&format!("Texture type '{}' unknown.", texture_type));
             }
         }
     }
@@ -587,6 +834,14 @@ impl Api {
     /// * `params` - Material parameters.
     pub fn pbrt_material(&mut self, name: String, params: &ParamSet) {
         if self.verify_world("Material") {
+            if let Some(writer) = &self.writer {
+                writer.named("Material", &name, params);
+                return;
+            }
+
+            let params =
+                GraphicsState::with_attributes(params, &self.graphics_state.material_attributes);
+            let params = &*params;
             let empty_params = ParamSet::new();
             let mut mp = TextureParams::new(
                 params.clone(),
@@ -594,9 +849,16 @@ impl Api {
                 self.graphics_state.float_textures.clone(),
                 self.graphics_state.spectrum_textures.clone(),
             );
-            if let Ok(mtl) = self.graphics_state.make_material(&name, &mut mp) {
-                self.graphics_state.current_material =
-                    Some(Arc::new(MaterialInstance::new(&name, mtl.clone(), params)))
+            match self
+                .graphics_state
+                .make_material(&name, &mut mp, self.strict)
+            {
+                Ok(mtl) => {
+                    mp.report_unused(&self.get_directive("Material", &name));
+                    self.graphics_state.current_material =
+                        Some(Arc::new(MaterialInstance::new(&name, mtl.clone(), params)))
+                }
+                Err(err) => self.report_error(&err),
             }
         }
     }
@@ -607,6 +869,14 @@ impl Api {
     /// * `params` - Material parameters.
     pub fn pbrt_make_named_material(&mut self, name: String, params: &ParamSet) {
         if self.verify_world("MakeNamedMaterial") {
+            if let Some(writer) = &self.writer {
+                writer.named("MakeNamedMaterial", &name, params);
+                return;
+            }
+
+            let params =
+                GraphicsState::with_attributes(params, &self.graphics_state.material_attributes);
+            let params = &*params;
             let empty_params = ParamSet::new();
             let mut mp = TextureParams::new(
                 params.clone(),
@@ -619,19 +889,26 @@ impl Api {
 
             let mat_name = mp.find_string("type", String::new());
             if mat_name.is_empty() {
-                error!("No parameter string 'type' found in MakeNamedMaterial.");
+                self.report_error("No parameter string 'type' found in MakeNamedMaterial.");
             } else {
-                if let Ok(mtl) = self.graphics_state.make_material(&mat_name, &mut mp) {
-                    if self.graphics_state.named_materials.contains_key(&name) {
-                        warn!("Named material '{}' redefined.", name);
-                    }
-                    if self.graphics_state.named_materials_shared {
-                        let nm = self.graphics_state.named_materials.clone();
-                        self.graphics_state.named_materials = nm;
-                        self.graphics_state.named_materials_shared = false;
+                match self
+                    .graphics_state
+                    .make_material(&mat_name, &mut mp, self.strict)
+                {
+                    Ok(mtl) => {
+                        mp.report_unused(&self.get_directive("MakeNamedMaterial", &name));
+                        if self.graphics_state.named_materials.contains_key(&name) {
+                            warn!("Named material '{}' redefined.", name);
+                        }
+                        if self.graphics_state.named_materials_shared {
+                            let nm = self.graphics_state.named_materials.clone();
+                            self.graphics_state.named_materials = nm;
+                            self.graphics_state.named_materials_shared = false;
+                        }
+                        let mtli = Arc::new(MaterialInstance::new(&mat_name, mtl.clone(), params));
+                        self.graphics_state.named_materials.insert(name, mtli);
                     }
-                    let mtli = Arc::new(MaterialInstance::new(&name, mtl.clone(), params));
-                    self.graphics_state.named_materials.insert(name, mtli);
+                    Err(err) => self.report_error(&err),
                 }
             }
         }
@@ -642,10 +919,15 @@ impl Api {
     /// * `name`   - Material name.
     pub fn pbrt_named_material(&mut self, name: String) {
         if self.verify_world("NamedMaterial") {
+            if let Some(writer) = &self.writer {
+                writer.directive(&format!("NamedMaterial \"{}\"", name));
+                return;
+            }
+
             if let Some(mtl) = self.graphics_state.named_materials.get(&name) {
                 self.graphics_state.current_material = Some((*mtl).clone());
             } else {
-                error!("NamedMaterial '{}' unknown.", name);
+                self.report_error(&format!("NamedMaterial '{}' unknown.", name));
             }
         }
     }
@@ -656,13 +938,26 @@ impl Api {
     /// * `params` - Light parameters.
     pub fn pbrt_light_source(&mut self, name: String, params: &ParamSet) {
         if self.verify_world("LightSource") {
+            if let Some(writer) = self.writer.as_mut() {
+                writer.transform(&self.current_transforms);
+                writer.named("LightSource", &name, params);
+                return;
+            }
+
             self.warn_if_animated_transform("LightSource");
 
+            let params =
+                GraphicsState::with_attributes(params, &self.graphics_state.light_attributes);
+            let params = &*params;
+
             let mi = self.create_medium_interface();
             let light2world = self.current_transforms[0].clone();
             match GraphicsState::make_light(&name, light2world, &mi, params) {
-                Ok(lt) => self.render_options.lights.push(lt),
-                Err(err) => error!("{}", err),
+                Ok(lt) => {
+                    params.report_unused(&self.get_directive("LightSource", &name));
+                    self.render_options.lights.push(lt);
+                }
+                Err(err) => self.report_error(&err),
             }
         }
     }
@@ -673,8 +968,15 @@ impl Api {
     /// * `params` - Area light parameters.
     pub fn pbrt_area_light_source(&mut self, name: String, params: &ParamSet) {
         if self.verify_world("AreaLightSource") {
+            if let Some(writer) = &self.writer {
+                writer.named("AreaLightSource", &name, params);
+                return;
+            }
+
             self.graphics_state.area_light = Some(name);
-            self.graphics_state.area_light_params = params.clone();
+            self.graphics_state.area_light_params =
+                GraphicsState::with_attributes(params, &self.graphics_state.light_attributes)
+                    .into_owned();
         }
     }
 
@@ -683,11 +985,32 @@ impl Api {
     /// * `name`   - Shape type (e.g. sphere, cone, etc)
     /// * `params` - Shape parameters.
     pub fn pbrt_shape(&mut self, name: String, params: &ParamSet) {
+        if let Some(mut writer) = self.writer.take() {
+            if self.verify_world("Shape") {
+                writer.transform(&self.current_transforms);
+                if let Err(err) = writer.shape(&name, params) {
+                    self.report_error(&err);
+                }
+            }
+            self.writer = Some(writer);
+            return;
+        }
+
+        if name == "objmesh" {
+            // OBJ groups can use different materials so they are added as
+            // separate triangle meshes.
+            self.obj_mesh(params);
+            return;
+        }
+
         if self.verify_world("Shape") {
+            let params =
+                GraphicsState::with_attributes(params, &self.graphics_state.shape_attributes);
+            let params = &*params;
             let mut prims: Vec<ArcPrimitive> = vec![];
             let mut area_lights: Vec<ArcLight> = vec![]; // Upcasting AreaLight -> Light not possible.
 
-            if self.current_transforms.is_animated() {
+            if !self.current_transforms.is_animated() {
                 // Initialize `prims` and `area_lights` for static shape.
 
                 // Create shapes for shape `name`.
@@ -696,22 +1019,33 @@ impl Api {
                 let tr_inv = Arc::new(tr.inverse());
                 let obj2world = transform_cache.lookup(tr.clone());
                 let world2obj = transform_cache.lookup(tr_inv);
-                let shapes = self
-                    .graphics_state
-                    .make_shape(
-                        &name,
-                        obj2world.clone(),
-                        world2obj.clone(),
-                        self.graphics_state.reverse_orientation,
-                        params,
-                    )
-                    .unwrap();
+                let shapes = match self.graphics_state.make_shape(
+                    &name,
+                    obj2world.clone(),
+                    world2obj.clone(),
+                    self.graphics_state.reverse_orientation,
+                    params,
+                    self.render_options.transform_start_time,
+                    self.render_options.transform_end_time,
+                ) {
+                    Ok(shapes) => shapes,
+                    Err(err) => {
+                        self.report_error(&err);
+                        return;
+                    }
+                };
 
                 if shapes.is_empty() {
                     return;
                 }
 
-                let mtl = self.graphics_state.get_material_for_shape(params).unwrap();
+                let mtl = match self.graphics_state.get_material_for_shape(params) {
+                    Ok(mtl) => mtl,
+                    Err(err) => {
+                        self.report_error(&err);
+                        return;
+                    }
+                };
                 let mi = self.create_medium_interface();
 
                 for shape in shapes.iter() {
@@ -736,29 +1070,40 @@ impl Api {
                 // Initialize `prims` and `area_lights` for animated shape.
 
                 // Create initial shape or shapes for animated shape.
-                if self.graphics_state.area_light.is_none() {
+                if self.graphics_state.area_light.is_some() {
                     warn!("Ignoring currently set area light when creating 'animated shape'.");
                 }
 
                 let mut transform_cache = self.transform_cache.lock().unwrap();
                 let identity = transform_cache.lookup(Arc::new(Transform::default()));
-                let shapes = self
-                    .graphics_state
-                    .make_shape(
-                        &name,
-                        identity.clone(),
-                        identity.clone(),
-                        self.graphics_state.reverse_orientation,
-                        params,
-                    )
-                    .unwrap();
+                let shapes = match self.graphics_state.make_shape(
+                    &name,
+                    identity.clone(),
+                    identity.clone(),
+                    self.graphics_state.reverse_orientation,
+                    params,
+                    self.render_options.transform_start_time,
+                    self.render_options.transform_end_time,
+                ) {
+                    Ok(shapes) => shapes,
+                    Err(err) => {
+                        self.report_error(&err);
+                        return;
+                    }
+                };
 
                 if shapes.is_empty() {
                     return;
                 }
 
                 // Create `GeometricPrimitive`(s) for animated shape.
-                let mtl = self.graphics_state.get_material_for_shape(params).unwrap();
+                let mtl = match self.graphics_state.get_material_for_shape(params) {
+                    Ok(mtl) => mtl,
+                    Err(err) => {
+                        self.report_error(&err);
+                        return;
+                    }
+                };
                 let mi = self.create_medium_interface();
 
                 for shape in shapes.iter() {
@@ -785,7 +1130,7 @@ impl Api {
                     self.render_options.transform_end_time,
                 );
                 if prims.len() > 1 {
-                    let bvh = BVHAccel::new(&prims, 1, SplitMethod::SAH);
+                    let bvh = BVHAccel::new(&prims, 1, SplitMethod::SAH, 2);
                     prims = vec![Arc::new(bvh)];
                 }
                 if prims.len() == 1 {
@@ -795,20 +1140,22 @@ impl Api {
                     ));
                     prims[0] = prim;
                 } else {
-                    error!("Error creating TransformedPrimitive in pbrtShape.");
+                    self.report_error("Error creating TransformedPrimitive in pbrtShape.");
                 }
             }
 
+            params.report_unused(&self.get_directive("Shape", &name));
+
             // Add `prims` and `area_lights` to scene or current instance.
-            if let Some(mut current_instance) = self.render_options.current_instance.clone() {
+            if let Some(current_instance) = self.render_options.current_instance.as_ref() {
                 if area_lights.len() > 0 {
                     warn!("Area lights not supported with object instancing.");
-                    Arc::get_mut(&mut current_instance)
-                        .unwrap()
-                        .append(&mut prims);
+                }
+                if let Some(instance) = self.render_options.instances.get_mut(current_instance) {
+                    Arc::make_mut(instance).append(&mut prims);
                 }
             } else {
-                self.render_options.current_instance = Some(Arc::new(prims));
+                self.render_options.primitives.append(&mut prims);
                 if area_lights.len() > 0 {
                     self.render_options.lights.append(&mut area_lights);
                 }
@@ -816,10 +1163,221 @@ impl Api {
         }
     }
 
+    /// Add the groups in an OBJ file as triangle meshes. The `materialmap`
+    /// parameter lists pairs of MTL material and named material names; groups
+    /// using a mapped MTL material use the named material instead. Other
+    /// materials in the MTL files referenced by the OBJ file are added as
+    /// named materials unless named materials with the same names are already
+    /// defined. Groups that don't use a material use the current material.
+    ///
+    /// * `params` - Shape parameters.
+    fn obj_mesh(&mut self, params: &ParamSet) {
+        if !self.verify_world("Shape") {
+            return;
+        }
+
+        let material_names = params.find_string("materialmap");
+        if material_names.len() % 2 != 0 {
+            self.report_error("Parameter 'materialmap' must be pairs of material names.");
+            return;
+        }
+        let material_map: HashMap<String, String> = material_names
+            .chunks_exact(2)
+            .map(|names| (names[0].clone(), names[1].clone()))
+            .collect();
+
+        let filename = params.find_one_filename("filename", String::new());
+        if filename.is_empty() {
+            self.report_error("OBJ file 'filename' not provided with objmesh shape.");
+            return;
+        }
+
+        let obj = match read_obj(&filename) {
+            Ok(obj) => obj,
+            Err(err) => {
+                self.report_error(&err);
+                return;
+            }
+        };
+
+        let dir = PathBuf::from(parent_path(&filename).unwrap_or_default());
+        if params.find_one_bool("materials", true) {
+            for mtllib in obj.mtllibs.iter() {
+                let path = dir.join(mtllib).to_string_lossy().into_owned();
+                match read_mtl(&path) {
+                    Ok(materials) => {
+                        for material in materials.iter() {
+                            if !material_map.contains_key(&material.name) {
+                                self.obj_material(material, &dir);
+                            }
+                        }
+                    }
+                    Err(err) => self.report_warning(&err),
+                }
+            }
+        }
+
+        for mesh in obj.meshes.iter() {
+            let mut mesh_params = params.clone();
+            mesh_params.erase_string("filename");
+            mesh_params.erase_bool("materials");
+            mesh_params.erase_string("materialmap");
+
+            let indices: Vec<Int> = mesh.indices.iter().map(|i| *i as Int).collect();
+            mesh_params.add_int("indices", &indices);
+            mesh_params.add_point3f("P", &mesh.p);
+            if !mesh.n.is_empty() {
+                mesh_params.add_normal3f("N", &mesh.n);
+            }
+            if !mesh.uv.is_empty() {
+                mesh_params.add_point2f("uv", &mesh.uv);
+            }
+
+            let current_material = self.graphics_state.current_material.clone();
+            if !mesh.material.is_empty() {
+                let material = material_map.get(&mesh.material).unwrap_or(&mesh.material);
+                match self.graphics_state.named_materials.get(material) {
+                    Some(mtl) => self.graphics_state.current_material = Some(mtl.clone()),
+                    None => self.report_warning(&format!(
+                        "Material '{}' used by OBJ group '{}' undefined. Using current material.",
+                        material, mesh.group
+                    )),
+                }
+            }
+
+            self.pbrt_shape(String::from("trianglemesh"), &mesh_params);
+
+            self.graphics_state.current_material = current_material;
+        }
+    }
+
+    /// Add a named material for an MTL material definition. Materials with
+    /// specular reflectance and an illumination model with highlights are
+    /// mapped to `plastic`; otherwise `matte`. The MTL colours and texture
+    /// maps are bound to the material as textures.
+    ///
+    /// * `material` - The MTL material.
+    /// * `dir`      - Directory used to resolve texture map files.
+    fn obj_material(&mut self, material: &OBJMaterial, dir: &PathBuf) {
+        if self
+            .graphics_state
+            .named_materials
+            .contains_key(&material.name)
+        {
+            return;
+        }
+
+        let tex2world = self.current_transforms[0].clone();
+        let constant_params = |value: &[Float]| {
+            let mut params = ParamSet::new();
+            if value.len() == 3 {
+                params.add_rgb_spectrum("value", value);
+            } else {
+                params.add_float("value", value);
+            }
+            TextureParams::new(params.clone(), params, HashMap::new(), HashMap::new())
+        };
+        let image_params = |map: &str| {
+            let path = dir.join(map).to_string_lossy().into_owned();
+            let mut params = ParamSet::new();
+            params.add_string("filename", &[path]);
+            TextureParams::new(params.clone(), params, HashMap::new(), HashMap::new())
+        };
+
+        let mut float_textures = FloatTextureMap::new();
+        let mut spectrum_textures = SpectrumTextureMap::new();
+        let kd = if material.map_kd.is_empty() {
+            GraphicsState::make_spectrum_texture(
+                "constant",
+                &tex2world,
+                &constant_params(&material.kd),
+            )
+        } else {
+            GraphicsState::make_spectrum_texture(
+                "imagemap",
+                &tex2world,
+                &image_params(&material.map_kd),
+            )
+        };
+        match kd {
+            Ok(kd) => {
+                spectrum_textures.insert(String::from("Kd"), kd);
+            }
+            Err(err) => self.report_warning(&err),
+        }
+
+        if !material.map_bump.is_empty() {
+            match GraphicsState::make_float_texture(
+                "imagemap",
+                &tex2world,
+                &image_params(&material.map_bump),
+            ) {
+                Ok(bump_map) => {
+                    float_textures.insert(String::from("bumpmap"), bump_map);
+                }
+                Err(err) => self.report_warning(&err),
+            }
+        }
+
+        let mut params = ParamSet::new();
+        let highlights = material.illum.map_or(true, |illum| illum >= 2);
+        if highlights && material.ks.iter().any(|ks| *ks > 0.0) {
+            params.add_string("type", &[String::from("plastic")]);
+            params.add_bool("remaproughness", &[false]);
+            if let Ok(ks) = GraphicsState::make_spectrum_texture(
+                "constant",
+                &tex2world,
+                &constant_params(&material.ks),
+            ) {
+                spectrum_textures.insert(String::from("Ks"), ks);
+            }
+
+            // Convert the Phong exponent to a microfacet distribution alpha.
+            let roughness = (2.0 / (material.ns.max(0.0) + 2.0)).sqrt();
+            if let Ok(roughness) = GraphicsState::make_float_texture(
+                "constant",
+                &tex2world,
+                &constant_params(&[roughness]),
+            ) {
+                float_textures.insert(String::from("roughness"), roughness);
+            }
+        } else {
+            params.add_string("type", &[String::from("matte")]);
+        }
+
+        let mat_name = params.find_one_string("type", String::new());
+        let mp = TextureParams::new(
+            ParamSet::new(),
+            params.clone(),
+            float_textures,
+            spectrum_textures,
+        );
+        match self
+            .graphics_state
+            .make_material(&mat_name, &mp, self.strict)
+        {
+            Ok(mtl) => {
+                if self.graphics_state.named_materials_shared {
+                    let nm = self.graphics_state.named_materials.clone();
+                    self.graphics_state.named_materials = nm;
+                    self.graphics_state.named_materials_shared = false;
+                }
+                let mtli = Arc::new(MaterialInstance::new(&mat_name, mtl, &params));
+                self.graphics_state
+                    .named_materials
+                    .insert(material.name.clone(), mtli);
+            }
+            Err(err) => self.report_error(&err),
+        }
+    }
+
     /// Reverse the orientation of surface normals for shapes that follow this
     /// directive.
     pub fn pbrt_reverse_orientation(&mut self) {
         if self.verify_world("ReverseOrientation") {
+            if let Some(writer) = &self.writer {
+                writer.directive("ReverseOrientation");
+            }
             self.graphics_state.reverse_orientation = !self.graphics_state.reverse_orientation;
         }
     }
@@ -829,16 +1387,22 @@ impl Api {
     /// * `name` - The object instance name.
     pub fn pbrt_object_begin(&mut self, name: String) {
         if self.verify_world("ObjectBegin") {
-            self.pbrt_attribute_begin();
+            self.push_graphics_state();
+
+            if let Some(writer) = self.writer.as_mut() {
+                writer.begin_block(&format!("ObjectBegin \"{}\"", name));
+                return;
+            }
 
             if let Some(_current_instance) = self.render_options.current_instance.clone() {
-                error!("ObjectBegin called inside of an instance definition.");
+                self.report_error("ObjectBegin called inside of an instance definition.");
             } else {
-                let new_instance: Arc<Vec<ArcPrimitive>> = Arc::new(vec![]);
+                // Instances from earlier frames can be redefined.
                 self.render_options
                     .instances
-                    .insert(name, new_instance.clone());
-                self.render_options.current_instance = Some(new_instance.clone());
+                    .insert(name.clone(), Arc::new(vec![]));
+                self.render_options.instance_keys.remove(&name);
+                self.render_options.current_instance = Some(name);
             }
         }
     }
@@ -846,12 +1410,20 @@ impl Api {
     /// End the definition of a named object instance.
     pub fn pbrt_object_end(&mut self) {
         if self.verify_world("ObjectEnd") {
-            if let Some(_current_instance) = self.render_options.current_instance.clone() {
-                error!("ObjectEnd called outside of instance definition.");
+            if self.writer.is_none() {
+                if self.render_options.current_instance.is_none() {
+                    self.report_error("ObjectEnd called outside of instance definition.");
+                }
+                self.render_options.current_instance = None;
             }
-            self.render_options.current_instance = None;
 
-            self.pbrt_attribute_end();
+            if self.pop_graphics_state() {
+                if let Some(writer) = self.writer.as_mut() {
+                    writer.end_block("ObjectEnd");
+                }
+            } else {
+                self.report_error("Unmatched pbrtObjectEnd() encountered. Ignoring it.");
+            }
         }
     }
 
@@ -860,12 +1432,27 @@ impl Api {
     /// * `name` - The object instance name.
     pub fn pbrt_object_instance(&mut self, name: String) {
         if self.verify_world("ObjectInstance") {
+            if let Some(writer) = self.writer.as_mut() {
+                writer.transform(&self.current_transforms);
+                writer.directive(&format!("ObjectInstance \"{}\"", name));
+                return;
+            }
+
             // Perform object instance error checking.
             if let Some(_current_instance) = self.render_options.current_instance.clone() {
-                error!("ObjectInstance can't be called inside of instance definition.");
+                self.report_error("ObjectInstance can't be called inside of instance definition.");
                 return;
             }
             if let Some(instance) = self.render_options.instances.get(&name).cloned() {
+                // Compute the key before the primitives are replaced with an
+                // aggregate.
+                let key = self
+                    .render_options
+                    .instance_keys
+                    .entry(name.clone())
+                    .or_insert_with(|| InstanceKey::new(&name, &instance))
+                    .clone();
+
                 let inst = match instance.len() {
                     0 => {
                         return;
@@ -876,11 +1463,20 @@ impl Api {
                         match GraphicsState::make_accelerator(
                             &self.render_options.accelerator_name,
                             &*instance,
-                            &self.render_options.accelerator_params,
+                            &self.render_options.nested_accelerator_params(),
+                            self.render_options.transform_start_time,
+                            self.render_options.transform_end_time,
                         ) {
-                            Ok(acc) => acc.clone(),
+                            Ok(acc) => {
+                                // Keep the aggregate for other instances and
+                                // later frames.
+                                self.render_options
+                                    .instances
+                                    .insert(name.clone(), Arc::new(vec![acc.clone()]));
+                                acc
+                            }
                             Err(err) => {
-                                error!("{}", err);
+                                self.report_error(&err);
                                 return;
                             }
                         }
@@ -905,22 +1501,101 @@ impl Api {
                     self.render_options.transform_end_time,
                 );
                 let prim = TransformedPrimitive::new(inst, animated_instance2world);
-                self.render_options.primitives.push(Arc::new(prim));
+                self.render_options
+                    .instanced_primitives
+                    .push((key, Arc::new(prim)));
             } else {
-                error!("Unable to find object instance named '{}'", name);
+                self.report_error(&format!("Unable to find object instance named '{}'", name));
             }
         }
     }
 
     /* Helpers */
 
+    /// Push the graphics state and transformations onto the stack.
+    fn push_graphics_state(&mut self) {
+        self.pushed_graphics_states
+            .push(self.graphics_state.clone());
+        self.graphics_state.float_textures_shared = true;
+        self.graphics_state.spectrum_textures_shared = true;
+        self.graphics_state.named_materials_shared = true;
+        self.pushed_transforms.push(self.current_transforms.clone());
+        self.pushed_active_transform_bits
+            .push(self.active_transform_bits);
+    }
+
+    /// Restore the graphics state and transformations from the stack.
+    /// Returns `false` if the stack is empty.
+    fn pop_graphics_state(&mut self) -> bool {
+        if let Some(graphics_state) = self.pushed_graphics_states.pop() {
+            self.graphics_state = graphics_state;
+            if let Some(transforms) = self.pushed_transforms.pop() {
+                self.current_transforms = transforms;
+            }
+            if let Some(active_transform_bits) = self.pushed_active_transform_bits.pop() {
+                self.active_transform_bits = active_transform_bits;
+            }
+            true
+        } else {
+            false
+        }
+    }
+
+    /// Returns a description of a directive for reports that includes the
+    /// location of the statement being processed, if known.
+    ///
+    /// * `directive` - Directive (e.g. `Shape`).
+    /// * `name`      - Name or type given to the directive.
+    fn get_directive(&self, directive: &str, name: &str) -> String {
+        match &self.location {
+            Some(location) => format!("{}: {} \"{}\"", location, directive, name),
+            None => format!("{} \"{}\"", directive, name),
+        }
+    }
+
+    /// Logs a warning with the location of the statement being processed.
+    ///
+    /// * `message` - The warning message.
+    fn report_warning(&self, message: &str) {
+        match &self.location {
+            Some(location) => warn!("{}: {}", location, message),
+            None => warn!("{}", message),
+        }
+    }
+
+    /// Reports an error at the location of the statement being processed.
+    /// In strict mode the first error is kept for the parser to abort with;
+    /// otherwise it is logged and processing continues.
+    ///
+    /// * `message` - The error message.
+    fn report_error(&self, message: &str) {
+        self.report_scene_error(SceneError::new(self.location.clone(), message));
+    }
+
+    /// Reports an error at its own location, such as the statement that set
+    /// an option used at `WorldEnd`. In strict mode the first error is kept
+    /// for the parser to abort with; otherwise it is logged.
+    ///
+    /// * `err` - The error.
+    fn report_scene_error(&self, err: SceneError) {
+        let mut first_error = self.first_error.borrow_mut();
+        if self.strict && first_error.is_none() {
+            *first_error = Some(err);
+        } else {
+            error!("{}", err);
+        }
+    }
+
     /// Returns `true` if the API state is initialized; otherwise it reports
     /// an error and returns `false`.
     ///
     /// * `func` - Function name to report.
     fn verify_initialized(&self, func: &str) -> bool {
         if self.current_api_state == ApiState::Uninitialized {
-            error!("pbrt_init() must be before calling '{}()'. Ignoring.", func);
+            self.report_error(&format!(
+                "pbrt_init() must be called before '{}'. Ignoring.",
+                func
+            ));
             false
         } else {
             true
@@ -935,11 +1610,10 @@ impl Api {
         if !self.verify_initialized(func) {
             false
         } else if self.current_api_state == ApiState::WorldBlock {
-            error!(
-                "Options cannot be set inside world block; 
-                '{}()' not allowed. Ignoring.",
+            self.report_error(&format!(
+                "Options cannot be set inside world block; '{}' not allowed. Ignoring.",
                 func
-            );
+            ));
             false
         } else {
             true
@@ -954,11 +1628,10 @@ impl Api {
         if !self.verify_initialized(func) {
             false
         } else if self.current_api_state == ApiState::OptionsBlock {
-            error!(
-                "Scene description must beinside world block; 
-                '{}()' not allowed. Ignoring.",
+            self.report_error(&format!(
+                "Scene description must be inside world block; '{}' not allowed. Ignoring.",
                 func
-            );
+            ));
             false
         } else {
             true
@@ -986,12 +1659,15 @@ impl Api {
         match name {
             Some(n) => {
                 if n.is_empty() {
-                    error!("Medium name is empty string for side '{}'.", side);
+                    self.report_error(&format!("Medium name is empty string for side '{}'.", side));
                     None
                 } else if let Some(medium) = self.render_options.named_media.get(&n) {
                     Some(medium.clone())
                 } else {
-                    error!("Named medium '{}' undefined for side '{}'.", n, side);
+                    self.report_error(&format!(
+                        "Named medium '{}' undefined for side '{}'.",
+                        n, side
+                    ));
                     None
                 }
             }
@@ -1010,3 +1686,364 @@ impl Api {
         MediumInterface::new(inside, outside)
     }
 }
+
+// ----------------------------------------------------------------------------
+// Tests
+// ----------------------------------------------------------------------------
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::core::parsers::*;
+
+    /// Returns an initialized API after parsing a scene description.
+    ///
+    /// * `scene` - The scene description.
+    fn parse(scene: &str) -> Api {
+        let mut api = Api::new();
+        api.pbrt_init();
+        PbrtFileParser::from_source_name("<test>")
+            .parse_reader(&mut scene.as_bytes(), &mut api)
+            .unwrap();
+        api
+    }
+
+    #[test]
+    fn film_params_reach_the_film() {
+        let api = parse(
+            r#"Film "image" "integer xresolution" [ 20 ] "integer yresolution" [ 10 ]
+               PixelFilter "gaussian" "float xwidth" [ 1 ] "float ywidth" [ 1 ]"#,
+        );
+        let render_options = &api.render_options;
+        assert_eq!(
+            render_options.film_params.find_one_int("xresolution", 0),
+            20
+        );
+        assert_eq!(
+            render_options.filter_params.find_one_float("xwidth", 0.0),
+            1.0
+        );
+        assert_eq!(
+            render_options.filter_params.find_one_int("xresolution", 0),
+            0
+        );
+
+        let camera = render_options.make_camera(&api.graphics_state).unwrap();
+        let film = camera.get_data().film.clone();
+        assert_eq!(film.full_resolution, Point2i::new(20, 10));
+    }
+
+    #[test]
+    fn unknown_materials_fall_back_to_matte() {
+        let api = parse(r#"WorldBegin Material "velvet" "float roughness" [ 0.5 ]"#);
+        assert!(api.take_error().is_none());
+        let material = api.graphics_state.current_material.as_ref().unwrap();
+        assert_eq!(material.name, "velvet");
+    }
+
+    #[test]
+    fn unknown_materials_are_errors_in_strict_mode() {
+        let mut api = Api::new();
+        api.strict = true;
+        api.pbrt_init();
+        let err = PbrtFileParser::from_source_name("<test>")
+            .parse_reader(&mut &b"WorldBegin\nMaterial \"velvet\""[..], &mut api)
+            .unwrap_err();
+        assert!(format!("{}", err).contains("Material 'velvet' unknown."));
+        let material = api.graphics_state.current_material.as_ref().unwrap();
+        assert_eq!(material.name, "matte");
+    }
+
+    /// Returns an initialized API in strict mode.
+    fn strict_api() -> Api {
+        let mut api = Api::new();
+        api.strict = true;
+        api.pbrt_init();
+        api
+    }
+
+    #[test]
+    fn render_option_errors_report_their_statement_in_strict_mode() {
+        for (scene, location, message) in [
+            (
+                "PixelFilter \"blob\"\nWorldBegin\nWorldEnd",
+                "<test>:1:1: ",
+                "Unable to create filter.",
+            ),
+            (
+                "\nFilm \"blob\"\nWorldBegin\nWorldEnd",
+                "<test>:2:1: ",
+                "Unable to create film.",
+            ),
+            (
+                "Integrator \"ambientocclusion\"\n  Camera \"blob\"\nWorldBegin\nWorldEnd",
+                "<test>:2:3: ",
+                "Unable to create camera.",
+            ),
+            (
+                "Integrator \"ambientocclusion\"\nSampler \"blob\"\nWorldBegin\nWorldEnd",
+                "<test>:2:1: ",
+                "Unable to create sampler.",
+            ),
+            (
+                "Integrator \"blob\"\nWorldBegin\nWorldEnd",
+                "<test>:1:1: ",
+                "Integrator 'blob' unknown.",
+            ),
+        ]
+        .iter()
+        {
+            let mut api = strict_api();
+            let err = PbrtFileParser::from_source_name("<test>")
+                .parse_reader(&mut scene.as_bytes(), &mut api)
+                .unwrap_err();
+            let err = format!("{}", err);
+            assert!(err.starts_with(location), "{}", err);
+            assert!(err.ends_with(message), "{}", err);
+        }
+    }
+
+    #[test]
+    fn render_option_errors_are_logged_outside_strict_mode() {
+        let api = parse("Sampler \"blob\"\nWorldBegin\nWorldEnd");
+        assert!(api.take_error().is_none());
+    }
+
+    #[test]
+    fn scene_descriptions_are_read_from_any_reader() {
+        let api = parse(r#"Camera "orthographic" Sampler "random" "integer pixelsamples" 4"#);
+        assert_eq!(api.render_options.camera_name, "orthographic");
+        assert_eq!(api.render_options.sampler_name, "random");
+    }
+
+    #[test]
+    fn reader_includes_are_relative_to_the_current_directory() {
+        let name = format!("stdin_include_{}.pbrt", std::process::id());
+        std::fs::write(
+            &name,
+            "Camera \"orthographic\"\nWorldBegin\nShape \"blob\"\n",
+        )
+        .unwrap();
+
+        let mut api = strict_api();
+        let scene = format!("\nInclude \"{}\"", name);
+        let result = PbrtFileParser::from_source_name("<stdin>")
+            .parse_reader(&mut scene.as_bytes(), &mut api);
+        let _ = std::fs::remove_file(&name);
+
+        // The include is found and errors in it report the include chain.
+        assert_eq!(api.render_options.camera_name, "orthographic");
+        let err = format!("{}", result.unwrap_err());
+        assert!(err.contains(&format!("{}:3:", name)), "{}", err);
+        assert!(err.contains("Shape 'blob' unknown."), "{}", err);
+        assert!(err.contains("included from <stdin>:2:"), "{}", err);
+    }
+
+    #[test]
+    fn reader_errors_report_the_source_name() {
+        let mut api = strict_api();
+        let err = PbrtFileParser::from_source_name("<stdin>")
+            .parse_reader(&mut &b"WorldBegin\n  Shape \"blob\""[..], &mut api)
+            .unwrap_err();
+        assert!(format!("{}", err).starts_with("<stdin>:2:3: "), "{}", err);
+
+        struct Failing;
+        impl std::io::Read for Failing {
+            fn read(&mut self, _buf: &mut [u8]) -> std::io::Result<usize> {
+                Err(std::io::Error::new(
+                    std::io::ErrorKind::Other,
+                    "broken pipe",
+                ))
+            }
+        }
+        match PbrtFileParser::from_source_name("<stdin>").parse_reader(&mut Failing, &mut api) {
+            Err(ParseError::Io { path, message }) => {
+                assert_eq!(path, "<stdin>");
+                assert!(message.contains("broken pipe"));
+            }
+            _ => panic!("expected an I/O error"),
+        }
+    }
+
+    #[test]
+    fn cleanup_inside_the_world_block_is_an_error() {
+        let mut api = strict_api();
+        api.pbrt_world_begin();
+        api.pbrt_cleanup();
+        let err = api.take_error().unwrap();
+        assert!(format!("{}", err).contains("pbrt_cleanup() called while inside world block."));
+        assert!(api.current_api_state == ApiState::Uninitialized);
+    }
+
+    #[test]
+    fn end_of_input_ends_an_open_world_block() {
+        // The unknown integrator keeps the scene from being rendered.
+        let mut api = parse(r#"Integrator "none" WorldBegin"#);
+        assert!(api.current_api_state == ApiState::WorldBlock);
+        api.pbrt_end_of_input();
+        assert!(api.current_api_state == ApiState::OptionsBlock);
+
+        // Scenes that end the world block are left alone.
+        api.pbrt_end_of_input();
+        assert!(api.current_api_state == ApiState::OptionsBlock);
+        api.pbrt_cleanup();
+        assert!(api.take_error().is_none());
+    }
+
+    #[test]
+    fn empty_medium_names_are_errors() {
+        let mut api = strict_api();
+        let err = PbrtFileParser::from_source_name("<test>")
+            .parse_reader(
+                &mut &b"WorldBegin MediumInterface \"\" \"\" Shape \"sphere\""[..],
+                &mut api,
+            )
+            .unwrap_err();
+        assert!(
+            format!("{}", err).contains("Medium name is empty string for side 'inside'."),
+            "{}",
+            err
+        );
+    }
+
+    #[test]
+    fn obj_meshes_use_mapped_and_converted_mtl_materials() {
+        let dir = std::env::temp_dir();
+        let obj = dir.join(format!("objmesh_{}.obj", std::process::id()));
+        let mtl = dir.join(format!("objmesh_{}.mtl", std::process::id()));
+        std::fs::write(
+            &obj,
+            format!(
+                "mtllib {}\nv 0 0 0\nv 1 0 0\nv 0 1 0\n\
+                 usemtl red\nf 1 2 3\nusemtl blue\nf 3 2 1\nusemtl dull\nf 2 3 1\n",
+                mtl.file_name().unwrap().to_str().unwrap()
+            ),
+        )
+        .unwrap();
+        std::fs::write(
+            &mtl,
+            "newmtl red\nKd 1 0 0\nnewmtl blue\nKd 0 0 1\nKs 0.5\n\
+             newmtl dull\nKs 0.5\nillum 1\n",
+        )
+        .unwrap();
+
+        // The filename is resolved to an absolute path.
+        let dotted = dir.join(".").join(obj.file_name().unwrap());
+        let scene = format!(
+            r#"WorldBegin MakeNamedMaterial "shiny" "string type" "plastic"
+               Shape "objmesh" "string filename" "{}" "string materialmap" [ "red" "shiny" ]"#,
+            dotted.to_str().unwrap()
+        );
+        let mut api = strict_api();
+        let result = PbrtFileParser::from_source_name("<test>")
+            .parse_reader(&mut scene.as_bytes(), &mut api);
+        std::fs::remove_file(&obj).unwrap();
+        std::fs::remove_file(&mtl).unwrap();
+        result.unwrap();
+
+        // Mapped MTL materials aren't converted.
+        let named_materials = &api.graphics_state.named_materials;
+        assert!(!named_materials.contains_key("red"));
+        assert_eq!(named_materials["shiny"].name, "plastic");
+        assert_eq!(named_materials["blue"].name, "plastic");
+        assert_eq!(named_materials["dull"].name, "matte");
+
+        let materials: Vec<crate::core::material::ArcMaterial> = api
+            .render_options
+            .primitives
+            .iter()
+            .map(|p| p.get_material().unwrap())
+            .collect();
+        assert_eq!(materials.len(), 3);
+        for (material, name) in materials.iter().zip(["shiny", "blue", "dull"].iter()) {
+            assert!(Arc::ptr_eq(material, &named_materials[*name].material));
+        }
+    }
+
+    #[test]
+    fn obj_material_maps_must_be_pairs() {
+        let mut api = strict_api();
+        let err = PbrtFileParser::from_source_name("<test>")
+            .parse_reader(
+                &mut &br#"WorldBegin Shape "objmesh" "string filename" "a.obj" "string materialmap" "red""#[..],
+                &mut api,
+            )
+            .unwrap_err();
+        assert!(
+            format!("{}", err).contains("Parameter 'materialmap' must be pairs of material names."),
+            "{}",
+            err
+        );
+    }
+
+    #[test]
+    fn instance_accel_is_reused_for_the_same_objects() {
+        let frame = |radius: Float, x: Float| {
+            format!(
+                "WorldBegin\n\
+                 ObjectBegin \"ball\" Shape \"sphere\" \"float radius\" [ {} ] ObjectEnd\n\
+                 Translate {} 0 0 ObjectInstance \"ball\"\n\
+                 Translate 0 3 0 ObjectInstance \"ball\"\n\
+                 WorldEnd\n",
+                radius, x
+            )
+        };
+        let mut api = parse(&frame(1.0, 0.0));
+        let accel = api.render_options.instance_accel.clone().unwrap();
+        let first = Arc::as_ptr(&accel);
+        let hash = accel.keys[0].geometry_hash;
+        drop(accel);
+
+        // The object is defined again with the same geometry; only the
+        // instance transforms change so the accelerator is refit.
+        let mut parser = PbrtFileParser::from_source_name("<test>");
+        parser
+            .parse_reader(&mut frame(1.0, 5.0).as_bytes(), &mut api)
+            .unwrap();
+        let accel = api.render_options.instance_accel.clone().unwrap();
+        assert_eq!(Arc::as_ptr(&accel), first);
+        assert_eq!(accel.keys[0].geometry_hash, hash);
+        assert!(accel.world_bound().p_max.x > 5.9);
+        drop(accel);
+
+        // Different geometry under the same name needs a new accelerator.
+        parser
+            .parse_reader(&mut frame(2.0, 5.0).as_bytes(), &mut api)
+            .unwrap();
+        let accel = api.render_options.instance_accel.clone().unwrap();
+        assert_ne!(accel.keys[0].geometry_hash, hash);
+        assert!(accel.world_bound().p_max.x > 6.9);
+    }
+
+    #[test]
+    fn instanced_scenes_only_cache_the_scene_aggregate() {
+        use std::os::unix::fs::MetadataExt;
+
+        let path =
+            std::env::temp_dir().join(format!("pbr-rust-instanced-{}.cache", std::process::id()));
+        let _ = std::fs::remove_file(&path);
+        let scene = format!(
+            "Accelerator \"bvh\" \"string cachefile\" \"{}\"\n\
+             WorldBegin\n\
+             ObjectBegin \"pair\"\n\
+             Shape \"sphere\" Translate 3 0 0 Shape \"sphere\"\n\
+             ObjectEnd\n\
+             ObjectInstance \"pair\"\n\
+             Translate 0 3 0 ObjectInstance \"pair\"\n\
+             Translate 0 3 0 Shape \"sphere\"\n\
+             WorldEnd\n",
+            path.display()
+        );
+
+        parse(&scene);
+        let written = std::fs::metadata(&path).unwrap();
+
+        // Cache files are replaced when they are written so the second build
+        // must have loaded the file written by the first.
+        parse(&scene);
+        let loaded = std::fs::metadata(&path).unwrap();
+        std::fs::remove_file(&path).unwrap();
+        assert_eq!(written.ino(), loaded.ino());
+        assert_eq!(written.mtime_nsec(), loaded.mtime_nsec());
+    }
+}
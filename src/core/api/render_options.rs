@@ -1,7 +1,10 @@
 //! Render options
 
 #![allow(dead_code)]
+use super::graphics_state::*;
+use super::scene_error::*;
 use super::transform_set::*;
+use crate::accelerators::*;
 use crate::core::camera::*;
 use crate::core::integrator::*;
 use crate::core::light::*;
@@ -9,7 +12,9 @@ use crate::core::medium::*;
 use crate::core::paramset::*;
 use crate::core::pbrt::*;
 use crate::core::primitive::*;
+use crate::core::primitives::*;
 use crate::core::scene::*;
+use crate::integrators::*;
 use std::collections::HashMap;
 use std::sync::Arc;
 
@@ -28,30 +33,45 @@ pub struct RenderOptions {
     /// Filter parameters.
     pub filter_params: ParamSet,
 
+    /// Location of the `PixelFilter` statement used to report errors.
+    pub filter_location: Option<SourceLocation>,
+
     /// Film name.
     pub film_name: String,
 
     /// Film parameters.
     pub film_params: ParamSet,
 
+    /// Location of the `Film` statement used to report errors.
+    pub film_location: Option<SourceLocation>,
+
     /// Sampler name.
     pub sampler_name: String,
 
     /// Sampler parameters.
     pub sampler_params: ParamSet,
 
+    /// Location of the `Sampler` statement used to report errors.
+    pub sampler_location: Option<SourceLocation>,
+
     /// Accelerator name.
     pub accelerator_name: String,
 
     /// Accelerator parameters.
     pub accelerator_params: ParamSet,
 
+    /// Location of the `Accelerator` statement used to report errors.
+    pub accelerator_location: Option<SourceLocation>,
+
     /// Integrator name.
     pub integrator_name: String,
 
     /// Integrator parameters.
     pub integrator_params: ParamSet,
 
+    /// Location of the `Integrator` statement used to report errors.
+    pub integrator_location: Option<SourceLocation>,
+
     /// Camera name.
     pub camera_name: String,
 
@@ -61,6 +81,9 @@ pub struct RenderOptions {
     /// Camera to world transformation.
     pub camera_to_world: TransformSet,
 
+    /// Location of the `Camera` statement used to report errors.
+    pub camera_location: Option<SourceLocation>,
+
     /// Named media.
     pub named_media: HashMap<String, ArcMedium>,
 
@@ -70,11 +93,25 @@ pub struct RenderOptions {
     /// Primitives.
     pub primitives: Vec<ArcPrimitive>,
 
-    /// Object instances (each is a collection of primitives).
+    /// Object instances (each is a collection of primitives). Instances are
+    /// kept for later frames so they don't need to be defined again.
     pub instances: HashMap<String, Arc<Vec<ArcPrimitive>>>,
 
-    /// Current instance (a collection of primitives).
-    pub current_instance: Option<Arc<Vec<ArcPrimitive>>>,
+    /// Name of the object instance being defined.
+    pub current_instance: Option<String>,
+
+    /// Keys of object instances computed when they are first added to the
+    /// scene.
+    pub instance_keys: HashMap<String, InstanceKey>,
+
+    /// Object instances added to the scene and keys of the object instances
+    /// they reference.
+    pub instanced_primitives: Vec<(InstanceKey, Arc<TransformedPrimitive>)>,
+
+    /// Top-level acceleration structure over the object instances of the
+    /// last frame. It is refit instead of rebuilt if the next frame uses
+    /// the same instances with different transforms.
+    pub instance_accel: Option<Arc<InstanceAccel>>,
 
     /// Is there scattering media in the scene.
     pub have_scattering_media: bool,
@@ -88,38 +125,227 @@ impl RenderOptions {
             transform_end_time: 1.0,
             filter_name: String::from("box"),
             filter_params: ParamSet::new(),
+            filter_location: None,
             film_name: String::from("image"),
             film_params: ParamSet::new(),
+            film_location: None,
             sampler_name: String::from("halton"),
             sampler_params: ParamSet::new(),
+            sampler_location: None,
             accelerator_name: String::from("bvh"),
             accelerator_params: ParamSet::new(),
+            accelerator_location: None,
             integrator_name: String::from("path"),
             integrator_params: ParamSet::new(),
+            integrator_location: None,
             camera_name: String::from("perspective"),
             camera_params: ParamSet::new(),
             camera_to_world: TransformSet::default(),
+            camera_location: None,
             named_media: HashMap::new(),
             lights: vec![],
             primitives: vec![],
             instances: HashMap::new(),
             current_instance: None,
+            instance_keys: HashMap::new(),
+            instanced_primitives: vec![],
+            instance_accel: None,
             have_scattering_media: false,
         }
     }
 
-    /// Returns an `Integrator` based on the render options.
-    pub fn make_integrator(&self) -> ArcIntegrator {
-        todo!();
+    /// Returns an `Integrator` based on the render options. Returns an
+    /// error at the location of the responsible statement if the camera,
+    /// sampler or integrator can't be created.
+    ///
+    /// * `graphics_state` - The graphics state.
+    pub fn make_integrator(
+        &self,
+        graphics_state: &GraphicsState,
+    ) -> Result<ArcIntegrator, SceneError> {
+        let camera = self.make_camera(graphics_state)?;
+
+        let sampler = GraphicsState::make_sampler(
+            &self.sampler_name,
+            &self.sampler_params,
+            camera.get_data().film.clone(),
+        )
+        .map_err(|err| {
+            SceneError::new(
+                self.sampler_location.clone(),
+                &format!("{} Unable to create sampler.", err),
+            )
+        })?;
+
+        let p = (&self.integrator_params, sampler, camera);
+        let integrator: ArcIntegrator = match self.integrator_name.as_str() {
+            "ambientocclusion" => Arc::new(AOIntegrator::from(p)),
+            _ => {
+                return Err(SceneError::new(
+                    self.integrator_location.clone(),
+                    &format!("Integrator '{}' unknown.", self.integrator_name),
+                ))
+            }
+        };
+
+        self.integrator_params
+            .report_unused(&format!("Integrator \"{}\"", self.integrator_name));
+        Ok(integrator)
     }
 
-    /// Returns a `Scene` based on the render options.
-    pub fn make_scene(&self) -> Arc<Scene> {
-        todo!();
+    /// Returns the accelerator parameters for acceleration structures that
+    /// aren't the scene aggregate, such as those of object instances. The
+    /// cache file is removed since all of them would share it and overwrite
+    /// each other's cache.
+    pub fn nested_accelerator_params(&self) -> ParamSet {
+        let mut params = self.accelerator_params.clone();
+        params.erase_string("cachefile");
+        params
     }
 
-    /// Returns a `Camera` based on the render options.
-    pub fn make_camera(&self) -> ArcCamera {
-        todo!();
+    /// Returns a `Scene` based on the render options. Object instances go
+    /// into a separate top-level acceleration structure that is reused by
+    /// the next frame if only the instance transforms change. Primitives and
+    /// lights are moved into the scene. If the accelerator can't be created
+    /// a BVH is used and the error is returned with the scene.
+    pub fn make_scene(&mut self) -> (Arc<Scene>, Option<SceneError>) {
+        let mut prims = std::mem::take(&mut self.primitives);
+
+        let instances = std::mem::take(&mut self.instanced_primitives);
+        if instances.is_empty() {
+            self.instance_accel = None;
+        } else {
+            let instance_accel = match self.instance_accel.take() {
+                Some(mut accel) if accel.can_update(&instances) => {
+                    Arc::make_mut(&mut accel).update(instances);
+                    accel
+                }
+                _ => {
+                    // The top-level acceleration structure is always a BVH
+                    // so it can be refit. It is only cached if it is also
+                    // the scene aggregate.
+                    let params = if self.accelerator_name != "bvh" {
+                        ParamSet::new()
+                    } else if prims.is_empty() {
+                        self.accelerator_params.clone()
+                    } else {
+                        self.nested_accelerator_params()
+                    };
+                    Arc::new(InstanceAccel::new(
+                        instances,
+                        &params,
+                        self.transform_start_time,
+                        self.transform_end_time,
+                    ))
+                }
+            };
+            self.instance_accel = Some(instance_accel.clone());
+            prims.push(instance_accel);
+        }
+
+        let mut error = None;
+        let aggregate = if prims.len() == 1 && self.instance_accel.is_some() {
+            prims[0].clone()
+        } else {
+            match GraphicsState::make_accelerator(
+                &self.accelerator_name,
+                &prims,
+                &self.accelerator_params,
+                self.transform_start_time,
+                self.transform_end_time,
+            ) {
+                Ok(accel) => accel,
+                Err(err) => {
+                    error = Some(SceneError::new(self.accelerator_location.clone(), &err));
+                    Arc::new(BVHAccel::new(&prims, 4, SplitMethod::SAH, 2))
+                }
+            }
+        };
+
+        let scene = Arc::new(Scene::new(aggregate, std::mem::take(&mut self.lights)));
+        (scene, error)
+    }
+
+    /// Returns a `Camera` based on the render options. Returns an error at
+    /// the location of the responsible statement if the filter, film or
+    /// camera can't be created.
+    ///
+    /// * `graphics_state` - The graphics state.
+    pub fn make_camera(&self, graphics_state: &GraphicsState) -> Result<ArcCamera, SceneError> {
+        let error = |location: &Option<SourceLocation>, err: String, what: &str| {
+            SceneError::new(
+                location.clone(),
+                &format!("{} Unable to create {}.", err, what),
+            )
+        };
+
+        let filter = GraphicsState::make_filter(&self.filter_name, &self.filter_params)
+            .map_err(|err| error(&self.filter_location, err, "filter"))?;
+
+        let film = graphics_state
+            .make_film(&self.film_name, &self.film_params, filter)
+            .map_err(|err| error(&self.film_location, err, "film"))?;
+
+        graphics_state
+            .make_camera(
+                &self.camera_name,
+                &self.camera_params,
+                &self.camera_to_world,
+                self.transform_start_time,
+                self.transform_end_time,
+                film,
+                &MediumInterface::new(None, None),
+            )
+            .map_err(|err| error(&self.camera_location, err, "camera"))
+    }
+}
+
+// ----------------------------------------------------------------------------
+// Tests
+// ----------------------------------------------------------------------------
+
+#[cfg(test)]
+mod tests {
+    use super::super::transform_cache::*;
+    use super::*;
+    use std::sync::Mutex;
+
+    fn render_options(integrator_name: &str) -> RenderOptions {
+        let mut render_options = RenderOptions::new();
+        render_options.integrator_name = String::from(integrator_name);
+        render_options.sampler_name = String::from("random");
+        render_options
+    }
+
+    #[test]
+    fn make_integrator_creates_named_integrator() {
+        let graphics_state = GraphicsState::new(Arc::new(Mutex::new(TransformCache::default())));
+        assert!(render_options("ambientocclusion")
+            .make_integrator(&graphics_state)
+            .is_ok());
+    }
+
+    #[test]
+    fn make_integrator_rejects_unknown_names() {
+        let graphics_state = GraphicsState::new(Arc::new(Mutex::new(TransformCache::default())));
+        let location = |line| Some(SourceLocation::new("<test>", line, 1, None));
+        let mut render_options = render_options("unknown");
+        render_options.integrator_location = location(1);
+        let err = render_options
+            .make_integrator(&graphics_state)
+            .err()
+            .unwrap();
+        assert_eq!(err.location, location(1));
+        assert_eq!(err.message, "Integrator 'unknown' unknown.");
+
+        render_options.integrator_name = String::from("ambientocclusion");
+        render_options.camera_name = String::from("unknown");
+        render_options.camera_location = location(2);
+        let err = render_options
+            .make_integrator(&graphics_state)
+            .err()
+            .unwrap();
+        assert_eq!(err.location, location(2));
+        assert!(err.message.ends_with("Unable to create camera."));
     }
 }
@@ -0,0 +1,92 @@
+//! Scene Errors
+
+use std::fmt;
+use std::sync::Arc;
+
+/// Location of a statement in a scene description file.
+#[derive(Clone, Debug, PartialEq)]
+pub struct SourceLocation {
+    /// Path to the scene description file.
+    pub file: String,
+
+    /// Line number starting at 1.
+    pub line: usize,
+
+    /// Column number starting at 1.
+    pub column: usize,
+
+    /// Location of the `Include` statement for the file if it was included
+    /// by another file.
+    pub included_from: Option<Arc<SourceLocation>>,
+}
+
+impl SourceLocation {
+    /// Create a new `SourceLocation`.
+    ///
+    /// * `file`          - Path to the scene description file.
+    /// * `line`          - Line number starting at 1.
+    /// * `column`        - Column number starting at 1.
+    /// * `included_from` - Location of the `Include` statement for the file.
+    pub fn new(
+        file: &str,
+        line: usize,
+        column: usize,
+        included_from: Option<Arc<SourceLocation>>,
+    ) -> Self {
+        Self {
+            file: String::from(file),
+            line,
+            column,
+            included_from,
+        }
+    }
+}
+
+impl fmt::Display for SourceLocation {
+    /// Formats the location as `file:line:col`.
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "{}:{}:{}", self.file, self.line, self.column)
+    }
+}
+
+/// An error in the scene description reported by the API.
+#[derive(Clone, Debug)]
+pub struct SceneError {
+    /// Location of the statement that caused the error if known.
+    pub location: Option<SourceLocation>,
+
+    /// The error message.
+    pub message: String,
+}
+
+impl SceneError {
+    /// Create a new `SceneError`.
+    ///
+    /// * `location` - Location of the statement that caused the error.
+    /// * `message`  - The error message.
+    pub fn new(location: Option<SourceLocation>, message: &str) -> Self {
+        Self {
+            location,
+            message: String::from(message),
+        }
+    }
+}
+
+impl fmt::Display for SceneError {
+    /// Formats the error as `file:line:col: message` followed by the chain
+    /// of `Include` statements.
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match &self.location {
+            Some(location) => {
+                write!(f, "{}: {}", location, self.message)?;
+                let mut included_from = location.included_from.clone();
+                while let Some(include) = included_from {
+                    write!(f, "\n    included from {}", include)?;
+                    included_from = include.included_from.clone();
+                }
+                Ok(())
+            }
+            None => write!(f, "{}", self.message),
+        }
+    }
+}
@@ -0,0 +1,604 @@
+//! Scene Writer
+
+#![allow(dead_code)]
+use super::transform_set::*;
+use crate::core::geometry::*;
+use crate::core::paramset::*;
+use crate::core::pbrt::*;
+use crate::core::ply::*;
+use crate::core::spectrum::*;
+use std::cell::{Cell, RefCell};
+use std::fmt;
+use std::fs::File;
+use std::io::{BufWriter, Write};
+use std::path::PathBuf;
+
+/// Number of spaces used to indent blocks and parameters.
+const INDENT: usize = 4;
+
+/// Maximum length of a line of parameter values before wrapping.
+const MAX_LINE_LENGTH: usize = 80;
+
+/// Writes the directives processed by the API as a pbrt scene description.
+/// Included files are written inline and transformations are written as
+/// resolved transformation matrices before the directives that use them.
+pub struct SceneWriter {
+    /// Output for the scene description.
+    out: RefCell<Box<dyn Write>>,
+
+    /// Set when writing to the output fails so the error is reported once.
+    failed: Cell<bool>,
+
+    /// Move triangle meshes into PLY files.
+    to_ply: bool,
+
+    /// Directory for the PLY files.
+    ply_dir: PathBuf,
+
+    /// Current indentation.
+    indent: usize,
+
+    /// Transformations in effect for a reader of the output.
+    transforms: TransformSet,
+
+    /// Used as a stack for the transformations at the start of blocks.
+    pushed_transforms: Vec<TransformSet>,
+
+    /// Number of PLY files written.
+    n_ply_files: usize,
+}
+
+impl SceneWriter {
+    /// Returns a new `SceneWriter`.
+    ///
+    /// * `out`     - Output for the scene description.
+    /// * `to_ply`  - Move triangle meshes into PLY files.
+    /// * `ply_dir` - Directory for the PLY files.
+    pub fn new(out: Box<dyn Write>, to_ply: bool, ply_dir: PathBuf) -> Self {
+        Self {
+            out: RefCell::new(out),
+            failed: Cell::new(false),
+            to_ply,
+            ply_dir,
+            indent: 0,
+            transforms: TransformSet::default(),
+            pushed_transforms: vec![],
+            n_ply_files: 0,
+        }
+    }
+
+    /// Returns a new `SceneWriter` that writes to a file or standard output.
+    /// PLY files are written to the directory of the output file.
+    ///
+    /// * `path`   - Output file path or an empty string for standard output.
+    /// * `to_ply` - Move triangle meshes into PLY files.
+    pub fn create(path: &str, to_ply: bool) -> Result<Self, String> {
+        if path.is_empty() {
+            return Ok(Self::new(
+                Box::new(std::io::stdout()),
+                to_ply,
+                PathBuf::new(),
+            ));
+        }
+
+        let file =
+            File::create(path).map_err(|err| format!("Unable to create {}. {}.", path, err))?;
+        let ply_dir = PathBuf::from(path)
+            .parent()
+            .map_or(PathBuf::new(), |dir| dir.to_path_buf());
+        Ok(Self::new(Box::new(BufWriter::new(file)), to_ply, ply_dir))
+    }
+
+    /// Write a line of output. Only the first error is reported.
+    ///
+    /// * `indent` - Number of spaces to indent the line.
+    /// * `text`   - The text.
+    fn write_line(&self, indent: usize, text: &str) {
+        let mut out = self.out.borrow_mut();
+        if let Err(err) = writeln!(out, "{:indent$}{}", "", text, indent = indent) {
+            if !self.failed.replace(true) {
+                error!("Error writing scene description. {}.", err);
+            }
+        }
+    }
+
+    /// Flush the output.
+    pub fn flush(&self) {
+        if let Err(err) = self.out.borrow_mut().flush() {
+            if !self.failed.replace(true) {
+                error!("Error writing scene description. {}.", err);
+            }
+        }
+    }
+
+    /// Write a directive without parameters.
+    ///
+    /// * `text` - The directive and its arguments.
+    pub fn directive(&self, text: &str) {
+        self.write_line(self.indent, text);
+    }
+
+    /// Write a directive with a type or name and parameters.
+    ///
+    /// * `directive` - The directive (e.g. `Shape`).
+    /// * `name`      - The type or name.
+    /// * `params`    - The parameters.
+    pub fn named(&self, directive: &str, name: &str, params: &ParamSet) {
+        self.directive(&format!("{} \"{}\"", directive, name));
+        self.params(params);
+    }
+
+    /// Write a `Texture` directive.
+    ///
+    /// * `name`         - Texture name.
+    /// * `texture_type` - Texture type (float or spectrum).
+    /// * `tex_name`     - Texture class (bilerp, checkerboard, etc).
+    /// * `params`       - Texture parameters.
+    pub fn texture(&self, name: &str, texture_type: &str, tex_name: &str, params: &ParamSet) {
+        self.directive(&format!(
+            "Texture \"{}\" \"{}\" \"{}\"",
+            name, texture_type, tex_name
+        ));
+        self.params(params);
+    }
+
+    /// Write a `Shape` directive. Triangle meshes are moved into PLY files
+    /// and written as `plymesh` shapes if requested. The PLY file names are
+    /// relative to the current directory like the output file's.
+    ///
+    /// * `name`   - Shape type.
+    /// * `params` - Shape parameters.
+    pub fn shape(&mut self, name: &str, params: &ParamSet) -> Result<(), String> {
+        if self.to_ply && name == "trianglemesh" {
+            if let Some(mesh) = get_ply_mesh(params) {
+                self.n_ply_files += 1;
+                let filename = self
+                    .ply_dir
+                    .join(format!("mesh_{:05}.ply", self.n_ply_files))
+                    .to_string_lossy()
+                    .into_owned();
+                write_ply(&filename, &mesh)?;
+
+                let mut ply_params = params.clone();
+                ply_params.erase_point3f("P");
+                ply_params.erase_normal3f("N");
+                ply_params.erase_point2f("uv");
+                ply_params.erase_point2f("st");
+                ply_params.erase_float("uv");
+                ply_params.erase_float("st");
+                ply_params.erase_int("indices");
+                ply_params.erase_int("faceIndices");
+                ply_params.add_string("filename", &[filename]);
+                self.named("Shape", "plymesh", &ply_params);
+                return Ok(());
+            }
+        }
+        self.named("Shape", name, params);
+        Ok(())
+    }
+
+    /// Write the start of a block and indent the directives that follow.
+    ///
+    /// * `text` - The directive starting the block and its arguments.
+    pub fn begin_block(&mut self, text: &str) {
+        self.directive(text);
+        self.indent += INDENT;
+        self.pushed_transforms.push(self.transforms.clone());
+    }
+
+    /// Write the end of a block.
+    ///
+    /// * `text` - The directive ending the block.
+    pub fn end_block(&mut self, text: &str) {
+        self.indent = self.indent.saturating_sub(INDENT);
+        if let Some(transforms) = self.pushed_transforms.pop() {
+            self.transforms = transforms;
+        }
+        self.directive(text);
+    }
+
+    /// Write `WorldBegin` which resets the transformations to identity.
+    pub fn world_begin(&mut self) {
+        self.directive("WorldBegin");
+        self.transforms.reset();
+    }
+
+    /// Write `WorldEnd` and reset the state for the next scene.
+    pub fn world_end(&mut self) {
+        self.indent = 0;
+        self.pushed_transforms.clear();
+        self.directive("WorldEnd");
+        self.transforms.reset();
+    }
+
+    /// Write the current transformations if they aren't the ones in effect
+    /// for a reader of the output. Animated transformations are written for
+    /// the starting and ending times separately.
+    ///
+    /// * `transforms` - The current transformations.
+    pub fn transform(&mut self, transforms: &TransformSet) {
+        let changed: Vec<usize> = (0..MAX_TRANSFORMS)
+            .filter(|i| *transforms[*i] != *self.transforms[*i])
+            .collect();
+        if changed.is_empty() {
+            return;
+        }
+
+        if changed.len() == MAX_TRANSFORMS && !transforms.is_animated() {
+            self.directive(&format!("Transform {}", format_matrix(&transforms[0])));
+        } else {
+            for i in changed.iter() {
+                let time = if *i == 0 { "StartTime" } else { "EndTime" };
+                self.directive(&format!("ActiveTransform {}", time));
+                self.directive(&format!("Transform {}", format_matrix(&transforms[*i])));
+            }
+            self.directive("ActiveTransform All");
+        }
+        self.transforms = transforms.clone();
+    }
+
+    /// Write parameters one per line in a fixed order.
+    ///
+    /// * `params` - The parameters.
+    pub fn params(&self, params: &ParamSet) {
+        let indent = self.indent + INDENT;
+        let mut lines: Vec<String> = vec![];
+        lines.extend(format_params("bool", &params.bools, |v| {
+            format!("\"{}\"", v)
+        }));
+        lines.extend(format_params("integer", &params.ints, |v| v.to_string()));
+        lines.extend(format_params("float", &params.floats, |v| v.to_string()));
+        lines.extend(format_params("point2", &params.point2fs, |v| {
+            format!("{} {}", v.x, v.y)
+        }));
+        lines.extend(format_params("vector2", &params.vector2fs, |v| {
+            format!("{} {}", v.x, v.y)
+        }));
+        lines.extend(format_params("point3", &params.point3fs, |v| {
+            format!("{} {} {}", v.x, v.y, v.z)
+        }));
+        lines.extend(format_params("vector3", &params.vector3fs, |v| {
+            format!("{} {} {}", v.x, v.y, v.z)
+        }));
+        lines.extend(format_params("normal", &params.normal3fs, |v| {
+            format!("{} {} {}", v.x, v.y, v.z)
+        }));
+        lines.extend(format_spectra(params));
+        lines.extend(format_params("string", &params.strings, |v| {
+            format!("\"{}\"", v)
+        }));
+        lines.extend(format_params("texture", &params.textures, |v| {
+            format!("\"{}\"", v)
+        }));
+
+        for line in lines.iter() {
+            for (i, part) in line.split('\n').enumerate() {
+                let extra = if i == 0 { 0 } else { INDENT };
+                self.write_line(indent + extra, part);
+            }
+        }
+    }
+}
+
+/// Returns a triangle mesh from `trianglemesh` parameters or `None` if the
+/// parameters can't be represented by a PLY file.
+///
+/// * `params` - Triangle mesh parameters.
+fn get_ply_mesh(params: &ParamSet) -> Option<PLYMesh> {
+    if params.vector3fs.contains_key("S") || params.point3fs.contains_key("Pmotion") {
+        return None;
+    }
+
+    let p = params.find_point3f("P");
+    let n = params.find_normal3f("N");
+
+    let mut uv = params.find_point2f("uv");
+    if uv.is_empty() {
+        uv = params.find_point2f("st");
+    }
+    if uv.is_empty() {
+        let mut fuv = params.find_float("uv");
+        if fuv.is_empty() {
+            fuv = params.find_float("st");
+        }
+        uv = fuv
+            .chunks_exact(2)
+            .map(|v| Point2f::new(v[0], v[1]))
+            .collect();
+    }
+
+    let indices = params.find_int("indices");
+    let face_indices = params.find_int("faceIndices");
+
+    let n_triangles = indices.len() / 3;
+    if p.is_empty()
+        || indices.is_empty()
+        || indices.len() % 3 != 0
+        || indices.iter().any(|i| *i < 0 || *i as usize >= p.len())
+        || (!n.is_empty() && n.len() != p.len())
+        || (!uv.is_empty() && uv.len() != p.len())
+        || (!face_indices.is_empty() && face_indices.len() != n_triangles)
+    {
+        return None;
+    }
+
+    Some(PLYMesh {
+        p,
+        n,
+        uv,
+        indices: indices.iter().map(|i| *i as usize).collect(),
+        face_indices: face_indices.iter().map(|i| *i as usize).collect(),
+    })
+}
+
+/// Returns the lines for parameters of a given type sorted by name. Values
+/// are wrapped on to new lines separated by `\n`.
+///
+/// * `param_type` - Parameter type.
+/// * `params`     - Parameters of the type.
+/// * `format`     - Formats a value.
+fn format_params<T, F>(param_type: &str, params: &ParamSetMap<T>, format: F) -> Vec<String>
+where
+    T: fmt::Display,
+    F: Fn(&T) -> String,
+{
+    let mut names: Vec<&String> = params.keys().collect();
+    names.sort();
+
+    names
+        .iter()
+        .map(|name| {
+            let values: Vec<String> = params[*name].values.iter().map(|v| format(v)).collect();
+            format_param(param_type, name, &values)
+        })
+        .collect()
+}
+
+/// Returns the lines for spectrum parameters sorted by name. Spectra are
+/// written with the values they were given with; others as RGB values.
+///
+/// * `params` - The parameters.
+fn format_spectra(params: &ParamSet) -> Vec<String> {
+    let mut names: Vec<&String> = params.spectra.keys().collect();
+    names.sort();
+
+    let floats =
+        |values: &[Float]| -> Vec<String> { values.iter().map(|v| v.to_string()).collect() };
+    names
+        .iter()
+        .map(|name| {
+            let (param_type, values) = match params.spectrum_values.get(*name) {
+                Some(SpectrumValues::Rgb(v)) => ("rgb", floats(v)),
+                Some(SpectrumValues::Xyz(v)) => ("xyz", floats(v)),
+                Some(SpectrumValues::Blackbody(v)) => ("blackbody", floats(v)),
+                Some(SpectrumValues::Sampled(v)) => ("spectrum", floats(v)),
+                Some(SpectrumValues::Named(s)) => {
+                    return format!("\"spectrum {}\" \"{}\"", name, s);
+                }
+                None => {
+                    let rgb: Vec<Float> = params.spectra[*name]
+                        .values
+                        .iter()
+                        .flat_map(|s| s.to_rgb().to_vec())
+                        .collect();
+                    ("rgb", floats(&rgb))
+                }
+            };
+            format_param(param_type, name, &values)
+        })
+        .collect()
+}
+
+/// Returns the line for a parameter. Values are wrapped on to new lines
+/// separated by `\n`.
+///
+/// * `param_type` - Parameter type.
+/// * `name`       - Parameter name.
+/// * `values`     - Formatted values.
+fn format_param(param_type: &str, name: &str, values: &[String]) -> String {
+    let mut line = format!("\"{} {}\" [", param_type, name);
+    let mut line_length = line.len();
+    for s in values.iter() {
+        if line_length + s.len() + 1 > MAX_LINE_LENGTH {
+            line.push('\n');
+            line_length = 0;
+        } else {
+            line.push(' ');
+            line_length += 1;
+        }
+        line_length += s.len();
+        line.push_str(s);
+    }
+    line.push_str(" ]");
+    line
+}
+
+/// Returns a transformation matrix in column-major order as a list of
+/// values for the `Transform` directive.
+///
+/// * `t` - The transformation.
+fn format_matrix(t: &Transform) -> String {
+    let mut values: Vec<String> = vec![];
+    for j in 0..4 {
+        for i in 0..4 {
+            values.push(t.m.m[i][j].to_string());
+        }
+    }
+    format!("[ {} ]", values.join(" "))
+}
+
+// ----------------------------------------------------------------------------
+// Tests
+// ----------------------------------------------------------------------------
+
+#[cfg(test)]
+mod tests {
+    use super::super::*;
+    use super::*;
+    use crate::core::parsers::*;
+    use std::sync::{Arc, Mutex};
+
+    /// Output buffer shared with the test.
+    #[derive(Clone, Default)]
+    struct Buffer(Arc<Mutex<Vec<u8>>>);
+
+    impl Write for Buffer {
+        fn write(&mut self, buf: &[u8]) -> std::io::Result<usize> {
+            self.0.lock().unwrap().extend_from_slice(buf);
+            Ok(buf.len())
+        }
+
+        fn flush(&mut self) -> std::io::Result<()> {
+            Ok(())
+        }
+    }
+
+    /// Parse a scene description with a scene writer and return the API.
+    /// Without a writer `WorldEnd` fails to create the default integrator,
+    /// which doesn't exist, after the rest of the scene is parsed.
+    fn parse(scene: &str, writer: Option<SceneWriter>) -> Api {
+        let mut api = Api::new();
+        api.strict = true;
+        let rendered = writer.is_none();
+        api.writer = writer;
+        api.pbrt_init();
+        match PbrtFileParser::from_source_name("<test>")
+            .parse_reader(&mut scene.as_bytes(), &mut api)
+        {
+            Err(ParseError::Scene(err))
+                if rendered && err.message == "Integrator 'path' unknown." => {}
+            result => result.unwrap(),
+        }
+        api
+    }
+
+    /// Returns the scene description written for a scene.
+    fn cat(scene: &str) -> String {
+        let buffer = Buffer::default();
+        let writer = SceneWriter::new(Box::new(buffer.clone()), false, PathBuf::new());
+        let mut api = parse(scene, Some(writer));
+        api.pbrt_cleanup();
+        let out = buffer.0.lock().unwrap().clone();
+        String::from_utf8(out).unwrap()
+    }
+
+    const SCENE: &str = r#"
+        LookAt 0 0 -5  0 0 0  0 1 0
+        Camera "perspective" "float fov" 45
+        Film "image" "integer xresolution" 16 "integer yresolution" 8
+        WorldBegin
+        AttributeBegin
+          Translate 1 2 3
+          Material "plastic" "rgb Kd" [ 0.1 0.2 0.3 ] "spectrum Ks" [ 400 0.5 700 0.25 ]
+          Shape "sphere" "float radius" 2
+        AttributeEnd
+        AttributeBegin
+          Rotate 90 0 0 1
+          Shape "trianglemesh" "point P" [ 0 0 0  1 0 0  1 1 0  0 1 0 ]
+            "integer indices" [ 0 1 2  0 2 3 ]
+        AttributeEnd
+        Texture "warm" "spectrum" "constant" "blackbody value" [ 6500 ]
+        Texture "grey" "spectrum" "constant" "xyz value" [ 1 1 1 ]
+        MakeNamedMaterial "glassy" "string type" "matte" "spectrum Kd" "glass-BK7"
+        WorldEnd
+    "#;
+
+    #[test]
+    fn written_scenes_parse_to_the_same_scene() {
+        let written = cat(SCENE);
+        assert!(!written.contains("LookAt"), "{}", written);
+        assert!(written.contains("\nWorldBegin\n"), "{}", written);
+
+        // Writing the written scene gives the same scene description.
+        assert_eq!(cat(&written), written);
+
+        let original = parse(SCENE, None).render_options;
+        let rewritten = parse(&written, None).render_options;
+        assert_eq!(rewritten.primitives.len(), original.primitives.len());
+        assert_eq!(rewritten.lights.len(), original.lights.len());
+        for (p1, p2) in original.primitives.iter().zip(rewritten.primitives.iter()) {
+            let (b1, b2) = (p1.world_bound(), p2.world_bound());
+            assert!((b1.p_min - b2.p_min).length() < 1e-5);
+            assert!((b1.p_max - b2.p_max).length() < 1e-5);
+        }
+        assert_eq!(rewritten.camera_name, "perspective");
+        assert!(*rewritten.camera_to_world[0] == *original.camera_to_world[0]);
+    }
+
+    #[test]
+    fn spectra_are_written_in_their_original_form() {
+        let written = cat(SCENE);
+        for param in [
+            r#""rgb Kd" [ 0.1 0.2 0.3 ]"#,
+            r#""spectrum Ks" [ 400 0.5 700 0.25 ]"#,
+            r#""blackbody value" [ 6500 ]"#,
+            r#""xyz value" [ 1 1 1 ]"#,
+            r#""spectrum Kd" "glass-BK7""#,
+        ]
+        .iter()
+        {
+            assert!(written.contains(param), "{} not in\n{}", param, written);
+        }
+
+        // Spectra without original values are written as RGB.
+        let mut params = ParamSet::new();
+        params.add_spectrum("Kd", &[Spectrum::new(0.5)]);
+        assert_eq!(format_spectra(&params), vec!["\"rgb Kd\" [ 0.5 0.5 0.5 ]"]);
+    }
+
+    #[test]
+    fn params_are_sorted_and_wrapped() {
+        let mut params = ParamSet::new();
+        params.add_float("b", &[1.0]);
+        let values: Vec<Float> = (0..40).map(|i| i as Float).collect();
+        params.add_float("a", &values);
+
+        let lines = format_params("float", &params.floats, |v| v.to_string());
+        assert_eq!(lines.len(), 2);
+        assert_eq!(lines[1], "\"float b\" [ 1 ]");
+
+        let parts: Vec<&str> = lines[0].split('\n').collect();
+        assert!(parts.len() > 1);
+        assert!(parts.iter().all(|part| part.len() <= MAX_LINE_LENGTH));
+        assert!(parts[0].starts_with("\"float a\" [ 0 1 2"));
+        let written: Vec<&str> = lines[0].split_whitespace().skip(3).collect();
+        assert_eq!(written.len(), 41);
+        assert_eq!(written[39], "39");
+        assert_eq!(written[40], "]");
+    }
+
+    #[test]
+    fn meshes_are_written_to_ply_files_next_to_the_output() {
+        let dir = std::env::temp_dir().join(format!("toply_{}", std::process::id()));
+        std::fs::create_dir_all(&dir).unwrap();
+        let path = dir.join("scene.pbrt");
+        let path = path.to_str().unwrap();
+
+        let writer = SceneWriter::create(path, true).unwrap();
+        let mut api = parse(SCENE, Some(writer));
+        api.pbrt_cleanup();
+        drop(api);
+
+        let written = std::fs::read_to_string(path).unwrap();
+        let ply_path = dir.join("mesh_00001.ply");
+        let ply_path = ply_path.to_str().unwrap();
+        let mesh = read_ply(ply_path);
+        let rewritten = parse(&written, None).render_options;
+        std::fs::remove_dir_all(&dir).unwrap();
+
+        assert!(written.contains("Shape \"plymesh\""), "{}", written);
+        assert!(!written.contains("trianglemesh"), "{}", written);
+        assert!(
+            written.contains(&format!("\"string filename\" [ \"{}\" ]", ply_path)),
+            "{}",
+            written
+        );
+        let mesh = mesh.unwrap();
+        assert_eq!(mesh.p.len(), 4);
+        assert_eq!(mesh.indices, vec![0, 1, 2, 0, 2, 3]);
+        assert_eq!(
+            rewritten.primitives.len(),
+            parse(SCENE, None).render_options.primitives.len()
+        );
+    }
+}
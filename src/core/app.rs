@@ -5,8 +5,13 @@ use crate::core::pbrt::Float;
 use clap::*;
 
 lazy_static! {
-    /// The global application options.
-    pub static ref OPTIONS: Options = Options::new();
+    /// The global application options. Tests use the defaults since the
+    /// command line holds the test harness arguments.
+    pub static ref OPTIONS: Options = if cfg!(test) {
+        Options::default()
+    } else {
+        Options::new()
+    };
 }
 
 /// System wide options.
@@ -27,6 +32,19 @@ pub struct Options {
     /// The crop window x0, x1, y0, y1.
     pub crop_window: [[Float; 2]; 2],
 
+    /// Resume rendering from the checkpoint of an interrupted render.
+    pub resume: bool,
+
+    /// Abort parsing the scene description on the first error.
+    pub strict: bool,
+
+    /// Print the scene description to standard output instead of rendering.
+    pub cat: bool,
+
+    /// Print the scene description to standard output instead of rendering
+    /// and move triangle meshes into PLY files.
+    pub to_ply: bool,
+
     /// Input file paths. Empty vector implies read from stdin.
     pub paths: Vec<String>,
 }
@@ -50,7 +68,10 @@ impl Options {
                     .long("outfile")
                     .value_name("FILE")
                     .takes_value(true)
-                    .help("Write the final image to the given filename."),
+                    .help(
+                        "Write the final image to the given filename. With --cat or --toply
+                        the scene description is written to it instead of standard output.",
+                    ),
             )
             .arg(
                 Arg::with_name("cropwindow")
@@ -75,14 +96,39 @@ impl Options {
                 Arg::with_name("quiet")
                     .long("quiet")
                     .takes_value(false)
-                    .default_value("false")
                     .help("Suppress all text output other than error messages."),
             )
+            .arg(
+                Arg::with_name("resume")
+                    .long("resume")
+                    .takes_value(false)
+                    .help("Resume rendering from the checkpoint of an interrupted render."),
+            )
+            .arg(
+                Arg::with_name("strict")
+                    .long("strict")
+                    .takes_value(false)
+                    .help("Abort on the first error in the scene description."),
+            )
+            .arg(Arg::with_name("cat").long("cat").takes_value(false).help(
+                "Print a reformatted version of the input file(s) to standard output. 
+                        Does not render an image.",
+            ))
+            .arg(
+                Arg::with_name("toply")
+                    .long("toply")
+                    .takes_value(false)
+                    .help(
+                        "Print a reformatted version of the input file(s) to standard output 
+                        and convert all triangle meshes to PLY files written next to the 
+                        output file. Does not render an image.",
+                    ),
+            )
             .arg(
                 Arg::with_name("INPUT")
                     .required(false)
                     .multiple(true)
-                    .help("Input files"),
+                    .help("Input files. Reads from standard input if none are given."),
             )
             .get_matches();
 
@@ -103,8 +149,14 @@ impl Options {
             _ => 1,
         };
 
+        let cat = matches.is_present("cat");
+
+        let to_ply = matches.is_present("toply");
+
+        // No image is rendered when printing the scene description.
         let image_file = match matches.value_of("outfile") {
             Some(s) => s.to_string(),
+            _ if cat || to_ply => String::new(),
             _ => panic!("Missing outfile"),
         };
 
@@ -130,10 +182,11 @@ impl Options {
             _ => false,
         };
 
-        let quiet = match matches.value_of("quiet") {
-            Some(s) => s.parse::<bool>().expect("Invalid quiet"),
-            _ => false,
-        };
+        let quiet = matches.is_present("quiet");
+
+        let resume = matches.is_present("resume");
+
+        let strict = matches.is_present("strict");
 
         let paths: Vec<String> = match matches.values_of("INPUT") {
             Some(p) => p.map(String::from).collect(),
@@ -146,7 +199,30 @@ impl Options {
             quiet,
             image_file,
             crop_window,
+            resume,
+            strict,
+            cat,
+            to_ply,
             paths,
         }
     }
 }
+
+impl Default for Options {
+    /// Returns the options used when no command line arguments are given,
+    /// except that no output file is required.
+    fn default() -> Self {
+        Self {
+            n_threads: 1,
+            quick_render: false,
+            quiet: false,
+            image_file: String::new(),
+            crop_window: [[0.0, 1.0], [0.0, 1.0]],
+            resume: false,
+            strict: false,
+            cat: false,
+            to_ply: false,
+            paths: vec![],
+        }
+    }
+}
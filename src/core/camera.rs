@@ -11,6 +11,9 @@ use std::sync::Arc;
 
 /// Light trait provides common behavior.
 pub trait Camera {
+    /// Returns the common camera data.
+    fn get_data(&self) -> &CameraData;
+
     /// Returns a ray corresponding to a given sample. It also returns, a floating
     /// point value that affects how much the radiance arriving at the film plane
     /// will contribute to final image.
@@ -30,6 +30,7 @@ impl EFloat {
     /// * `err` - The error (default to 0.0).
     pub fn new(v: f32, err: f32) -> Self {
         let mut r = Self::default();
+        r.v = v;
 
         if err == 0.0 {
             r.low = v;
@@ -71,21 +72,21 @@ impl EFloat {
         r
     }
 
-    /// Asserts low < high, low < v_precise < high for finite non-NAN values.
+    /// Asserts low <= high, low <= v_precise <= high for finite non-NAN values.
     fn check(&self) {
         if self.low.is_finite()
             && !self.low.is_nan()
             && self.high.is_finite()
             && !self.high.is_nan()
         {
-            assert!(self.low < self.high);
+            assert!(self.low <= self.high);
         }
 
         #[cfg(debug_assertions)]
         {
             if self.v.is_finite() && !self.v.is_nan() {
-                assert!((self.low as f64) < self.v_precise);
-                assert!(self.v_precise < (self.high as f64));
+                assert!((self.low as f64) <= self.v_precise);
+                assert!(self.v_precise <= (self.high as f64));
             }
         }
     }
@@ -476,3 +477,30 @@ impl Quadratic {
         }
     }
 }
+
+// ----------------------------------------------------------------------------
+// Tests
+// ----------------------------------------------------------------------------
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn bounds_contain_value() {
+        let a = EFloat::new(2.0, 0.1);
+        assert_eq!(f32::from(a), 2.0);
+        assert!(a.lower_bound() < 1.9 && a.upper_bound() > 2.1);
+
+        let b = EFloat::new(-3.0, 0.0);
+        assert_eq!((b.lower_bound(), b.upper_bound()), (-3.0, -3.0));
+    }
+
+    #[test]
+    fn products_of_zero_contain_zero() {
+        let zero = EFloat::from(0.0);
+        let p = zero * zero;
+        assert!(p.lower_bound() < 0.0 && p.upper_bound() > 0.0);
+        assert!(next_float_down(0.0) < 0.0 && next_float_up(0.0) > 0.0);
+    }
+}
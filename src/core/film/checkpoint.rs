@@ -0,0 +1,348 @@
+//! Film Checkpoints
+
+use super::*;
+use byteorder::{LittleEndian, ReadBytesExt, WriteBytesExt};
+use std::fs::{remove_file, rename, File};
+use std::io::{BufReader, BufWriter, Read, Write};
+
+/// The first 8 bytes of a checkpoint file are the header `PBRCKPT`
+/// terminated with the format version.
+const CHECKPOINT_HEADER: [u8; 8] = [b'P', b'B', b'R', b'C', b'K', b'P', b'T', b'\x04'];
+
+impl Film {
+    /// Returns the path of the checkpoint file for the output image.
+    pub fn get_checkpoint_filename(&self) -> String {
+        format!("{}.checkpoint", self.filename)
+    }
+
+    /// Write the pixel buffers and rendering progress to the checkpoint file.
+    /// The file is written to a temporary path first and then renamed so that
+    /// an interrupted write does not corrupt a previous checkpoint.
+    ///
+    /// * `samples_per_pixel` - Total number of samples per pixel.
+    /// * `samples_completed` - Number of samples per pixel completed.
+    pub fn write_checkpoint(
+        &self,
+        samples_per_pixel: usize,
+        samples_completed: usize,
+    ) -> Result<(), String> {
+        let path = self.get_checkpoint_filename();
+        let tmp_path = format!("{}.tmp", path);
+        info!(
+            "Writing checkpoint {} at {}/{} samples per pixel",
+            path, samples_completed, samples_per_pixel
+        );
+
+        let file = match File::create(&tmp_path) {
+            Ok(file) => file,
+            Err(err) => return Err(format!("Could not create {}. {}", tmp_path, err)),
+        };
+
+        let mut writer = BufWriter::new(file);
+        if let Err(err) =
+            self.write_checkpoint_data(&mut writer, samples_per_pixel, samples_completed)
+        {
+            return Err(format!("Error writing checkpoint {}. {}", tmp_path, err));
+        }
+        if let Err(err) = writer.flush() {
+            return Err(format!("Error writing checkpoint {}. {}", tmp_path, err));
+        }
+
+        match rename(&tmp_path, &path) {
+            Ok(()) => Ok(()),
+            Err(err) => Err(format!(
+                "Could not rename {} to {}. {}",
+                tmp_path, path, err
+            )),
+        }
+    }
+
+    /// Remove the checkpoint file of a completed render if there is one.
+    pub fn remove_checkpoint(&self) {
+        let path = self.get_checkpoint_filename();
+        match remove_file(&path) {
+            Ok(()) => info!("Removed checkpoint {}", path),
+            Err(err) if err.kind() == std::io::ErrorKind::NotFound => (),
+            Err(err) => warn!("Could not remove checkpoint {}. {}", path, err),
+        }
+    }
+
+    /// Read the pixel buffers from the checkpoint file and return the number
+    /// of samples per pixel completed. The film is left unchanged if the
+    /// checkpoint can't be read or doesn't match the render.
+    ///
+    /// * `samples_per_pixel` - Total number of samples per pixel.
+    pub fn read_checkpoint(&self, samples_per_pixel: usize) -> Result<usize, String> {
+        let path = self.get_checkpoint_filename();
+        let file = match File::open(&path) {
+            Ok(file) => file,
+            Err(err) => return Err(format!("Could not open {}. {}", path, err)),
+        };
+
+        let mut reader = BufReader::new(file);
+        match self.read_checkpoint_data(&mut reader, samples_per_pixel) {
+            Ok(samples_completed) => {
+                info!(
+                    "Resuming from checkpoint {} at {}/{} samples per pixel",
+                    path, samples_completed, samples_per_pixel
+                );
+                Ok(samples_completed)
+            }
+            Err(err) => Err(format!("Error reading checkpoint {}. {}", path, err)),
+        }
+    }
+
+    /// Write the checkpoint header, progress and pixel data.
+    ///
+    /// * `writer`            - The writer.
+    /// * `samples_per_pixel` - Total number of samples per pixel.
+    /// * `samples_completed` - Number of samples per pixel completed.
+    fn write_checkpoint_data<W: Write>(
+        &self,
+        writer: &mut W,
+        samples_per_pixel: usize,
+        samples_completed: usize,
+    ) -> std::io::Result<()> {
+        writer.write_all(&CHECKPOINT_HEADER)?;
+
+        // The cropped pixel bounds identify the pixel buffer layout.
+        writer.write_i32::<LittleEndian>(self.cropped_pixel_bounds.p_min.x)?;
+        writer.write_i32::<LittleEndian>(self.cropped_pixel_bounds.p_min.y)?;
+        writer.write_i32::<LittleEndian>(self.cropped_pixel_bounds.p_max.x)?;
+        writer.write_i32::<LittleEndian>(self.cropped_pixel_bounds.p_max.y)?;
+
+        writer.write_u64::<LittleEndian>(samples_per_pixel as u64)?;
+        writer.write_u64::<LittleEndian>(samples_completed as u64)?;
+
+        let pixels = self.pixels.read().unwrap();
+        for pixel in pixels.iter() {
+            for i in 0..3 {
+                writer.write_f32::<LittleEndian>(pixel.xyz[i] as f32)?;
+            }
+            writer.write_f32::<LittleEndian>(pixel.filter_weight_sum as f32)?;
+            for i in 0..3 {
+                writer.write_f32::<LittleEndian>(pixel.splat_xyz[i] as f32)?;
+            }
+            writer.write_f32::<LittleEndian>(pixel.alpha_sum as f32)?;
+        }
+
+        let pixel_variance = self.pixel_variance.read().unwrap();
+        for variance in pixel_variance.iter() {
+            write_variance(writer, variance)?;
+        }
+
+        // Denoiser guides.
+        let pixel_aovs = self.pixel_aovs.read().unwrap();
+        for aov in pixel_aovs.iter() {
+            for i in 0..3 {
+                writer.write_f32::<LittleEndian>(aov.albedo[i] as f32)?;
+            }
+            for i in 0..3 {
+                writer.write_f32::<LittleEndian>(aov.normal[i] as f32)?;
+            }
+            writer.write_f32::<LittleEndian>(aov.weight_sum as f32)?;
+            for variance in aov.variance.iter() {
+                write_variance(writer, variance)?;
+            }
+        }
+
+        Ok(())
+    }
+
+    /// Read the checkpoint header, progress and pixel data.
+    ///
+    /// * `reader`            - The reader.
+    /// * `samples_per_pixel` - Total number of samples per pixel.
+    fn read_checkpoint_data<R: Read>(
+        &self,
+        reader: &mut R,
+        samples_per_pixel: usize,
+    ) -> Result<usize, String> {
+        let mut header = [0_u8; 8];
+        reader.read_exact(&mut header).map_err(|e| e.to_string())?;
+        if header != CHECKPOINT_HEADER {
+            return Err(String::from("Invalid header."));
+        }
+
+        let mut bounds = [0; 4];
+        for b in bounds.iter_mut() {
+            *b = reader
+                .read_i32::<LittleEndian>()
+                .map_err(|e| e.to_string())?;
+        }
+        let bounds = Bounds2i::new(
+            Point2i::new(bounds[0], bounds[1]),
+            Point2i::new(bounds[2], bounds[3]),
+        );
+        if bounds != self.cropped_pixel_bounds {
+            return Err(format!(
+                "Pixel bounds {} don't match film pixel bounds {}.",
+                bounds, self.cropped_pixel_bounds
+            ));
+        }
+
+        // Samplers generate different sample sequences for a different number
+        // of samples per pixel so the render can't be continued.
+        let spp = reader
+            .read_u64::<LittleEndian>()
+            .map_err(|e| e.to_string())? as usize;
+        if spp != samples_per_pixel {
+            return Err(format!(
+                "Checkpoint has {} samples per pixel. Expected {}.",
+                spp, samples_per_pixel
+            ));
+        }
+
+        let samples_completed = reader
+            .read_u64::<LittleEndian>()
+            .map_err(|e| e.to_string())? as usize;
+
+        // Read all pixels before updating the film.
+        let n = self.cropped_pixel_bounds.area() as usize;
+        let mut values = vec![0.0_f32; 8 * n];
+        reader
+            .read_f32_into::<LittleEndian>(&mut values)
+            .map_err(|e| e.to_string())?;
+
+        let mut variances = vec![VarianceEstimator::default(); n];
+        for variance in variances.iter_mut() {
+            *variance = read_variance(reader).map_err(|e| e.to_string())?;
+        }
+
+        let mut aovs = vec![PixelAOV::default(); n];
+        for aov in aovs.iter_mut() {
+            let mut v = [0.0_f32; 7];
+            reader
+                .read_f32_into::<LittleEndian>(&mut v)
+                .map_err(|e| e.to_string())?;
+            aov.albedo = [v[0] as Float, v[1] as Float, v[2] as Float];
+            aov.normal = [v[3] as Float, v[4] as Float, v[5] as Float];
+            aov.weight_sum = v[6] as Float;
+            for variance in aov.variance.iter_mut() {
+                *variance = read_variance(reader).map_err(|e| e.to_string())?;
+            }
+        }
+
+        *self.pixel_variance.write().unwrap() = variances;
+        *self.pixel_aovs.write().unwrap() = aovs;
+
+        let mut pixels = self.pixels.write().unwrap();
+        for (pixel, v) in pixels.iter_mut().zip(values.chunks_exact(8)) {
+            pixel.xyz = [v[0] as Float, v[1] as Float, v[2] as Float];
+            pixel.filter_weight_sum = v[3] as Float;
+            pixel.splat_xyz = [v[4] as Float, v[5] as Float, v[6] as Float];
+            pixel.alpha_sum = v[7] as Float;
+        }
+
+        Ok(min(samples_completed, samples_per_pixel))
+    }
+}
+
+/// Write a variance estimator.
+///
+/// * `writer`   - The writer.
+/// * `variance` - The variance estimator.
+fn write_variance<W: Write>(writer: &mut W, variance: &VarianceEstimator) -> std::io::Result<()> {
+    writer.write_u32::<LittleEndian>(variance.n)?;
+    writer.write_f32::<LittleEndian>(variance.mean as f32)?;
+    writer.write_f32::<LittleEndian>(variance.m2 as f32)
+}
+
+/// Read a variance estimator.
+///
+/// * `reader` - The reader.
+fn read_variance<R: Read>(reader: &mut R) -> std::io::Result<VarianceEstimator> {
+    Ok(VarianceEstimator {
+        n: reader.read_u32::<LittleEndian>()?,
+        mean: reader.read_f32::<LittleEndian>()? as Float,
+        m2: reader.read_f32::<LittleEndian>()? as Float,
+    })
+}
+
+// ----------------------------------------------------------------------------
+// Tests
+// ----------------------------------------------------------------------------
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::filters::*;
+
+    /// Returns a 4x3 film writing to a unique file in the temporary directory.
+    fn film(name: &str) -> Film {
+        let path = std::env::temp_dir().join(format!("{}_{}.pfm", name, std::process::id()));
+        let mut params = ParamSet::new();
+        params.add_int("xresolution", &[4]);
+        params.add_int("yresolution", &[3]);
+        params.add_string("filename", &[path.to_str().unwrap().to_owned()]);
+        Film::from((
+            &params,
+            Arc::new(BoxFilter::from(&ParamSet::new())) as ArcFilter,
+        ))
+    }
+
+    #[test]
+    fn round_trip() {
+        let film1 = film("checkpoint_round_trip");
+        for (i, pixel) in film1.pixels.write().unwrap().iter_mut().enumerate() {
+            let v = i as Float;
+            pixel.xyz = [v, v + 0.5, v + 0.25];
+            pixel.filter_weight_sum = 2.0 * v;
+            pixel.splat_xyz = [0.0, v, 1.0];
+            pixel.alpha_sum = 0.5;
+        }
+        for (i, variance) in film1.pixel_variance.write().unwrap().iter_mut().enumerate() {
+            variance.add(i as Float);
+            variance.add(1.0);
+        }
+        for (i, aov) in film1.pixel_aovs.write().unwrap().iter_mut().enumerate() {
+            let v = i as Float;
+            aov.albedo = [v, 0.5, 0.25];
+            aov.normal = [0.0, v, 1.0];
+            aov.weight_sum = 3.0;
+            for c in 0..3 {
+                aov.variance[c].add(v * c as Float);
+                aov.variance[c].add(2.0);
+            }
+        }
+        film1.write_checkpoint(8, 3).unwrap();
+
+        let film2 = film("checkpoint_round_trip");
+        assert_eq!(film2.read_checkpoint(8), Ok(3));
+        film2.remove_checkpoint();
+        assert!(!std::path::Path::new(&film2.get_checkpoint_filename()).exists());
+
+        let (pixels1, pixels2) = (film1.pixels.read().unwrap(), film2.pixels.read().unwrap());
+        for (p1, p2) in pixels1.iter().zip(pixels2.iter()) {
+            assert_eq!(p1.xyz, p2.xyz);
+            assert_eq!(p1.filter_weight_sum, p2.filter_weight_sum);
+            assert_eq!(p1.splat_xyz, p2.splat_xyz);
+            assert_eq!(p1.alpha_sum, p2.alpha_sum);
+        }
+        let v1 = film1.pixel_variance.read().unwrap();
+        let v2 = film2.pixel_variance.read().unwrap();
+        for (a, b) in v1.iter().zip(v2.iter()) {
+            assert_eq!((a.n, a.mean, a.m2), (b.n, b.mean, b.m2));
+        }
+        let aovs1 = film1.pixel_aovs.read().unwrap();
+        let aovs2 = film2.pixel_aovs.read().unwrap();
+        for (a, b) in aovs1.iter().zip(aovs2.iter()) {
+            assert_eq!(a.albedo, b.albedo);
+            assert_eq!(a.normal, b.normal);
+            assert_eq!(a.weight_sum, b.weight_sum);
+            for c in 0..3 {
+                let (va, vb) = (a.variance[c], b.variance[c]);
+                assert_eq!((va.n, va.mean, va.m2), (vb.n, vb.mean, vb.m2));
+            }
+        }
+    }
+
+    #[test]
+    fn rejects_other_sample_counts() {
+        let film1 = film("checkpoint_sample_count");
+        film1.write_checkpoint(8, 4).unwrap();
+        assert!(film1.read_checkpoint(16).is_err());
+        film1.remove_checkpoint();
+        assert!(film1.read_checkpoint(8).is_err());
+    }
+}
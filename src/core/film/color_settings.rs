@@ -0,0 +1,105 @@
+//! Film Color Settings
+
+use crate::core::image_io::*;
+use crate::core::paramset::*;
+use crate::core::pbrt::*;
+use crate::core::spectrum::*;
+
+/// Controls how pixel values are converted to output image values.
+#[derive(Copy, Clone, Debug)]
+pub struct ColorSettings {
+    /// RGB color space of the output image.
+    pub color_space: ColorSpace,
+
+    /// Exposure adjustment in stops (EV).
+    pub exposure: Float,
+
+    /// Matrix for white balancing XYZ values.
+    pub white_balance: Option<[[Float; 3]; 3]>,
+
+    /// Tone mapping operator for 8-bit and 16-bit image formats.
+    pub tone_map: ToneMapOperator,
+
+    /// Bits per channel (8 or 16) for PNG images.
+    pub bit_depth: u8,
+
+    /// Whether to write an alpha channel for image formats that support it.
+    pub alpha: bool,
+}
+
+impl ColorSettings {
+    /// Returns the RGB value in the output color space for the given XYZ
+    /// value after white balancing.
+    ///
+    /// * `xyz` - The XYZ coefficients.
+    pub fn xyz_to_rgb(&self, xyz: &[Float; 3]) -> [Float; 3] {
+        match self.white_balance {
+            Some(m) => self.color_space.xyz_to_rgb(&mat3_mul_vec(&m, xyz)),
+            None => self.color_space.xyz_to_rgb(xyz),
+        }
+    }
+
+    /// Returns the scale factor for the exposure adjustment.
+    pub fn exposure_scale(&self) -> Float {
+        (2.0 as Float).powf(self.exposure)
+    }
+}
+
+impl From<&ParamSet> for ColorSettings {
+    /// Create `ColorSettings` from given film parameter set.
+    ///
+    /// * `params` - Film parameter set.
+    fn from(params: &ParamSet) -> Self {
+        let color_space_name = params.find_one_string("colorspace", String::from("srgb"));
+        let color_space = match &color_space_name[..] {
+            "srgb" => ColorSpace::SRGB,
+            "acescg" => ColorSpace::ACEScg,
+            "rec2020" => ColorSpace::Rec2020,
+            "displayp3" => ColorSpace::DisplayP3,
+            cs => {
+                warn!("Color space '{}' unknown. Using 'srgb'.", cs);
+                ColorSpace::SRGB
+            }
+        };
+
+        let tone_map_name = params.find_one_string("tonemap", String::from("clamp"));
+        let tone_map = match &tone_map_name[..] {
+            "clamp" => ToneMapOperator::Clamp,
+            "reinhard" => ToneMapOperator::Reinhard,
+            "aces" | "filmic" => ToneMapOperator::ACES,
+            tm => {
+                warn!("Tone mapping operator '{}' unknown. Using 'clamp'.", tm);
+                ToneMapOperator::Clamp
+            }
+        };
+
+        let bit_depth = match params.find_one_int("bitdepth", 8) {
+            8 => 8,
+            16 => 16,
+            bd => {
+                warn!("Bit depth {} not supported. Using 8.", bd);
+                8
+            }
+        };
+
+        let exposure = params.find_one_float("exposure", 0.0);
+
+        let alpha = params.find_one_bool("alpha", false);
+
+        let temperature = params.find_one_float("whitebalance", 0.0);
+        let white_balance = if temperature > 0.0 {
+            Some(white_balance_matrix(temperature))
+        } else {
+            None
+        };
+
+        Self {
+            color_space,
+            exposure,
+            white_balance,
+            tone_map,
+            bit_depth,
+            alpha,
+        }
+    }
+}
@@ -0,0 +1,268 @@
+//! Denoiser
+
+use crate::core::pbrt::*;
+use rayon::prelude::*;
+
+/// Default radius of the denoising filter in pixels.
+pub const DEFAULT_DENOISE_RADIUS: usize = 5;
+
+/// Auxiliary buffers used to guide the denoiser. All buffers store values in
+/// the same pixel order as the image being denoised.
+pub struct DenoiserGuides<'a> {
+    /// Average RGB albedo at the first intersection for each pixel.
+    pub albedo: &'a [Float],
+
+    /// Average shading normal at the first intersection for each pixel.
+    pub normal: &'a [Float],
+
+    /// Variance of the pixel value estimate for each pixel.
+    pub variance: &'a [Float],
+}
+
+/// A cross-bilateral filter that removes Monte Carlo noise from an image. The
+/// filter weights between pixels depend on their distance, colour difference
+/// relative to the pixel variance, and the difference in albedo and normal so
+/// that texture and geometric edges are preserved.
+#[derive(Copy, Clone, Debug)]
+pub struct Denoiser {
+    /// Radius of the filter in pixels.
+    radius: usize,
+
+    /// Standard deviation of the spatial weights.
+    sigma_spatial: Float,
+
+    /// Standard deviation of the albedo weights.
+    sigma_albedo: Float,
+
+    /// Scale of the normal weights in terms of `1 - cos(theta)`.
+    sigma_normal: Float,
+
+    /// Scale applied to the pixel variance for colour weights.
+    k: Float,
+}
+
+impl Denoiser {
+    /// Create a new `Denoiser`.
+    ///
+    /// * `radius` - Radius of the filter in pixels.
+    pub fn new(radius: usize) -> Self {
+        Self {
+            radius,
+            sigma_spatial: max(1.0, radius as Float / 2.0),
+            sigma_albedo: 0.1,
+            sigma_normal: 0.1,
+            k: 0.5,
+        }
+    }
+
+    /// Returns the denoised RGB image.
+    ///
+    /// * `rgb`    - RGB pixel data.
+    /// * `guides` - Guide buffers.
+    /// * `width`  - Image width.
+    /// * `height` - Image height.
+    pub fn denoise(
+        &self,
+        rgb: &[Float],
+        guides: &DenoiserGuides,
+        width: usize,
+        height: usize,
+    ) -> Vec<Float> {
+        info!(
+            "Denoising {}x{} image with filter radius {}",
+            width, height, self.radius
+        );
+
+        let mut output = vec![0.0; rgb.len()];
+        if width == 0 {
+            return output;
+        }
+
+        output
+            .par_chunks_mut(3 * width)
+            .enumerate()
+            .for_each(|(y, row)| {
+                for x in 0..width {
+                    let filtered = self.filter_pixel(rgb, guides, width, height, x, y);
+                    row[3 * x..3 * x + 3].copy_from_slice(&filtered);
+                }
+            });
+
+        output
+    }
+
+    /// Returns the filtered value of a pixel.
+    ///
+    /// * `rgb`    - RGB pixel data.
+    /// * `guides` - Guide buffers.
+    /// * `width`  - Image width.
+    /// * `height` - Image height.
+    /// * `x`      - Pixel x-coordinate.
+    /// * `y`      - Pixel y-coordinate.
+    fn filter_pixel(
+        &self,
+        rgb: &[Float],
+        guides: &DenoiserGuides,
+        width: usize,
+        height: usize,
+        x: usize,
+        y: usize,
+    ) -> [Float; 3] {
+        let p = y * width + x;
+        let inv_two_sigma_spatial2 = 1.0 / (2.0 * self.sigma_spatial * self.sigma_spatial);
+        let inv_two_sigma_albedo2 = 1.0 / (2.0 * self.sigma_albedo * self.sigma_albedo);
+
+        let x0 = x.saturating_sub(self.radius);
+        let x1 = min(x + self.radius, width - 1);
+        let y0 = y.saturating_sub(self.radius);
+        let y1 = min(y + self.radius, height - 1);
+
+        let mut sum = [0.0; 3];
+        let mut weight_sum = 0.0;
+        for qy in y0..=y1 {
+            for qx in x0..=x1 {
+                let q = qy * width + qx;
+
+                // Spatial distance.
+                let dx = qx as Float - x as Float;
+                let dy = qy as Float - y as Float;
+                let d_spatial = (dx * dx + dy * dy) * inv_two_sigma_spatial2;
+
+                // Colour distance relative to the variance of both pixels with
+                // the expected difference due to noise removed.
+                let mut d_color = 0.0;
+                for c in 0..3 {
+                    let d = rgb[3 * q + c] - rgb[3 * p + c];
+                    d_color += d * d;
+                }
+                let variance = guides.variance[p] + guides.variance[q];
+                let d_color =
+                    max(0.0, d_color / 3.0 - variance) / (1e-4 + self.k * self.k * variance);
+
+                // Albedo distance.
+                let mut d_albedo = 0.0;
+                for c in 0..3 {
+                    let d = guides.albedo[3 * q + c] - guides.albedo[3 * p + c];
+                    d_albedo += d * d;
+                }
+                let d_albedo = d_albedo * inv_two_sigma_albedo2;
+
+                // Normal distance. The normals of pixels without an
+                // intersection are zero so they only match each other.
+                let n_p = &guides.normal[3 * p..3 * p + 3];
+                let n_q = &guides.normal[3 * q..3 * q + 3];
+                let d_normal = match (is_zero(n_p), is_zero(n_q)) {
+                    (true, true) => 0.0,
+                    (true, false) | (false, true) => 1.0 / self.sigma_normal,
+                    (false, false) => {
+                        let cos_theta = n_p[0] * n_q[0] + n_p[1] * n_q[1] + n_p[2] * n_q[2];
+                        max(0.0, 1.0 - cos_theta) / self.sigma_normal
+                    }
+                };
+
+                let weight = (-(d_spatial + d_color + d_albedo + d_normal)).exp();
+                for c in 0..3 {
+                    sum[c] += weight * rgb[3 * q + c];
+                }
+                weight_sum += weight;
+            }
+        }
+
+        if weight_sum > 0.0 {
+            [
+                sum[0] / weight_sum,
+                sum[1] / weight_sum,
+                sum[2] / weight_sum,
+            ]
+        } else {
+            [rgb[3 * p], rgb[3 * p + 1], rgb[3 * p + 2]]
+        }
+    }
+}
+
+/// Returns `true` if all components of a value are zero.
+///
+/// * `v` - The value.
+#[inline]
+fn is_zero(v: &[Float]) -> bool {
+    v.iter().all(|c| *c == 0.0)
+}
+
+// ----------------------------------------------------------------------------
+// Tests
+// ----------------------------------------------------------------------------
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    const WIDTH: usize = 8;
+    const HEIGHT: usize = 4;
+
+    /// Returns a greyscale RGB image from per-pixel values.
+    fn grey(f: impl Fn(usize, usize) -> Float) -> Vec<Float> {
+        let mut rgb = vec![];
+        for y in 0..HEIGHT {
+            for x in 0..WIDTH {
+                rgb.extend_from_slice(&[f(x, y); 3]);
+            }
+        }
+        rgb
+    }
+
+    fn denoise(rgb: &[Float], albedo: &[Float], variance: Float) -> Vec<Float> {
+        let n = WIDTH * HEIGHT;
+        let normal: Vec<Float> = (0..n).flat_map(|_| vec![0.0, 0.0, 1.0]).collect();
+        let variance = vec![variance; n];
+        let guides = DenoiserGuides {
+            albedo,
+            normal: &normal,
+            variance: &variance,
+        };
+        Denoiser::new(2).denoise(rgb, &guides, WIDTH, HEIGHT)
+    }
+
+    /// Returns the mean squared difference of two images.
+    fn mse(a: &[Float], b: &[Float]) -> Float {
+        a.iter()
+            .zip(b.iter())
+            .map(|(a, b)| (a - b) * (a - b))
+            .sum::<Float>()
+            / a.len() as Float
+    }
+
+    #[test]
+    fn constant_images_are_unchanged() {
+        let rgb = grey(|_, _| 0.5);
+        let albedo = grey(|_, _| 1.0);
+        for (a, b) in denoise(&rgb, &albedo, 0.01).iter().zip(rgb.iter()) {
+            assert!((a - b).abs() < 1e-6);
+        }
+    }
+
+    #[test]
+    fn noise_is_reduced() {
+        let clean = grey(|_, _| 0.5);
+        let noisy = grey(|x, y| if (x + y) % 2 == 0 { 0.4 } else { 0.6 });
+        let albedo = grey(|_, _| 1.0);
+        let denoised = denoise(&noisy, &albedo, 0.04);
+        assert!(mse(&denoised, &clean) < 0.1 * mse(&noisy, &clean));
+
+        // Differences well above the pixel variance are kept.
+        let denoised = denoise(&noisy, &albedo, 1e-4);
+        assert!(mse(&denoised, &noisy) < 0.1 * mse(&noisy, &clean));
+    }
+
+    #[test]
+    fn albedo_edges_are_preserved() {
+        let edge = |x: usize, _| if x < WIDTH / 2 { 0.1 } else { 0.9 };
+        let rgb = grey(edge);
+        let albedo = grey(edge);
+        let denoised = denoise(&rgb, &albedo, 0.5);
+        assert!(mse(&denoised, &rgb) < 1e-4);
+
+        // Without albedo differences, the high variance blurs the edge.
+        let flat = grey(|_, _| 1.0);
+        assert!(mse(&denoise(&rgb, &flat, 0.5), &rgb) > 1e-3);
+    }
+}
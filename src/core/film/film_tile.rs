@@ -1,5 +1,6 @@
 //! Film tile
 
+use super::{VarianceEstimator, FILTER_TABLE_WIDTH};
 use crate::core::geometry::*;
 use crate::core::pbrt::*;
 use crate::core::spectrum::*;
@@ -58,7 +59,9 @@ impl<'a> FilmTile<'a> {
     /// * `p_film`         - Point on film.
     /// * `l`              - Radiance value `L`.
     /// * `sample_weight`  - Weight for the sample's contribution.
-    pub fn add_sample(&mut self, p_film: Point2f, l: Spectrum, sample_weight: Float) {
+    /// * `alpha`          - Coverage of the sample; 1 if the camera ray hit
+    ///                      geometry and 0 otherwise.
+    pub fn add_sample(&mut self, p_film: Point2f, l: Spectrum, sample_weight: Float, alpha: Float) {
         let ly = l.y();
         let l = if ly > self.max_sample_luminance {
             l * self.max_sample_luminance / ly
@@ -66,6 +69,20 @@ impl<'a> FilmTile<'a> {
             l
         };
 
+        // Track the variance of sample luminance in the pixel containing the
+        // sample for adaptive sampling and of its RGB channels for the
+        // denoiser.
+        let p_pixel = Point2i::from(p_film.floor());
+        if self.pixel_bounds.contains_exclusive(&p_pixel) {
+            let pixel_offset = self.get_pixel_offset(&p_pixel);
+            let pixel = &mut self.pixels[pixel_offset];
+            pixel.variance.add(l.y() * sample_weight);
+            let rgb = l.to_rgb();
+            for c in 0..3 {
+                pixel.rgb_variance[c].add(rgb[c] * sample_weight);
+            }
+        }
+
         // Compute sample's raster bounds.
         let p_film_discrete = p_film - Vector2f::new(0.5, 0.5);
         let mut p0 = Point2i::from((p_film_discrete - self.filter_radius).ceil());
@@ -75,10 +92,10 @@ impl<'a> FilmTile<'a> {
         p1 = p1.min(&self.pixel_bounds.p_max);
 
         // Loop over filter support and add sample to pixel arrays.
-        let filter_table_size = self.filter_table.len();
+        let filter_table_size = FILTER_TABLE_WIDTH;
 
         // Precompute `x` and `y` filter table offsets.
-        let mut ifx: Vec<Int> = vec![0, p1.x - p0.x];
+        let mut ifx: Vec<Int> = vec![0; max(0, p1.x - p0.x) as usize];
         for x in p0.x..p1.x {
             let fx = abs((x as Float - p_film_discrete.x)
                 * self.inv_filter_radius.x
@@ -86,7 +103,7 @@ impl<'a> FilmTile<'a> {
             ifx[(x - p0.x) as usize] = min(fx.floor(), (filter_table_size - 1) as Float) as Int;
         }
 
-        let mut ify: Vec<Int> = vec![0, p1.y - p0.y];
+        let mut ify: Vec<Int> = vec![0; max(0, p1.y - p0.y) as usize];
         for y in p0.y..p1.y {
             let fy = abs((y as Float - p_film_discrete.y)
                 * self.inv_filter_radius.y
@@ -105,10 +122,29 @@ impl<'a> FilmTile<'a> {
                 let pixel_offset = self.get_pixel_offset(&Point2i::new(x, y));
                 self.pixels[pixel_offset].contrib_sum += l * sample_weight * filter_weight;
                 self.pixels[pixel_offset].filter_weight_sum += filter_weight;
+                self.pixels[pixel_offset].alpha_sum += alpha * filter_weight;
             }
         }
     }
 
+    /// Add the auxiliary values at the first intersection of a camera ray used
+    /// to guide the denoiser. These are averaged over the samples within the
+    /// pixel containing the sample.
+    ///
+    /// * `p_film` - Point on film.
+    /// * `albedo` - Albedo of the surface.
+    /// * `n`      - Shading normal of the surface.
+    pub fn add_aov(&mut self, p_film: Point2f, albedo: &Spectrum, n: &Normal3f) {
+        let p_pixel = Point2i::from(p_film.floor());
+        if self.pixel_bounds.contains_exclusive(&p_pixel) {
+            let pixel_offset = self.get_pixel_offset(&p_pixel);
+            let pixel = &mut self.pixels[pixel_offset];
+            pixel.albedo_sum += *albedo;
+            pixel.normal_sum += *n;
+            pixel.aov_weight_sum += 1.0;
+        }
+    }
+
     /// Converts pixel coordinates with respect to the overall image and to
     /// coordinates in the film tile and returns the correspdoning pixel.
     ///
@@ -134,4 +170,22 @@ pub struct FilmTilePixel {
 
     /// Sum of filter weights.
     pub filter_weight_sum: Float,
+
+    /// Sum of filter weighted alpha values.
+    pub alpha_sum: Float,
+
+    /// Luminance variance of samples taken within the pixel.
+    pub variance: VarianceEstimator,
+
+    /// Variance of the RGB channels of samples taken within the pixel.
+    pub rgb_variance: [VarianceEstimator; 3],
+
+    /// Sum of albedo values of samples taken within the pixel.
+    pub albedo_sum: Spectrum,
+
+    /// Sum of shading normals of samples taken within the pixel.
+    pub normal_sum: Normal3f,
+
+    /// Number of samples contributing to `albedo_sum` and `normal_sum`.
+    pub aov_weight_sum: Float,
 }
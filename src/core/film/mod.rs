@@ -10,10 +10,17 @@ use crate::core::pbrt::*;
 use crate::core::spectrum::*;
 use std::sync::{Arc, RwLock};
 
+mod checkpoint;
+mod color_settings;
+mod denoiser;
 mod film_tile;
+mod variance_estimator;
 
 // Re-export.
+pub use color_settings::*;
+pub use denoiser::*;
 pub use film_tile::*;
+pub use variance_estimator::*;
 
 /// Filter table width.
 pub const FILTER_TABLE_WIDTH: usize = 16;
@@ -39,9 +46,26 @@ pub struct Pixel {
     /// Holds an unweighted sum of sample splats.
     pub splat_xyz: [Float; 3],
 
-    /// Used to pad this struct to the 32-bit/64-bit. This will work for both
-    /// `Float` => `f32` and `Float` => `f64`.
-    pad: Float,
+    /// Holds the sum of filter weighted alpha (coverage) values for the
+    /// sample contributions to the pixel. This also pads this struct to
+    /// 32-bit/64-bit for both `Float` => `f32` and `Float` => `f64`.
+    pub alpha_sum: Float,
+}
+
+/// Auxiliary pixel data used to guide the denoiser.
+#[derive(Copy, Clone, Default)]
+pub struct PixelAOV {
+    /// Sum of RGB albedo values at the first intersection.
+    pub albedo: [Float; 3],
+
+    /// Sum of shading normals at the first intersection.
+    pub normal: [Float; 3],
+
+    /// Number of samples contributing to `albedo` and `normal`.
+    pub weight_sum: Float,
+
+    /// Variance of the RGB channels of samples taken within the pixel.
+    pub variance: [VarianceEstimator; 3],
 }
 
 /// Models the sensing device in a simulated camera. It stores all of the sample
@@ -74,6 +98,18 @@ pub struct Film {
 
     /// Stores the image pixels.
     pixels: Arc<RwLock<Vec<Pixel>>>,
+
+    /// Stores the luminance variance of samples taken within each pixel.
+    pixel_variance: Arc<RwLock<Vec<VarianceEstimator>>>,
+
+    /// Stores the auxiliary data used to guide the denoiser.
+    pixel_aovs: Arc<RwLock<Vec<PixelAOV>>>,
+
+    /// Denoiser applied to images after they are written.
+    denoiser: Option<Denoiser>,
+
+    /// Controls conversion of pixel values to output image values.
+    color: ColorSettings,
 }
 
 impl Film {
@@ -90,6 +126,9 @@ impl Film {
     ///                            None specified, sets to 1.0.
     /// * `max_sample_luminance` - Optional maximum sample luminence to use use.
     ///                            Defaults to `INFINITY`.
+    /// * `denoiser`             - Optional denoiser to apply to written images.
+    /// * `color`                - Controls conversion of pixel values to
+    ///                            output image values.
     pub fn new(
         resolution: &Point2i,
         crop_window: &Bounds2f,
@@ -98,6 +137,8 @@ impl Film {
         filename: &str,
         scale: Option<Float>,
         max_sample_luminance: Option<Float>,
+        denoiser: Option<Denoiser>,
+        color: ColorSettings,
     ) -> Self {
         // Compute the film image bounds.
         let cropped_pixel_bounds = Bounds2i::new(
@@ -119,8 +160,8 @@ impl Film {
         for y in 0..FILTER_TABLE_WIDTH {
             for x in 0..FILTER_TABLE_WIDTH {
                 let p = Point2f::new(
-                    (x as Float + 0.5) * filter_data.radius.x / INV_FILTER_TABLE_WIDTH,
-                    (y as Float + 0.5) * filter_data.radius.y / INV_FILTER_TABLE_WIDTH,
+                    (x as Float + 0.5) * filter_data.radius.x * INV_FILTER_TABLE_WIDTH,
+                    (y as Float + 0.5) * filter_data.radius.y * INV_FILTER_TABLE_WIDTH,
                 );
                 filter_table[offset] = filter.evaluate(&p);
                 offset += 1;
@@ -130,6 +171,8 @@ impl Film {
         // Allocate film image storage.
         let n = cropped_pixel_bounds.area() as usize;
         let pixels = Arc::new(RwLock::new(vec![Pixel::default(); n]));
+        let pixel_variance = Arc::new(RwLock::new(vec![VarianceEstimator::default(); n]));
+        let pixel_aovs = Arc::new(RwLock::new(vec![PixelAOV::default(); n]));
 
         Self {
             full_resolution: *resolution,
@@ -147,6 +190,10 @@ impl Film {
                 None => INFINITY,
             },
             pixels,
+            pixel_variance,
+            pixel_aovs,
+            denoiser,
+            color,
         }
     }
 
@@ -187,11 +234,11 @@ impl Film {
         offset as usize
     }
 
-    /// Returns an `Arc<FilmTile>` that stores the contributions for pixels in
-    /// the specified region of the image.
+    /// Returns a `FilmTile` that stores the contributions for pixels in the
+    /// specified region of the image.
     ///
     /// * `sample_bounds` - Tile region in the overall image.
-    pub fn get_film_tile(&self, sample_bounds: Bounds2i) -> Arc<FilmTile> {
+    pub fn get_film_tile(&self, sample_bounds: Bounds2i) -> FilmTile {
         let filter_data = self.filter.get_data();
         let half_pixel = Vector2f::new(0.5, 0.5);
 
@@ -202,12 +249,12 @@ impl Film {
             + Point2i::new(1, 1);
         let tile_pixel_bounds = Bounds2i::new(p0, p1).intersect(&self.cropped_pixel_bounds);
 
-        Arc::new(FilmTile::new(
+        FilmTile::new(
             tile_pixel_bounds,
             filter_data.radius,
             &self.filter_table,
             Some(self.max_sample_luminance),
-        ))
+        )
     }
 
     /// Clear the splats for all pixels in the image.
@@ -219,11 +266,14 @@ impl Film {
         }
     }
 
-    /// Merge the `FilmTile`'s pixel contribution into the image.
+    /// Merge the `FilmTile`'s pixel contribution into the image. Tiles can be
+    /// merged from multiple rendering threads.
     ///
     /// * `tile` - The `FilmTile` to merge.
-    pub fn merge_film_tile(&mut self, tile: Arc<FilmTile>) {
+    pub fn merge_film_tile(&self, tile: &FilmTile) {
         let mut pixels = self.pixels.write().unwrap();
+        let mut pixel_variance = self.pixel_variance.write().unwrap();
+        let mut pixel_aovs = self.pixel_aovs.write().unwrap();
         for pixel in tile.get_pixel_bounds() {
             let tile_pixel = tile.get_pixel_offset(&pixel);
             let merge_pixel = self.get_pixel_offset(&pixel);
@@ -232,9 +282,51 @@ impl Film {
                 (*pixels)[merge_pixel].xyz[i] += xyz[i];
             }
             (*pixels)[merge_pixel].filter_weight_sum += tile.pixels[tile_pixel].filter_weight_sum;
+            (*pixels)[merge_pixel].alpha_sum += tile.pixels[tile_pixel].alpha_sum;
+            (*pixel_variance)[merge_pixel].merge(&tile.pixels[tile_pixel].variance);
+
+            let tile_pixel = &tile.pixels[tile_pixel];
+            let aov = &mut (*pixel_aovs)[merge_pixel];
+            for c in 0..3 {
+                aov.variance[c].merge(&tile_pixel.rgb_variance[c]);
+            }
+            if tile_pixel.aov_weight_sum > 0.0 {
+                let albedo = tile_pixel.albedo_sum.to_rgb();
+                for i in 0..3 {
+                    aov.albedo[i] += albedo[i];
+                    aov.normal[i] += tile_pixel.normal_sum[i];
+                }
+                aov.weight_sum += tile_pixel.aov_weight_sum;
+            }
         }
     }
 
+    /// Returns `true` if auxiliary data for the denoiser should be recorded
+    /// with `FilmTile::add_aov()`.
+    pub fn needs_aovs(&self) -> bool {
+        self.denoiser.is_some()
+    }
+
+    /// Returns `true` if the output image stores an alpha channel and sample
+    /// coverage should be passed to `FilmTile::add_sample()`.
+    pub fn needs_alpha(&self) -> bool {
+        self.color.alpha
+    }
+
+    /// Returns a flag for each pixel in the cropped area indicating whether
+    /// the relative error of its sample luminance is below a threshold.
+    ///
+    /// * `max_error`   - Maximum relative error.
+    /// * `min_samples` - Minimum number of samples needed to estimate the
+    ///                   error of a pixel.
+    pub fn get_converged_pixels(&self, max_error: Float, min_samples: usize) -> Vec<bool> {
+        let pixel_variance = self.pixel_variance.read().unwrap();
+        pixel_variance
+            .iter()
+            .map(|v| v.n as usize >= min_samples && v.relative_error() < max_error)
+            .collect()
+    }
+
     /// Sets all pixel values in the cropped area with the given spectrum values.
     ///
     /// * `img` - The spectrum values for the cropped area.
@@ -244,6 +336,7 @@ impl Film {
         for i in (0..n_pixels).map(|i| i as usize) {
             (*pixels)[i].xyz = img[i].to_xyz();
             (*pixels)[i].filter_weight_sum = 1.0;
+            (*pixels)[i].alpha_sum = 1.0;
             (*pixels)[i].splat_xyz = [0.0; 3];
         }
     }
@@ -294,23 +387,46 @@ impl Film {
         }
     }
 
-    /// Write the image to an output file.
+    /// Write the image to an output file. This can be called while rendering
+    /// is in progress.
     ///
     /// * `splat_scale` - Scale factor provided to `add_splat()`.
     pub fn write_image(&self, splat_scale: Float) {
+        self.write_output(splat_scale, false);
+    }
+
+    /// Write the image of a finished render to an output file. If the film
+    /// has a denoiser, the denoised image is written as well.
+    ///
+    /// * `splat_scale` - Scale factor provided to `add_splat()`.
+    pub fn write_final_image(&self, splat_scale: Float) {
+        self.write_output(splat_scale, true);
+    }
+
+    /// Write the image to an output file and optionally the denoised image.
+    ///
+    /// * `splat_scale` - Scale factor provided to `add_splat()`.
+    /// * `denoise`     - Also write the denoised image.
+    fn write_output(&self, splat_scale: Float, denoise: bool) {
         info!("Converting image to RGB and computing final weighted pixel values");
 
-        let mut pixels = self.pixels.write().unwrap();
+        let pixels = self.pixels.read().unwrap();
 
         let n = 3 * self.cropped_pixel_bounds.area() as usize;
         let mut rgb = vec![0.0; n];
+        let mut alpha = vec![1.0; n / 3];
 
+        let scale = self.scale * self.color.exposure_scale();
         let mut offset = 0;
         for p in self.cropped_pixel_bounds {
-            // Convert pixel XYZ color to RGB.
+            // Convert pixel XYZ color to RGB in the output color space. The
+            // pixel itself is left as is so the image can be written again
+            // while rendering is in progress.
             let pixel_offset = self.get_pixel_offset(&p);
-            (*pixels)[pixel_offset].xyz =
-                xyz_to_rgb(&[rgb[3 * offset], rgb[3 * offset + 1], rgb[3 * offset + 2]]);
+            let pixel_rgb = self.color.xyz_to_rgb(&(*pixels)[pixel_offset].xyz);
+            rgb[3 * offset] = pixel_rgb[0];
+            rgb[3 * offset + 1] = pixel_rgb[1];
+            rgb[3 * offset + 2] = pixel_rgb[2];
 
             // Normalize pixel with weight sum.
             let filter_weight_sum = (*pixels)[pixel_offset].filter_weight_sum;
@@ -319,26 +435,125 @@ impl Film {
                 rgb[3 * offset] = max(0.0, rgb[3 * offset] * inv_wt);
                 rgb[3 * offset + 1] = max(0.0, rgb[3 * offset + 1] * inv_wt);
                 rgb[3 * offset + 2] = max(0.0, rgb[3 * offset + 2] * inv_wt);
+                alpha[offset] = clamp((*pixels)[pixel_offset].alpha_sum * inv_wt, 0.0, 1.0);
             }
 
             // Add splat value at pixel.
-            let splat_rgb = xyz_to_rgb(&(*pixels)[pixel_offset].splat_xyz);
+            let splat_rgb = self.color.xyz_to_rgb(&(*pixels)[pixel_offset].splat_xyz);
             rgb[3 * offset] += splat_scale * splat_rgb[0];
             rgb[3 * offset + 1] += splat_scale * splat_rgb[1];
             rgb[3 * offset + 2] += splat_scale * splat_rgb[2];
 
-            // Scale pixel value by `scale`.
-            rgb[3 * offset] *= self.scale;
-            rgb[3 * offset + 1] *= self.scale;
-            rgb[3 * offset + 2] *= self.scale;
+            // Scale pixel value by `scale` and exposure.
+            rgb[3 * offset] *= scale;
+            rgb[3 * offset + 1] *= scale;
+            rgb[3 * offset + 2] *= scale;
 
             offset += 1;
         }
 
         // Write RGB image
-        if let Err(err) = write_image(&self.filename, &rgb, &self.cropped_pixel_bounds) {
+        let alpha = if self.needs_alpha() {
+            Some(&alpha[..])
+        } else {
+            None
+        };
+        if let Err(err) = write_image(
+            &self.filename,
+            &rgb,
+            alpha,
+            &self.cropped_pixel_bounds,
+            self.color.tone_map,
+            self.color.bit_depth,
+        ) {
             panic!("Error writing output image {}. {:}.", self.filename, err);
         }
+
+        // Release the pixels before denoising so rendering can continue.
+        drop(pixels);
+
+        if let (true, Some(denoiser)) = (denoise, self.denoiser) {
+            self.write_denoised_image(&denoiser, &rgb, alpha);
+        }
+    }
+
+    /// Denoise the RGB image and write it to an output file alongside the
+    /// original image.
+    ///
+    /// * `denoiser` - The denoiser.
+    /// * `rgb`      - Final RGB pixel values written by `write_image()`.
+    /// * `alpha`    - Optional alpha values written by `write_image()`.
+    fn write_denoised_image(&self, denoiser: &Denoiser, rgb: &[Float], alpha: Option<&[Float]>) {
+        let n = self.cropped_pixel_bounds.area() as usize;
+
+        // Average the guide buffers.
+        let mut albedo = vec![0.0; 3 * n];
+        let mut normal = vec![0.0; 3 * n];
+        {
+            let pixel_aovs = self.pixel_aovs.read().unwrap();
+            for (i, aov) in pixel_aovs.iter().enumerate() {
+                if aov.weight_sum > 0.0 {
+                    let n = Normal3f::new(aov.normal[0], aov.normal[1], aov.normal[2]);
+                    let n = if n.length_squared() > 0.0 {
+                        n.normalize()
+                    } else {
+                        n
+                    };
+                    for c in 0..3 {
+                        albedo[3 * i + c] = aov.albedo[c] / aov.weight_sum;
+                        normal[3 * i + c] = n[c];
+                    }
+                }
+            }
+        }
+
+        // Use the variance of the mean of the pixel estimates averaged over
+        // the RGB channels.
+        let variance: Vec<Float> = {
+            let pixel_aovs = self.pixel_aovs.read().unwrap();
+            let scale = self.scale * self.color.exposure_scale();
+            let scale2 = scale * scale;
+            pixel_aovs
+                .iter()
+                .map(|aov| {
+                    aov.variance
+                        .iter()
+                        .filter(|v| v.n > 0)
+                        .map(|v| scale2 * v.variance() / v.n as Float)
+                        .sum::<Float>()
+                        / 3.0
+                })
+                .collect()
+        };
+
+        let guides = DenoiserGuides {
+            albedo: &albedo,
+            normal: &normal,
+            variance: &variance,
+        };
+        let resolution = self.cropped_pixel_bounds.diagonal();
+        let denoised = denoiser.denoise(rgb, &guides, resolution.x as usize, resolution.y as usize);
+
+        let filename = self.get_denoised_filename();
+        if let Err(err) = write_image(
+            &filename,
+            &denoised,
+            alpha,
+            &self.cropped_pixel_bounds,
+            self.color.tone_map,
+            self.color.bit_depth,
+        ) {
+            panic!("Error writing output image {}. {:}.", filename, err);
+        }
+    }
+
+    /// Returns the path of the denoised output image. The suffix `_denoised`
+    /// is added to the file name of the output image.
+    pub fn get_denoised_filename(&self) -> String {
+        match self.filename.rfind('.') {
+            Some(i) => format!("{}_denoised{}", &self.filename[..i], &self.filename[i..]),
+            None => format!("{}_denoised", self.filename),
+        }
     }
 }
 
@@ -399,6 +614,14 @@ impl From<(&ParamSet, ArcFilter)> for Film {
         let scale = params.find_one_float("scale", 1.0);
         let diagonal = params.find_one_float("diagonal", 35.0);
         let max_sample_luminance = params.find_one_float("maxsampleluminance", INFINITY);
+
+        let denoiser = if params.find_one_bool("denoise", false) {
+            let radius = params.find_one_int("denoiseradius", DEFAULT_DENOISE_RADIUS as Int);
+            Some(Denoiser::new(max(1, radius) as usize))
+        } else {
+            None
+        };
+
         Self::new(
             &Point2i::new(xres, yres),
             &crop,
@@ -407,6 +630,99 @@ impl From<(&ParamSet, ArcFilter)> for Film {
             &filename,
             Some(scale),
             Some(max_sample_luminance),
+            denoiser,
+            ColorSettings::from(params),
         )
     }
 }
+
+// ----------------------------------------------------------------------------
+// Tests
+// ----------------------------------------------------------------------------
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::filters::*;
+
+    /// Returns a 2x1 film with a box filter.
+    fn film() -> Film {
+        film_with(ParamSet::new())
+    }
+
+    /// Returns a 2x1 film with a box filter and additional parameters.
+    fn film_with(mut params: ParamSet) -> Film {
+        params.add_int("xresolution", &[2]);
+        params.add_int("yresolution", &[1]);
+        Film::from((
+            &params,
+            Arc::new(BoxFilter::from(&ParamSet::new())) as ArcFilter,
+        ))
+    }
+
+    #[test]
+    fn converged_pixels_need_min_samples_and_low_error() {
+        let film = film();
+        let mut tile = film.get_film_tile(film.get_sample_bounds());
+
+        // Pixel 0 gets constant samples and pixel 1 noisy ones.
+        for i in 0..4 {
+            let v = if i % 2 == 0 { 0.1 } else { 10.0 };
+            tile.add_sample(Point2f::new(0.5, 0.5), Spectrum::new(1.0), 1.0, 1.0);
+            tile.add_sample(Point2f::new(1.5, 0.5), Spectrum::new(v), 1.0, 1.0);
+        }
+        film.merge_film_tile(&tile);
+
+        assert_eq!(film.get_converged_pixels(0.1, 4), vec![true, false]);
+        assert_eq!(film.get_converged_pixels(0.1, 5), vec![false, false]);
+        assert_eq!(film.get_converged_pixels(10.0, 2), vec![true, true]);
+    }
+
+    #[test]
+    fn variance_is_tracked_in_the_pixel_containing_the_sample() {
+        let film = film();
+        let mut tile = film.get_film_tile(film.get_sample_bounds());
+        tile.add_sample(Point2f::new(1.0, 0.5), Spectrum::new(1.0), 1.0, 1.0);
+        film.merge_film_tile(&tile);
+
+        let pixel_variance = film.pixel_variance.read().unwrap();
+        assert_eq!((pixel_variance[0].n, pixel_variance[1].n), (0, 1));
+    }
+
+    #[test]
+    fn tracks_rgb_variance_for_the_denoiser() {
+        let film = film();
+        let mut tile = film.get_film_tile(film.get_sample_bounds());
+        let p = Point2f::new(0.5, 0.5);
+        tile.add_sample(p, Spectrum::from_rgb(&[1.0, 2.0, 0.0], None), 1.0, 1.0);
+        tile.add_sample(p, Spectrum::from_rgb(&[3.0, 2.0, 0.0], None), 1.0, 1.0);
+        film.merge_film_tile(&tile);
+
+        let pixel_aovs = film.pixel_aovs.read().unwrap();
+        let variance = &pixel_aovs[0].variance;
+        assert!((variance[0].variance() - 2.0).abs() < 1e-4);
+        assert!(variance[1].variance().abs() < 1e-4);
+        assert_eq!(variance[2].variance(), 0.0);
+        assert_eq!(pixel_aovs[1].variance[0].n, 0);
+    }
+
+    #[test]
+    fn only_final_images_are_denoised() {
+        let path = std::env::temp_dir().join(format!("denoise_final_{}.pfm", std::process::id()));
+        let mut params = ParamSet::new();
+        params.add_string("filename", &[path.to_str().unwrap().to_owned()]);
+        params.add_bool("denoise", &[true]);
+        let film = film_with(params);
+        let denoised = film.get_denoised_filename();
+
+        film.write_image(1.0);
+        assert!(path.exists());
+        assert!(!std::path::Path::new(&denoised).exists());
+
+        film.write_final_image(1.0);
+        assert!(std::path::Path::new(&denoised).exists());
+
+        let _ = std::fs::remove_file(&path);
+        let _ = std::fs::remove_file(&denoised);
+    }
+}
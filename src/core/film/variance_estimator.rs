@@ -0,0 +1,127 @@
+//! Variance Estimator
+
+use crate::core::pbrt::*;
+
+/// Tracks the running mean and variance of sample values using Welford's
+/// algorithm.
+#[derive(Copy, Clone, Default, Debug)]
+pub struct VarianceEstimator {
+    /// Number of samples.
+    pub n: u32,
+
+    /// Mean of sample values.
+    pub mean: Float,
+
+    /// Sum of squared differences from the mean.
+    pub m2: Float,
+}
+
+impl VarianceEstimator {
+    /// Add a sample value.
+    ///
+    /// * `x` - The sample value.
+    pub fn add(&mut self, x: Float) {
+        self.n += 1;
+        let delta = x - self.mean;
+        self.mean += delta / self.n as Float;
+        self.m2 += delta * (x - self.mean);
+    }
+
+    /// Merge the samples tracked by another estimator.
+    ///
+    /// * `other` - The other estimator.
+    pub fn merge(&mut self, other: &Self) {
+        if other.n == 0 {
+            return;
+        }
+
+        let n = self.n + other.n;
+        let delta = other.mean - self.mean;
+        self.mean += delta * other.n as Float / n as Float;
+        self.m2 += other.m2 + delta * delta * self.n as Float * other.n as Float / n as Float;
+        self.n = n;
+    }
+
+    /// Returns the sample variance.
+    pub fn variance(&self) -> Float {
+        if self.n > 1 {
+            self.m2 / (self.n - 1) as Float
+        } else {
+            0.0
+        }
+    }
+
+    /// Returns the standard error of the mean relative to the mean. If the
+    /// mean is zero, this is zero if all samples were zero and infinity
+    /// otherwise.
+    pub fn relative_error(&self) -> Float {
+        if self.n == 0 {
+            return INFINITY;
+        }
+
+        let std_error = (self.variance() / self.n as Float).sqrt();
+        if self.mean != 0.0 {
+            std_error / abs(self.mean)
+        } else if std_error == 0.0 {
+            0.0
+        } else {
+            INFINITY
+        }
+    }
+}
+
+// ----------------------------------------------------------------------------
+// Tests
+// ----------------------------------------------------------------------------
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    const VALUES: [Float; 6] = [1.0, 3.0, 2.0, 7.0, 4.0, 1.0];
+
+    fn estimator(values: &[Float]) -> VarianceEstimator {
+        let mut v = VarianceEstimator::default();
+        for &x in values.iter() {
+            v.add(x);
+        }
+        v
+    }
+
+    #[test]
+    fn matches_sample_variance() {
+        let v = estimator(&VALUES);
+        let n = VALUES.len() as Float;
+        let mean = VALUES.iter().sum::<Float>() / n;
+        let variance = VALUES
+            .iter()
+            .map(|x| (x - mean) * (x - mean))
+            .sum::<Float>()
+            / (n - 1.0);
+        assert_eq!(v.n, 6);
+        assert!((v.mean - mean).abs() < 1e-5);
+        assert!((v.variance() - variance).abs() < 1e-5);
+        assert!((v.relative_error() - (variance / n).sqrt() / mean).abs() < 1e-5);
+    }
+
+    #[test]
+    fn merge_matches_adding_all_samples() {
+        let all = estimator(&VALUES);
+        for split in 0..=VALUES.len() {
+            let mut v = estimator(&VALUES[..split]);
+            v.merge(&estimator(&VALUES[split..]));
+            assert_eq!(v.n, all.n);
+            assert!((v.mean - all.mean).abs() < 1e-5);
+            assert!((v.m2 - all.m2).abs() < 1e-4);
+        }
+    }
+
+    #[test]
+    fn relative_error_edge_cases() {
+        assert_eq!(estimator(&[]).relative_error(), INFINITY);
+        assert_eq!(estimator(&[2.0]).variance(), 0.0);
+        assert_eq!(estimator(&[0.0, 0.0]).relative_error(), 0.0);
+        assert_eq!(estimator(&[-1.0, 1.0]).relative_error(), INFINITY);
+        assert_eq!(estimator(&[5.0, 5.0, 5.0]).relative_error(), 0.0);
+    }
+}
@@ -1308,6 +1308,49 @@ impl AnimatedTransform {
         })
     }
 
+    /// Compute bounding boxes at the start and end of a time interval such
+    /// that linearly interpolating them encompasses the motion of a bounding
+    /// box over the interval.
+    ///
+    /// * `b`     - The bounding box.
+    /// * `time0` - Start of the time interval.
+    /// * `time1` - End of the time interval.
+    pub fn motion_bounds_linear(
+        &self,
+        b: &Bounds3f,
+        time0: Float,
+        time1: Float,
+    ) -> (Bounds3f, Bounds3f) {
+        if !self.actually_animated {
+            let bounds = self.start_transform.transform_bounds(b);
+            return (bounds, bounds);
+        }
+
+        // Motion is clamped outside the animation time period.
+        let t0 = clamp(time0, self.start_time, self.end_time);
+        let t1 = clamp(time1, self.start_time, self.end_time);
+        let start_transform = self.interpolate(t0);
+        if t1 <= t0 {
+            let bounds = start_transform.transform_bounds(b);
+            return (bounds, bounds);
+        }
+
+        let end_transform = self.interpolate(t1);
+        if !self.has_rotation {
+            let b0 = start_transform.transform_bounds(b);
+            let b1 = end_transform.transform_bounds(b);
+            if t0 == time0 && t1 == time1 {
+                // Box corners move linearly without rotation.
+                return (b0, b1);
+            }
+            let bounds = b0.union(&b1);
+            return (bounds, bounds);
+        }
+
+        let bounds = Self::new(start_transform, end_transform, t0, t1).motion_bounds(b);
+        (bounds, bounds)
+    }
+
     /// Use the coefficients `ki`, based on the keyframe matrices to compute
     /// a robust bound of the motion of point `p`.
     ///
@@ -1374,7 +1417,6 @@ fn decompose(m: &Matrix4x4, t: &mut Vector3f, r_quat: &mut Quaternion, s: &mut M
 
     // Extract rotation R from transformation matrix
     let mut r = m1;
-    let mut norm = 0.0;
     let mut count = 0;
     loop {
         // Compute the next matrix R_next in series
@@ -1387,6 +1429,7 @@ fn decompose(m: &Matrix4x4, t: &mut Vector3f, r_quat: &mut Quaternion, s: &mut M
         }
 
         // Compute norm of difference between R and R_next
+        let mut norm = 0.0;
         for i in 0..3 {
             let n = abs(r[i][0] - r_next[i][0])
                 + abs(r[i][1] - r_next[i][1])
@@ -1397,7 +1440,7 @@ fn decompose(m: &Matrix4x4, t: &mut Vector3f, r_quat: &mut Quaternion, s: &mut M
         r = r_next;
 
         count += 1;
-        if count >= 100 && norm <= 0.0001 {
+        if count >= 100 || norm <= 0.0001 {
             break;
         }
     }
@@ -1430,3 +1473,32 @@ impl DerivativeTerm {
         self.kc + self.kx * p.x + self.ky * p.y + self.kz * p.z
     }
 }
+
+// ----------------------------------------------------------------------------
+// Tests
+// ----------------------------------------------------------------------------
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn trs(x: Float, theta: Float, s: Float) -> Transform {
+        Transform::translate(&Vector3f::new(x, 0.0, 0.0))
+            * Transform::rotate_z(theta)
+            * Transform::scale(s, s, s)
+    }
+
+    #[test]
+    fn interpolate_rotation_and_scale() {
+        let at = AnimatedTransform::new(
+            Arc::new(trs(0.0, 30.0, 2.0)),
+            Arc::new(trs(4.0, 90.0, 2.0)),
+            0.0,
+            1.0,
+        );
+        let p = Point3f::new(1.0, 0.5, -0.25);
+        let expected = trs(2.0, 60.0, 2.0).transform_point(&p);
+        let actual = at.interpolate(0.5).transform_point(&p);
+        assert!((actual - expected).length() < 1e-4, "{} != {}", actual, expected);
+    }
+}
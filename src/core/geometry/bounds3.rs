@@ -254,14 +254,14 @@ impl<T: Num + Copy> Bounds3<T> {
         }
     }
 
-    /// Returns the coordinates of one of the four corners.
+    /// Returns the coordinates of one of the eight corners.
     ///
-    /// * `corner` -
+    /// * `corner` - The corner (0-7).
     pub fn corner(&self, corner: u8) -> Point3<T>
     where
         T: Copy,
     {
-        debug_assert!(corner <= 4);
+        debug_assert!(corner < 8);
         let x = corner & 1;
         let y = if corner & 2 == 0 { 0 } else { 1 };
         let z = if corner & 4 == 0 { 0 } else { 1 };
@@ -0,0 +1,85 @@
+//! Geometry Hashing
+
+#![allow(dead_code)]
+use super::*;
+use crate::core::pbrt::*;
+
+/// FNV-1a offset basis.
+const FNV_OFFSET_BASIS: u64 = 0xcbf29ce484222325;
+
+/// FNV-1a prime.
+const FNV_PRIME: u64 = 0x100000001b3;
+
+/// Computes a hash of geometry for validating cached data. Unlike
+/// `DefaultHasher`, FNV-1a gives the same hash across builds so the hashes
+/// can be stored in files.
+#[derive(Copy, Clone, Debug)]
+pub struct GeometryHasher {
+    /// The hash of the values written so far.
+    hash: u64,
+}
+
+impl GeometryHasher {
+    /// Create a new hasher.
+    pub fn new() -> Self {
+        Self {
+            hash: FNV_OFFSET_BASIS,
+        }
+    }
+
+    /// Returns the hash of the values written so far.
+    pub fn finish(&self) -> u64 {
+        self.hash
+    }
+
+    /// Hash an integer.
+    ///
+    /// * `v` - The value.
+    pub fn write_u64(&mut self, v: u64) {
+        for byte in v.to_le_bytes().iter() {
+            self.hash = (self.hash ^ *byte as u64).wrapping_mul(FNV_PRIME);
+        }
+    }
+
+    /// Hash a floating point value by its bits.
+    ///
+    /// * `v` - The value.
+    pub fn write_float(&mut self, v: Float) {
+        self.write_u64(v.to_bits() as u64);
+    }
+
+    /// Hash a point.
+    ///
+    /// * `p` - The point.
+    pub fn write_point(&mut self, p: &Point3f) {
+        self.write_float(p.x);
+        self.write_float(p.y);
+        self.write_float(p.z);
+    }
+
+    /// Hash a bounding box.
+    ///
+    /// * `b` - The bounding box.
+    pub fn write_bounds(&mut self, b: &Bounds3f) {
+        self.write_point(&b.p_min);
+        self.write_point(&b.p_max);
+    }
+
+    /// Hash a transformation by its matrix.
+    ///
+    /// * `t` - The transformation.
+    pub fn write_transform(&mut self, t: &Transform) {
+        for row in t.m.m.iter() {
+            for v in row.iter() {
+                self.write_float(*v);
+            }
+        }
+    }
+}
+
+impl Default for GeometryHasher {
+    /// Returns a new hasher.
+    fn default() -> Self {
+        Self::new()
+    }
+}
@@ -90,6 +90,7 @@ mod bounds2;
 mod bounds3;
 mod common;
 mod coordinate_system;
+mod hash;
 mod interaction;
 mod interval;
 mod matrix4x4;
@@ -111,6 +112,7 @@ pub use bounds2::*;
 pub use bounds3::*;
 pub use common::*;
 pub use coordinate_system::*;
+pub use hash::*;
 pub use interaction::*;
 pub use interval::*;
 pub use matrix4x4::*;
@@ -24,6 +24,42 @@ pub trait Shape {
             .transform_bounds(&self.object_bound())
     }
 
+    /// Returns a bounding box in the world space of the part of the shape
+    /// inside the given bounds. The result is empty if no part of the shape
+    /// is inside.
+    ///
+    /// Default is to clip the world bounds. Override for tighter bounds.
+    ///
+    /// * `bounds` - The bounds to clip to.
+    fn clipped_world_bound(&self, bounds: &Bounds3f) -> Bounds3f {
+        self.world_bound().intersect(bounds)
+    }
+
+    /// Returns bounding boxes in the world space at the start and end of a
+    /// time interval such that linearly interpolating them bounds the shape
+    /// at any time in the interval.
+    ///
+    /// Default is to return the world bounds for both times.
+    ///
+    /// * `time0` - Start of the time interval.
+    /// * `time1` - End of the time interval.
+    fn motion_world_bounds(&self, _time0: Float, _time1: Float) -> (Bounds3f, Bounds3f) {
+        let bounds = self.world_bound();
+        (bounds, bounds)
+    }
+
+    /// Hash the data that determines the bounds of the shape, including
+    /// clipped and motion bounds. Acceleration structure caches use this to
+    /// detect changed geometry.
+    ///
+    /// Default is to hash the world bounds. Override for shapes whose
+    /// geometry can change without changing their world bounds.
+    ///
+    /// * `hasher` - The hasher.
+    fn hash_geometry(&self, hasher: &mut GeometryHasher) {
+        hasher.write_bounds(&self.world_bound());
+    }
+
     /// Returns geometric details if a ray intersects the shape intersection.
     /// If there is no intersection, `None` is returned.
     ///
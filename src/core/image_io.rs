@@ -4,10 +4,17 @@
 use crate::core::geometry::*;
 use crate::core::pbrt::*;
 use crate::core::spectrum::*;
+use ::tiff::decoder::{Decoder as TiffDecoder, DecodingResult};
+use ::tiff::encoder::{colortype as tiff_colortype, TiffEncoder};
+use ::tiff::ColorType as TiffColorType;
+use byteorder::{BigEndian, LittleEndian, ReadBytesExt, WriteBytesExt};
 use exr::prelude as exrs;
 use exr::prelude::*;
+use image::codecs::hdr::{HdrDecoder, HdrEncoder};
 use image::*;
 use regex::Regex;
+use std::fs::File;
+use std::io::{BufReader, BufWriter, Read, Write};
 use std::result::Result;
 
 /// Stores RGB image data.
@@ -17,6 +24,68 @@ pub struct RGBImage {
 
     /// Image resolution.
     pub resolution: Point2<usize>,
+
+    /// Alpha values of the pixels if the image has an alpha channel.
+    pub alpha: Option<Vec<Float>>,
+}
+
+/// Tone mapping operators that map high dynamic range RGB values to the
+/// displayable range for 8-bit image formats.
+#[derive(Copy, Clone, Debug, PartialEq)]
+pub enum ToneMapOperator {
+    /// Values are clamped.
+    Clamp,
+
+    /// Reinhard's global operator applied to luminance.
+    Reinhard,
+
+    /// Filmic curve fitted to the ACES reference rendering transform.
+    ACES,
+}
+
+impl Default for ToneMapOperator {
+    /// Returns the default operator, `ToneMapOperator::Clamp`.
+    fn default() -> Self {
+        Self::Clamp
+    }
+}
+
+impl ToneMapOperator {
+    /// Returns the tone mapped RGB value.
+    ///
+    /// * `rgb` - Linear RGB value.
+    pub fn apply(&self, rgb: &[Float; 3]) -> [Float; 3] {
+        match self {
+            Self::Clamp => *rgb,
+            Self::Reinhard => {
+                let l = 0.2126 * rgb[0] + 0.7152 * rgb[1] + 0.0722 * rgb[2];
+                if l > 0.0 {
+                    let s = 1.0 / (1.0 + l);
+                    [rgb[0] * s, rgb[1] * s, rgb[2] * s]
+                } else {
+                    *rgb
+                }
+            }
+            Self::ACES => [
+                aces_filmic(rgb[0]),
+                aces_filmic(rgb[1]),
+                aces_filmic(rgb[2]),
+            ],
+        }
+    }
+}
+
+/// Returns the value of Krzysztof Narkowicz's fit of the ACES filmic curve.
+///
+/// * `x` - Linear value.
+#[inline]
+fn aces_filmic(x: Float) -> Float {
+    let x = max(0.0, x);
+    clamp(
+        (x * (2.51 * x + 0.03)) / (x * (2.43 * x + 0.59) + 0.14),
+        0.0,
+        1.0,
+    )
 }
 
 /// Read an image.
@@ -25,7 +94,10 @@ pub struct RGBImage {
 pub fn read_image(path: &str) -> Result<RGBImage, String> {
     match get_extension_from_filename(path) {
         Some(".exr") => read_exr(path),
-        Some(_extension) => read_8_bit(path),
+        Some(".pfm") => read_pfm(path),
+        Some(".hdr") => read_hdr(path),
+        Some(".tif") | Some(".tiff") => read_tiff(path),
+        Some(_extension) => read_fixed_point(path),
         None => Err(format!(
             "Can't determine file type from suffix of filename {}.",
             path
@@ -44,9 +116,11 @@ fn read_exr(path: &str) -> Result<RGBImage, String> {
             |layer_info: &exrs::RgbaChannelsInfo| {
                 let width = layer_info.resolution.width();
                 let height = layer_info.resolution.height();
+                // Pixels without an alpha value are opaque.
                 RGBImage {
                     pixels: vec![RGBSpectrum::default(); width * height],
                     resolution: Point2::new(width, height),
+                    alpha: Some(vec![1.0; width * height]),
                 }
             },
             |img: &mut RGBImage, position: exrs::Vec2<usize>, pixel: exrs::RgbaPixel| {
@@ -56,6 +130,9 @@ fn read_exr(path: &str) -> Result<RGBImage, String> {
                     pixel.green.to_f32(),
                     pixel.blue.to_f32(),
                 ]);
+                if let (Some(alpha), Some(a)) = (img.alpha.as_mut(), pixel.alpha) {
+                    alpha[offset] = a.to_f32();
+                }
             },
         )
         .first_valid_layer()
@@ -68,13 +145,13 @@ fn read_exr(path: &str) -> Result<RGBImage, String> {
     }
 }
 
-/// Read an 8-bit image format.
+/// Read an 8-bit or 16-bit image format. 16-bit images keep their full
+/// precision.
 ///
 /// * `path` - Input file path.
-fn read_8_bit(path: &str) -> Result<RGBImage, String> {
-    // Read image and convert to RGB.
-    let img: RgbImage = match open(path) {
-        Ok(i) => i.into_rgb8(),
+fn read_fixed_point(path: &str) -> Result<RGBImage, String> {
+    let img = match open(path) {
+        Ok(i) => i,
         Err(err) => return Err(format!("{:}", err)),
     };
 
@@ -83,36 +160,278 @@ fn read_8_bit(path: &str) -> Result<RGBImage, String> {
     let height = img.height() as usize;
     let resolution = Point2::new(width, height);
 
-    // Iterate over the coordinates and pixels of the image
-    let pixels: Vec<RGBSpectrum> = img
-        .pixels()
-        .map(|rgb_u8| {
-            RGBSpectrum::from(vec![
-                rgb_u8[0] as Float / 255.0,
-                rgb_u8[1] as Float / 255.0,
-                rgb_u8[2] as Float / 255.0,
-            ])
+    let has_alpha = img.color().has_alpha();
+
+    // Convert to RGBA and iterate over the pixels of the image.
+    let rgba: Vec<[Float; 4]> = match img {
+        DynamicImage::ImageLuma16(_)
+        | DynamicImage::ImageLumaA16(_)
+        | DynamicImage::ImageRgb16(_)
+        | DynamicImage::ImageRgba16(_) => img
+            .into_rgba16()
+            .pixels()
+            .map(|p| {
+                [
+                    p[0] as Float / 65535.0,
+                    p[1] as Float / 65535.0,
+                    p[2] as Float / 65535.0,
+                    p[3] as Float / 65535.0,
+                ]
+            })
+            .collect(),
+        _ => img
+            .into_rgba8()
+            .pixels()
+            .map(|p| {
+                [
+                    p[0] as Float / 255.0,
+                    p[1] as Float / 255.0,
+                    p[2] as Float / 255.0,
+                    p[3] as Float / 255.0,
+                ]
+            })
+            .collect(),
+    };
+
+    let pixels = rgba
+        .iter()
+        .map(|p| RGBSpectrum::from(vec![p[0], p[1], p[2]]))
+        .collect();
+    let alpha = if has_alpha {
+        Some(rgba.iter().map(|p| p[3]).collect())
+    } else {
+        None
+    };
+
+    // Return the `RGBImage`.
+    Ok(RGBImage {
+        pixels,
+        resolution,
+        alpha,
+    })
+}
+
+/// Read a Radiance RGBE (.hdr) file.
+///
+/// * `path` - Input file path.
+fn read_hdr(path: &str) -> Result<RGBImage, String> {
+    let file = match File::open(path) {
+        Ok(file) => file,
+        Err(err) => return Err(format!("Could not open {}. {}", path, err)),
+    };
+
+    let decoder = match HdrDecoder::new(BufReader::new(file)) {
+        Ok(decoder) => decoder,
+        Err(err) => return Err(format!("{:}", err)),
+    };
+
+    // Read metadata.
+    let metadata = decoder.metadata();
+    let resolution = Point2::new(metadata.width as usize, metadata.height as usize);
+
+    let pixels = match decoder.read_image_hdr() {
+        Ok(pixels) => pixels
+            .iter()
+            .map(|rgb| RGBSpectrum::from(vec![rgb[0] as Float, rgb[1] as Float, rgb[2] as Float]))
+            .collect(),
+        Err(err) => return Err(format!("{:}", err)),
+    };
+
+    // Return the `RGBImage`.
+    Ok(RGBImage {
+        pixels,
+        resolution,
+        alpha: None,
+    })
+}
+
+/// Read a TIFF file. 32-bit floating point images keep their full range and
+/// integer images are read as fixed point.
+///
+/// * `path` - Input file path.
+fn read_tiff(path: &str) -> Result<RGBImage, String> {
+    let file = match File::open(path) {
+        Ok(file) => file,
+        Err(err) => return Err(format!("Could not open {}. {}", path, err)),
+    };
+
+    let mut decoder = match TiffDecoder::new(BufReader::new(file)) {
+        Ok(decoder) => decoder,
+        Err(err) => return Err(format!("{:}", err)),
+    };
+
+    // Read metadata.
+    let (width, height) = match decoder.dimensions() {
+        Ok((w, h)) => (w as usize, h as usize),
+        Err(err) => return Err(format!("{:}", err)),
+    };
+    let n_channels = match decoder.colortype() {
+        Ok(TiffColorType::Gray(32)) => 1,
+        Ok(TiffColorType::RGB(32)) => 3,
+        Ok(TiffColorType::RGBA(32)) => 4,
+        Ok(_) => return read_fixed_point(path),
+        Err(err) => return Err(format!("{:}", err)),
+    };
+
+    let values = match decoder.read_image() {
+        Ok(DecodingResult::F32(values)) => values,
+        Ok(_) => return read_fixed_point(path),
+        Err(err) => return Err(format!("{:}", err)),
+    };
+    if values.len() < n_channels * width * height {
+        return Err(format!("Premature end of TIFF data in {}.", path));
+    }
+
+    let pixels = values
+        .chunks_exact(n_channels)
+        .take(width * height)
+        .map(|p| {
+            if n_channels == 1 {
+                RGBSpectrum::new(p[0] as Float)
+            } else {
+                RGBSpectrum::from(vec![p[0] as Float, p[1] as Float, p[2] as Float])
+            }
         })
         .collect();
+    let alpha = if n_channels == 4 {
+        Some(
+            values
+                .chunks_exact(4)
+                .take(width * height)
+                .map(|p| p[3] as Float)
+                .collect(),
+        )
+    } else {
+        None
+    };
+
+    // Return the `RGBImage`.
+    Ok(RGBImage {
+        pixels,
+        resolution: Point2::new(width, height),
+        alpha,
+    })
+}
+
+/// Read a Portable Float Map (.pfm) file.
+///
+/// * `path` - Input file path.
+fn read_pfm(path: &str) -> Result<RGBImage, String> {
+    let mut data = vec![];
+    if let Err(err) = File::open(path).and_then(|mut file| file.read_to_end(&mut data)) {
+        return Err(format!("Could not read {}. {}", path, err));
+    }
+
+    // Read the header tokens.
+    let mut pos = 0;
+    let mut tokens = vec![];
+    while tokens.len() < 4 {
+        while pos < data.len() && data[pos].is_ascii_whitespace() {
+            pos += 1;
+        }
+        let start = pos;
+        while pos < data.len() && !data[pos].is_ascii_whitespace() {
+            pos += 1;
+        }
+        if start == pos {
+            return Err(format!("Premature end of file in PFM header {}.", path));
+        }
+        tokens.push(String::from_utf8_lossy(&data[start..pos]).to_string());
+    }
+    // A single whitespace character separates the header from the data.
+    pos += 1;
+
+    let n_channels = match &tokens[0][..] {
+        "PF" => 3,
+        "Pf" => 1,
+        header => return Err(format!("Invalid PFM header '{}' in {}.", header, path)),
+    };
+    let width = tokens[1]
+        .parse::<usize>()
+        .map_err(|_| format!("Invalid PFM width '{}' in {}.", tokens[1], path))?;
+    let height = tokens[2]
+        .parse::<usize>()
+        .map_err(|_| format!("Invalid PFM height '{}' in {}.", tokens[2], path))?;
+    let scale = tokens[3]
+        .parse::<f32>()
+        .map_err(|_| format!("Invalid PFM scale '{}' in {}.", tokens[3], path))?;
+
+    // A negative scale indicates little endian data.
+    let n = n_channels * width * height;
+    let mut values = vec![0.0_f32; n];
+    let mut reader = if pos <= data.len() {
+        &data[pos..]
+    } else {
+        &data[data.len()..]
+    };
+    let result = if scale < 0.0 {
+        reader.read_f32_into::<LittleEndian>(&mut values)
+    } else {
+        reader.read_f32_into::<BigEndian>(&mut values)
+    };
+    if let Err(err) = result {
+        return Err(format!("Error reading PFM data from {}. {}", path, err));
+    }
+
+    // Scale the values and flip the rows which are stored bottom to top.
+    let abs_scale = abs(scale) as Float;
+    let mut pixels = Vec::with_capacity(width * height);
+    for y in (0..height).rev() {
+        for x in 0..width {
+            let offset = n_channels * (y * width + x);
+            let rgb = if n_channels == 3 {
+                vec![values[offset], values[offset + 1], values[offset + 2]]
+            } else {
+                vec![values[offset]; 3]
+            };
+            pixels.push(RGBSpectrum::from(
+                rgb.iter()
+                    .map(|v| *v as Float * abs_scale)
+                    .collect::<Vec<Float>>(),
+            ));
+        }
+    }
 
     // Return the `RGBImage`.
-    Ok(RGBImage { pixels, resolution })
+    Ok(RGBImage {
+        pixels,
+        resolution: Point2::new(width, height),
+        alpha: None,
+    })
 }
 
 /// Write the output image to given path.
 ///
 /// * `path`             - Output file path.
 /// * `rgb`              - Floating point RGB pixel data.
+/// * `alpha`            - Optional floating point alpha pixel data written
+///                        by formats that support it.
 /// * `output_bounds`    - The bounds for the image output.
-pub fn write_image(path: &str, rgb: &[Float], output_bounds: &Bounds2i) -> Result<(), String> {
+/// * `tone_map`         - Tone mapping operator for 8-bit and 16-bit image
+///                        formats.
+/// * `bit_depth`        - Bits per channel (8 or 16) for PNG images.
+pub fn write_image(
+    path: &str,
+    rgb: &[Float],
+    alpha: Option<&[Float]>,
+    output_bounds: &Bounds2i,
+    tone_map: ToneMapOperator,
+    bit_depth: u8,
+) -> Result<(), String> {
     let resolution = output_bounds.diagonal();
     let res_x = resolution.x as u32;
     let res_y = resolution.y as u32;
 
     match get_extension_from_filename(path) {
-        Some(".exr") => write_exr(path, rgb, res_x, res_y),
-        Some(".tga") => write_8_bit(path, rgb, res_x, res_y, ImageFormat::Tga),
-        Some(".png") => write_8_bit(path, rgb, res_x, res_y, ImageFormat::Png),
+        Some(".exr") => write_exr(path, rgb, alpha, res_x, res_y),
+        Some(".pfm") => write_pfm(path, rgb, res_x, res_y),
+        Some(".hdr") => write_hdr(path, rgb, res_x, res_y),
+        Some(".tga") => write_8_bit(path, rgb, alpha, res_x, res_y, ImageFormat::Tga, tone_map),
+        Some(".png") if bit_depth == 16 => {
+            write_16_bit(path, rgb, alpha, res_x, res_y, ImageFormat::Png, tone_map)
+        }
+        Some(".png") => write_8_bit(path, rgb, alpha, res_x, res_y, ImageFormat::Png, tone_map),
+        Some(".tif") | Some(".tiff") => write_tiff(path, rgb, alpha, res_x, res_y),
         Some(extension) => Err(format!("Extension {} is not supported", extension)),
         None => Err(format!(
             "Can't determine file type from suffix of filename {}",
@@ -142,18 +461,41 @@ fn get_extension_from_filename(path: &str) -> Option<&str> {
 ///
 /// * `path`        - Output file path.
 /// * `rgb`         - Floating point RGB pixel data.
+/// * `alpha`       - Optional floating point alpha pixel data.
 /// * `res_x`       - X resolution.
 /// * `res_y`       - Y resolution.
-fn write_exr(path: &str, rgb: &[Float], res_x: u32, res_y: u32) -> Result<(), String> {
+fn write_exr(
+    path: &str,
+    rgb: &[Float],
+    alpha: Option<&[Float]>,
+    res_x: u32,
+    res_y: u32,
+) -> Result<(), String> {
     info!("Writing image {} with resolution {}x{}", path, res_x, res_y);
-    match write_rgb_f32_file(
-        String::from(path),
-        (res_x as usize, res_y as usize),
-        |x, y| {
-            let offset = y * (res_x as usize) + x;
-            (rgb[offset], rgb[offset + 1], rgb[offset + 2])
-        },
-    ) {
+    let result = match alpha {
+        Some(alpha) => write_rgba_f32_file(
+            String::from(path),
+            (res_x as usize, res_y as usize),
+            |x, y| {
+                let offset = y * (res_x as usize) + x;
+                (
+                    rgb[3 * offset],
+                    rgb[3 * offset + 1],
+                    rgb[3 * offset + 2],
+                    alpha[offset],
+                )
+            },
+        ),
+        None => write_rgb_f32_file(
+            String::from(path),
+            (res_x as usize, res_y as usize),
+            |x, y| {
+                let offset = 3 * (y * (res_x as usize) + x);
+                (rgb[offset], rgb[offset + 1], rgb[offset + 2])
+            },
+        ),
+    };
+    match result {
         Ok(()) => Ok(()),
         Err(err) => Err(format!("Error saving output image {}. {:}.", path, err)),
     }
@@ -163,32 +505,236 @@ fn write_exr(path: &str, rgb: &[Float], res_x: u32, res_y: u32) -> Result<(), St
 ///
 /// * `path`         - Output file path.
 /// * `rgb`          - Floating point RGB pixel data.
+/// * `alpha`        - Optional floating point alpha pixel data.
 /// * `res_x`        - X resolution.
 /// * `res_y`        - Y resolution.
 /// * `image_format` - Image format.
+/// * `tone_map`     - Tone mapping operator.
 fn write_8_bit(
     path: &str,
     rgb: &[Float],
+    alpha: Option<&[Float]>,
     res_x: u32,
     res_y: u32,
     image_format: ImageFormat,
+    tone_map: ToneMapOperator,
 ) -> std::result::Result<(), String> {
     info!("Writing image {} with resolution {}x{}", path, res_x, res_y);
 
-    // Allocate an image buffer.
-    let mut imgbuf = ImageBuffer::new(res_x, res_y);
-    let mut offset = 0;
-    for y in 0..res_y {
-        for x in 0..res_x {
-            // 8-bit format; apply gamma and clamp.
-            let rgb = apply_gamma(&[rgb[offset], rgb[offset + 1], rgb[offset + 2]]);
-            imgbuf.put_pixel(x, res_y - 1 - y, Rgb(rgb));
-            offset += 3;
+    // 8-bit format; apply tone mapping, gamma and clamp.
+    let result = match alpha {
+        Some(alpha) => {
+            let mut imgbuf = ImageBuffer::new(res_x, res_y);
+            for (x, y, pixel) in imgbuf.enumerate_pixels_mut() {
+                let offset = (y * res_x + x) as usize;
+                let (rgb, a) = ldr_pixel(rgb, Some(alpha), offset, tone_map);
+                let rgb = apply_gamma(&rgb);
+                *pixel = Rgba([rgb[0], rgb[1], rgb[2], clamp_byte_linear(a)]);
+            }
+            imgbuf.save_with_format(String::from(path), image_format)
         }
+        None => {
+            let mut imgbuf = ImageBuffer::new(res_x, res_y);
+            for (x, y, pixel) in imgbuf.enumerate_pixels_mut() {
+                let offset = (y * res_x + x) as usize;
+                let (rgb, _) = ldr_pixel(rgb, None, offset, tone_map);
+                *pixel = Rgb(apply_gamma(&rgb));
+            }
+            imgbuf.save_with_format(String::from(path), image_format)
+        }
+    };
+
+    // Write the output file.
+    match result {
+        Ok(()) => Ok(()),
+        Err(err) => Err(format!("Error saving output image {}. {:}.", path, err)),
     }
+}
+
+/// Writes the image in a 16-bit image format.
+///
+/// * `path`         - Output file path.
+/// * `rgb`          - Floating point RGB pixel data.
+/// * `alpha`        - Optional floating point alpha pixel data.
+/// * `res_x`        - X resolution.
+/// * `res_y`        - Y resolution.
+/// * `image_format` - Image format.
+/// * `tone_map`     - Tone mapping operator.
+fn write_16_bit(
+    path: &str,
+    rgb: &[Float],
+    alpha: Option<&[Float]>,
+    res_x: u32,
+    res_y: u32,
+    image_format: ImageFormat,
+    tone_map: ToneMapOperator,
+) -> std::result::Result<(), String> {
+    info!("Writing image {} with resolution {}x{}", path, res_x, res_y);
+
+    // 16-bit format; apply tone mapping, gamma and clamp.
+    let result = match alpha {
+        Some(alpha) => {
+            let mut imgbuf: ImageBuffer<Rgba<u16>, Vec<u16>> = ImageBuffer::new(res_x, res_y);
+            for (x, y, pixel) in imgbuf.enumerate_pixels_mut() {
+                let offset = (y * res_x + x) as usize;
+                let (rgb, a) = ldr_pixel(rgb, Some(alpha), offset, tone_map);
+                *pixel = Rgba([
+                    clamp_u16(rgb[0]),
+                    clamp_u16(rgb[1]),
+                    clamp_u16(rgb[2]),
+                    clamp_u16_linear(a),
+                ]);
+            }
+            imgbuf.save_with_format(String::from(path), image_format)
+        }
+        None => {
+            let mut imgbuf: ImageBuffer<Rgb<u16>, Vec<u16>> = ImageBuffer::new(res_x, res_y);
+            for (x, y, pixel) in imgbuf.enumerate_pixels_mut() {
+                let offset = (y * res_x + x) as usize;
+                let (rgb, _) = ldr_pixel(rgb, None, offset, tone_map);
+                *pixel = Rgb([clamp_u16(rgb[0]), clamp_u16(rgb[1]), clamp_u16(rgb[2])]);
+            }
+            imgbuf.save_with_format(String::from(path), image_format)
+        }
+    };
 
     // Write the output file.
-    match imgbuf.save_with_format(String::from(path), image_format) {
+    match result {
+        Ok(()) => Ok(()),
+        Err(err) => Err(format!("Error saving output image {}. {:}.", path, err)),
+    }
+}
+
+/// Returns the tone mapped RGB value and alpha value of a pixel for 8-bit and
+/// 16-bit image formats. These formats store colors that are not
+/// premultiplied by alpha.
+///
+/// * `rgb`      - Floating point RGB pixel data premultiplied by alpha.
+/// * `alpha`    - Optional floating point alpha pixel data.
+/// * `offset`   - Pixel offset.
+/// * `tone_map` - Tone mapping operator.
+#[inline]
+fn ldr_pixel(
+    rgb: &[Float],
+    alpha: Option<&[Float]>,
+    offset: usize,
+    tone_map: ToneMapOperator,
+) -> ([Float; 3], Float) {
+    let mut c = [rgb[3 * offset], rgb[3 * offset + 1], rgb[3 * offset + 2]];
+    let a = alpha.map_or(1.0, |alpha| alpha[offset]);
+    if a > 0.0 && a < 1.0 {
+        for v in c.iter_mut() {
+            *v /= a;
+        }
+    }
+    (tone_map.apply(&c), a)
+}
+
+/// Writes the image in Radiance RGBE (.hdr) format.
+///
+/// * `path`  - Output file path.
+/// * `rgb`   - Floating point RGB pixel data.
+/// * `res_x` - X resolution.
+/// * `res_y` - Y resolution.
+fn write_hdr(path: &str, rgb: &[Float], res_x: u32, res_y: u32) -> Result<(), String> {
+    info!("Writing image {} with resolution {}x{}", path, res_x, res_y);
+
+    let file = match File::create(path) {
+        Ok(file) => file,
+        Err(err) => return Err(format!("Error saving output image {}. {:}.", path, err)),
+    };
+
+    let pixels: Vec<Rgb<f32>> = rgb
+        .chunks_exact(3)
+        .map(|c| Rgb([c[0] as f32, c[1] as f32, c[2] as f32]))
+        .collect();
+
+    match HdrEncoder::new(BufWriter::new(file)).encode(&pixels, res_x as usize, res_y as usize) {
+        Ok(()) => Ok(()),
+        Err(err) => Err(format!("Error saving output image {}. {:}.", path, err)),
+    }
+}
+
+/// Writes the image in 32-bit floating point TIFF format. Like OpenEXR, the
+/// values are linear and colors are premultiplied by alpha.
+///
+/// * `path`  - Output file path.
+/// * `rgb`   - Floating point RGB pixel data.
+/// * `alpha` - Optional floating point alpha pixel data.
+/// * `res_x` - X resolution.
+/// * `res_y` - Y resolution.
+fn write_tiff(
+    path: &str,
+    rgb: &[Float],
+    alpha: Option<&[Float]>,
+    res_x: u32,
+    res_y: u32,
+) -> Result<(), String> {
+    info!("Writing image {} with resolution {}x{}", path, res_x, res_y);
+
+    let file = match File::create(path) {
+        Ok(file) => file,
+        Err(err) => return Err(format!("Error saving output image {}. {:}.", path, err)),
+    };
+    let mut encoder = match TiffEncoder::new(BufWriter::new(file)) {
+        Ok(encoder) => encoder,
+        Err(err) => return Err(format!("Error saving output image {}. {:}.", path, err)),
+    };
+
+    let n = (res_x * res_y) as usize;
+    let result = match alpha {
+        Some(alpha) => {
+            let data: Vec<f32> = (0..n)
+                .flat_map(|i| {
+                    vec![
+                        rgb[3 * i] as f32,
+                        rgb[3 * i + 1] as f32,
+                        rgb[3 * i + 2] as f32,
+                        alpha[i] as f32,
+                    ]
+                })
+                .collect();
+            encoder.write_image::<tiff_colortype::RGBA32Float>(res_x, res_y, &data)
+        }
+        None => {
+            let data: Vec<f32> = rgb[..3 * n].iter().map(|v| *v as f32).collect();
+            encoder.write_image::<tiff_colortype::RGB32Float>(res_x, res_y, &data)
+        }
+    };
+
+    match result {
+        Ok(()) => Ok(()),
+        Err(err) => Err(format!("Error saving output image {}. {:}.", path, err)),
+    }
+}
+
+/// Writes the image in Portable Float Map (.pfm) format.
+///
+/// * `path`  - Output file path.
+/// * `rgb`   - Floating point RGB pixel data.
+/// * `res_x` - X resolution.
+/// * `res_y` - Y resolution.
+fn write_pfm(path: &str, rgb: &[Float], res_x: u32, res_y: u32) -> Result<(), String> {
+    info!("Writing image {} with resolution {}x{}", path, res_x, res_y);
+
+    let write = || -> std::io::Result<()> {
+        let mut writer = BufWriter::new(File::create(path)?);
+
+        // Negative scale indicates little endian data.
+        write!(writer, "PF\n{} {}\n-1\n", res_x, res_y)?;
+
+        // Rows are stored bottom to top.
+        let width = 3 * res_x as usize;
+        for row in rgb.chunks_exact(width).rev() {
+            for v in row.iter() {
+                writer.write_f32::<LittleEndian>(*v as f32)?;
+            }
+        }
+
+        writer.flush()
+    };
+
+    match write() {
         Ok(()) => Ok(()),
         Err(err) => Err(format!("Error saving output image {}. {:}.", path, err)),
     }
@@ -210,3 +756,205 @@ fn apply_gamma(rgb: &[Float; 3]) -> [u8; 3] {
 fn clamp_byte(v: Float) -> u8 {
     clamp(255.0 * gamma_correct(v) + 0.5, 0.0, 255.0) as u8
 }
+
+/// Clamp floating point value to 16-bit range [0, 65535] after applying gamma
+/// correction.
+///
+/// * `v` - Value to clamp.
+#[inline]
+fn clamp_u16(v: Float) -> u16 {
+    clamp(65535.0 * gamma_correct(v) + 0.5, 0.0, 65535.0) as u16
+}
+
+/// Clamp a linear floating point value, such as alpha, to 8-bit range
+/// [0, 255].
+///
+/// * `v` - Value to clamp.
+#[inline]
+fn clamp_byte_linear(v: Float) -> u8 {
+    clamp(255.0 * v + 0.5, 0.0, 255.0) as u8
+}
+
+/// Clamp a linear floating point value, such as alpha, to 16-bit range
+/// [0, 65535].
+///
+/// * `v` - Value to clamp.
+#[inline]
+fn clamp_u16_linear(v: Float) -> u16 {
+    clamp(65535.0 * v + 0.5, 0.0, 65535.0) as u16
+}
+
+// ----------------------------------------------------------------------------
+// Tests
+// ----------------------------------------------------------------------------
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn temp_path(name: &str) -> String {
+        std::env::temp_dir()
+            .join(format!("{}_{}", std::process::id(), name))
+            .to_string_lossy()
+            .to_string()
+    }
+
+    fn bounds(res_x: i32, res_y: i32) -> Bounds2i {
+        Bounds2i::new(Point2i::new(0, 0), Point2i::new(res_x, res_y))
+    }
+
+    #[test]
+    fn tiff_round_trips_float_values() {
+        let path = temp_path("float.tif");
+        let rgb = [
+            0.0, 0.25, 1.0, 2.5, 100.0, 1e-4, 0.5, 3.0, 7.25, 42.0, 0.125, 65536.0,
+        ];
+        write_image(&path, &rgb, None, &bounds(2, 2), ToneMapOperator::Clamp, 8).unwrap();
+
+        let img = read_image(&path).unwrap();
+        std::fs::remove_file(&path).unwrap();
+
+        assert_eq!(img.resolution, Point2::new(2, 2));
+        assert!(img.alpha.is_none());
+        for (i, p) in img.pixels.iter().enumerate() {
+            assert_eq!(p.to_rgb(), [rgb[3 * i], rgb[3 * i + 1], rgb[3 * i + 2]]);
+        }
+    }
+
+    #[test]
+    fn tiff_keeps_alpha_and_premultiplied_colors() {
+        let path = temp_path("alpha.tiff");
+        let rgb = [0.5, 0.25, 2.0, 0.0, 0.0, 0.0];
+        let alpha = [0.5, 0.0];
+        write_image(
+            &path,
+            &rgb,
+            Some(&alpha),
+            &bounds(2, 1),
+            ToneMapOperator::ACES,
+            16,
+        )
+        .unwrap();
+
+        let img = read_image(&path).unwrap();
+        std::fs::remove_file(&path).unwrap();
+
+        assert_eq!(img.resolution, Point2::new(2, 1));
+        assert_eq!(img.pixels[0].to_rgb(), [0.5, 0.25, 2.0]);
+        assert_eq!(img.pixels[1].to_rgb(), [0.0, 0.0, 0.0]);
+        assert_eq!(img.alpha, Some(vec![0.5, 0.0]));
+    }
+
+    #[test]
+    fn tiff_reads_fixed_point_images() {
+        let path = temp_path("fixed.tif");
+        let mut imgbuf: ImageBuffer<Rgb<u16>, Vec<u16>> = ImageBuffer::new(1, 1);
+        imgbuf.put_pixel(0, 0, Rgb([0, 65535, 32768]));
+        imgbuf.save_with_format(&path, ImageFormat::Tiff).unwrap();
+
+        let img = read_image(&path).unwrap();
+        std::fs::remove_file(&path).unwrap();
+
+        let rgb = img.pixels[0].to_rgb();
+        assert_eq!(rgb[0], 0.0);
+        assert_eq!(rgb[1], 1.0);
+        assert!((rgb[2] - 32768.0 / 65535.0).abs() < 1e-6);
+    }
+
+    #[test]
+    fn hdr_round_trips_values_above_one() {
+        let path = temp_path("values.hdr");
+        let rgb = [
+            2.5, 100.0, 0.75, 1.0, 1.5, 0.5, 0.001, 0.002, 0.003, 0.0, 0.0, 0.0,
+        ];
+        write_image(&path, &rgb, None, &bounds(2, 2), ToneMapOperator::Clamp, 8).unwrap();
+
+        let img = read_image(&path).unwrap();
+        std::fs::remove_file(&path).unwrap();
+
+        // RGBE shares an 8-bit exponent so each channel is accurate to 1/256
+        // of the largest channel in the pixel.
+        assert_eq!(img.resolution, Point2::new(2, 2));
+        assert!(img.alpha.is_none());
+        for (i, p) in img.pixels.iter().enumerate() {
+            let expected = &rgb[3 * i..3 * i + 3];
+            let max_value = expected.iter().cloned().fold(0.0, Float::max);
+            for (v, e) in p.to_rgb().iter().zip(expected.iter()) {
+                assert!((v - e).abs() <= max_value / 256.0, "{} != {}", v, e);
+            }
+        }
+        assert!(img.pixels[0].to_rgb()[1] > 99.0);
+    }
+
+    #[test]
+    fn png16_keeps_steps_below_8_bits() {
+        // Find two values one 16-bit code apart after gamma correction that
+        // are the same 8-bit code.
+        let v0 = 0.5;
+        let mut v1 = v0;
+        while clamp_u16(v1) == clamp_u16(v0) {
+            v1 = next_float_up(v1);
+        }
+        assert_eq!(clamp_u16(v1), clamp_u16(v0) + 1);
+        assert_eq!(clamp_byte(v1), clamp_byte(v0));
+
+        let path = temp_path("steps.png");
+        let rgb = [v0, v1, 0.0, 1.0, 0.0, 0.0];
+        let alpha = [1.0, 32768.0 / 65535.0];
+        write_image(
+            &path,
+            &rgb,
+            Some(&alpha),
+            &bounds(2, 1),
+            ToneMapOperator::Clamp,
+            16,
+        )
+        .unwrap();
+
+        let img = read_image(&path).unwrap();
+        std::fs::remove_file(&path).unwrap();
+
+        let code = |v: Float| (v * 65535.0).round() as u16;
+        let p = img.pixels[0].to_rgb();
+        assert_eq!(code(p[0]), clamp_u16(v0));
+        assert_eq!(code(p[1]), clamp_u16(v1));
+        assert_eq!(code(p[1]) - code(p[0]), 1);
+
+        // Alpha is linear and colors are divided by it.
+        let alpha_read = img.alpha.unwrap();
+        assert_eq!(code(alpha_read[0]), 65535);
+        assert_eq!(code(alpha_read[1]), 32768);
+        assert_eq!(
+            code(img.pixels[1].to_rgb()[0]),
+            clamp_u16(65535.0 / 32768.0)
+        );
+    }
+
+    #[test]
+    fn pfm_round_trips_float_values() {
+        let path = temp_path("float.pfm");
+        let rgb = [
+            0.0, 0.25, 1.0, 2.5, 100.0, 1e-4, 0.5, 3.0, 7.25, 42.0, 0.125, 65536.0,
+        ];
+        write_image(&path, &rgb, None, &bounds(2, 2), ToneMapOperator::Clamp, 8).unwrap();
+
+        let img = read_image(&path).unwrap();
+        std::fs::remove_file(&path).unwrap();
+
+        assert_eq!(img.resolution, Point2::new(2, 2));
+        for (i, p) in img.pixels.iter().enumerate() {
+            assert_eq!(p.to_rgb(), [rgb[3 * i], rgb[3 * i + 1], rgb[3 * i + 2]]);
+        }
+    }
+
+    #[test]
+    fn ldr_pixels_divide_premultiplied_colors_by_alpha() {
+        let rgb = [0.25, 0.5, 0.0, 0.0, 0.0, 0.0, 0.5, 0.5, 0.5];
+        let alpha = [0.5, 0.0, 1.0];
+        let tm = ToneMapOperator::Clamp;
+        assert_eq!(ldr_pixel(&rgb, Some(&alpha), 0, tm), ([0.5, 1.0, 0.0], 0.5));
+        assert_eq!(ldr_pixel(&rgb, Some(&alpha), 1, tm), ([0.0, 0.0, 0.0], 0.0));
+        assert_eq!(ldr_pixel(&rgb, Some(&alpha), 2, tm), ([0.5, 0.5, 0.5], 1.0));
+        assert_eq!(ldr_pixel(&rgb, None, 0, tm), ([0.25, 0.5, 0.0], 1.0));
+    }
+}
@@ -1,5 +1,9 @@
 //! Integrator
 
+mod sampler_integrator;
+
+pub use sampler_integrator::*;
+
 use crate::core::scene::*;
 use std::sync::Arc;
 
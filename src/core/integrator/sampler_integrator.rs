@@ -0,0 +1,571 @@
+//! Sampler Integrator
+
+#![allow(dead_code)]
+use crate::core::app::OPTIONS;
+use crate::core::camera::*;
+use crate::core::geometry::*;
+use crate::core::material::*;
+use crate::core::paramset::*;
+use crate::core::pbrt::*;
+use crate::core::reflection::*;
+use crate::core::sampler::*;
+use crate::core::scene::*;
+use crate::core::spectrum::*;
+use crate::core::stats::*;
+use rayon::prelude::*;
+use std::sync::Arc;
+use std::time::Instant;
+
+/// Size of the square image tiles rendered by each task.
+pub const TILE_SIZE: Int = 16;
+
+/// Number of camera rays traced.
+static N_CAMERA_RAYS: Stat = Stat::counter("Integrator/Camera rays traced");
+
+/// Fixed sample points used to estimate the albedo of a BSDF for the denoiser
+/// without consuming sampler dimensions.
+const ALBEDO_SAMPLES: [Point2f; 4] = [
+    Point2f { x: 0.25, y: 0.25 },
+    Point2f { x: 0.75, y: 0.25 },
+    Point2f { x: 0.25, y: 0.75 },
+    Point2f { x: 0.75, y: 0.75 },
+];
+
+/// Options that control progressive rendering. Samples for each pixel are
+/// taken in passes over the entire image so that the film can be written out
+/// periodically while rendering is in progress and a checkpoint to resume
+/// from is saved. Between passes, pixels whose estimated error is low enough
+/// can be skipped for adaptive sampling.
+#[derive(Copy, Clone, Debug, Default)]
+pub struct ProgressiveOptions {
+    /// Number of samples per pixel to take in each pass. If 0, all samples are
+    /// taken in a single pass.
+    pub samples_per_pass: usize,
+
+    /// Write a checkpoint after a pass if this many seconds have elapsed since
+    /// the last checkpoint. If 0, a checkpoint is written after every pass.
+    /// If `None`, no checkpoints are written.
+    pub checkpoint_frequency: Option<Float>,
+
+    /// Write the film after this many seconds have elapsed since the last
+    /// write. If 0, the film is not written based on elapsed time.
+    pub write_frequency: Float,
+
+    /// Write the film after this many passes. If 0, the film is not written
+    /// based on completed passes.
+    pub write_passes: usize,
+
+    /// Stop rendering after the pass that exceeds this many seconds. If 0,
+    /// there is no time limit.
+    pub max_time: Float,
+
+    /// Stop sampling pixels whose relative error falls below this threshold.
+    /// If 0, all pixels are sampled at the full samples per pixel.
+    pub max_error: Float,
+
+    /// Minimum number of samples per pixel before the relative error of the
+    /// pixel is considered for adaptive sampling.
+    pub min_samples: usize,
+}
+
+impl ProgressiveOptions {
+    /// Returns `true` if the film should be written after a pass.
+    ///
+    /// * `passes`          - Number of passes completed so far.
+    /// * `since_last_write` - Seconds elapsed since the film was last written.
+    pub fn should_write(&self, passes: usize, since_last_write: Float) -> bool {
+        (self.write_passes > 0 && passes % self.write_passes == 0)
+            || (self.write_frequency > 0.0 && since_last_write >= self.write_frequency)
+    }
+
+    /// Returns `true` if a checkpoint should be written after a pass.
+    ///
+    /// * `since_last_checkpoint` - Seconds elapsed since the last checkpoint.
+    pub fn should_checkpoint(&self, since_last_checkpoint: Float) -> bool {
+        self.checkpoint_frequency
+            .map_or(false, |frequency| since_last_checkpoint >= frequency)
+    }
+
+    /// Returns `true` if the time budget for rendering is exhausted.
+    ///
+    /// * `elapsed` - Seconds elapsed since rendering started.
+    pub fn out_of_time(&self, elapsed: Float) -> bool {
+        self.max_time > 0.0 && elapsed >= self.max_time
+    }
+
+    /// Returns `true` if adaptive sampling is enabled.
+    pub fn is_adaptive(&self) -> bool {
+        self.max_error > 0.0
+    }
+}
+
+impl From<&ParamSet> for ProgressiveOptions {
+    /// Create `ProgressiveOptions` from given integrator parameter set.
+    ///
+    /// * `params` - Integrator parameter set.
+    fn from(params: &ParamSet) -> Self {
+        let samples_per_pass = params.find_one_int("passsamples", 16);
+        let checkpoint_frequency = params.find_one_float("checkpointfrequency", 0.0);
+        let write_frequency = params.find_one_float("writefrequency", 0.0);
+        let write_passes = params.find_one_int("writepasses", 0);
+        let max_time = params.find_one_float("maxtime", 0.0);
+        let max_error = params.find_one_float("maxerror", 0.0);
+        let min_samples = params.find_one_int("minsamples", 16);
+
+        Self {
+            samples_per_pass: max(0, samples_per_pass) as usize,
+            checkpoint_frequency: if checkpoint_frequency < 0.0 {
+                None
+            } else {
+                Some(checkpoint_frequency)
+            },
+            write_frequency: max(0.0, write_frequency),
+            write_passes: max(0, write_passes) as usize,
+            max_time: max(0.0, max_time),
+            max_error: max(0.0, max_error),
+            min_samples: max(2, min_samples) as usize,
+        }
+    }
+}
+
+/// Returns the bounds of the pixels to render from the optional
+/// "pixelbounds" integrator parameter, limited to the film's sample bounds.
+///
+/// * `params` - Integrator parameter set.
+/// * `camera` - The camera.
+pub fn get_pixel_bounds(params: &ParamSet, camera: &ArcCamera) -> Bounds2i {
+    let sample_bounds = camera.get_data().film.get_sample_bounds();
+    let pb = params.find_int("pixelbounds");
+    if pb.is_empty() {
+        sample_bounds
+    } else if pb.len() != 4 {
+        error!(
+            "Expected four values for 'pixelbounds' parameter. Got {}.",
+            pb.len()
+        );
+        sample_bounds
+    } else {
+        let pixel_bounds = sample_bounds.intersect(&Bounds2i::new(
+            Point2i::new(pb[0], pb[2]),
+            Point2i::new(pb[1], pb[3]),
+        ));
+        if pixel_bounds.area() == 0 {
+            error!("Degenerate 'pixelbounds' specified.");
+        }
+        pixel_bounds
+    }
+}
+
+/// Stores common data for integrators that use a `Sampler` to generate camera
+/// rays for each pixel.
+#[derive(Clone)]
+pub struct SamplerIntegratorData {
+    /// The camera.
+    pub camera: ArcCamera,
+
+    /// The sampler used to generate sample values for each pixel.
+    pub sampler: ArcSampler,
+
+    /// The bounds of the pixels to render.
+    pub pixel_bounds: Bounds2i,
+
+    /// Progressive rendering options.
+    pub progressive: ProgressiveOptions,
+}
+
+impl SamplerIntegratorData {
+    /// Create a new `SamplerIntegratorData`.
+    ///
+    /// * `camera`       - The camera.
+    /// * `sampler`      - The sampler.
+    /// * `pixel_bounds` - The bounds of the pixels to render.
+    /// * `progressive`  - Progressive rendering options.
+    pub fn new(
+        camera: ArcCamera,
+        sampler: ArcSampler,
+        pixel_bounds: Bounds2i,
+        progressive: ProgressiveOptions,
+    ) -> Self {
+        Self {
+            camera,
+            sampler,
+            pixel_bounds,
+            progressive,
+        }
+    }
+}
+
+/// Interface for integrators that render the image by tracing camera rays
+/// generated by a `Sampler` through each pixel.
+pub trait SamplerIntegrator: Send + Sync {
+    /// Returns the common sampler integrator data.
+    fn get_data(&self) -> &SamplerIntegratorData;
+
+    /// Returns the incident radiance at the origin of a given ray.
+    ///
+    /// * `ray`     - The ray.
+    /// * `scene`   - The scene.
+    /// * `sampler` - The sampler.
+    /// * `depth`   - The number of ray bounces from the camera.
+    fn li(
+        &self,
+        ray: &mut Ray,
+        scene: &Scene,
+        sampler: &mut (dyn Sampler + Send + Sync),
+        depth: usize,
+    ) -> Spectrum;
+
+    /// Returns the incident radiance along a camera ray and whether the ray
+    /// hits geometry, which determines the alpha value of the sample.
+    /// Integrators should override this to report whether the first
+    /// intersection found by `li()` exists instead of tracing the camera ray
+    /// again.
+    ///
+    /// * `ray`     - The camera ray.
+    /// * `scene`   - The scene.
+    /// * `sampler` - The sampler.
+    fn li_camera(
+        &self,
+        ray: &mut Ray,
+        scene: &Scene,
+        sampler: &mut (dyn Sampler + Send + Sync),
+    ) -> (Spectrum, bool) {
+        let hit = scene.intersect_p(ray);
+        (self.li(ray, scene, sampler, 0), hit)
+    }
+
+    /// Returns the albedo and shading normal at the first intersection of a
+    /// camera ray. These are used to guide the denoiser.
+    ///
+    /// * `ray`   - The camera ray.
+    /// * `scene` - The scene.
+    fn get_aov(&self, ray: &mut Ray, scene: &Scene) -> (Spectrum, Normal3f) {
+        match scene.intersect(ray) {
+            Some(mut isect) => {
+                if let Some(primitive) = isect.primitive {
+                    primitive.compute_scattering_functions(
+                        &mut isect,
+                        TransportMode::Radiance,
+                        true,
+                    );
+                }
+                let albedo = match isect.bsdf.as_ref() {
+                    Some(bsdf) => {
+                        bsdf.rho_hd(&isect.hit.wo, &ALBEDO_SAMPLES, BxDFType::from(BSDF_ALL))
+                    }
+                    None => Spectrum::new(0.0),
+                };
+                (albedo, isect.shading.n)
+            }
+            None => (Spectrum::new(0.0), Normal3f::default()),
+        }
+    }
+
+    /// Render the scene in passes over all image tiles. Each pass takes up to
+    /// `ProgressiveOptions::samples_per_pass` samples per pixel and the film
+    /// and a checkpoint may be written between passes. Rendering stops when
+    /// all samples per pixel have been taken or the time budget is exhausted.
+    /// The checkpoint is removed once the render completes. With the
+    /// `--resume` option, rendering continues from the checkpoint.
+    ///
+    /// * `scene` - The scene.
+    fn render(&self, scene: Arc<Scene>) {
+        let data = self.get_data();
+        let options = data.progressive;
+        let film = data.camera.get_data().film.clone();
+        let needs_aovs = film.needs_aovs();
+        let needs_alpha = film.needs_alpha();
+
+        // Compute number of tiles to use for parallel rendering.
+        let sample_bounds = film.get_sample_bounds();
+        let sample_extent = sample_bounds.diagonal();
+        let n_tiles = Point2i::new(
+            (sample_extent.x + TILE_SIZE - 1) / TILE_SIZE,
+            (sample_extent.y + TILE_SIZE - 1) / TILE_SIZE,
+        );
+        let n_tiles_total = max(0, n_tiles.x * n_tiles.y) as usize;
+
+        // The prototype sampler can't be borrowed mutably so query the number
+        // of samples per pixel from a clone.
+        let mut prototype = Sampler::clone(&*data.sampler, 0);
+        let spp = Arc::get_mut(&mut prototype)
+            .expect("Unable to access sampler")
+            .get_data()
+            .samples_per_pixel;
+        let samples_per_pass = if options.samples_per_pass > 0 {
+            min(options.samples_per_pass, spp)
+        } else if options.is_adaptive() {
+            // Adaptive sampling needs passes to check pixel errors in between.
+            min(options.min_samples, spp)
+        } else {
+            spp
+        };
+        let n_passes = (spp + samples_per_pass - 1) / max(1, samples_per_pass);
+
+        // Continue an interrupted render from its checkpoint if requested.
+        let mut first_sample = 0;
+        if OPTIONS.resume {
+            match film.read_checkpoint(spp) {
+                Ok(samples_completed) => first_sample = samples_completed,
+                Err(err) => warn!("{} Rendering from the start.", err),
+            }
+        }
+
+        info!(
+            "Rendering {}x{} tiles in {} pass(es) of {} samples per pixel",
+            n_tiles.x, n_tiles.y, n_passes, samples_per_pass
+        );
+
+        let start_time = Instant::now();
+        let mut last_write = 0.0;
+        let mut last_checkpoint = 0.0;
+        let mut pass = 0;
+        let mut all_converged = false;
+
+        while first_sample < spp {
+            let end_sample = min(first_sample + samples_per_pass, spp);
+
+            // Find pixels that don't need more samples.
+            let converged = if options.is_adaptive() {
+                let converged = film.get_converged_pixels(options.max_error, options.min_samples);
+                let n_converged = converged.iter().filter(|c| **c).count();
+                info!(
+                    "{}/{} pixels converged to relative error {}",
+                    n_converged,
+                    converged.len(),
+                    options.max_error
+                );
+                if n_converged == converged.len() {
+                    all_converged = true;
+                    break;
+                }
+                Some(converged)
+            } else {
+                None
+            };
+            let crop_bounds = film.cropped_pixel_bounds;
+
+            (0..n_tiles_total).into_par_iter().for_each(|t| {
+                // Render section of image corresponding to `tile`.
+
+                // Get sampler instance for tile. The seed only depends on the
+                // tile so every pass and resumed render generates the same
+                // sample sequence for a pixel and continues it at the first
+                // sample of the pass.
+                let mut tile_sampler = Sampler::clone(&*data.sampler, t as u64);
+                let sampler =
+                    Arc::get_mut(&mut tile_sampler).expect("Unable to access tile sampler");
+
+                // Compute sample bounds for tile.
+                let tile = Point2i::new(t as Int % n_tiles.x, t as Int / n_tiles.x);
+                let x0 = sample_bounds.p_min.x + tile.x * TILE_SIZE;
+                let x1 = min(x0 + TILE_SIZE, sample_bounds.p_max.x);
+                let y0 = sample_bounds.p_min.y + tile.y * TILE_SIZE;
+                let y1 = min(y0 + TILE_SIZE, sample_bounds.p_max.y);
+                let tile_bounds = Bounds2i::new(Point2i::new(x0, y0), Point2i::new(x1, y1));
+
+                // Get `FilmTile` for tile.
+                let mut film_tile = film.get_film_tile(tile_bounds);
+
+                // Loop over pixels in tile to render them.
+                for pixel in tile_bounds {
+                    sampler.start_pixel(&pixel);
+
+                    // Do this check after the `start_pixel()` call; this keeps
+                    // the usage of RNG values from (most) samplers that use
+                    // RNGs consistent, which improves reproducability.
+                    if !data.pixel_bounds.contains_exclusive(&pixel) {
+                        continue;
+                    }
+
+                    // Skip converged pixels. Pixels outside the cropped image
+                    // use the error of the nearest pixel inside it.
+                    if let Some(converged) = &converged {
+                        let p = Point2i::new(
+                            clamp(pixel.x, crop_bounds.p_min.x, crop_bounds.p_max.x - 1),
+                            clamp(pixel.y, crop_bounds.p_min.y, crop_bounds.p_max.y - 1),
+                        );
+                        if converged[film.get_pixel_offset(&p)] {
+                            continue;
+                        }
+                    }
+
+                    // Skip to the first sample of this pass.
+                    if !sampler.set_sample_number(first_sample) {
+                        continue;
+                    }
+
+                    loop {
+                        // Initialize `CameraSample` for current sample.
+                        let camera_sample = sampler.get_camera_sample(&pixel);
+
+                        // Generate camera ray for current sample.
+                        let (mut ray, ray_weight) =
+                            data.camera.generate_ray_differential(&camera_sample);
+                        ray.scale_differentials(1.0 / (spp as Float).sqrt());
+
+                        // Record denoiser guides at the first intersection.
+                        if needs_aovs && ray_weight > 0.0 {
+                            let (albedo, n) = self.get_aov(&mut ray.clone(), &scene);
+                            film_tile.add_aov(camera_sample.p_film, &albedo, &n);
+                        }
+
+                        // Evaluate radiance along camera ray. With an alpha
+                        // channel, camera rays that don't hit geometry are
+                        // transparent and their radiance is dropped so that
+                        // colors stay premultiplied by alpha.
+                        let mut l = Spectrum::new(0.0);
+                        let mut alpha = if needs_alpha { 0.0 } else { 1.0 };
+                        if ray_weight > 0.0 {
+                            N_CAMERA_RAYS.inc();
+                            if needs_alpha {
+                                let (li, hit) = self.li_camera(&mut ray, &scene, sampler);
+                                if hit {
+                                    l = li;
+                                    alpha = 1.0;
+                                }
+                            } else {
+                                l = self.li(&mut ray, &scene, sampler, 0);
+                            }
+                        }
+
+                        // Issue warning if unexpected radiance value returned.
+                        let sample_index = sampler.get_data().current_pixel_sample_index;
+                        if l.has_nans() {
+                            error!(
+                                "Not-a-number radiance value returned for pixel ({}, {}), \
+                                sample {}. Setting to black.",
+                                pixel.x, pixel.y, sample_index
+                            );
+                            l = Spectrum::new(0.0);
+                        } else if l.y() < -1e-5 {
+                            error!(
+                                "Negative luminance value, {}, returned for pixel ({}, {}), \
+                                sample {}. Setting to black.",
+                                l.y(),
+                                pixel.x,
+                                pixel.y,
+                                sample_index
+                            );
+                            l = Spectrum::new(0.0);
+                        } else if l.y().is_infinite() {
+                            error!(
+                                "Infinite luminance value returned for pixel ({}, {}), \
+                                sample {}. Setting to black.",
+                                pixel.x, pixel.y, sample_index
+                            );
+                            l = Spectrum::new(0.0);
+                        }
+
+                        // Add camera ray's contribution to image.
+                        film_tile.add_sample(camera_sample.p_film, l, ray_weight, alpha);
+
+                        if !sampler.start_next_sample()
+                            || sampler.get_data().current_pixel_sample_index >= end_sample
+                        {
+                            break;
+                        }
+                    }
+                }
+
+                // Merge image tile into `Film`.
+                film.merge_film_tile(&film_tile);
+            });
+
+            first_sample = end_sample;
+            pass += 1;
+
+            let elapsed = start_time.elapsed().as_secs_f32() as Float;
+            info!(
+                "Finished pass {} ({}/{} samples per pixel) in {:.1}s",
+                pass, end_sample, spp, elapsed
+            );
+
+            if end_sample >= spp {
+                break;
+            }
+
+            if options.out_of_time(elapsed) {
+                info!(
+                    "Time budget of {}s exhausted after {} of {} samples per pixel",
+                    options.max_time, end_sample, spp
+                );
+                break;
+            }
+
+            // Save the render progress so it can be resumed if it gets
+            // interrupted.
+            if options.should_checkpoint(elapsed - last_checkpoint) {
+                if let Err(err) = film.write_checkpoint(spp, first_sample) {
+                    error!("{}", err);
+                }
+                last_checkpoint = elapsed;
+            }
+
+            // Write current film state so convergence can be monitored.
+            if options.should_write(pass, elapsed - last_write) {
+                film.write_image(1.0);
+                last_write = elapsed;
+            }
+        }
+
+        info!("Rendering finished");
+
+        // Save final image after rendering. Keep a checkpoint of unfinished
+        // renders to continue them later.
+        film.write_final_image(1.0);
+        if first_sample >= spp || all_converged {
+            film.remove_checkpoint();
+        } else if options.checkpoint_frequency.is_some() {
+            if let Err(err) = film.write_checkpoint(spp, first_sample) {
+                error!("{}", err);
+            }
+        }
+    }
+}
+
+// ----------------------------------------------------------------------------
+// Tests
+// ----------------------------------------------------------------------------
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn progressive_defaults_checkpoint_every_pass() {
+        let options = ProgressiveOptions::from(&ParamSet::new());
+        assert_eq!(options.samples_per_pass, 16);
+        assert!(options.should_checkpoint(0.0));
+        assert!(!options.should_write(1, 1000.0));
+        assert!(!options.out_of_time(1000.0));
+        assert!(!options.is_adaptive());
+    }
+
+    #[test]
+    fn progressive_checkpoint_frequency() {
+        let mut params = ParamSet::new();
+        params.add_float("checkpointfrequency", &[30.0]);
+        let options = ProgressiveOptions::from(&params);
+        assert!(!options.should_checkpoint(29.0));
+        assert!(options.should_checkpoint(30.0));
+
+        params.add_float("checkpointfrequency", &[-1.0]);
+        let options = ProgressiveOptions::from(&params);
+        assert!(!options.should_checkpoint(1000.0));
+    }
+
+    #[test]
+    fn progressive_write_schedule() {
+        let mut params = ParamSet::new();
+        params.add_int("writepasses", &[2]);
+        params.add_float("writefrequency", &[10.0]);
+        params.add_float("maxtime", &[60.0]);
+        let options = ProgressiveOptions::from(&params);
+        assert!(!options.should_write(1, 0.0));
+        assert!(options.should_write(2, 0.0));
+        assert!(options.should_write(3, 10.0));
+        assert!(!options.out_of_time(59.0));
+        assert!(options.out_of_time(60.0));
+    }
+}
@@ -1526,7 +1526,7 @@ pub fn compute_radical_inverse_permutations(rng: &mut RNG) -> Vec<u16> {
             p[j] = j as u16;
         }
 
-        rng.shuffle(&mut p, PRIMES[i], 1);
+        rng.shuffle(&mut p[..PRIMES[i]], PRIMES[i], 1);
         p = &mut p[PRIMES[i]..];
     }
 
@@ -1904,3 +1904,26 @@ fn sobol_sample_f64(a: u64, dimension: u16, scramble: u64) -> f64 {
         DOUBLE_ONE_MINUS_EPSILON,
     )
 }
+
+// ----------------------------------------------------------------------------
+// Tests
+// ----------------------------------------------------------------------------
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn radical_inverse_permutations_permute_each_base() {
+        let mut rng = RNG::new(0);
+        let perms = compute_radical_inverse_permutations(&mut rng);
+
+        let mut p = &perms[..];
+        for i in 0..PRIME_TABLE_SIZE {
+            let mut digits: Vec<u16> = p[..PRIMES[i]].to_vec();
+            digits.sort_unstable();
+            assert!(digits.iter().enumerate().all(|(j, d)| *d as usize == j));
+            p = &p[PRIMES[i]..];
+        }
+    }
+}
@@ -79,20 +79,33 @@ where
     let RGBImage {
         pixels: mut texels,
         resolution,
+        alpha,
     } = match read_image(info.path.as_str()) {
         Ok(img) => img,
         Err(err) => return Err(format!("Error reading texture {}, {:}.", info.path, err)),
     };
 
+    // Use the alpha channel as greyscale texels if requested.
+    if info.alpha {
+        texels = match alpha {
+            Some(alpha) => alpha.iter().map(|a| RGBSpectrum::new(*a)).collect(),
+            None => {
+                warn!(
+                    "Texture {} has no alpha channel. Using alpha of 1.",
+                    info.path
+                );
+                vec![RGBSpectrum::new(1.0); texels.len()]
+            }
+        };
+    }
+
     // Flip image in y; texture coordinate space has (0,0) at the lower
     // left corner.
     for y in 0..resolution.y / 2 {
         for x in 0..resolution.x {
             let o1 = y * resolution.x + x;
             let o2 = (resolution.y - 1 - y) * resolution.x + x;
-            let tmp = texels[o1];
-            texels[o2] = texels[o1];
-            texels[o1] = tmp;
+            texels.swap(o1, o2);
         }
     }
 
@@ -23,6 +23,9 @@ pub struct TexInfo {
     /// Do gamma correction for the texel values.
     pub gamma: bool,
 
+    /// Use the image's alpha channel as texel values instead of its colors.
+    pub alpha: bool,
+
     /// Used to clamp the ellipse eccentricity (EWA).
     /// Set to 0 if EWA is not being used.
     pub max_anisotropy: Float,
@@ -36,6 +39,7 @@ impl TexInfo {
     /// * `wrap_mode`        - Image wrapping convention.
     /// * `scale`            - Scale for the texel values.
     /// * `gamma`            - Do gamma correction for the texel values.
+    /// * `alpha`            - Use the image's alpha channel as texel values.
     /// * `max_anisotropy`   - Used to clamp the ellipse eccentricity (EWA).
     ///                        Set to 0 if EWA is not being used.
     pub fn new(
@@ -44,6 +48,7 @@ impl TexInfo {
         wrap_mode: ImageWrap,
         scale: Float,
         gamma: bool,
+        alpha: bool,
         max_anisotropy: Float,
     ) -> Self {
         Self {
@@ -52,6 +57,7 @@ impl TexInfo {
             wrap_mode,
             scale,
             gamma,
+            alpha,
             max_anisotropy,
         }
     }
@@ -67,6 +73,7 @@ impl PartialEq for TexInfo {
             && self.wrap_mode == other.wrap_mode
             && self.scale == other.scale
             && self.gamma == other.gamma
+            && self.alpha == other.alpha
     }
 }
 
@@ -79,6 +86,7 @@ impl Hash for TexInfo {
         self.filtering_method.hash(state);
         self.wrap_mode.hash(state);
         self.gamma.hash(state);
+        self.alpha.hash(state);
         OrderedFloat::from(self.scale).hash(state);
         OrderedFloat::from(self.max_anisotropy).hash(state);
     }
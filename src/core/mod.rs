@@ -20,9 +20,11 @@ pub mod medium;
 pub mod memory;
 pub mod microfacet;
 pub mod mipmap;
+pub mod obj;
 pub mod paramset;
 pub mod parsers;
 pub mod pbrt;
+pub mod ply;
 pub mod primitive;
 pub mod primitives;
 pub mod reflection;
@@ -32,4 +34,5 @@ pub mod sampling;
 pub mod scene;
 pub mod sobol_matrices;
 pub mod spectrum;
+pub mod stats;
 pub mod texture;
@@ -0,0 +1,662 @@
+//! Wavefront OBJ and MTL Files
+
+#![allow(dead_code)]
+use crate::core::geometry::*;
+use crate::core::pbrt::*;
+use crate::shapes::compute_normals;
+use std::collections::HashMap;
+use std::fs::File;
+use std::io::{BufRead, BufReader};
+use std::result::Result;
+
+/// Triangle mesh for the faces of an OBJ group that use the same material.
+#[derive(Clone, Default)]
+pub struct OBJMesh {
+    /// Group name. This will be empty for faces outside a group.
+    pub group: String,
+
+    /// Material name. This will be empty if no material is used.
+    pub material: String,
+
+    /// Vertex positions.
+    pub p: Vec<Point3f>,
+
+    /// Vertex normals. This will be empty if there are none.
+    pub n: Vec<Normal3f>,
+
+    /// Vertex uv-coordinates. This will be empty if there are none.
+    pub uv: Vec<Point2f>,
+
+    /// Vertex indices for triangles.
+    pub indices: Vec<usize>,
+}
+
+/// Contents of an OBJ file.
+#[derive(Clone, Default)]
+pub struct OBJFile {
+    /// Triangle meshes for each group and material.
+    pub meshes: Vec<OBJMesh>,
+
+    /// Material library files referenced by the OBJ file.
+    pub mtllibs: Vec<String>,
+}
+
+/// Material definition from an MTL file.
+#[derive(Clone, Debug)]
+pub struct OBJMaterial {
+    /// Material name.
+    pub name: String,
+
+    /// Diffuse reflectance.
+    pub kd: [Float; 3],
+
+    /// Specular reflectance.
+    pub ks: [Float; 3],
+
+    /// Specular exponent.
+    pub ns: Float,
+
+    /// Illumination model. This will be `None` if not given.
+    pub illum: Option<u32>,
+
+    /// Diffuse reflectance map. This will be empty if there is none.
+    pub map_kd: String,
+
+    /// Bump map. This will be empty if there is none.
+    pub map_bump: String,
+}
+
+impl OBJMaterial {
+    /// Returns a new material with the default MTL values.
+    ///
+    /// * `name` - Material name.
+    fn new(name: &str) -> Self {
+        Self {
+            name: String::from(name),
+            kd: [0.8, 0.8, 0.8],
+            ks: [0.0, 0.0, 0.0],
+            ns: 0.0,
+            illum: None,
+            map_kd: String::new(),
+            map_bump: String::new(),
+        }
+    }
+}
+
+/// An OBJ face vertex given by its position, uv-coordinate and normal indices.
+type OBJVertex = (usize, Option<usize>, Option<usize>);
+
+/// Builds a triangle mesh from OBJ faces by merging face vertices that share
+/// the same position, uv-coordinate, normal and smoothing group.
+struct OBJMeshBuilder {
+    /// Group name.
+    group: String,
+
+    /// Material name.
+    material: String,
+
+    /// Face vertices in the mesh.
+    vertices: Vec<OBJVertex>,
+
+    /// Index of each face vertex and smoothing group in `vertices`. Vertices
+    /// with normals are shared by all smoothing groups.
+    vertex_map: HashMap<(OBJVertex, u32), usize>,
+
+    /// Vertex indices for triangles.
+    indices: Vec<usize>,
+
+    /// Smoothing group of each triangle. This is 0 for flat faces.
+    smoothing_groups: Vec<u32>,
+
+    /// Whether or not any face is in a smoothing group.
+    smooth: bool,
+}
+
+impl OBJMeshBuilder {
+    /// Create a new `OBJMeshBuilder`.
+    ///
+    /// * `group`    - Group name.
+    /// * `material` - Material name.
+    fn new(group: &str, material: &str) -> Self {
+        Self {
+            group: String::from(group),
+            material: String::from(material),
+            vertices: vec![],
+            vertex_map: HashMap::new(),
+            indices: vec![],
+            smoothing_groups: vec![],
+            smooth: false,
+        }
+    }
+
+    /// Add a polygon face. Faces with more than three vertices are
+    /// triangulated as a fan around the first vertex.
+    ///
+    /// * `face`            - Face vertices.
+    /// * `smoothing_group` - Smoothing group of the face or 0 if it is flat.
+    fn add_face(&mut self, face: &[OBJVertex], smoothing_group: u32) {
+        let indices: Vec<usize> = face
+            .iter()
+            .map(|v| self.vertex_index(*v, smoothing_group))
+            .collect();
+        for i in 1..indices.len() - 1 {
+            self.indices.push(indices[0]);
+            self.indices.push(indices[i]);
+            self.indices.push(indices[i + 1]);
+            self.smoothing_groups.push(smoothing_group);
+        }
+        self.smooth |= smoothing_group != 0;
+    }
+
+    /// Returns the index of a face vertex in the mesh adding it if needed.
+    ///
+    /// * `v`               - The face vertex.
+    /// * `smoothing_group` - Smoothing group of the face.
+    fn vertex_index(&mut self, v: OBJVertex, smoothing_group: u32) -> usize {
+        let key = (v, if v.2.is_some() { 0 } else { smoothing_group });
+        if let Some(i) = self.vertex_map.get(&key) {
+            return *i;
+        }
+        let i = self.vertices.len();
+        self.vertices.push(v);
+        self.vertex_map.insert(key, i);
+        i
+    }
+
+    /// Returns the triangle mesh.
+    ///
+    /// * `p`  - Vertex positions in the file.
+    /// * `n`  - Vertex normals in the file.
+    /// * `uv` - Vertex uv-coordinates in the file.
+    fn build(&self, p: &[Point3f], n: &[Normal3f], uv: &[Point2f]) -> OBJMesh {
+        let has_n = self.vertices.iter().any(|v| v.2.is_some());
+        let needs_n = has_n || self.smooth;
+
+        // Flat faces in a mesh with normals need the face normal at their
+        // vertices, so vertices without normals aren't shared between them.
+        let mut vertices = self.vertices.clone();
+        let mut indices = self.indices.clone();
+        if needs_n {
+            for (tri, smoothing_group) in indices.chunks_exact_mut(3).zip(&self.smoothing_groups) {
+                if *smoothing_group != 0 {
+                    continue;
+                }
+                for i in tri.iter_mut() {
+                    if vertices[*i].2.is_none() {
+                        vertices.push(vertices[*i]);
+                        *i = vertices.len() - 1;
+                    }
+                }
+            }
+
+            // Drop the vertices no longer used.
+            let mut remap = vec![usize::MAX; vertices.len()];
+            let mut used: Vec<OBJVertex> = Vec::with_capacity(vertices.len());
+            for i in indices.iter_mut() {
+                if remap[*i] == usize::MAX {
+                    remap[*i] = used.len();
+                    used.push(vertices[*i]);
+                }
+                *i = remap[*i];
+            }
+            vertices = used;
+        }
+
+        let mesh_p: Vec<Point3f> = vertices.iter().map(|v| p[v.0]).collect();
+
+        let has_uv = vertices.iter().any(|v| v.1.is_some());
+        let mesh_uv: Vec<Point2f> = if has_uv {
+            vertices
+                .iter()
+                .map(|v| v.1.map_or(Point2f::default(), |i| uv[i]))
+                .collect()
+        } else {
+            vec![]
+        };
+
+        // Use the normals in the file and fill in any missing ones with
+        // normals computed from the faces.
+        let mesh_n: Vec<Normal3f> = if needs_n {
+            let smooth_n = if vertices.iter().all(|v| v.2.is_some()) {
+                vec![]
+            } else {
+                compute_normals(&mesh_p, &indices)
+            };
+            vertices
+                .iter()
+                .enumerate()
+                .map(|(i, v)| v.2.map_or_else(|| smooth_n[i], |j| n[j]))
+                .collect()
+        } else {
+            vec![]
+        };
+
+        OBJMesh {
+            group: self.group.clone(),
+            material: self.material.clone(),
+            p: mesh_p,
+            n: mesh_n,
+            uv: mesh_uv,
+            indices,
+        }
+    }
+}
+
+/// Read an OBJ file. Faces are grouped into triangle meshes by group and
+/// material. Polygons are triangulated as fans and negative indices are
+/// resolved relative to the end of the vertex lists. Faces in a smoothing
+/// group without vertex normals get smooth normals computed from the faces
+/// of the group; other faces without vertex normals are flat.
+/// Lines, points, curves and surfaces are ignored.
+///
+/// * `path` - Input file path.
+pub fn read_obj(path: &str) -> Result<OBJFile, String> {
+    let lines = read_lines(path)?;
+
+    let mut p: Vec<Point3f> = vec![];
+    let mut n: Vec<Normal3f> = vec![];
+    let mut uv: Vec<Point2f> = vec![];
+    let mut mtllibs: Vec<String> = vec![];
+
+    let mut builders: Vec<OBJMeshBuilder> = vec![];
+    let mut builder_map: HashMap<(String, String), usize> = HashMap::new();
+    let mut group = String::new();
+    let mut material = String::new();
+    let mut smoothing_group = 0;
+
+    for (line_num, line) in lines.iter().enumerate() {
+        let mut tokens = line.split_whitespace();
+        let keyword = match tokens.next() {
+            Some(keyword) => keyword,
+            None => continue,
+        };
+        let args: Vec<&str> = tokens.collect();
+        let err = |msg: String| format!("Error reading {} line {}. {}", path, line_num + 1, msg);
+
+        match keyword {
+            "v" => {
+                let v = parse_floats(&args, 3).map_err(err)?;
+                p.push(Point3f::new(v[0], v[1], v[2]));
+            }
+            "vn" => {
+                let v = parse_floats(&args, 3).map_err(err)?;
+                n.push(Normal3f::new(v[0], v[1], v[2]));
+            }
+            "vt" => {
+                let v = parse_floats(&args, 2).map_err(err)?;
+                uv.push(Point2f::new(v[0], v[1]));
+            }
+            "f" => {
+                if args.len() < 3 {
+                    return Err(err(format!("Face has {} vertices.", args.len())));
+                }
+                let face = args
+                    .iter()
+                    .map(|arg| parse_face_vertex(arg, p.len(), uv.len(), n.len()))
+                    .collect::<Result<Vec<OBJVertex>, String>>()
+                    .map_err(err)?;
+
+                let key = (group.clone(), material.clone());
+                let i = match builder_map.get(&key) {
+                    Some(i) => *i,
+                    None => {
+                        builders.push(OBJMeshBuilder::new(&group, &material));
+                        builder_map.insert(key, builders.len() - 1);
+                        builders.len() - 1
+                    }
+                };
+                builders[i].add_face(&face, smoothing_group);
+            }
+            "g" | "o" => group = args.join(" "),
+            "usemtl" => material = args.join(" "),
+            "mtllib" => mtllibs.extend(args.iter().map(|s| String::from(*s))),
+            "s" => {
+                smoothing_group = match args.first() {
+                    None | Some(&"off") => 0,
+                    Some(s) => s.parse::<u32>().unwrap_or(1),
+                }
+            }
+            _ => (),
+        }
+    }
+
+    Ok(OBJFile {
+        meshes: builders.iter().map(|b| b.build(&p, &n, &uv)).collect(),
+        mtllibs,
+    })
+}
+
+/// Read an MTL file containing material definitions.
+///
+/// * `path` - Input file path.
+pub fn read_mtl(path: &str) -> Result<Vec<OBJMaterial>, String> {
+    let lines = read_lines(path)?;
+
+    let mut materials: Vec<OBJMaterial> = vec![];
+    for (line_num, line) in lines.iter().enumerate() {
+        let mut tokens = line.split_whitespace();
+        let keyword = match tokens.next() {
+            Some(keyword) => keyword,
+            None => continue,
+        };
+        let args: Vec<&str> = tokens.collect();
+        let err = |msg: String| format!("Error reading {} line {}. {}", path, line_num + 1, msg);
+
+        if keyword == "newmtl" {
+            materials.push(OBJMaterial::new(&args.join(" ")));
+            continue;
+        }
+
+        let material = match materials.last_mut() {
+            Some(material) => material,
+            None => continue,
+        };
+        match keyword {
+            "Kd" | "Ks" => {
+                let v = parse_floats(&args, 1).map_err(err)?;
+                // A single value is used for all channels.
+                let rgb = if v.len() >= 3 {
+                    [v[0], v[1], v[2]]
+                } else {
+                    [v[0], v[0], v[0]]
+                };
+                if keyword == "Kd" {
+                    material.kd = rgb;
+                } else {
+                    material.ks = rgb;
+                }
+            }
+            "Ns" => material.ns = parse_floats(&args, 1).map_err(err)?[0],
+            "illum" => {
+                let illum = args.first().map_or("", |s| *s);
+                material.illum = Some(
+                    illum
+                        .parse::<u32>()
+                        .map_err(|_| err(format!("Invalid illumination model '{}'.", illum)))?,
+                );
+            }
+            // Texture map options precede the file name.
+            "map_Kd" => material.map_kd = args.last().map_or(String::new(), |s| String::from(*s)),
+            "map_Bump" | "map_bump" | "bump" => {
+                material.map_bump = args.last().map_or(String::new(), |s| String::from(*s))
+            }
+            _ => (),
+        }
+    }
+
+    Ok(materials)
+}
+
+/// Returns the lines in a file with comments removed and lines ending with
+/// a backslash joined with the next line.
+///
+/// * `path` - Input file path.
+fn read_lines(path: &str) -> Result<Vec<String>, String> {
+    let file = File::open(path).map_err(|err| format!("Unable to open {}. {}", path, err))?;
+    let reader = BufReader::new(file);
+
+    let mut lines: Vec<String> = vec![];
+    let mut continued = false;
+    for line in reader.lines() {
+        let line = line.map_err(|err| format!("Error reading {}. {}", path, err))?;
+        let line = match line.find('#') {
+            Some(i) => &line[..i],
+            None => &line[..],
+        };
+        let (line, continues) = match line.trim_end().strip_suffix('\\') {
+            Some(line) => (line, true),
+            None => (line, false),
+        };
+
+        if continued {
+            let last = lines.last_mut().unwrap();
+            last.push(' ');
+            last.push_str(line);
+        } else {
+            lines.push(String::from(line));
+        }
+        continued = continues;
+    }
+
+    Ok(lines)
+}
+
+/// Parse at least `min_count` floating point values.
+///
+/// * `args`      - The values.
+/// * `min_count` - Minimum number of values expected.
+fn parse_floats(args: &[&str], min_count: usize) -> Result<Vec<Float>, String> {
+    if args.len() < min_count {
+        return Err(format!(
+            "Expected {} values but found {}.",
+            min_count,
+            args.len()
+        ));
+    }
+    args.iter()
+        .map(|s| {
+            s.parse::<Float>()
+                .map_err(|_| format!("Invalid number '{}'.", s))
+        })
+        .collect()
+}
+
+/// Parse a face vertex of the form `v`, `v/vt`, `v//vn` or `v/vt/vn` and
+/// return 0-based indices.
+///
+/// * `arg`     - The face vertex.
+/// * `n_p`     - Number of vertex positions read so far.
+/// * `n_uv`    - Number of vertex uv-coordinates read so far.
+/// * `n_n`     - Number of vertex normals read so far.
+fn parse_face_vertex(arg: &str, n_p: usize, n_uv: usize, n_n: usize) -> Result<OBJVertex, String> {
+    let mut parts = arg.split('/');
+    let p = match parts.next() {
+        Some(s) if !s.is_empty() => parse_index(s, n_p)?,
+        _ => return Err(format!("Invalid face vertex '{}'.", arg)),
+    };
+    let uv = match parts.next() {
+        Some(s) if !s.is_empty() => Some(parse_index(s, n_uv)?),
+        _ => None,
+    };
+    let n = match parts.next() {
+        Some(s) if !s.is_empty() => Some(parse_index(s, n_n)?),
+        _ => None,
+    };
+    Ok((p, uv, n))
+}
+
+/// Parse a 1-based index or negative index relative to the end of a vertex
+/// list and return a 0-based index.
+///
+/// * `s`     - The index.
+/// * `count` - Number of elements in the vertex list.
+fn parse_index(s: &str, count: usize) -> Result<usize, String> {
+    let i = s
+        .parse::<i64>()
+        .map_err(|_| format!("Invalid index '{}'.", s))?;
+    let index = if i < 0 { count as i64 + i } else { i - 1 };
+    if index < 0 || index >= count as i64 {
+        Err(format!("Index {} out of bounds.", i))
+    } else {
+        Ok(index as usize)
+    }
+}
+
+// ----------------------------------------------------------------------------
+// Tests
+// ----------------------------------------------------------------------------
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    /// Write a file to the temporary directory and read it.
+    fn read<T>(name: &str, data: &str, f: fn(&str) -> Result<T, String>) -> Result<T, String> {
+        let path = std::env::temp_dir().join(format!("{}_{}", std::process::id(), name));
+        let path = path.to_str().unwrap();
+        std::fs::write(path, data).unwrap();
+        let result = f(path);
+        std::fs::remove_file(path).unwrap();
+        result
+    }
+
+    /// Read a single mesh from OBJ file contents.
+    fn read_mesh(name: &str, data: &str) -> OBJMesh {
+        let obj = read(name, data, read_obj).unwrap();
+        assert_eq!(obj.meshes.len(), 1);
+        obj.meshes[0].clone()
+    }
+
+    /// Two triangles sharing the edge from (1, 0, 0) to (0, 1, 0) that aren't
+    /// coplanar. The smoothing group of each face is given.
+    fn folded(s1: &str, s2: &str) -> String {
+        format!(
+            "v 0 0 0\nv 1 0 0\nv 0 1 0\nv 1 1 1\ns {}\nf 1 2 3\ns {}\nf 2 4 3\n",
+            s1, s2
+        )
+    }
+
+    #[test]
+    fn negative_indices_are_relative_to_the_end() {
+        let mesh = read_mesh(
+            "negative.obj",
+            "v 9 9 9\nv 0 0 0\nv 1 0 0\nv 1 1 0\nf -3 -2 -1\nv 0 1 0\nf 2 -2 -1\n",
+        );
+        assert_eq!(
+            mesh.p,
+            vec![
+                Point3f::new(0.0, 0.0, 0.0),
+                Point3f::new(1.0, 0.0, 0.0),
+                Point3f::new(1.0, 1.0, 0.0),
+                Point3f::new(0.0, 1.0, 0.0),
+            ]
+        );
+        assert_eq!(mesh.indices, vec![0, 1, 2, 0, 2, 3]);
+
+        let err = read("bounds.obj", "v 0 0 0\nv 1 0 0\nf 1 2 -3\n", read_obj)
+            .err()
+            .unwrap();
+        assert!(err.ends_with("line 3. Index -3 out of bounds."), "{}", err);
+    }
+
+    #[test]
+    fn polygons_are_triangulated_as_fans() {
+        let mesh = read_mesh(
+            "pentagon.obj",
+            "v 0 0 0\nv 2 0 0\nv 3 1 0\nv 1 2 0\nv -1 1 0\nf 1 2 3 4 5\n",
+        );
+        assert_eq!(mesh.p.len(), 5);
+        assert_eq!(mesh.indices, vec![0, 1, 2, 0, 2, 3, 0, 3, 4]);
+        assert!(mesh.n.is_empty());
+        assert!(mesh.uv.is_empty());
+    }
+
+    #[test]
+    fn face_vertices_may_have_normals_without_uvs() {
+        let mesh = read_mesh(
+            "normals.obj",
+            "v 0 0 0\nv 1 0 0\nv 0 1 0\nvn 0 0 1\nvn 0 0 -1\nf 1//1 2//1 3//2\n",
+        );
+        assert!(mesh.uv.is_empty());
+        assert_eq!(
+            mesh.n,
+            vec![
+                Normal3f::new(0.0, 0.0, 1.0),
+                Normal3f::new(0.0, 0.0, 1.0),
+                Normal3f::new(0.0, 0.0, -1.0),
+            ]
+        );
+
+        let mesh = read_mesh(
+            "uvs.obj",
+            "v 0 0 0\nv 1 0 0\nv 0 1 0\nvt 0 0\nvt 1 0\nvt 0 1\nf 1/1 2/2 3/3\n",
+        );
+        assert!(mesh.n.is_empty());
+        assert_eq!(mesh.uv[2], Point2f::new(0.0, 1.0));
+    }
+
+    #[test]
+    fn vertices_are_shared_within_smoothing_groups() {
+        let face_n1 = Normal3f::new(0.0, 0.0, 1.0);
+        let face_n2 = Normal3f::new(-1.0, -1.0, 1.0);
+
+        let mesh = read_mesh("smooth.obj", &folded("1", "1"));
+        assert_eq!(mesh.p.len(), 4);
+        assert_eq!(mesh.indices, vec![0, 1, 2, 1, 3, 2]);
+        assert_eq!(mesh.n[0], face_n1);
+        assert_eq!(mesh.n[1], (face_n1 + face_n2).normalize());
+        assert_eq!(mesh.n[2], (face_n1 + face_n2).normalize());
+        assert_eq!(mesh.n[3], face_n2.normalize());
+
+        // Faces in different smoothing groups don't share normals.
+        for (s1, s2) in [("1", "2"), ("1", "off"), ("0", "1")].iter() {
+            let mesh = read_mesh("groups.obj", &folded(s1, s2));
+            assert_eq!(mesh.p.len(), 6);
+            assert_eq!(mesh.indices, vec![0, 1, 2, 3, 4, 5]);
+            assert!(mesh.n[..3].iter().all(|n| *n == face_n1));
+            assert!(mesh.n[3..].iter().all(|n| *n == face_n2.normalize()));
+        }
+
+        // Flat meshes don't need normals.
+        let mesh = read_mesh("flat.obj", &folded("off", "0"));
+        assert_eq!(mesh.p.len(), 4);
+        assert!(mesh.n.is_empty());
+    }
+
+    #[test]
+    fn meshes_are_split_by_group_and_material() {
+        let obj = read(
+            "split.obj",
+            "mtllib a.mtl b.mtl\nv 0 0 0\nv 1 0 0\nv 0 1 0\n\
+             f 1 2 3\ng top\nusemtl red\nf 1 2 3\n\
+             g bottom\nf 1 \\\n 2 3\ng top\nf 3 2 1\n",
+            read_obj,
+        )
+        .unwrap();
+        assert_eq!(obj.mtllibs, vec!["a.mtl", "b.mtl"]);
+        let meshes: Vec<(&str, &str, usize)> = obj
+            .meshes
+            .iter()
+            .map(|m| (&m.group[..], &m.material[..], m.indices.len()))
+            .collect();
+        assert_eq!(
+            meshes,
+            vec![("", "", 3), ("top", "red", 6), ("bottom", "red", 3)]
+        );
+    }
+
+    #[test]
+    fn reads_mtl_materials() {
+        let materials = read(
+            "materials.mtl",
+            "Kd 1 1 1\nnewmtl red # comment\nKd 0.5 0 0\nKs 0.2\nNs 10\nillum 2\n\
+             map_Kd -s 2 2 2 red.png\nbump -bm 0.5 bump.png\nnewmtl plain\n",
+            read_mtl,
+        )
+        .unwrap();
+        assert_eq!(materials.len(), 2);
+
+        let red = &materials[0];
+        assert_eq!(red.name, "red");
+        assert_eq!(red.kd, [0.5, 0.0, 0.0]);
+        assert_eq!(red.ks, [0.2, 0.2, 0.2]);
+        assert_eq!(red.ns, 10.0);
+        assert_eq!(red.illum, Some(2));
+        assert_eq!(red.map_kd, "red.png");
+        assert_eq!(red.map_bump, "bump.png");
+
+        let plain = &materials[1];
+        assert_eq!(plain.kd, [0.8, 0.8, 0.8]);
+        assert_eq!(plain.illum, None);
+        assert!(plain.map_kd.is_empty());
+
+        let err = read("invalid.mtl", "newmtl a\nillum x\n", read_mtl)
+            .err()
+            .unwrap();
+        assert!(
+            err.ends_with("line 2. Invalid illumination model 'x'."),
+            "{}",
+            err
+        );
+    }
+}
@@ -8,6 +8,7 @@ use crate::core::spectrum::*;
 use crate::core::texture::*;
 use std::collections::HashMap;
 use std::fmt;
+use std::sync::{Arc, Mutex};
 
 mod paramset_item;
 mod texture_params;
@@ -19,6 +20,29 @@ pub use texture_params::*;
 /// A hashmap of parameter sets stored by name.
 pub type ParamSetMap<T> = HashMap<String, ParamSetItem<T>>;
 
+/// A hashmap of the parameter types that were looked up by name but not found.
+type MissingLookups = HashMap<String, Vec<&'static str>>;
+
+/// The values a spectrum parameter was given with. These are kept so that
+/// the parameter can be written back out in its original form.
+#[derive(Clone, Debug, PartialEq)]
+pub enum SpectrumValues {
+    /// RGB values.
+    Rgb(Vec<Float>),
+
+    /// XYZ values.
+    Xyz(Vec<Float>),
+
+    /// Blackbody temperatures with optional scales.
+    Blackbody(Vec<Float>),
+
+    /// (wavelength, sample) values.
+    Sampled(Vec<Float>),
+
+    /// Named spectrum or spectrum data file.
+    Named(String),
+}
+
 /// Stores parameter set items of different types in hashmaps.
 #[derive(Clone)]
 pub struct ParamSet {
@@ -34,6 +58,14 @@ pub struct ParamSet {
     pub strings: ParamSetMap<String>,
     pub textures: ParamSetMap<String>,
     pub cached_spectra: HashMap<String, Spectrum>,
+
+    /// Original values of spectrum parameters by name. Spectra added with
+    /// `add_spectrum()` have none.
+    pub spectrum_values: HashMap<String, SpectrumValues>,
+
+    /// Parameter types looked up by name but not found. Used to report type
+    /// mismatches. This is shared by copies of the `ParamSet`.
+    missing_lookups: Arc<Mutex<MissingLookups>>,
 }
 
 /// Define a macro that can be used to generate a function for adding/replacing
@@ -61,18 +93,22 @@ macro_rules! paramset_erase {
 /// Define a macro that can be used to generate a function for finding
 /// parameter set item that is stored as a single item.
 macro_rules! paramset_find_one {
-    ($func: ident, $t: ty, $paramset: ident) => {
+    ($func: ident, $t: ty, $paramset: ident, $param_type: literal) => {
         pub fn $func(&self, name: &str, default: $t) -> $t {
             let n = String::from(name);
             match self.$paramset.get(&n) {
                 Some(param) => {
                     if param.values.len() == 1 {
+                        param.mark_looked_up();
                         param.values[0].clone()
                     } else {
                         default.clone()
                     }
                 }
-                None => default.clone(),
+                None => {
+                    self.add_missing_lookup(name, $param_type);
+                    default.clone()
+                }
             }
         }
     };
@@ -81,12 +117,80 @@ macro_rules! paramset_find_one {
 /// Define a macro that can be used to generate a function for finding
 /// parameter set item that is stored as a list.
 macro_rules! paramset_find {
-    ($func: ident, $t: ty, $paramset: ident) => {
+    ($func: ident, $t: ty, $paramset: ident, $param_type: literal) => {
         pub fn $func(&self, name: &str) -> Vec<$t> {
             let n = String::from(name);
             match self.$paramset.get(&n) {
-                Some(param) => param.values.clone(),
-                None => vec![],
+                Some(param) => {
+                    param.mark_looked_up();
+                    param.values.clone()
+                }
+                None => {
+                    self.add_missing_lookup(name, $param_type);
+                    vec![]
+                }
+            }
+        }
+    };
+}
+
+/// Define a macro that can be used to copy parameter set items that are not
+/// already present. The copies have their own looked up flags.
+macro_rules! paramset_add_defaults {
+    ($params: expr, $defaults: expr) => {
+        for (name, param) in $defaults.iter() {
+            if !$params.contains_key(name) {
+                $params.insert(name.clone(), param.detach());
+            }
+        }
+    };
+}
+
+/// Define a macro that can be used to copy parameter set items with their own
+/// looked up flags.
+macro_rules! paramset_detach {
+    ($params: expr) => {
+        $params
+            .iter()
+            .map(|(name, param)| (name.clone(), param.detach()))
+            .collect()
+    };
+}
+
+/// Define a macro that can be used to mark parameter set items as looked up
+/// if the items with the same name in another parameter set were looked up.
+macro_rules! paramset_merge_lookups {
+    ($params: expr, $other: expr) => {
+        for (name, param) in $other.iter() {
+            if param.is_looked_up() {
+                if let Some(p) = $params.get(name) {
+                    p.mark_looked_up();
+                }
+            }
+        }
+    };
+}
+
+/// Define a macro that can be used to report parameter set items that were
+/// not looked up.
+macro_rules! report_unused_param {
+    ($params: expr, $param_type: literal, $directive: expr, $missing: expr) => {
+        for (name, param) in $params.iter() {
+            if param.is_looked_up() {
+                continue;
+            }
+            match $missing.get(name) {
+                Some(expected) => error!(
+                    "{}: parameter \"{} {}\" has the wrong type. Expected '{}'.",
+                    $directive,
+                    $param_type,
+                    name,
+                    expected.join("' or '")
+                ),
+                None => warn!(
+                    "{}: parameter \"{} {}\" unused.",
+                    $directive, $param_type, name
+                ),
             }
         }
     };
@@ -142,62 +246,182 @@ impl ParamSet {
             strings: HashMap::new(),
             textures: HashMap::new(),
             cached_spectra: HashMap::new(),
+            spectrum_values: HashMap::new(),
+            missing_lookups: Arc::new(Mutex::new(HashMap::new())),
         }
     }
 
     paramset_erase!(erase_int, ints);
-    paramset_find_one!(find_one_int, Int, ints);
-    paramset_find!(find_int, Int, ints);
+    paramset_find_one!(find_one_int, Int, ints, "integer");
+    paramset_find!(find_int, Int, ints, "integer");
     paramset_add!(add_int, Int, ints);
 
     paramset_erase!(erase_bool, bools);
-    paramset_find_one!(find_one_bool, bool, bools);
-    paramset_find!(find_bool, bool, bools);
+    paramset_find_one!(find_one_bool, bool, bools, "bool");
+    paramset_find!(find_bool, bool, bools, "bool");
     paramset_add!(add_bool, bool, bools);
 
     paramset_erase!(erase_float, floats);
-    paramset_find_one!(find_one_float, Float, floats);
-    paramset_find!(find_float, Float, floats);
     paramset_add!(add_float, Float, floats);
 
     paramset_erase!(erase_point2f, point2fs);
-    paramset_find_one!(find_one_point2f, Point2f, point2fs);
-    paramset_find!(find_point2f, Point2f, point2fs);
+    paramset_find_one!(find_one_point2f, Point2f, point2fs, "point2");
+    paramset_find!(find_point2f, Point2f, point2fs, "point2");
     paramset_add!(add_point2f, Point2f, point2fs);
 
     paramset_erase!(erase_vector2f, vector2fs);
-    paramset_find_one!(find_one_vector2f, Vector2f, vector2fs);
-    paramset_find!(find_vector2f, Vector2f, vector2fs);
+    paramset_find_one!(find_one_vector2f, Vector2f, vector2fs, "vector2");
+    paramset_find!(find_vector2f, Vector2f, vector2fs, "vector2");
     paramset_add!(add_vector2f, Vector2f, vector2fs);
 
     paramset_erase!(erase_point3f, point3fs);
-    paramset_find_one!(find_one_point3f, Point3f, point3fs);
-    paramset_find!(find_point3f, Point3f, point3fs);
+    paramset_find_one!(find_one_point3f, Point3f, point3fs, "point3");
+    paramset_find!(find_point3f, Point3f, point3fs, "point3");
     paramset_add!(add_point3f, Point3f, point3fs);
 
     paramset_erase!(erase_vector3f, vector3fs);
-    paramset_find_one!(find_one_vector3f, Vector3f, vector3fs);
-    paramset_find!(find_vector3f, Vector3f, vector3fs);
+    paramset_find_one!(find_one_vector3f, Vector3f, vector3fs, "vector3");
+    paramset_find!(find_vector3f, Vector3f, vector3fs, "vector3");
     paramset_add!(add_vector3f, Vector3f, vector3fs);
 
     paramset_erase!(erase_normal3f, normal3fs);
-    paramset_find_one!(find_one_normal3f, Normal3f, normal3fs);
-    paramset_find!(find_normal3f, Normal3f, normal3fs);
+    paramset_find_one!(find_one_normal3f, Normal3f, normal3fs, "normal");
+    paramset_find!(find_normal3f, Normal3f, normal3fs, "normal");
     paramset_add!(add_normal3f, Normal3f, normal3fs);
 
     paramset_erase!(erase_string, strings);
-    paramset_find_one!(find_one_string, String, strings);
-    paramset_find!(find_string, String, strings);
+    paramset_find_one!(find_one_string, String, strings, "string");
+    paramset_find!(find_string, String, strings, "string");
     paramset_add!(add_string, String, strings);
 
     paramset_erase!(erase_texture, textures);
-    paramset_find_one!(find_one_texture, String, textures);
-    paramset_find!(find_texture, String, textures);
+    paramset_find_one!(find_one_texture, String, textures, "texture");
+    paramset_find!(find_texture, String, textures, "texture");
     paramset_add!(add_texture, String, textures);
 
-    paramset_erase!(erase_spectrum, spectra);
-    paramset_find_one!(find_one_spectrum, Spectrum, spectra);
-    paramset_find!(find_spectrum, Spectrum, spectra);
+    paramset_find_one!(find_one_spectrum, Spectrum, spectra, "spectrum");
+    paramset_find!(find_spectrum, Spectrum, spectra, "spectrum");
+
+    /// Removes a spectrum.
+    ///
+    /// * `name` - Parameter name.
+    pub fn erase_spectrum(&mut self, name: &str) -> bool {
+        self.spectrum_values.remove(name);
+        self.spectra.remove(name).is_some()
+    }
+
+    /// Add/replace spectra.
+    ///
+    /// * `name`   - Parameter name.
+    /// * `values` - The spectra.
+    pub fn add_spectrum(&mut self, name: &str, values: &[Spectrum]) {
+        self.spectrum_values.remove(name);
+        self.spectra
+            .insert(String::from(name), ParamSetItem::new(values.to_vec()));
+    }
+
+    /// Add/replace spectra and record the values they were given with.
+    ///
+    /// * `name`    - Parameter name.
+    /// * `spectra` - The spectra.
+    /// * `values`  - The original values.
+    fn insert_spectrum(&mut self, name: &str, spectra: Vec<Spectrum>, values: SpectrumValues) {
+        self.spectra
+            .insert(String::from(name), ParamSetItem::new(spectra));
+        self.spectrum_values.insert(String::from(name), values);
+    }
+
+    /// Finds a floating point value. Integer values are promoted if no
+    /// floating point value is found.
+    ///
+    /// * `name`    - Parameter name.
+    /// * `default` - Default value.
+    pub fn find_one_float(&self, name: &str, default: Float) -> Float {
+        match self.floats.get(name) {
+            Some(param) => {
+                if param.values.len() == 1 {
+                    param.mark_looked_up();
+                    param.values[0]
+                } else {
+                    default
+                }
+            }
+            None => match self.find_promoted_ints(name) {
+                Some(values) if values.len() == 1 => values[0],
+                _ => default,
+            },
+        }
+    }
+
+    /// Finds a list of floating point values. Integer values are promoted if
+    /// no floating point values are found.
+    ///
+    /// * `name` - Parameter name.
+    pub fn find_float(&self, name: &str) -> Vec<Float> {
+        match self.floats.get(name) {
+            Some(param) => {
+                param.mark_looked_up();
+                param.values.clone()
+            }
+            None => self.find_promoted_ints(name).unwrap_or_default(),
+        }
+    }
+
+    /// Finds integer values for a floating point parameter and promotes them
+    /// to floating point values. If there are none, the lookup is recorded
+    /// as missing.
+    ///
+    /// * `name` - Parameter name.
+    fn find_promoted_ints(&self, name: &str) -> Option<Vec<Float>> {
+        match self.ints.get(name) {
+            Some(param) => {
+                warn!(
+                    "Parameter \"integer {}\" promoted to \"float {}\".",
+                    name, name
+                );
+                param.mark_looked_up();
+                Some(param.values.iter().map(|v| *v as Float).collect())
+            }
+            None => {
+                self.add_missing_lookup(name, "float");
+                None
+            }
+        }
+    }
+
+    /// Records that a parameter was looked up but not found.
+    ///
+    /// * `name`       - Parameter name.
+    /// * `param_type` - Parameter type.
+    fn add_missing_lookup(&self, name: &str, param_type: &'static str) {
+        let mut missing_lookups = self.missing_lookups.lock().unwrap();
+        let types = missing_lookups
+            .entry(String::from(name))
+            .or_insert_with(Vec::new);
+        if !types.contains(&param_type) {
+            types.push(param_type);
+        }
+    }
+
+    /// Reports parameters that were not looked up. Parameters that were
+    /// looked up with a different type are reported as type mismatches.
+    ///
+    /// * `directive` - The scene directive used in the report (e.g. `Shape
+    ///                 "sphere"`).
+    pub fn report_unused(&self, directive: &str) {
+        let missing = self.missing_lookups.lock().unwrap();
+        report_unused_param!(self.bools, "bool", directive, missing);
+        report_unused_param!(self.ints, "integer", directive, missing);
+        report_unused_param!(self.floats, "float", directive, missing);
+        report_unused_param!(self.point2fs, "point2", directive, missing);
+        report_unused_param!(self.vector2fs, "vector2", directive, missing);
+        report_unused_param!(self.point3fs, "point3", directive, missing);
+        report_unused_param!(self.vector3fs, "vector3", directive, missing);
+        report_unused_param!(self.normal3fs, "normal", directive, missing);
+        report_unused_param!(self.spectra, "spectrum", directive, missing);
+        report_unused_param!(self.strings, "string", directive, missing);
+        report_unused_param!(self.textures, "texture", directive, missing);
+    }
 
     /// Add/replace an RGB spectrum.
     ///
@@ -207,14 +431,13 @@ impl ParamSet {
         let n = values.len();
         assert!(n % 3 == 0, "RGB spectrum values % 3 != 0");
 
-        self.spectra.insert(
-            String::from(name),
-            ParamSetItem::new(
-                (0..n)
-                    .step_by(3)
-                    .map(|i| Spectrum::from_rgb(&[values[i], values[i + 1], values[i + 2]], None))
-                    .collect(),
-            ),
+        self.insert_spectrum(
+            name,
+            (0..n)
+                .step_by(3)
+                .map(|i| Spectrum::from_rgb(&[values[i], values[i + 1], values[i + 2]], None))
+                .collect(),
+            SpectrumValues::Rgb(values.to_vec()),
         );
     }
 
@@ -226,30 +449,36 @@ impl ParamSet {
         let n = values.len();
         assert!(n % 3 == 0, "XYZ spectrum values % 3 != 0");
 
-        self.spectra.insert(
-            String::from(name),
-            ParamSetItem::new(
-                (0..n)
-                    .step_by(3)
-                    .map(|i| Spectrum::from_xyz(&[values[i], values[i + 1], values[i + 2]], None))
-                    .collect(),
-            ),
+        self.insert_spectrum(
+            name,
+            (0..n)
+                .step_by(3)
+                .map(|i| Spectrum::from_xyz(&[values[i], values[i + 1], values[i + 2]], None))
+                .collect(),
+            SpectrumValues::Xyz(values.to_vec()),
         );
     }
 
-    /// Add/replace a blackbody spectrum.
+    /// Add/replace a blackbody spectrum. A single value is a temperature with
+    /// a scale of 1 as used by the pbrt-v4 scene format.
     ///
     /// * `name`   - Parameter name.
     /// * `values` - List of (temperature (Kelvin), scale) values in a linear array.
     pub fn add_blackbody_spectrum(&mut self, name: &str, values: &[Float]) {
-        let n = values.len();
+        let pairs = if values.len() == 1 {
+            vec![values[0], 1.0]
+        } else {
+            values.to_vec()
+        };
+
+        let n = pairs.len();
         assert!(n % 2 == 0, "Blackbody spectrum values % 2 != 0");
 
         let lambda = CIE::lambda();
         let spectra: Vec<Spectrum> = (0..n)
             .step_by(2)
             .map(|i| {
-                let (temp, scale) = (values[i], values[i + 1]);
+                let (temp, scale) = (pairs[i], pairs[i + 1]);
                 let values = blackbody_normalized(&lambda, temp);
                 let samples: Vec<Sample> = lambda
                     .iter()
@@ -260,8 +489,7 @@ impl ParamSet {
             })
             .collect();
 
-        self.spectra
-            .insert(String::from(name), ParamSetItem::new(spectra));
+        self.insert_spectrum(name, spectra, SpectrumValues::Blackbody(values.to_vec()));
     }
 
     /// Add/replace a sampled spectrum.
@@ -271,8 +499,23 @@ impl ParamSet {
     pub fn add_sampled_spectrum(&mut self, name: &str, values: &[Float]) {
         let samples = Sample::list(values);
         let spectra = vec![Spectrum::from(&samples)];
-        self.spectra
-            .insert(String::from(name), ParamSetItem::new(spectra));
+        self.insert_spectrum(name, spectra, SpectrumValues::Sampled(values.to_vec()));
+    }
+
+    /// Add/replace a pbrt-v4 named spectrum. Names that aren't known spectra
+    /// are read as spectrum data files.
+    ///
+    /// * `name`          - Parameter name.
+    /// * `spectrum_name` - Spectrum name or path to the data file.
+    pub fn add_named_spectrum(&mut self, name: &str, spectrum_name: &str) {
+        match get_named_spectrum(spectrum_name) {
+            Some(spectrum) => self.insert_spectrum(
+                name,
+                vec![spectrum],
+                SpectrumValues::Named(String::from(spectrum_name)),
+            ),
+            None => self.add_sampled_spectrum_files(name, &[String::from(spectrum_name)]),
+        }
     }
 
     /// Add/replace a spectra from files.
@@ -316,6 +559,85 @@ impl ParamSet {
 
         self.spectra
             .insert(String::from(name), ParamSetItem::new(spectra));
+        if paths.len() == 1 {
+            self.spectrum_values
+                .insert(String::from(name), SpectrumValues::Named(paths[0].clone()));
+        } else {
+            self.spectrum_values.remove(name);
+        }
+    }
+
+    /// Add parameters from another parameter set that are not already present.
+    /// Parameters with the same name but a different type are also added.
+    ///
+    /// * `defaults` - The parameters to add.
+    pub fn add_defaults(&mut self, defaults: &ParamSet) {
+        paramset_add_defaults!(self.bools, defaults.bools);
+        paramset_add_defaults!(self.ints, defaults.ints);
+        paramset_add_defaults!(self.floats, defaults.floats);
+        paramset_add_defaults!(self.point2fs, defaults.point2fs);
+        paramset_add_defaults!(self.vector2fs, defaults.vector2fs);
+        paramset_add_defaults!(self.point3fs, defaults.point3fs);
+        paramset_add_defaults!(self.vector3fs, defaults.vector3fs);
+        paramset_add_defaults!(self.normal3fs, defaults.normal3fs);
+        for (name, values) in defaults.spectrum_values.iter() {
+            if !self.spectra.contains_key(name) {
+                self.spectrum_values.insert(name.clone(), values.clone());
+            }
+        }
+        paramset_add_defaults!(self.spectra, defaults.spectra);
+        paramset_add_defaults!(self.strings, defaults.strings);
+        paramset_add_defaults!(self.textures, defaults.textures);
+    }
+
+    /// Returns a copy of the parameter set whose items have their own looked
+    /// up flags. Lookups through the copy aren't tracked by this parameter
+    /// set.
+    pub fn detach(&self) -> Self {
+        Self {
+            bools: paramset_detach!(self.bools),
+            ints: paramset_detach!(self.ints),
+            floats: paramset_detach!(self.floats),
+            point2fs: paramset_detach!(self.point2fs),
+            vector2fs: paramset_detach!(self.vector2fs),
+            point3fs: paramset_detach!(self.point3fs),
+            vector3fs: paramset_detach!(self.vector3fs),
+            normal3fs: paramset_detach!(self.normal3fs),
+            spectra: paramset_detach!(self.spectra),
+            strings: paramset_detach!(self.strings),
+            textures: paramset_detach!(self.textures),
+            cached_spectra: self.cached_spectra.clone(),
+            spectrum_values: self.spectrum_values.clone(),
+            missing_lookups: Arc::new(Mutex::new(self.missing_lookups.lock().unwrap().clone())),
+        }
+    }
+
+    /// Marks parameters as looked up if they were looked up in a parameter set
+    /// copied with `detach()`. Lookups of missing parameters are also merged.
+    ///
+    /// * `other` - The detached parameter set.
+    pub fn merge_lookups(&self, other: &ParamSet) {
+        paramset_merge_lookups!(self.bools, other.bools);
+        paramset_merge_lookups!(self.ints, other.ints);
+        paramset_merge_lookups!(self.floats, other.floats);
+        paramset_merge_lookups!(self.point2fs, other.point2fs);
+        paramset_merge_lookups!(self.vector2fs, other.vector2fs);
+        paramset_merge_lookups!(self.point3fs, other.point3fs);
+        paramset_merge_lookups!(self.vector3fs, other.vector3fs);
+        paramset_merge_lookups!(self.normal3fs, other.normal3fs);
+        paramset_merge_lookups!(self.spectra, other.spectra);
+        paramset_merge_lookups!(self.strings, other.strings);
+        paramset_merge_lookups!(self.textures, other.textures);
+
+        if Arc::ptr_eq(&self.missing_lookups, &other.missing_lookups) {
+            return;
+        }
+        let other_missing = other.missing_lookups.lock().unwrap().clone();
+        for (name, types) in other_missing.iter() {
+            for param_type in types.iter() {
+                self.add_missing_lookup(name, param_type);
+            }
+        }
     }
 
     /// Finds a filename and returns the absolute path to the file.
@@ -331,6 +653,21 @@ impl ParamSet {
         }
     }
 
+    /// Returns `true` if there are no parameter set items.
+    pub fn is_empty(&self) -> bool {
+        self.bools.is_empty()
+            && self.ints.is_empty()
+            && self.floats.is_empty()
+            && self.point2fs.is_empty()
+            && self.vector2fs.is_empty()
+            && self.point3fs.is_empty()
+            && self.vector3fs.is_empty()
+            && self.normal3fs.is_empty()
+            && self.spectra.is_empty()
+            && self.strings.is_empty()
+            && self.textures.is_empty()
+    }
+
     /// Clear all parameter set items.
     pub fn clear(&mut self) {
         self.bools.clear();
@@ -345,6 +682,8 @@ impl ParamSet {
         self.strings.clear();
         self.textures.clear();
         self.cached_spectra.clear();
+        self.spectrum_values.clear();
+        self.missing_lookups = Arc::new(Mutex::new(HashMap::new()));
     }
 }
 
@@ -365,3 +704,103 @@ impl fmt::Display for ParamSet {
         Ok(())
     }
 }
+
+// ----------------------------------------------------------------------------
+// Tests
+// ----------------------------------------------------------------------------
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn attribute_defaults_have_their_own_lookups() {
+        let mut attributes = ParamSet::new();
+        attributes.add_float("radius", &[2.0]);
+        attributes.add_int("nsamples", &[4]);
+
+        let mut sphere = ParamSet::new();
+        sphere.add_defaults(&attributes);
+        let mut disk = ParamSet::new();
+        disk.add_defaults(&attributes);
+
+        assert_eq!(sphere.find_one_float("radius", 1.0), 2.0);
+        assert!(sphere.floats["radius"].is_looked_up());
+        assert!(!disk.floats["radius"].is_looked_up());
+        assert!(!attributes.floats["radius"].is_looked_up());
+        assert!(!sphere.ints["nsamples"].is_looked_up());
+    }
+
+    #[test]
+    fn add_defaults_keeps_existing_parameters() {
+        let mut attributes = ParamSet::new();
+        attributes.add_float("radius", &[2.0]);
+        let mut params = ParamSet::new();
+        params.add_float("radius", &[3.0]);
+        params.add_defaults(&attributes);
+        assert_eq!(params.find_one_float("radius", 1.0), 3.0);
+    }
+
+    #[test]
+    fn detached_lookups_are_not_shared_until_merged() {
+        let mut params = ParamSet::new();
+        params.add_spectrum("Kd", &[Spectrum::new(0.25)]);
+        params.add_float("radius", &[1.0]);
+
+        let detached = params.detach();
+        assert_eq!(
+            detached.find_one_spectrum("Kd", Spectrum::new(0.0))[0],
+            0.25
+        );
+        assert_eq!(detached.find_one_int("radius", 0), 0);
+        assert!(detached.spectra["Kd"].is_looked_up());
+        assert!(!params.spectra["Kd"].is_looked_up());
+
+        // Clones share their lookups.
+        let clone = params.clone();
+        params.merge_lookups(&detached);
+        assert!(clone.spectra["Kd"].is_looked_up());
+        assert!(!clone.floats["radius"].is_looked_up());
+        assert_eq!(
+            clone.missing_lookups.lock().unwrap().get("radius"),
+            Some(&vec!["integer"])
+        );
+    }
+
+    #[test]
+    fn spectra_keep_the_values_they_were_given_with() {
+        let mut params = ParamSet::new();
+        params.add_rgb_spectrum("Kd", &[0.1, 0.2, 0.3]);
+        params.add_blackbody_spectrum("L", &[6500.0]);
+        params.add_named_spectrum("eta", "glass-BK7");
+        assert_eq!(
+            params.spectrum_values["Kd"],
+            SpectrumValues::Rgb(vec![0.1, 0.2, 0.3])
+        );
+        assert_eq!(
+            params.spectrum_values["L"],
+            SpectrumValues::Blackbody(vec![6500.0])
+        );
+        assert_eq!(
+            params.spectrum_values["eta"],
+            SpectrumValues::Named(String::from("glass-BK7"))
+        );
+
+        // Spectra replaced without values or removed forget them.
+        params.add_spectrum("Kd", &[Spectrum::new(0.5)]);
+        assert!(params.erase_spectrum("L"));
+        assert!(!params.spectrum_values.contains_key("Kd"));
+        assert!(!params.spectrum_values.contains_key("L"));
+
+        // Defaults bring their values along unless the spectrum is present.
+        let mut defaults = ParamSet::new();
+        defaults.add_xyz_spectrum("Kd", &[1.0, 1.0, 1.0]);
+        defaults.add_sampled_spectrum("Ks", &[400.0, 0.5, 700.0, 0.5]);
+        params.add_defaults(&defaults);
+        assert!(!params.spectrum_values.contains_key("Kd"));
+        assert_eq!(
+            params.spectrum_values["Ks"],
+            SpectrumValues::Sampled(vec![400.0, 0.5, 700.0, 0.5])
+        );
+    }
+}
@@ -2,19 +2,46 @@
 
 #![allow(dead_code)]
 use std::fmt;
+use std::sync::atomic::{AtomicBool, Ordering};
+use std::sync::Arc;
 
 /// Stores a parameter set item consisting of a list of values of type `T`.
 #[derive(Clone, Default)]
 pub struct ParamSetItem<T: fmt::Display> {
     /// The values.
     pub values: Vec<T>,
+
+    /// Indicates whether the value was looked up. This is shared by copies of
+    /// the item so that lookups through a cloned `ParamSet` are tracked.
+    looked_up: Arc<AtomicBool>,
 }
 
 impl<T: fmt::Display> ParamSetItem<T> {
     /// Create new `ParamSet<T>`.
     /// * `values`    - The values.
     pub fn new(values: Vec<T>) -> Self {
-        Self { values }
+        Self {
+            values,
+            looked_up: Arc::new(AtomicBool::new(false)),
+        }
+    }
+
+    /// Returns a copy of the item with its own looked up flag.
+    pub fn detach(&self) -> Self
+    where
+        T: Clone,
+    {
+        Self::new(self.values.clone())
+    }
+
+    /// Marks the item as looked up.
+    pub fn mark_looked_up(&self) {
+        self.looked_up.store(true, Ordering::Relaxed);
+    }
+
+    /// Returns `true` if the item was looked up.
+    pub fn is_looked_up(&self) -> bool {
+        self.looked_up.load(Ordering::Relaxed)
     }
 }
 
@@ -32,7 +32,9 @@ macro_rules! texture_params_find {
 }
 
 impl TextureParams {
-    /// Create a new `TextureParams`.
+    /// Create a new `TextureParams`. The parameter sets are copied with their
+    /// own looked up flags so that lookups can be reported per material or
+    /// texture.
     ///
     /// * `geom_params`       - Geometry parameters.
     /// * `mat_params`        - Material parameters.
@@ -47,8 +49,8 @@ impl TextureParams {
         Self {
             float_textures,
             spectrum_textures,
-            geom_params,
-            mat_params,
+            geom_params: geom_params.detach(),
+            mat_params: mat_params.detach(),
         }
     }
 
@@ -94,6 +96,14 @@ impl TextureParams {
             .map_or(default.clone(), |v| v.clone())
     }
 
+    /// Reports geometry and material parameters that were not looked up.
+    ///
+    /// * `directive` - The scene directive used in the report.
+    pub fn report_unused(&self, directive: &str) {
+        self.geom_params.report_unused(directive);
+        self.mat_params.report_unused(directive);
+    }
+
     texture_params_find!(find_float, Float, find_one_float);
     texture_params_find!(find_string, String, find_one_string);
     texture_params_find!(find_filename, String, find_one_filename);
@@ -116,3 +126,57 @@ impl Default for TextureParams {
         )
     }
 }
+
+// ----------------------------------------------------------------------------
+// Tests
+// ----------------------------------------------------------------------------
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::textures::ConstantTexture;
+    use std::sync::Arc;
+
+    #[test]
+    fn texture_params_have_their_own_lookups() {
+        let mut geom_params = ParamSet::new();
+        geom_params.add_float("roughness", &[0.5]);
+        let mut mat_params = ParamSet::new();
+        mat_params.add_float("roughness", &[0.1]);
+        mat_params.add_float("sigma", &[10.0]);
+
+        let tp = TextureParams::new(
+            geom_params.clone(),
+            mat_params.clone(),
+            HashMap::new(),
+            HashMap::new(),
+        );
+
+        // Geometry parameters take precedence over material parameters.
+        assert_eq!(tp.find_float("roughness", 0.0), 0.5);
+        assert_eq!(tp.find_float("sigma", 0.0), 10.0);
+        assert!(tp.geom_params.floats["roughness"].is_looked_up());
+        assert!(tp.mat_params.floats["sigma"].is_looked_up());
+        assert!(!geom_params.floats["roughness"].is_looked_up());
+        assert!(!mat_params.floats["sigma"].is_looked_up());
+
+        let other = TextureParams::new(geom_params, mat_params, HashMap::new(), HashMap::new());
+        assert!(!other.mat_params.floats["sigma"].is_looked_up());
+    }
+
+    #[test]
+    fn textures_are_found_by_name() {
+        let mut float_textures: FloatTextureMap = HashMap::new();
+        float_textures.insert(String::from("bumpmap"), Arc::new(ConstantTexture::new(0.5)));
+        let tp = TextureParams::new(
+            ParamSet::new(),
+            ParamSet::new(),
+            float_textures,
+            HashMap::new(),
+        );
+
+        assert!(tp.get_float_texture("bumpmap").is_some());
+        assert!(tp.get_float_texture("sigma").is_none());
+        assert!(tp.get_spectrum_texture("Kd").is_none());
+    }
+}
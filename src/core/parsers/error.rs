@@ -0,0 +1,52 @@
+//! Parse Errors
+
+use crate::core::api::{SceneError, SourceLocation};
+use std::fmt;
+
+/// Errors returned while parsing a scene description.
+#[derive(Clone, Debug)]
+pub enum ParseError {
+    /// The scene description could not be read.
+    Io {
+        /// Path to the scene description file.
+        path: String,
+
+        /// The error message.
+        message: String,
+    },
+
+    /// The scene description has invalid syntax.
+    Syntax {
+        /// Location of the syntax error.
+        location: SourceLocation,
+
+        /// The error message.
+        message: String,
+    },
+
+    /// A statement in the scene description is invalid. These are only
+    /// returned in strict mode.
+    Scene(SceneError),
+}
+
+impl fmt::Display for ParseError {
+    /// Formats the error as `file:line:col: message`.
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            Self::Io { path, message } => write!(f, "{}: {}", path, message),
+            Self::Syntax { location, message } => {
+                write!(f, "{}", SceneError::new(Some(location.clone()), message))
+            }
+            Self::Scene(err) => write!(f, "{}", err),
+        }
+    }
+}
+
+impl From<SceneError> for ParseError {
+    /// Wraps a `SceneError` reported by the API.
+    ///
+    /// * `err` - The error.
+    fn from(err: SceneError) -> Self {
+        Self::Scene(err)
+    }
+}
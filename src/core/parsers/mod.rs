@@ -1,8 +1,10 @@
 //! Parsers
 
+mod error;
 mod floats;
 mod pbrt;
 
 // Re-exports.
+pub use error::ParseError;
 pub use floats::parse_float_file;
 pub use pbrt::PbrtFileParser;
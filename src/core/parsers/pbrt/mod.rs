@@ -2,15 +2,21 @@
 
 #![allow(dead_code)]
 
+use super::ParseError;
 use crate::core::api::*;
 use crate::core::fileutil::*;
 use crate::core::geometry::*;
 use crate::core::paramset::*;
 use crate::core::pbrt::*;
+use pest::error::LineColLocation;
 use pest::iterators::*;
 use pest::Parser;
+use std::cell::RefCell;
 use std::fs;
+use std::io::Read;
 use std::result::Result;
+use std::str::FromStr;
+use std::sync::Arc;
 
 /// The `pest` parser generated from a grammar.
 #[derive(Parser)]
@@ -24,6 +30,15 @@ pub struct PbrtFileParser {
 
     /// Parent path for navigating to includes.
     parent_path: String,
+
+    /// Location of the `Include` statement if the file is included by
+    /// another file.
+    included_from: Option<Arc<SourceLocation>>,
+
+    /// The first syntax error found after the grammar matched a statement,
+    /// such as an invalid number in a number list. This is returned after the
+    /// statement is processed.
+    syntax_error: RefCell<Option<ParseError>>,
 }
 
 impl PbrtFileParser {
@@ -35,6 +50,8 @@ impl PbrtFileParser {
             Self {
                 file_path: String::from(path),
                 parent_path: parent,
+                included_from: None,
+                syntax_error: RefCell::new(None),
             }
         } else {
             // We were passed the root path itself which is not a file.
@@ -42,25 +59,95 @@ impl PbrtFileParser {
         }
     }
 
-    /// Reads a PBRT file format and calls the API wrapper functions.
+    /// Returns a new instance of `PbrtFileParser` for a scene description
+    /// that isn't read from a file, such as standard input. Includes are
+    /// resolved relative to the current directory.
+    ///
+    /// * `name` - Name of the source used to report errors (e.g. `<stdin>`).
+    pub fn from_source_name(name: &str) -> Self {
+        Self {
+            file_path: String::from(name),
+            parent_path: String::new(),
+            included_from: None,
+            syntax_error: RefCell::new(None),
+        }
+    }
+
+    /// Reads a PBRT file format and calls the API wrapper functions. Errors
+    /// reported by the API are logged and parsing continues unless the API is
+    /// in strict mode, in which case the first error is returned.
     ///
     /// * `api`  - The PBRT API interface.
-    pub fn parse(&self, api: &mut Api) -> Result<(), String> {
-        // Load the file and parse the `file` rule.
-        let unparsed_file = file_to_string(&self.file_path)?;
-        let pbrt = self.parse_pbrt_rule(&unparsed_file)?;
+    pub fn parse(&self, api: &mut Api) -> Result<(), ParseError> {
+        #[cfg(feature = "mmap")]
+        {
+            // Large scenes are mapped into memory instead of being copied.
+            match map_file(&self.file_path)? {
+                Some(mmap) => match std::str::from_utf8(&mmap) {
+                    Ok(unparsed_file) => self.parse_contents(unparsed_file, api),
+                    Err(err) => Err(ParseError::Io {
+                        path: self.file_path.clone(),
+                        message: format!("Error reading file. {}", err),
+                    }),
+                },
+                None => self.parse_contents("", api),
+            }
+        }
+
+        #[cfg(not(feature = "mmap"))]
+        {
+            let unparsed_file = file_to_string(&self.file_path)?;
+            self.parse_contents(&unparsed_file, api)
+        }
+    }
+
+    /// Reads a PBRT file format from a reader and calls the API wrapper
+    /// functions. See `parse()`.
+    ///
+    /// * `reader` - The reader.
+    /// * `api`    - The PBRT API interface.
+    pub fn parse_reader<R: Read>(&self, reader: &mut R, api: &mut Api) -> Result<(), ParseError> {
+        let mut unparsed_file = String::new();
+        if let Err(err) = reader.read_to_string(&mut unparsed_file) {
+            return Err(ParseError::Io {
+                path: self.file_path.clone(),
+                message: format!("Error reading scene description. {}", err),
+            });
+        }
+        self.parse_contents(&unparsed_file, api)
+    }
+
+    /// Parses the contents of a PBRT file format and calls the API wrapper
+    /// functions.
+    ///
+    /// * `unparsed_file` - Contents of the file to parse.
+    /// * `api`           - The PBRT API interface.
+    fn parse_contents(&self, unparsed_file: &str, api: &mut Api) -> Result<(), ParseError> {
+        // Parse the `file` rule.
+        let pbrt = self.parse_pbrt_rule(unparsed_file)?;
 
         // Parse all the `stmt` rules.
         for pair in pbrt.into_inner() {
             match pair.as_rule() {
                 Rule::stmt => {
+                    let location = self.get_location(&pair);
+                    api.set_location(Some(location.clone()));
+
                     let mut inner_rules = pair.into_inner();
-                    self.parse_stmt_rule(&mut inner_rules, api);
+                    self.parse_stmt_rule(&mut inner_rules, api, location)?;
+
+                    if let Some(err) = self.syntax_error.borrow_mut().take() {
+                        return Err(err);
+                    }
+                    if let Some(err) = api.take_error() {
+                        return Err(ParseError::from(err));
+                    }
                 }
                 Rule::EOI => (), // Done
                 _ => unreachable!(),
             }
         }
+        api.set_location(None);
 
         Ok(())
     }
@@ -69,17 +156,77 @@ impl PbrtFileParser {
     /// pairs for remaining rules.
     ///
     /// * `unparsed_file` - Contents of the file to parse.
-    fn parse_pbrt_rule<'a>(&self, unparsed_file: &'a str) -> Result<Pair<'a, Rule>, String> {
+    fn parse_pbrt_rule<'a>(&self, unparsed_file: &'a str) -> Result<Pair<'a, Rule>, ParseError> {
         match PbrtParser::parse(Rule::pbrt, &unparsed_file) {
             Ok(mut pairs) => Ok(pairs.next().unwrap()),
-            Err(err) => Err(format!("Error parsing pbrt rule. {}", err)),
+            Err(err) => {
+                let (line, column) = match err.line_col {
+                    LineColLocation::Pos(pos) => pos,
+                    LineColLocation::Span(start, _) => start,
+                };
+                Err(ParseError::Syntax {
+                    location: SourceLocation::new(
+                        &self.file_path,
+                        line,
+                        column,
+                        self.included_from.clone(),
+                    ),
+                    message: err.variant.message().to_string(),
+                })
+            }
+        }
+    }
+
+    /// Keeps the first syntax error found in a statement matched by the
+    /// grammar.
+    ///
+    /// * `location` - Location of the error.
+    /// * `message`  - The error message.
+    fn report_syntax_error(&self, location: SourceLocation, message: &str) {
+        let mut syntax_error = self.syntax_error.borrow_mut();
+        if syntax_error.is_none() {
+            *syntax_error = Some(ParseError::Syntax {
+                location,
+                message: String::from(message),
+            });
+        }
+    }
+
+    /// Returns the location of the start of a token pair.
+    ///
+    /// * `pair` - The token pair.
+    fn get_location(&self, pair: &Pair<Rule>) -> SourceLocation {
+        self.get_offset_location(pair, 0)
+    }
+
+    /// Returns the location of a byte offset in a token pair.
+    ///
+    /// * `pair`   - The token pair.
+    /// * `offset` - Byte offset from the start of the token pair.
+    fn get_offset_location(&self, pair: &Pair<Rule>, offset: usize) -> SourceLocation {
+        let (mut line, mut column) = pair.as_span().start_pos().line_col();
+        let prefix = &pair.as_str()[..offset];
+        match prefix.rfind('\n') {
+            Some(i) => {
+                line += prefix.matches('\n').count();
+                column = offset - i;
+            }
+            None => column += offset,
         }
+        SourceLocation::new(&self.file_path, line, column, self.included_from.clone())
     }
 
     /// Parse a `stmt` rule of the grammar and call the API.
     ///
-    /// * `pairs` - The inner token pairs for matched `stmt` rule.
-    fn parse_stmt_rule(&self, pairs: &mut Pairs<Rule>, api: &mut Api) {
+    /// * `pairs`    - The inner token pairs for matched `stmt` rule.
+    /// * `api`      - The PBRT API interface.
+    /// * `location` - Location of the statement.
+    fn parse_stmt_rule(
+        &self,
+        pairs: &mut Pairs<Rule>,
+        api: &mut Api,
+        location: SourceLocation,
+    ) -> Result<(), ParseError> {
         let next_pair = pairs.next().unwrap();
         let rule = next_pair.as_rule();
         let mut inner_rules = next_pair.into_inner();
@@ -87,22 +234,36 @@ impl PbrtFileParser {
         match rule {
             Rule::empty_stmt => (),   // Ignore
             Rule::comment_stmt => (), // Ignore
-            Rule::include_stmt => self.parse_include_stmt(&mut inner_rules, api),
+            Rule::include_stmt => return self.parse_include_stmt(&mut inner_rules, api, location),
+            Rule::import_stmt => {
+                // pbrt-v4 imports only named objects which are a subset of
+                // what an include provides.
+                return self.parse_include_stmt(&mut inner_rules, api, location);
+            }
             Rule::option_stmt => self.parse_option_stmt(&mut inner_rules, api),
             Rule::scene_stmt => self.parse_scene_stmt(&mut inner_rules, api),
             Rule::block_stmt => self.parse_block_stmt(&mut inner_rules, api),
             Rule::ctm_stmt => self.parse_ctm_stmt(&mut inner_rules, api),
             _ => unreachable!(),
         }
+
+        Ok(())
     }
 
-    /// Parse a `include_stmt` rule of the grammar and call the API.
-    /// This will create a new parser to parse the included file and
+    /// Parse a `include_stmt` or `import_stmt` rule of the grammar and call
+    /// the API. This will create a new parser to parse the included file and
     /// parse it entirely while calling the API before returning.
     ///
-    /// * `pairs` - The inner token pairs for matched `include_stmt` rule.
-    /// * `api`   - The PBRT API interface.
-    fn parse_include_stmt(&self, pairs: &mut Pairs<Rule>, api: &mut Api) {
+    /// * `pairs`    - The inner token pairs for matched `include_stmt` or
+    ///                `import_stmt` rule.
+    /// * `api`      - The PBRT API interface.
+    /// * `location` - Location of the `Include` statement.
+    fn parse_include_stmt(
+        &self,
+        pairs: &mut Pairs<Rule>,
+        api: &mut Api,
+        location: SourceLocation,
+    ) -> Result<(), ParseError> {
         let next_pair = pairs.next().unwrap();
         match next_pair.as_rule() {
             Rule::quoted_str_expr => {
@@ -110,16 +271,19 @@ impl PbrtFileParser {
                 let mut path = self.parse_quoted_str(&mut inner_rules);
                 debug!("Include: '{}'", path);
 
-                if is_relative_path(&path) {
+                if is_relative_path(&path) && !self.parent_path.is_empty() {
                     // Path is relative to the parent path of the file being parsed.
                     path = self.parent_path.clone() + "/" + &path;
                 }
 
-                let parser = Self::new(&path);
+                let mut parser = Self::new(&path);
+                parser.included_from = Some(Arc::new(location));
                 match parser.parse(api) {
                     Ok(()) => debug!("Finished parsing include '{}'", path),
+                    Err(err) if api.is_strict() => return Err(err),
                     Err(err) => error!("{}", err),
                 }
+                Ok(())
             }
             _ => unreachable!(),
         }
@@ -136,6 +300,8 @@ impl PbrtFileParser {
             Rule::world_end_stmt => api.pbrt_world_end(),
             Rule::attribute_begin_stmt => api.pbrt_attribute_begin(),
             Rule::attribute_end_stmt => api.pbrt_attribute_end(),
+            Rule::transform_begin_stmt => api.pbrt_transform_begin(),
+            Rule::transform_end_stmt => api.pbrt_transform_end(),
             Rule::object_begin_stmt => {
                 let mut inner_rules = next_pair.into_inner();
                 let str = self.parse_quoted_str(&mut inner_rules);
@@ -171,6 +337,16 @@ impl PbrtFileParser {
                 self.parse_named_param_list(&mut inner_rules, "MakeNamedMedium", api)
             }
             Rule::sampler_stmt => self.parse_named_param_list(&mut inner_rules, "Sampler", api),
+            Rule::color_space_stmt => {
+                let name = self.parse_quoted_str(&mut inner_rules.next().unwrap().into_inner());
+                debug!("ColorSpace: '{}'", name);
+                api.pbrt_color_space(name);
+            }
+            Rule::option_param_stmt => {
+                let params = self.parse_param_list(inner_rules.next().unwrap().into_inner());
+                debug!("Option: {:}", params);
+                api.pbrt_option(&params);
+            }
             _ => unreachable!(),
         }
     }
@@ -197,10 +373,11 @@ impl PbrtFileParser {
             }
             Rule::material_stmt => self.parse_named_param_list(&mut inner_rules, "Material", api),
             Rule::shape_stmt => self.parse_named_param_list(&mut inner_rules, "Shape", api),
+            Rule::attribute_stmt => self.parse_named_param_list(&mut inner_rules, "Attribute", api),
             Rule::texture_stmt => {
-                let name = inner_rules.next().unwrap().as_str().to_string();
-                let texture_type = inner_rules.next().unwrap().as_str().to_string();
-                let texture_name = inner_rules.next().unwrap().as_str().to_string();
+                let name = self.parse_quoted_str(&mut inner_rules);
+                let texture_type = self.parse_quoted_str(&mut inner_rules);
+                let texture_name = self.parse_quoted_str(&mut inner_rules);
                 let params = inner_rules.next().map_or(ParamSet::new(), |param_list| {
                     self.parse_param_list(param_list.into_inner())
                 });
@@ -218,8 +395,12 @@ impl PbrtFileParser {
             }
             Rule::reverse_orientation_stmt => api.pbrt_reverse_orientation(),
             Rule::medium_interface_stmt => {
-                let inside_medium = inner_rules.next().unwrap().as_str().to_string();
-                let outside_medium = inner_rules.next().unwrap().as_str().to_string();
+                let inside_medium =
+                    self.parse_quoted_str(&mut inner_rules.next().unwrap().into_inner());
+                // pbrt-v4 allows a single medium for both sides.
+                let outside_medium = inner_rules.next().map_or(inside_medium.clone(), |pair| {
+                    self.parse_quoted_str(&mut pair.into_inner())
+                });
                 debug!("MediumInterface: '{}', '{}'", inside_medium, outside_medium);
                 api.pbrt_medium_interface(inside_medium, outside_medium);
             }
@@ -326,27 +507,31 @@ impl PbrtFileParser {
                 api.pbrt_coord_sys_transform(name);
             }
             Rule::transform_stmt => {
-                let tr = self.parse_float_list(next_pair.into_inner());
-                assert!(
-                    tr.len() == 16,
-                    "float_list in transform_stmt not of len 16."
-                );
+                let float_list_expr = next_pair.into_inner().next().unwrap();
+                let location = self.get_location(&float_list_expr);
+                let tr = self.parse_float_list(float_list_expr.into_inner());
+                if tr.len() != 16 {
+                    self.report_syntax_error(location, "Transform requires 16 values.");
+                    return;
+                }
                 debug!("Transform: {:?}", tr);
                 api.pbrt_transform(&[
                     tr[0], tr[1], tr[2], tr[3], tr[4], tr[5], tr[6], tr[7], tr[8], tr[9], tr[10],
-                    tr[11], tr[11], tr[12], tr[13], tr[14],
+                    tr[11], tr[12], tr[13], tr[14], tr[15],
                 ]);
             }
             Rule::concat_transform_stmt => {
-                let tr = self.parse_float_list(next_pair.into_inner());
-                assert!(
-                    tr.len() == 16,
-                    "float_list in concat_transform_stmt not of len 16."
-                );
+                let float_list_expr = next_pair.into_inner().next().unwrap();
+                let location = self.get_location(&float_list_expr);
+                let tr = self.parse_float_list(float_list_expr.into_inner());
+                if tr.len() != 16 {
+                    self.report_syntax_error(location, "ConcatTransform requires 16 values.");
+                    return;
+                }
                 debug!("ConcatTransform: {:?}", tr);
                 api.pbrt_concat_transform(&[
                     tr[0], tr[1], tr[2], tr[3], tr[4], tr[5], tr[6], tr[7], tr[8], tr[9], tr[10],
-                    tr[11], tr[11], tr[12], tr[13], tr[14],
+                    tr[11], tr[12], tr[13], tr[14], tr[15],
                 ]);
             }
             Rule::transform_times_stmt => {
@@ -384,6 +569,7 @@ impl PbrtFileParser {
             "MakeNamedMaterial" => api.pbrt_make_named_material(name, &params),
             "Material" => api.pbrt_material(name, &params),
             "Shape" => api.pbrt_shape(name, &params),
+            "Attribute" => api.pbrt_attribute(name, &params),
             _ => warn!("'{}' not supported", option_name),
         }
     }
@@ -626,6 +812,8 @@ impl PbrtFileParser {
                 vec![self.parse_quoted_bool(&mut inner_rules)]
             }
             Rule::quoted_bool_list_expr => self.parse_quoted_bool_list(value.into_inner()),
+            Rule::bool_expr => vec![self.parse_bool(&mut value.into_inner())],
+            Rule::bool_list_expr => self.parse_bool_list(value.into_inner()),
             _ => unreachable!(),
         };
         params.add_bool(ident, &list);
@@ -720,8 +908,8 @@ impl PbrtFileParser {
             }
             Rule::quoted_str_expr => {
                 let mut inner_rules = value.into_inner();
-                let filename = self.parse_quoted_str(&mut inner_rules);
-                params.add_sampled_spectrum_files(ident, &[filename]);
+                let name = self.parse_quoted_str(&mut inner_rules);
+                params.add_named_spectrum(ident, &name);
             }
             _ => unreachable!(),
         };
@@ -780,7 +968,7 @@ impl PbrtFileParser {
         let mut v: Vec<Float> = vec![];
         for pair in pairs {
             match pair.as_rule() {
-                Rule::float => v.push(self.parse_float(pair)),
+                Rule::float_list => self.parse_numbers(&pair, &mut v),
                 _ => unreachable!(),
             }
         }
@@ -794,13 +982,32 @@ impl PbrtFileParser {
         let mut v: Vec<Int> = vec![];
         for pair in pairs {
             match pair.as_rule() {
-                Rule::int => v.push(self.parse_int(pair)),
+                Rule::int_list => self.parse_numbers(&pair, &mut v),
                 _ => unreachable!(),
             }
         }
         v
     }
 
+    /// Parse the numbers in a `float_list` or `int_list` rule of the grammar.
+    /// The grammar matches the lists up to the closing bracket so invalid
+    /// numbers are found here. They are skipped and the first one is
+    /// returned as a syntax error once the statement is processed.
+    ///
+    /// * `pair`   - The matched `float_list` or `int_list` rule.
+    /// * `values` - The list to add the numbers to.
+    fn parse_numbers<T: FromStr>(&self, pair: &Pair<Rule>, values: &mut Vec<T>) {
+        for (offset, s) in NumberTokens::new(pair.as_str()) {
+            match s.parse::<T>() {
+                Ok(value) => values.push(value),
+                Err(_) => self.report_syntax_error(
+                    self.get_offset_location(pair, offset),
+                    &format!("Invalid number '{}'.", s),
+                ),
+            }
+        }
+    }
+
     /// Parse an `quoted_str_list_expr` rule of the grammar and return a `Vec<String>`.
     ///
     /// * `pairs`  - The inner token pairs for matched `quoted_str_list_expr` rule.
@@ -835,6 +1042,20 @@ impl PbrtFileParser {
         v
     }
 
+    /// Parse an `bool_list_expr` rule of the grammar and return a `Vec<bool>`.
+    ///
+    /// * `pairs`  - The inner token pairs for matched `bool_list_expr` rule.
+    fn parse_bool_list(&self, pairs: Pairs<Rule>) -> Vec<bool> {
+        let mut v: Vec<bool> = vec![];
+        for pair in pairs {
+            match pair.as_rule() {
+                Rule::bool => v.push(pair.as_str().parse::<bool>().unwrap()),
+                _ => unreachable!(),
+            }
+        }
+        v
+    }
+
     /// Parse a `quoted_str` rule of the grammar and return the unquoted
     /// `String` value.
     ///
@@ -951,9 +1172,241 @@ impl PbrtFileParser {
 /// Read the entire file and return its contents as a String.
 ///
 /// * `path` - Path to file.
-fn file_to_string(path: &str) -> Result<String, String> {
+fn file_to_string(path: &str) -> Result<String, ParseError> {
     match fs::read_to_string(path) {
         Ok(s) => Ok(s),
-        _ => Err(format!("Error reading file '{}'", path)),
+        Err(err) => Err(ParseError::Io {
+            path: String::from(path),
+            message: format!("Error reading file. {}", err),
+        }),
+    }
+}
+
+/// Map the file into memory and return the mapping or `None` if the file is
+/// empty, as empty files can't be mapped.
+///
+/// * `path` - Path to file.
+#[cfg(feature = "mmap")]
+fn map_file(path: &str) -> Result<Option<memmap2::Mmap>, ParseError> {
+    let io_error = |err: std::io::Error| ParseError::Io {
+        path: String::from(path),
+        message: format!("Error reading file. {}", err),
+    };
+
+    let file = fs::File::open(path).map_err(io_error)?;
+    if file.metadata().map_err(io_error)?.len() == 0 {
+        return Ok(None);
+    }
+
+    // The mapping is only valid as long as the file isn't modified while the
+    // scene is parsed.
+    unsafe { memmap2::Mmap::map(&file) }
+        .map(Some)
+        .map_err(io_error)
+}
+
+/// Iterator over the numbers in a `float_list` or `int_list` rule of the
+/// grammar and their byte offsets. Number lists are matched as a single
+/// token so that large lists don't need a token for every number; this
+/// splits them the same way the grammar matches numbers, which don't need
+/// to be separated by whitespace (e.g. `1-2`). Anything else up to the next
+/// whitespace is returned as a token that isn't a valid number.
+struct NumberTokens<'a> {
+    /// The number list.
+    s: &'a str,
+
+    /// Byte offset of the next number.
+    pos: usize,
+}
+
+impl<'a> NumberTokens<'a> {
+    /// Returns a new `NumberTokens`.
+    ///
+    /// * `s` - The number list.
+    fn new(s: &'a str) -> Self {
+        Self { s, pos: 0 }
+    }
+}
+
+impl<'a> Iterator for NumberTokens<'a> {
+    type Item = (usize, &'a str);
+
+    /// Returns the next number and its byte offset.
+    fn next(&mut self) -> Option<Self::Item> {
+        let b = self.s.as_bytes();
+        let skip_digits = |mut i: usize| {
+            while i < b.len() && b[i].is_ascii_digit() {
+                i += 1;
+            }
+            i
+        };
+
+        let mut i = self.pos;
+        while i < b.len() && b[i].is_ascii_whitespace() {
+            i += 1;
+        }
+        if i == b.len() {
+            self.pos = i;
+            return None;
+        }
+
+        let start = i;
+        if b[i] == b'+' || b[i] == b'-' {
+            i += 1;
+        }
+        i = skip_digits(i);
+        if i < b.len() && b[i] == b'.' {
+            i = skip_digits(i + 1);
+        }
+        if i < b.len() && (b[i] == b'e' || b[i] == b'E') {
+            i += 1;
+            if i < b.len() && (b[i] == b'+' || b[i] == b'-') {
+                i += 1;
+            }
+            i = skip_digits(i);
+        }
+        if i == start {
+            while i < b.len() && !b[i].is_ascii_whitespace() {
+                i += 1;
+            }
+        }
+
+        self.pos = i;
+        Some((start, &self.s[start..i]))
+    }
+}
+
+// ----------------------------------------------------------------------------
+// Tests
+// ----------------------------------------------------------------------------
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    /// Parse a number list expression with the grammar and return the
+    /// values.
+    fn floats(s: &str) -> Vec<Float> {
+        let mut pairs = PbrtParser::parse(Rule::float_list_expr, s).unwrap();
+        let pair = pairs.next().unwrap();
+        assert_eq!(pair.as_str(), s);
+        PbrtFileParser::from_source_name("<test>").parse_float_list(pair.into_inner())
+    }
+
+    /// Parse an integer list expression with the grammar and return the
+    /// values.
+    fn ints(s: &str) -> Vec<Int> {
+        let mut pairs = PbrtParser::parse(Rule::int_list_expr, s).unwrap();
+        let pair = pairs.next().unwrap();
+        assert_eq!(pair.as_str(), s);
+        PbrtFileParser::from_source_name("<test>").parse_int_list(pair.into_inner())
+    }
+
+    #[test]
+    fn number_tokens_split_numbers_like_the_grammar() {
+        let tokens = |s| NumberTokens::new(s).map(|(_, t)| t).collect::<Vec<&str>>();
+        assert_eq!(tokens("1-2"), vec!["1", "-2"]);
+        assert_eq!(tokens("-.5"), vec!["-.5"]);
+        assert_eq!(tokens("1e-3"), vec!["1e-3"]);
+        assert_eq!(tokens("1E+3-1e3"), vec!["1E+3", "-1e3"]);
+        assert_eq!(tokens("1."), vec!["1."]);
+        assert_eq!(tokens("+3"), vec!["+3"]);
+        assert_eq!(tokens("1.5.5"), vec!["1.5", ".5"]);
+        assert_eq!(tokens(" 1\t2\n3\r\n 4 \t\n"), vec!["1", "2", "3", "4"]);
+        assert!(tokens("").is_empty());
+        assert!(tokens(" \n\t").is_empty());
+
+        // Anything else is split at whitespace.
+        assert_eq!(tokens("1x2 \"a b\""), vec!["1", "x2", "\"a", "b\""]);
+        let offsets: Vec<usize> = NumberTokens::new(" 1 -2\n3").map(|(i, _)| i).collect();
+        assert_eq!(offsets, vec![1, 3, 6]);
+    }
+
+    #[test]
+    fn number_lists_are_parsed() {
+        assert_eq!(floats("[1-2]"), vec![1.0, -2.0]);
+        assert_eq!(floats("[ -.5 +.5 ]"), vec![-0.5, 0.5]);
+        assert_eq!(floats("[1e-3 1E3 2.5e+1]"), vec![1e-3, 1e3, 25.0]);
+        assert_eq!(floats("[ 1. +3 ]"), vec![1.0, 3.0]);
+        assert_eq!(floats("[ 1\t2\n 3\r\n4 \n]"), vec![1.0, 2.0, 3.0, 4.0]);
+        assert_eq!(ints("[ 0 1-2 +3\n4 ]"), vec![0, 1, -2, 3, 4]);
+        assert!(floats("[ ]").is_empty());
+    }
+
+    #[test]
+    fn invalid_numbers_are_syntax_errors() {
+        let parse = |scene: &str| {
+            let mut api = Api::new();
+            api.pbrt_init();
+            PbrtFileParser::from_source_name("<test>")
+                .parse_reader(&mut scene.as_bytes(), &mut api)
+                .unwrap_err()
+                .to_string()
+        };
+        assert!(parse("Film \"image\" \"float a\" [ 1 2x ]")
+            .starts_with("<test>:1:29: Invalid number 'x'."));
+        assert!(
+            parse("WorldBegin\nShape \"sphere\"\n  \"integer a\" [ 1\n 2.5 -e ]")
+                .starts_with("<test>:4:2: Invalid number '2.5'.")
+        );
+        assert!(parse("Transform [ 1 - ]").starts_with("<test>:1:15: Invalid number '-'."));
+        assert!(parse("ConcatTransform [ 1 2 ]")
+            .starts_with("<test>:1:17: ConcatTransform requires 16 values."));
+    }
+
+    /// Grammar that matches every number in a list as its own token the way
+    /// number lists were matched before `NumberTokens`. Used to compare the
+    /// two in benchmarks.
+    mod number_pairs {
+        #[derive(Parser)]
+        #[grammar_inline = r#"
+            WHITESPACE = _{ " " | "\t" | NEWLINE }
+            float = @{
+                ("+" | "-")? ~ (ASCII_DIGIT+ ~ ("." ~ ASCII_DIGIT*)? | "." ~ ASCII_DIGIT+)
+                ~ (^"e" ~ ("+" | "-")? ~ ASCII_DIGIT+)?
+            }
+            float_list_expr = { "[" ~ float+ ~ "]" }
+        "#]
+        pub struct NumberPairsParser;
+    }
+
+    /// Returns a number list expression with a large number of vertex
+    /// positions.
+    fn large_number_list() -> String {
+        let mut s = String::from("[");
+        for i in 0..100_000 {
+            s.push_str(&format!(" {} -{}.25 {}e-3\n", i, i, i));
+        }
+        s.push(']');
+        s
+    }
+
+    #[bench]
+    fn bench_number_list_tokens(b: &mut test::Bencher) {
+        let s = large_number_list();
+        let parser = PbrtFileParser::from_source_name("<bench>");
+        b.iter(|| {
+            let pair = PbrtParser::parse(Rule::float_list_expr, &s)
+                .unwrap()
+                .next()
+                .unwrap();
+            parser.parse_float_list(pair.into_inner())
+        });
+    }
+
+    #[bench]
+    fn bench_number_list_pairs(b: &mut test::Bencher) {
+        use number_pairs::*;
+
+        let s = large_number_list();
+        b.iter(|| {
+            let pair = NumberPairsParser::parse(Rule::float_list_expr, &s)
+                .unwrap()
+                .next()
+                .unwrap();
+            pair.into_inner()
+                .map(|p| p.as_str().parse::<Float>().unwrap())
+                .collect::<Vec<Float>>()
+        });
     }
 }
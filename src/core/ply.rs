@@ -0,0 +1,660 @@
+//! PLY Files
+
+#![allow(dead_code)]
+use crate::core::geometry::*;
+use crate::core::pbrt::*;
+use byteorder::{BigEndian, ByteOrder, LittleEndian, ReadBytesExt, WriteBytesExt};
+use std::fs::File;
+use std::io::{BufRead, BufReader, BufWriter, Read, Write};
+use std::result::Result;
+
+/// Polygon mesh read from a PLY file. Quads are split into two triangles.
+#[derive(Clone, Default)]
+pub struct PLYMesh {
+    /// Vertex positions.
+    pub p: Vec<Point3f>,
+
+    /// Vertex normals. This will be empty if there are none.
+    pub n: Vec<Normal3f>,
+
+    /// Vertex uv-coordinates. This will be empty if there are none.
+    pub uv: Vec<Point2f>,
+
+    /// Vertex indices for triangles.
+    pub indices: Vec<usize>,
+
+    /// Face indices per triangle. This will be empty if there are none.
+    pub face_indices: Vec<usize>,
+}
+
+/// PLY file body formats.
+#[derive(Copy, Clone, Debug, PartialEq)]
+enum PLYFormat {
+    /// Whitespace separated text.
+    Ascii,
+
+    /// Little endian binary.
+    BinaryLittleEndian,
+
+    /// Big endian binary.
+    BinaryBigEndian,
+}
+
+/// PLY property scalar types.
+#[derive(Copy, Clone, Debug, PartialEq)]
+enum PLYScalar {
+    Int8,
+    UInt8,
+    Int16,
+    UInt16,
+    Int32,
+    UInt32,
+    Float32,
+    Float64,
+}
+
+impl PLYScalar {
+    /// Returns the scalar type for a PLY type name.
+    ///
+    /// * `name` - The type name.
+    fn from_name(name: &str) -> Result<Self, String> {
+        match name {
+            "char" | "int8" => Ok(Self::Int8),
+            "uchar" | "uint8" => Ok(Self::UInt8),
+            "short" | "int16" => Ok(Self::Int16),
+            "ushort" | "uint16" => Ok(Self::UInt16),
+            "int" | "int32" => Ok(Self::Int32),
+            "uint" | "uint32" => Ok(Self::UInt32),
+            "float" | "float32" => Ok(Self::Float32),
+            "double" | "float64" => Ok(Self::Float64),
+            _ => Err(format!("Unknown property type '{}'.", name)),
+        }
+    }
+}
+
+/// A property of a PLY element.
+#[derive(Clone, Debug)]
+struct PLYProperty {
+    /// Property name.
+    name: String,
+
+    /// Type of the value or list items.
+    value_type: PLYScalar,
+
+    /// Type of the list length if the property is a list.
+    count_type: Option<PLYScalar>,
+}
+
+/// An element declared in the PLY header.
+#[derive(Clone, Debug)]
+struct PLYElement {
+    /// Element name.
+    name: String,
+
+    /// Number of element instances.
+    count: usize,
+
+    /// Element properties.
+    properties: Vec<PLYProperty>,
+}
+
+/// Reads values from the body of a PLY file.
+trait PLYValueReader {
+    /// Returns the next value.
+    ///
+    /// * `value_type` - Type of the value.
+    fn read_value(&mut self, value_type: PLYScalar) -> Result<f64, String>;
+}
+
+/// Reads values from the body of an ASCII PLY file.
+struct AsciiReader {
+    /// Whitespace separated values.
+    tokens: std::vec::IntoIter<String>,
+}
+
+impl PLYValueReader for AsciiReader {
+    fn read_value(&mut self, _value_type: PLYScalar) -> Result<f64, String> {
+        match self.tokens.next() {
+            Some(token) => token
+                .parse::<f64>()
+                .map_err(|_| format!("Invalid value '{}'.", token)),
+            None => Err(String::from("Unexpected end of file.")),
+        }
+    }
+}
+
+/// Reads values from the body of a binary PLY file.
+struct BinaryReader<R: Read, B: ByteOrder> {
+    /// The reader.
+    reader: R,
+
+    /// Byte order of the values.
+    byte_order: std::marker::PhantomData<B>,
+}
+
+impl<R: Read, B: ByteOrder> PLYValueReader for BinaryReader<R, B> {
+    fn read_value(&mut self, value_type: PLYScalar) -> Result<f64, String> {
+        let r = &mut self.reader;
+        let value = match value_type {
+            PLYScalar::Int8 => r.read_i8().map(|v| v as f64),
+            PLYScalar::UInt8 => r.read_u8().map(|v| v as f64),
+            PLYScalar::Int16 => r.read_i16::<B>().map(|v| v as f64),
+            PLYScalar::UInt16 => r.read_u16::<B>().map(|v| v as f64),
+            PLYScalar::Int32 => r.read_i32::<B>().map(|v| v as f64),
+            PLYScalar::UInt32 => r.read_u32::<B>().map(|v| v as f64),
+            PLYScalar::Float32 => r.read_f32::<B>().map(|v| v as f64),
+            PLYScalar::Float64 => r.read_f64::<B>(),
+        };
+        value.map_err(|err| err.to_string())
+    }
+}
+
+/// Read a PLY file containing a polygon mesh. Vertex positions, normals,
+/// uv-coordinates, triangle and quad faces and face indices are read. Other
+/// elements and properties are ignored.
+///
+/// * `path` - Input file path.
+pub fn read_ply(path: &str) -> Result<PLYMesh, String> {
+    let file = File::open(path).map_err(|err| format!("Unable to open {}. {}", path, err))?;
+    let mut reader = BufReader::new(file);
+
+    let (format, elements) =
+        read_header(&mut reader).map_err(|err| format!("Error reading {}. {}", path, err))?;
+
+    let result = match format {
+        PLYFormat::Ascii => {
+            let mut body = String::new();
+            reader
+                .read_to_string(&mut body)
+                .map_err(|err| format!("Error reading {}. {}", path, err))?;
+            let tokens: Vec<String> = body.split_whitespace().map(String::from).collect();
+            let mut values = AsciiReader {
+                tokens: tokens.into_iter(),
+            };
+            read_body(&mut values, &elements)
+        }
+        PLYFormat::BinaryLittleEndian => {
+            let mut values = BinaryReader::<_, LittleEndian> {
+                reader,
+                byte_order: std::marker::PhantomData,
+            };
+            read_body(&mut values, &elements)
+        }
+        PLYFormat::BinaryBigEndian => {
+            let mut values = BinaryReader::<_, BigEndian> {
+                reader,
+                byte_order: std::marker::PhantomData,
+            };
+            read_body(&mut values, &elements)
+        }
+    };
+
+    result.map_err(|err| format!("Error reading {}. {}", path, err))
+}
+
+/// Write a triangle mesh to a binary little endian PLY file.
+///
+/// * `path` - Output file path.
+/// * `mesh` - The mesh.
+pub fn write_ply(path: &str, mesh: &PLYMesh) -> Result<(), String> {
+    let write = || -> std::io::Result<()> {
+        let mut writer = BufWriter::new(File::create(path)?);
+
+        writeln!(writer, "ply")?;
+        writeln!(writer, "format binary_little_endian 1.0")?;
+        writeln!(writer, "element vertex {}", mesh.p.len())?;
+        writeln!(writer, "property float x")?;
+        writeln!(writer, "property float y")?;
+        writeln!(writer, "property float z")?;
+        if !mesh.n.is_empty() {
+            writeln!(writer, "property float nx")?;
+            writeln!(writer, "property float ny")?;
+            writeln!(writer, "property float nz")?;
+        }
+        if !mesh.uv.is_empty() {
+            writeln!(writer, "property float u")?;
+            writeln!(writer, "property float v")?;
+        }
+        writeln!(writer, "element face {}", mesh.indices.len() / 3)?;
+        writeln!(writer, "property list uchar int vertex_indices")?;
+        if !mesh.face_indices.is_empty() {
+            writeln!(writer, "property int face_indices")?;
+        }
+        writeln!(writer, "end_header")?;
+
+        for i in 0..mesh.p.len() {
+            writer.write_f32::<LittleEndian>(mesh.p[i].x as f32)?;
+            writer.write_f32::<LittleEndian>(mesh.p[i].y as f32)?;
+            writer.write_f32::<LittleEndian>(mesh.p[i].z as f32)?;
+            if !mesh.n.is_empty() {
+                writer.write_f32::<LittleEndian>(mesh.n[i].x as f32)?;
+                writer.write_f32::<LittleEndian>(mesh.n[i].y as f32)?;
+                writer.write_f32::<LittleEndian>(mesh.n[i].z as f32)?;
+            }
+            if !mesh.uv.is_empty() {
+                writer.write_f32::<LittleEndian>(mesh.uv[i].x as f32)?;
+                writer.write_f32::<LittleEndian>(mesh.uv[i].y as f32)?;
+            }
+        }
+
+        for (t, v) in mesh.indices.chunks_exact(3).enumerate() {
+            writer.write_u8(3)?;
+            for i in v.iter() {
+                writer.write_i32::<LittleEndian>(*i as i32)?;
+            }
+            if !mesh.face_indices.is_empty() {
+                writer.write_i32::<LittleEndian>(mesh.face_indices[t] as i32)?;
+            }
+        }
+
+        writer.flush()
+    };
+
+    write().map_err(|err| format!("Error writing {}. {}.", path, err))
+}
+
+/// Read the PLY header and return the body format and element declarations.
+///
+/// * `reader` - The reader positioned at the start of the file.
+fn read_header<R: BufRead>(reader: &mut R) -> Result<(PLYFormat, Vec<PLYElement>), String> {
+    let mut format: Option<PLYFormat> = None;
+    let mut elements: Vec<PLYElement> = vec![];
+    let mut first_line = true;
+
+    loop {
+        let mut line = String::new();
+        match reader.read_line(&mut line) {
+            Ok(0) => return Err(String::from("Missing 'end_header'.")),
+            Ok(_) => (),
+            Err(err) => return Err(err.to_string()),
+        }
+
+        let tokens: Vec<&str> = line.split_whitespace().collect();
+        if first_line {
+            if tokens != ["ply"] {
+                return Err(String::from("Not a PLY file."));
+            }
+            first_line = false;
+            continue;
+        }
+
+        match tokens.as_slice() {
+            [] => (),
+            ["comment", ..] | ["obj_info", ..] => (),
+            ["format", f, _version] => {
+                format = match *f {
+                    "ascii" => Some(PLYFormat::Ascii),
+                    "binary_little_endian" => Some(PLYFormat::BinaryLittleEndian),
+                    "binary_big_endian" => Some(PLYFormat::BinaryBigEndian),
+                    _ => return Err(format!("Unknown format '{}'.", f)),
+                }
+            }
+            ["element", name, count] => {
+                let count = count
+                    .parse::<usize>()
+                    .map_err(|_| format!("Invalid element count '{}'.", count))?;
+                elements.push(PLYElement {
+                    name: String::from(*name),
+                    count,
+                    properties: vec![],
+                });
+            }
+            ["property", "list", count_type, value_type, name] => {
+                let property = PLYProperty {
+                    name: String::from(*name),
+                    value_type: PLYScalar::from_name(value_type)?,
+                    count_type: Some(PLYScalar::from_name(count_type)?),
+                };
+                match elements.last_mut() {
+                    Some(element) => element.properties.push(property),
+                    None => return Err(format!("Property '{}' without element.", name)),
+                }
+            }
+            ["property", value_type, name] => {
+                let property = PLYProperty {
+                    name: String::from(*name),
+                    value_type: PLYScalar::from_name(value_type)?,
+                    count_type: None,
+                };
+                match elements.last_mut() {
+                    Some(element) => element.properties.push(property),
+                    None => return Err(format!("Property '{}' without element.", name)),
+                }
+            }
+            ["end_header"] => break,
+            _ => return Err(format!("Invalid header line '{}'.", line.trim_end())),
+        }
+    }
+
+    match format {
+        Some(format) => Ok((format, elements)),
+        None => Err(String::from("Missing 'format'.")),
+    }
+}
+
+/// Read the element instances in the body of the PLY file.
+///
+/// * `values`   - Reads values from the body.
+/// * `elements` - Element declarations from the header.
+fn read_body<V: PLYValueReader>(
+    values: &mut V,
+    elements: &[PLYElement],
+) -> Result<PLYMesh, String> {
+    let mut mesh = PLYMesh::default();
+    let mut skipped_faces = 0;
+
+    for element in elements.iter() {
+        let property_index = |names: &[&str]| {
+            element
+                .properties
+                .iter()
+                .position(|p| names.contains(&p.name.as_str()))
+        };
+
+        match element.name.as_str() {
+            "vertex" => {
+                let (x, y, z) = match (
+                    property_index(&["x"]),
+                    property_index(&["y"]),
+                    property_index(&["z"]),
+                ) {
+                    (Some(x), Some(y), Some(z)) => (x, y, z),
+                    _ => return Err(String::from("Vertex coordinates x, y, z missing.")),
+                };
+                let normal = match (
+                    property_index(&["nx"]),
+                    property_index(&["ny"]),
+                    property_index(&["nz"]),
+                ) {
+                    (Some(nx), Some(ny), Some(nz)) => Some((nx, ny, nz)),
+                    _ => None,
+                };
+                let uv = match (
+                    property_index(&["u", "s", "texture_u", "texture_s"]),
+                    property_index(&["v", "t", "texture_v", "texture_t"]),
+                ) {
+                    (Some(u), Some(v)) => Some((u, v)),
+                    _ => None,
+                };
+
+                mesh.p.reserve(element.count);
+                for _ in 0..element.count {
+                    let v = read_instance(values, element)?;
+                    let value = |i: usize| v[i][0] as Float;
+                    mesh.p.push(Point3f::new(value(x), value(y), value(z)));
+                    if let Some((nx, ny, nz)) = normal {
+                        mesh.n.push(Normal3f::new(value(nx), value(ny), value(nz)));
+                    }
+                    if let Some((u, v)) = uv {
+                        mesh.uv.push(Point2f::new(value(u), value(v)));
+                    }
+                }
+            }
+            "face" => {
+                let vertex_indices = match property_index(&["vertex_indices", "vertex_index"]) {
+                    Some(i) => i,
+                    None => return Err(String::from("Face vertex indices missing.")),
+                };
+                let face_index = property_index(&["face_indices"]);
+
+                for _ in 0..element.count {
+                    let v = read_instance(values, element)?;
+                    let face: Vec<usize> = v[vertex_indices].iter().map(|i| *i as usize).collect();
+                    let n_tris = match face.len() {
+                        3 => {
+                            mesh.indices.extend_from_slice(&face);
+                            1
+                        }
+                        4 => {
+                            mesh.indices.extend_from_slice(&[face[0], face[1], face[2]]);
+                            mesh.indices.extend_from_slice(&[face[3], face[0], face[2]]);
+                            2
+                        }
+                        _ => {
+                            skipped_faces += 1;
+                            0
+                        }
+                    };
+                    if let Some(f) = face_index {
+                        for _ in 0..n_tris {
+                            mesh.face_indices.push(v[f][0] as usize);
+                        }
+                    }
+                }
+            }
+            _ => {
+                for _ in 0..element.count {
+                    read_instance(values, element)?;
+                }
+            }
+        }
+    }
+
+    if skipped_faces > 0 {
+        warn!(
+            "Ignoring {} faces that are not triangles or quads.",
+            skipped_faces
+        );
+    }
+
+    if let Some(i) = mesh.indices.iter().find(|i| **i >= mesh.p.len()) {
+        return Err(format!(
+            "Vertex index {} out of bounds ({} vertices).",
+            i,
+            mesh.p.len()
+        ));
+    }
+
+    Ok(mesh)
+}
+
+/// Read an element instance and return the values of each property. Scalar
+/// properties have a single value.
+///
+/// * `values`  - Reads values from the body.
+/// * `element` - The element declaration.
+fn read_instance<V: PLYValueReader>(
+    values: &mut V,
+    element: &PLYElement,
+) -> Result<Vec<Vec<f64>>, String> {
+    let mut instance = Vec::with_capacity(element.properties.len());
+    for property in element.properties.iter() {
+        match property.count_type {
+            Some(count_type) => {
+                let count = values.read_value(count_type)? as usize;
+                let mut list = Vec::with_capacity(count);
+                for _ in 0..count {
+                    list.push(values.read_value(property.value_type)?);
+                }
+                instance.push(list);
+            }
+            None => instance.push(vec![values.read_value(property.value_type)?]),
+        }
+    }
+    Ok(instance)
+}
+
+// ----------------------------------------------------------------------------
+// Tests
+// ----------------------------------------------------------------------------
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    /// Writes the PLY file to a unique path in the temporary directory, reads
+    /// it back and removes it.
+    fn read(name: &str, data: &[u8]) -> Result<PLYMesh, String> {
+        let path = std::env::temp_dir().join(format!("{}_{}.ply", name, std::process::id()));
+        let path = path.to_str().unwrap();
+        std::fs::write(path, data).unwrap();
+        let mesh = read_ply(path);
+        std::fs::remove_file(path).unwrap();
+        mesh
+    }
+
+    /// Returns a binary PLY file with a unit square made of a quad and a
+    /// triangle sharing its diagonal, and a skipped pentagon.
+    fn binary<B: ByteOrder>(format: &str) -> Vec<u8> {
+        let mut data = format!(
+            "ply\nformat {} 1.0\ncomment test\nelement vertex 4\n\
+             property float x\nproperty float y\nproperty float z\n\
+             property double u\nproperty double v\n\
+             element face 3\nproperty list uchar uint vertex_indices\n\
+             property short face_indices\nend_header\n",
+            format
+        )
+        .into_bytes();
+        let p = [[0.0, 0.0], [1.0, 0.0], [1.0, 1.0], [0.0, 1.0]];
+        for [x, y] in p.iter() {
+            data.write_f32::<B>(*x).unwrap();
+            data.write_f32::<B>(*y).unwrap();
+            data.write_f32::<B>(2.0).unwrap();
+            data.write_f64::<B>(*x as f64).unwrap();
+            data.write_f64::<B>(*y as f64).unwrap();
+        }
+        for (f, face) in [vec![0, 1, 2, 3], vec![0, 1, 2], vec![0, 1, 2, 3, 0]]
+            .iter()
+            .enumerate()
+        {
+            data.write_u8(face.len() as u8).unwrap();
+            for i in face.iter() {
+                data.write_u32::<B>(*i).unwrap();
+            }
+            data.write_i16::<B>(10 + f as i16).unwrap();
+        }
+        data
+    }
+
+    /// Checks the mesh returned by `binary()`.
+    fn check_binary(mesh: &PLYMesh) {
+        assert_eq!(mesh.p.len(), 4);
+        assert_eq!(mesh.p[2], Point3f::new(1.0, 1.0, 2.0));
+        assert_eq!(mesh.uv[3], Point2f::new(0.0, 1.0));
+        assert!(mesh.n.is_empty());
+        assert_eq!(mesh.indices, vec![0, 1, 2, 3, 0, 2, 0, 1, 2]);
+        assert_eq!(mesh.face_indices, vec![10, 10, 11]);
+    }
+
+    #[test]
+    fn reads_ascii_files() {
+        let data = "ply\nformat ascii 1.0\n\
+                    element vertex 3\nproperty float x\nproperty float y\nproperty float z\n\
+                    property float nx\nproperty float ny\nproperty float nz\n\
+                    property float s\nproperty float t\n\
+                    element material 1\nproperty uchar red\n\
+                    element face 1\nproperty list uchar int vertex_index\nend_header\n\
+                    0 0 0  0 0 1  0 0\n1 0 0  0 0 1  1 0\n\t0 1 -1.5e0  0 0 1 0 1\n\
+                    255\n3 2 1 0\n";
+        let mesh = read("ascii", data.as_bytes()).unwrap();
+        assert_eq!(
+            mesh.p,
+            vec![
+                Point3f::new(0.0, 0.0, 0.0),
+                Point3f::new(1.0, 0.0, 0.0),
+                Point3f::new(0.0, 1.0, -1.5),
+            ]
+        );
+        assert_eq!(mesh.n, vec![Normal3f::new(0.0, 0.0, 1.0); 3]);
+        assert_eq!(mesh.uv[1], Point2f::new(1.0, 0.0));
+        assert_eq!(mesh.indices, vec![2, 1, 0]);
+        assert!(mesh.face_indices.is_empty());
+    }
+
+    #[test]
+    fn reads_binary_little_endian_files() {
+        check_binary(&read("le", &binary::<LittleEndian>("binary_little_endian")).unwrap());
+    }
+
+    #[test]
+    fn reads_binary_big_endian_files() {
+        check_binary(&read("be", &binary::<BigEndian>("binary_big_endian")).unwrap());
+    }
+
+    #[test]
+    fn splits_quads_into_triangles() {
+        let data = "ply\nformat ascii 1.0\n\
+                    element vertex 4\nproperty float x\nproperty float y\nproperty float z\n\
+                    element face 1\nproperty list uchar int vertex_indices\nend_header\n\
+                    0 0 0\n1 0 0\n1 1 0\n0 1 0\n4 0 1 2 3\n";
+        let mesh = read("quad", data.as_bytes()).unwrap();
+        assert_eq!(mesh.indices, vec![0, 1, 2, 3, 0, 2]);
+
+        // Both triangles cover the quad without overlapping.
+        let area = |t: &[usize]| {
+            let (p0, p1, p2) = (mesh.p[t[0]], mesh.p[t[1]], mesh.p[t[2]]);
+            0.5 * (p1 - p0).cross(&(p2 - p0)).z
+        };
+        assert_eq!(area(&mesh.indices[0..3]), 0.5);
+        assert_eq!(area(&mesh.indices[3..6]), 0.5);
+    }
+
+    #[test]
+    fn rejects_invalid_files() {
+        let header = "ply\nformat ascii 1.0\n\
+                      element vertex 1\nproperty float x\nproperty float y\nproperty float z\n\
+                      element face 1\nproperty list uchar int vertex_indices\nend_header\n";
+        let err = read("bounds", format!("{}0 0 0\n3 0 1 2\n", header).as_bytes());
+        assert!(err.err().unwrap().contains("Vertex index 1 out of bounds"));
+        assert!(read("truncated", format!("{}0 0 0\n3 0", header).as_bytes()).is_err());
+        assert!(read("header", b"ply\nformat ascii 1.0\n").is_err());
+        assert!(read("magic", b"obj\n")
+            .err()
+            .unwrap()
+            .contains("Not a PLY file."));
+    }
+
+    #[test]
+    fn written_files_read_back() {
+        let mesh = PLYMesh {
+            p: vec![
+                Point3f::new(0.0, 0.0, 0.0),
+                Point3f::new(1.0, 0.0, 0.0),
+                Point3f::new(1.0, 1.0, 0.5),
+                Point3f::new(0.0, 1.0, -0.5),
+            ],
+            n: vec![Normal3f::new(0.0, 0.0, 1.0); 4],
+            uv: vec![
+                Point2f::new(0.0, 0.0),
+                Point2f::new(1.0, 0.0),
+                Point2f::new(1.0, 1.0),
+                Point2f::new(0.0, 1.0),
+            ],
+            indices: vec![0, 1, 2, 0, 2, 3],
+            face_indices: vec![7, 9],
+        };
+
+        let path = std::env::temp_dir().join(format!("write_{}.ply", std::process::id()));
+        let path = path.to_str().unwrap();
+        write_ply(path, &mesh).unwrap();
+        let read_back = read_ply(path);
+        std::fs::remove_file(path).unwrap();
+
+        let read_back = read_back.unwrap();
+        assert_eq!(read_back.p, mesh.p);
+        assert_eq!(read_back.n, mesh.n);
+        assert_eq!(read_back.uv, mesh.uv);
+        assert_eq!(read_back.indices, mesh.indices);
+        assert_eq!(read_back.face_indices, mesh.face_indices);
+
+        // Optional vertex properties are left out.
+        let mesh = PLYMesh {
+            n: vec![],
+            uv: vec![],
+            face_indices: vec![],
+            ..mesh
+        };
+        let path = std::env::temp_dir().join(format!("write_p_{}.ply", std::process::id()));
+        let path = path.to_str().unwrap();
+        write_ply(path, &mesh).unwrap();
+        let read_back = read_ply(path);
+        std::fs::remove_file(path).unwrap();
+
+        let read_back = read_back.unwrap();
+        assert_eq!(read_back.p, mesh.p);
+        assert!(read_back.n.is_empty());
+        assert!(read_back.uv.is_empty());
+        assert!(read_back.face_indices.is_empty());
+        assert!(write_ply("/nonexistent/mesh.ply", &mesh).is_err());
+    }
+}
@@ -4,6 +4,7 @@
 use crate::core::geometry::*;
 use crate::core::light::*;
 use crate::core::material::*;
+use crate::core::pbrt::*;
 use std::sync::Arc;
 
 /// Primitive trait provide common behavior.
@@ -11,6 +12,42 @@ pub trait Primitive {
     /// Returns a bounding box in the world space.
     fn world_bound(&self) -> Bounds3f;
 
+    /// Returns a bounding box in the world space of the part of the primitive
+    /// inside the given bounds. The result is empty if no part of the
+    /// primitive is inside.
+    ///
+    /// Default is to clip the world bounds. Override for tighter bounds.
+    ///
+    /// * `bounds` - The bounds to clip to.
+    fn clipped_world_bound(&self, bounds: &Bounds3f) -> Bounds3f {
+        self.world_bound().intersect(bounds)
+    }
+
+    /// Returns bounding boxes in the world space at the start and end of a
+    /// time interval such that linearly interpolating them bounds the
+    /// primitive at any time in the interval.
+    ///
+    /// Default is to return the world bounds for both times.
+    ///
+    /// * `time0` - Start of the time interval.
+    /// * `time1` - End of the time interval.
+    fn motion_world_bounds(&self, _time0: Float, _time1: Float) -> (Bounds3f, Bounds3f) {
+        let bounds = self.world_bound();
+        (bounds, bounds)
+    }
+
+    /// Hash the data that determines the bounds of the primitive, including
+    /// clipped and motion bounds. Acceleration structure caches use this to
+    /// detect changed geometry.
+    ///
+    /// Default is to hash the world bounds. Override for primitives whose
+    /// geometry can change without changing their world bounds.
+    ///
+    /// * `hasher` - The hasher.
+    fn hash_geometry(&self, hasher: &mut GeometryHasher) {
+        hasher.write_bounds(&self.world_bound());
+    }
+
     /// Returns geometric details if a ray intersects the primitive and updates
     /// the t_max parameter of the ray. If there is no intersection, `None` is
     /// returned.
@@ -23,6 +60,29 @@ pub trait Primitive {
     /// * `r`                  - The ray.
     fn intersect_p(&self, r: &Ray) -> bool;
 
+    /// Returns geometric details for the closest intersection of each ray in
+    /// a packet and updates the t_max parameter of the rays. Packets work
+    /// best with coherent rays such as camera rays or rays sorted by origin
+    /// and direction.
+    ///
+    /// Default is to intersect the rays one at a time. Override for
+    /// aggregates that can traverse the rays together.
+    ///
+    /// * `rays`               - The rays.
+    fn intersect_n(&self, rays: &mut [Ray]) -> Vec<Option<SurfaceInteraction>> {
+        rays.iter_mut().map(|r| self.intersect(r)).collect()
+    }
+
+    /// Returns whether each ray in a packet intersects the primitive.
+    ///
+    /// Default is to test the rays one at a time. Override for aggregates
+    /// that can traverse the rays together.
+    ///
+    /// * `rays`               - The rays.
+    fn occluded_n(&self, rays: &[Ray]) -> Vec<bool> {
+        rays.iter().map(|r| self.intersect_p(r)).collect()
+    }
+
     /// Returns a reference to the AreaLight that describes the primitive’s
     /// emission distribution, if the primitive is itself a light source.
     /// If the primitive is not emissive, this method should return `None`.  
@@ -5,6 +5,7 @@ use crate::core::geometry::*;
 use crate::core::light::*;
 use crate::core::material::*;
 use crate::core::medium::*;
+use crate::core::pbrt::*;
 use crate::core::primitive::*;
 
 /// GeometricPrimitive represents a single shape in a scene.
@@ -55,6 +56,31 @@ impl Primitive for GeometricPrimitive {
         self.shape.world_bound()
     }
 
+    /// Returns a bounding box in the world space of the part of the primitive
+    /// inside the given bounds.
+    ///
+    /// * `bounds` - The bounds to clip to.
+    fn clipped_world_bound(&self, bounds: &Bounds3f) -> Bounds3f {
+        self.shape.clipped_world_bound(bounds)
+    }
+
+    /// Returns bounding boxes in the world space at the start and end of a
+    /// time interval such that linearly interpolating them bounds the
+    /// primitive at any time in the interval.
+    ///
+    /// * `time0` - Start of the time interval.
+    /// * `time1` - End of the time interval.
+    fn motion_world_bounds(&self, time0: Float, time1: Float) -> (Bounds3f, Bounds3f) {
+        self.shape.motion_world_bounds(time0, time1)
+    }
+
+    /// Hash the data that determines the bounds of the primitive.
+    ///
+    /// * `hasher` - The hasher.
+    fn hash_geometry(&self, hasher: &mut GeometryHasher) {
+        self.shape.hash_geometry(hasher);
+    }
+
     /// Returns geometric details if a ray intersects the primitive and updates
     /// the t_max parameter of the ray. If there is no intersection, `None` is
     /// returned.
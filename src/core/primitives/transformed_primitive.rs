@@ -4,6 +4,7 @@
 use crate::core::geometry::*;
 use crate::core::light::*;
 use crate::core::material::*;
+use crate::core::pbrt::*;
 use crate::core::primitive::*;
 
 /// TransformedPrimitive stores an underlying primitive and animated transform
@@ -37,6 +38,30 @@ impl Primitive for TransformedPrimitive {
             .motion_bounds(&self.primitive.world_bound())
     }
 
+    /// Returns bounding boxes in the world space at the start and end of a
+    /// time interval such that linearly interpolating them bounds the
+    /// primitive at any time in the interval.
+    ///
+    /// * `time0` - Start of the time interval.
+    /// * `time1` - End of the time interval.
+    fn motion_world_bounds(&self, time0: Float, time1: Float) -> (Bounds3f, Bounds3f) {
+        self.primitive_to_world
+            .motion_bounds_linear(&self.primitive.world_bound(), time0, time1)
+    }
+
+    /// Hash the data that determines the bounds of the primitive. This is
+    /// the animated transform and the primitive.
+    ///
+    /// * `hasher` - The hasher.
+    fn hash_geometry(&self, hasher: &mut GeometryHasher) {
+        let t = &self.primitive_to_world;
+        hasher.write_transform(&t.start_transform);
+        hasher.write_transform(&t.end_transform);
+        hasher.write_float(t.start_time);
+        hasher.write_float(t.end_time);
+        self.primitive.hash_geometry(hasher);
+    }
+
     /// Returns geometric details if a ray intersects the primitive and updates
     /// the t_max parameter of the ray. If there is no intersection, `None` is
     /// returned.
@@ -53,6 +53,13 @@ impl RNG {
         }
     }
 
+    /// Skip ahead in the sequence by the given number of 32-bit values.
+    ///
+    /// * `delta` - Number of values to skip.
+    pub fn advance(&mut self, delta: u64) {
+        self.r.advance(delta);
+    }
+
     /// Returns a uniformly distributed value over the closed interval containing
     /// the given bounds.
     ///
@@ -208,6 +208,55 @@ pub fn uniform_sample_triangle(u: &Point2f) -> Point2f {
     Point2f::new(1.0 - su0, u[1] * su0)
 }
 
+/// Sample a value in [0, 1) from the linear function that goes from `a` at
+/// 0 to `b` at 1. A function that is zero everywhere is sampled uniformly.
+///
+/// * `u` - The random sample.
+/// * `a` - Value of the function at 0.
+/// * `b` - Value of the function at 1.
+pub fn sample_linear(u: Float, a: Float, b: Float) -> Float {
+    if a == 0.0 && b == 0.0 {
+        return u;
+    }
+    if u == 0.0 && a == 0.0 {
+        return 0.0;
+    }
+    let x = u * (a + b) / (a + lerp(u, a * a, b * b).sqrt());
+    min(x, ONE_MINUS_EPSILON)
+}
+
+/// Sample a point in [0, 1)^2 from the bilinear function with the given
+/// values at the corners.
+///
+/// * `u` - The random sample point.
+/// * `w` - Values at (0, 0), (1, 0), (0, 1) and (1, 1).
+pub fn sample_bilinear(u: &Point2f, w: &[Float; 4]) -> Point2f {
+    // Sample y for the marginal distribution and x for the conditional
+    // distribution.
+    let y = sample_linear(u[1], w[0] + w[1], w[2] + w[3]);
+    let x = sample_linear(u[0], lerp(y, w[0], w[2]), lerp(y, w[1], w[3]));
+    Point2f::new(x, y)
+}
+
+/// Returns the PDF for sampling a point with `sample_bilinear()`.
+///
+/// * `p` - The sampled point.
+/// * `w` - Values at (0, 0), (1, 0), (0, 1) and (1, 1).
+pub fn bilinear_pdf(p: &Point2f, w: &[Float; 4]) -> Float {
+    if p.x < 0.0 || p.x > 1.0 || p.y < 0.0 || p.y > 1.0 {
+        return 0.0;
+    }
+    let sum = w[0] + w[1] + w[2] + w[3];
+    if sum == 0.0 {
+        return 1.0;
+    }
+    4.0 * ((1.0 - p.x) * (1.0 - p.y) * w[0]
+        + p.x * (1.0 - p.y) * w[1]
+        + (1.0 - p.x) * p.y * w[2]
+        + p.x * p.y * w[3])
+        / sum
+}
+
 /// Sample a direction on a hemisphere using cosine-weighted sampling.
 ///
 /// * `u` - The random sample point.
@@ -249,3 +298,144 @@ pub fn power_heuristic(nf: Int, f_pdf: Float, ng: Int, g_pdf: Float) -> Float {
     let g = ng as Float * g_pdf;
     (f * f) / (f * f + g * g)
 }
+
+// ----------------------------------------------------------------------------
+// Tests
+// ----------------------------------------------------------------------------
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    /// Weights of bilinear functions at (0, 0), (1, 0), (0, 1) and (1, 1).
+    const BILINEAR_WEIGHTS: [[Float; 4]; 5] = [
+        [1.0, 1.0, 1.0, 1.0],
+        [1.0, 2.0, 3.0, 4.0],
+        [0.0, 1.0, 1.0, 0.0],
+        [5.0, 0.0, 0.0, 0.0],
+        [0.0, 0.0, 2.0, 2.0],
+    ];
+
+    /// Returns a grid of `n` x `n` points in (0, 1)^2.
+    fn grid(n: usize) -> Vec<Point2f> {
+        (0..n * n)
+            .map(|i| {
+                Point2f::new(
+                    ((i % n) as Float + 0.5) / n as Float,
+                    ((i / n) as Float + 0.5) / n as Float,
+                )
+            })
+            .collect()
+    }
+
+    #[test]
+    fn sample_linear_inverts_the_cdf() {
+        for &(a, b) in [(1.0, 1.0), (0.0, 1.0), (1.0, 0.0), (2.0, 5.0), (3.0, 0.5)].iter() {
+            for i in 0..100 {
+                let u = i as Float / 100.0;
+                let x = sample_linear(u, a, b);
+                assert!(x >= 0.0 && x < 1.0);
+                let cdf = (a * x + (b - a) * x * x / 2.0) / ((a + b) / 2.0);
+                assert!(
+                    (cdf - u).abs() < 1e-5,
+                    "{} {} {}: {} != {}",
+                    a,
+                    b,
+                    x,
+                    cdf,
+                    u
+                );
+            }
+        }
+    }
+
+    #[test]
+    fn sample_linear_edge_cases() {
+        assert_eq!(sample_linear(0.0, 0.0, 1.0), 0.0);
+        assert_eq!(sample_linear(0.0, 1.0, 0.0), 0.0);
+        assert!(sample_linear(ONE_MINUS_EPSILON, 1.0, 0.0) < 1.0);
+        assert!(sample_linear(ONE_MINUS_EPSILON, 0.0, 1.0) < 1.0);
+
+        // Zero functions are sampled uniformly like `bilinear_pdf()` assumes.
+        assert_eq!(sample_linear(0.3, 0.0, 0.0), 0.3);
+        let p = sample_bilinear(&Point2f::new(0.3, 0.7), &[0.0; 4]);
+        assert_eq!(p, Point2f::new(0.3, 0.7));
+        assert_eq!(bilinear_pdf(&p, &[0.0; 4]), 1.0);
+    }
+
+    #[test]
+    fn bilinear_pdf_integrates_to_one() {
+        for w in BILINEAR_WEIGHTS.iter() {
+            let points = grid(64);
+            let integral: Float =
+                points.iter().map(|p| bilinear_pdf(p, w)).sum::<Float>() / points.len() as Float;
+            assert!((integral - 1.0).abs() < 1e-4, "{:?}: {}", w, integral);
+        }
+
+        let w = [1.0, 2.0, 3.0, 4.0];
+        assert_eq!(bilinear_pdf(&Point2f::new(-0.1, 0.5), &w), 0.0);
+        assert_eq!(bilinear_pdf(&Point2f::new(0.5, 1.1), &w), 0.0);
+        assert_eq!(bilinear_pdf(&Point2f::new(0.0, 0.0), &w), 0.4);
+        assert_eq!(bilinear_pdf(&Point2f::new(1.0, 1.0), &w), 1.6);
+    }
+
+    #[test]
+    fn sample_bilinear_follows_pdf() {
+        for w in BILINEAR_WEIGHTS.iter() {
+            let samples: Vec<Point2f> = grid(128)
+                .iter()
+                .map(|u| {
+                    let p = sample_bilinear(u, w);
+                    assert!(p.x >= 0.0 && p.x < 1.0 && p.y >= 0.0 && p.y < 1.0);
+                    p
+                })
+                .collect();
+            let n = samples.len() as Float;
+
+            // The means of x, y and xy match their expected values.
+            for g in [
+                |p: &Point2f| p.x,
+                |p: &Point2f| p.y,
+                |p: &Point2f| p.x * p.y,
+            ]
+            .iter()
+            {
+                let mean = samples.iter().map(|p| g(p)).sum::<Float>() / n;
+                let points = grid(64);
+                let expected = points
+                    .iter()
+                    .map(|p| g(p) * bilinear_pdf(p, w))
+                    .sum::<Float>()
+                    / points.len() as Float;
+                assert!(
+                    (mean - expected).abs() < 1e-3,
+                    "{:?}: {} != {}",
+                    w,
+                    mean,
+                    expected
+                );
+            }
+
+            // The fraction of samples in each quadrant is the integral of the
+            // pdf over it.
+            for &(x0, y0) in [(0.0, 0.0), (0.5, 0.0), (0.0, 0.5), (0.5, 0.5)].iter() {
+                let inside =
+                    |p: &Point2f| p.x >= x0 && p.x < x0 + 0.5 && p.y >= y0 && p.y < y0 + 0.5;
+                let fraction = samples.iter().filter(|p| inside(p)).count() as Float / n;
+                let points = grid(64);
+                let integral = points
+                    .iter()
+                    .map(|p| bilinear_pdf(&Point2f::new(x0 + 0.5 * p.x, y0 + 0.5 * p.y), w))
+                    .sum::<Float>()
+                    / (4.0 * points.len() as Float);
+                assert!(
+                    (fraction - integral).abs() < 1e-2,
+                    "{:?}: {} != {}",
+                    w,
+                    fraction,
+                    integral
+                );
+            }
+        }
+    }
+}
@@ -6,6 +6,13 @@ use crate::core::light::*;
 use crate::core::primitive::*;
 use crate::core::sampler::*;
 use crate::core::spectrum::*;
+use crate::core::stats::*;
+
+/// Number of rays traced to find the closest intersection.
+static N_INTERSECTION_TESTS: Stat = Stat::counter("Intersections/Regular ray intersection tests");
+
+/// Number of rays traced to test for any intersection.
+static N_SHADOW_TESTS: Stat = Stat::counter("Intersections/Shadow ray intersection tests");
 
 /// Scene.
 #[derive(Clone)]
@@ -46,6 +53,7 @@ impl Scene {
     ///
     /// * `ray` - The ray to trace.
     pub fn intersect(&self, ray: &mut Ray) -> Option<SurfaceInteraction> {
+        N_INTERSECTION_TESTS.inc();
         self.aggregate.intersect(ray)
     }
 
@@ -54,9 +62,29 @@ impl Scene {
     ///
     /// * `ray` - The ray to trace.
     pub fn intersect_p(&self, ray: &Ray) -> bool {
+        N_SHADOW_TESTS.inc();
         self.aggregate.intersect_p(ray)
     }
 
+    /// Traces a packet of rays into the scene and returns the
+    /// `SurfaceInteraction` of each ray that hit something. Coherent rays
+    /// such as camera rays are traced together through the aggregate.
+    ///
+    /// * `rays` - The rays to trace.
+    pub fn intersect_n(&self, rays: &mut [Ray]) -> Vec<Option<SurfaceInteraction>> {
+        N_INTERSECTION_TESTS.add(rays.len() as i64);
+        self.aggregate.intersect_n(rays)
+    }
+
+    /// Traces a packet of shadow rays into the scene and returns whether
+    /// each of them is occluded.
+    ///
+    /// * `rays` - The rays to trace.
+    pub fn occluded_n(&self, rays: &[Ray]) -> Vec<bool> {
+        N_SHADOW_TESTS.add(rays.len() as i64);
+        self.aggregate.occluded_n(rays)
+    }
+
     /// Traces the ray into the scene and returns the first intersection with a
     /// light scattering surface along the given ray as the beam transmittance
     /// up to that point.
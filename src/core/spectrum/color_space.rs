@@ -0,0 +1,147 @@
+//! Color Spaces
+
+#![allow(dead_code)]
+use super::*;
+use crate::core::pbrt::*;
+
+/// RGB color spaces for output images. All color spaces use D65 XYZ values
+/// as input.
+#[derive(Copy, Clone, Debug, PartialEq)]
+pub enum ColorSpace {
+    /// Linear sRGB / Rec.709 primaries.
+    SRGB,
+
+    /// ACES AP1 primaries with D60 white point.
+    ACEScg,
+
+    /// Rec.2020 primaries.
+    Rec2020,
+
+    /// Display P3 primaries.
+    DisplayP3,
+}
+
+impl Default for ColorSpace {
+    /// Returns the default color space, `ColorSpace::SRGB`.
+    fn default() -> Self {
+        Self::SRGB
+    }
+}
+
+impl ColorSpace {
+    /// Converts the given XYZ coefficients to RGB coefficients in this color
+    /// space.
+    ///
+    /// * `xyz` - The XYZ coefficients.
+    #[rustfmt::skip]
+    pub fn xyz_to_rgb(&self, xyz: &[Float; 3]) -> [Float; 3] {
+        match self {
+            Self::SRGB => xyz_to_rgb(xyz),
+            // Includes Bradford chromatic adaptation from D65 to D60.
+            Self::ACEScg => [
+                 1.6410234 * xyz[0] - 0.3248033 * xyz[1] - 0.2364247 * xyz[2],
+                -0.6636629 * xyz[0] + 1.6153316 * xyz[1] + 0.0167563 * xyz[2],
+                 0.0117219 * xyz[0] - 0.0082844 * xyz[1] + 0.9883949 * xyz[2],
+            ],
+            Self::Rec2020 => [
+                 1.7166512 * xyz[0] - 0.3556708 * xyz[1] - 0.2533663 * xyz[2],
+                -0.6666844 * xyz[0] + 1.6164812 * xyz[1] + 0.0157685 * xyz[2],
+                 0.0176399 * xyz[0] - 0.0427706 * xyz[1] + 0.9421031 * xyz[2],
+            ],
+            Self::DisplayP3 => [
+                 2.4934969 * xyz[0] - 0.9313836 * xyz[1] - 0.4027108 * xyz[2],
+                -0.8294890 * xyz[0] + 1.7626641 * xyz[1] + 0.0236247 * xyz[2],
+                 0.0358458 * xyz[0] - 0.0761724 * xyz[1] + 0.9568845 * xyz[2],
+            ],
+        }
+    }
+}
+
+/// XYZ coefficients of the D65 white point.
+pub const D65_WHITE_XYZ: [Float; 3] = [0.95047, 1.0, 1.08883];
+
+/// Bradford matrix converting XYZ to cone responses.
+#[rustfmt::skip]
+const BRADFORD: [[Float; 3]; 3] = [
+    [ 0.8951,  0.2664, -0.1614],
+    [-0.7502,  1.7135,  0.0367],
+    [ 0.0389, -0.0685,  1.0296],
+];
+
+/// Inverse of the Bradford matrix.
+#[rustfmt::skip]
+const INV_BRADFORD: [[Float; 3]; 3] = [
+    [ 0.9869929, -0.1470543, 0.1599627],
+    [ 0.4323053,  0.5183603, 0.0492912],
+    [-0.0085287,  0.0400428, 0.9684867],
+];
+
+/// Returns the XYZ coefficients, normalized to `Y = 1`, of the white point of
+/// a blackbody emitter at the given temperature. This uses the cubic spline
+/// approximation of the Planckian locus by Kim et al., valid between 1667K and
+/// 25000K. Temperatures outside that range are clamped.
+///
+/// * `t` - Temperature in Kelvin.
+pub fn blackbody_white_xyz(t: Float) -> [Float; 3] {
+    let t = clamp(t, 1667.0, 25000.0);
+    let t2 = t * t;
+    let t3 = t2 * t;
+
+    let x = if t <= 4000.0 {
+        -0.2661239e9 / t3 - 0.2343589e6 / t2 + 0.8776956e3 / t + 0.179910
+    } else {
+        -3.0258469e9 / t3 + 2.1070379e6 / t2 + 0.2226347e3 / t + 0.240390
+    };
+
+    let x2 = x * x;
+    let x3 = x2 * x;
+    let y = if t <= 2222.0 {
+        -1.1063814 * x3 - 1.34811020 * x2 + 2.18555832 * x - 0.20219683
+    } else if t <= 4000.0 {
+        -0.9549476 * x3 - 1.37418593 * x2 + 2.09137015 * x - 0.16748867
+    } else {
+        3.0817580 * x3 - 5.87338670 * x2 + 3.75112997 * x - 0.37001483
+    };
+
+    [x / y, 1.0, (1.0 - x - y) / y]
+}
+
+/// Returns a matrix that white balances XYZ coefficients so that a blackbody
+/// illuminant at the given temperature maps to the D65 white point, using the
+/// Bradford chromatic adaptation transform.
+///
+/// * `t` - Temperature of the illuminant in Kelvin.
+pub fn white_balance_matrix(t: Float) -> [[Float; 3]; 3] {
+    let src = mat3_mul_vec(&BRADFORD, &blackbody_white_xyz(t));
+    let dst = mat3_mul_vec(&BRADFORD, &D65_WHITE_XYZ);
+
+    // Scale cone responses by the ratio of the white points.
+    let mut scaled = BRADFORD;
+    for (i, row) in scaled.iter_mut().enumerate() {
+        let s = dst[i] / src[i];
+        for v in row.iter_mut() {
+            *v *= s;
+        }
+    }
+
+    let mut m = [[0.0; 3]; 3];
+    for i in 0..3 {
+        for j in 0..3 {
+            m[i][j] = (0..3).map(|k| INV_BRADFORD[i][k] * scaled[k][j]).sum();
+        }
+    }
+    m
+}
+
+/// Multiplies a 3x3 matrix and a 3-vector.
+///
+/// * `m` - The matrix.
+/// * `v` - The vector.
+#[inline]
+pub fn mat3_mul_vec(m: &[[Float; 3]; 3], v: &[Float; 3]) -> [Float; 3] {
+    [
+        m[0][0] * v[0] + m[0][1] * v[1] + m[0][2] * v[2],
+        m[1][0] * v[0] + m[1][1] * v[1] + m[1][2] * v[2],
+        m[2][0] * v[0] + m[2][1] * v[1] + m[2][2] * v[2],
+    ]
+}
@@ -1,14 +1,18 @@
 //! Spectrum
 
 mod cie;
+mod color_space;
 mod common;
+mod named_spectra;
 mod rgb;
 mod rgb_spectrum;
 mod sampled_spectrum;
 
 // Re-export
 pub use cie::*;
+pub use color_space::*;
 pub use common::*;
+pub use named_spectra::*;
 pub use rgb::*;
 pub use rgb_spectrum::*;
 pub use sampled_spectrum::*;
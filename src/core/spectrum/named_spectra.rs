@@ -0,0 +1,54 @@
+//! Named Spectra
+
+#![allow(dead_code)]
+use super::*;
+use crate::core::pbrt::*;
+
+/// Named spectra from the pbrt-v4 scene format that are approximated by RGB
+/// values. Metals use the RGB fit of their complex index of refraction and
+/// glasses use the refractive index at the sodium d-line (587.6nm).
+#[rustfmt::skip]
+const NAMED_SPECTRA: [(&str, [Float; 3]); 19] = [
+    ("stdillum-D65",  [1.0, 1.0, 1.0]),
+    ("metal-Ag-eta",  [0.155265, 0.116723, 0.138342]),
+    ("metal-Ag-k",    [4.828350, 3.122250, 2.146960]),
+    ("metal-Al-eta",  [1.657460, 0.880369, 0.521229]),
+    ("metal-Al-k",    [9.223870, 6.269520, 4.837000]),
+    ("metal-Au-eta",  [0.143119, 0.374957, 1.442480]),
+    ("metal-Au-k",    [3.983160, 2.385720, 1.603220]),
+    ("metal-Cu-eta",  [0.200438, 0.924033, 1.102210]),
+    ("metal-Cu-k",    [3.912950, 2.452850, 2.142190]),
+    ("glass-BK7",     [1.5168, 1.5168, 1.5168]),
+    ("glass-BAF10",   [1.6700, 1.6700, 1.6700]),
+    ("glass-FK51A",   [1.4866, 1.4866, 1.4866]),
+    ("glass-LASF9",   [1.8503, 1.8503, 1.8503]),
+    ("glass-F5",      [1.6034, 1.6034, 1.6034]),
+    ("glass-F10",     [1.6200, 1.6200, 1.6200]),
+    ("glass-F11",     [1.6209, 1.6209, 1.6209]),
+    ("glass-SF5",     [1.6727, 1.6727, 1.6727]),
+    ("glass-SF10",    [1.7283, 1.7283, 1.7283]),
+    ("glass-SF11",    [1.7847, 1.7847, 1.7847]),
+];
+
+/// Returns the spectrum for a pbrt-v4 named spectrum (e.g. `metal-Cu-eta`)
+/// or `None` if the name is unknown.
+///
+/// * `name` - The spectrum name.
+pub fn get_named_spectrum(name: &str) -> Option<Spectrum> {
+    if name == "stdillum-A" {
+        // CIE illuminant A is a 2856K blackbody.
+        let lambda = CIE::lambda();
+        let values = blackbody_normalized(&lambda, 2856.0);
+        let samples: Vec<Sample> = lambda
+            .iter()
+            .zip(values.iter())
+            .map(|(l, v)| Sample::new(*l, *v))
+            .collect();
+        return Some(Spectrum::from(&samples));
+    }
+
+    NAMED_SPECTRA
+        .iter()
+        .find(|(n, _)| *n == name)
+        .map(|(_, rgb)| Spectrum::from_rgb(rgb, None))
+}
@@ -85,20 +85,19 @@ impl From<&Vec<Sample>> for RGBSpectrum {
         };
 
         let xyz = (0..CIE_SAMPLES).fold([0.0; 3], |v, i| {
-            let val = interpolate_spectrum_samples(samples, (CIE_LAMBDA_START + i) as Float);
+            let lambda = (CIE_LAMBDA_START + i) as Float;
+            let val = interpolate_spectrum_samples(&sorted_samples, lambda);
             [
-                v[0] + val * CIE_CURVES.x[i],
-                v[1] + val * CIE_CURVES.y[i],
-                v[2] + val * CIE_CURVES.z[i],
+                v[0] + val * CIE_X[i],
+                v[1] + val * CIE_Y[i],
+                v[2] + val * CIE_Z[i],
             ]
         });
 
         let scale =
             (CIE_LAMBDA_END - CIE_LAMBDA_START) as Float / (CIE_Y_INTEGRAL * CIE_SAMPLES as Float);
 
-        Self {
-            c: [xyz[0] * scale, xyz[1] * scale, xyz[2] * scale],
-        }
+        Self::from_xyz(&[xyz[0] * scale, xyz[1] * scale, xyz[2] * scale], None)
     }
 }
 
@@ -385,3 +384,37 @@ impl fmt::Display for RGBSpectrum {
         write!(f, "[{}, {}, {}]", self.c[0], self.c[1], self.c[2])
     }
 }
+
+// ----------------------------------------------------------------------------
+// Tests
+// ----------------------------------------------------------------------------
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn from_samples_converts_xyz_to_rgb() {
+        // An equal energy spectrum has XYZ close to (1, 1, 1).
+        let samples = vec![Sample::new(300.0, 1.0), Sample::new(800.0, 1.0)];
+        let xyz = RGBSpectrum::from(&samples).to_xyz();
+        for i in 0..3 {
+            assert!(abs(xyz[i] - 1.0) < 0.01, "xyz = {:?}", xyz);
+        }
+    }
+
+    #[test]
+    fn from_unsorted_samples() {
+        let samples = vec![
+            Sample::new(400.0, 0.2),
+            Sample::new(500.0, 0.8),
+            Sample::new(600.0, 0.5),
+            Sample::new(700.0, 0.1),
+        ];
+        let reversed: Vec<Sample> = samples.iter().rev().copied().collect();
+        assert_eq!(
+            RGBSpectrum::from(&samples).samples(),
+            RGBSpectrum::from(&reversed).samples()
+        );
+    }
+}
@@ -0,0 +1,563 @@
+//! Statistics
+
+#![allow(dead_code)]
+use std::collections::BTreeMap;
+use std::io::Write;
+use std::sync::atomic::{AtomicI64, AtomicUsize, Ordering};
+use std::sync::{Arc, Mutex};
+use std::time::Instant;
+
+/// Maximum number of values recorded per thread.
+const MAX_STAT_VALUES: usize = 1024;
+
+/// Marks a statistic that has no values assigned yet.
+const UNREGISTERED: usize = usize::MAX;
+
+/// Statistics are only recorded when built with the `stats` feature.
+/// Otherwise recording does nothing and no statistics are printed.
+const STATS_ENABLED: bool = cfg!(feature = "stats");
+
+lazy_static! {
+    /// The registered statistics and the values of all threads.
+    static ref REGISTRY: Mutex<StatsRegistry> = Mutex::new(StatsRegistry::default());
+}
+
+thread_local! {
+    /// Values recorded by the current thread.
+    static THREAD_STATS: Arc<ThreadStats> = ThreadStats::register();
+}
+
+/// Types of statistics.
+#[derive(Copy, Clone, Debug, PartialEq)]
+pub enum StatKind {
+    /// A count of events.
+    Counter,
+
+    /// Number of bytes of memory used.
+    Memory,
+
+    /// Average, minimum and maximum of reported values.
+    Distribution,
+
+    /// Fraction of events shown as a percentage.
+    Percent,
+
+    /// Ratio of two counts.
+    Ratio,
+
+    /// Accumulated time.
+    Timer,
+}
+
+impl StatKind {
+    /// Returns the number of values used to record a statistic.
+    fn n_values(&self) -> usize {
+        match self {
+            StatKind::Counter | StatKind::Memory | StatKind::Timer => 1,
+            StatKind::Percent | StatKind::Ratio => 2,
+            StatKind::Distribution => 4,
+        }
+    }
+}
+
+/// A statistic that code can record values for. Statistics are declared as
+/// statics next to the code that records them with a name of the form
+/// `"Category/Title"`. Each thread records values separately without locking
+/// and all threads are combined when the statistics are printed.
+pub struct Stat {
+    /// Type of statistic.
+    kind: StatKind,
+
+    /// Category and title separated by `/`.
+    name: &'static str,
+
+    /// Index of the first value of the statistic in each thread's values.
+    offset: AtomicUsize,
+}
+
+impl Stat {
+    /// Create a statistic.
+    ///
+    /// * `kind` - Type of statistic.
+    /// * `name` - Category and title separated by `/`.
+    const fn new(kind: StatKind, name: &'static str) -> Self {
+        Self {
+            kind,
+            name,
+            offset: AtomicUsize::new(UNREGISTERED),
+        }
+    }
+
+    /// Create a statistic that counts events.
+    ///
+    /// * `name` - Category and title separated by `/`.
+    pub const fn counter(name: &'static str) -> Self {
+        Self::new(StatKind::Counter, name)
+    }
+
+    /// Create a statistic that counts bytes of memory.
+    ///
+    /// * `name` - Category and title separated by `/`.
+    pub const fn memory(name: &'static str) -> Self {
+        Self::new(StatKind::Memory, name)
+    }
+
+    /// Create a statistic for the average, minimum and maximum of values.
+    ///
+    /// * `name` - Category and title separated by `/`.
+    pub const fn distribution(name: &'static str) -> Self {
+        Self::new(StatKind::Distribution, name)
+    }
+
+    /// Create a statistic for a fraction of events shown as a percentage.
+    ///
+    /// * `name` - Category and title separated by `/`.
+    pub const fn percent(name: &'static str) -> Self {
+        Self::new(StatKind::Percent, name)
+    }
+
+    /// Create a statistic for the ratio of two counts.
+    ///
+    /// * `name` - Category and title separated by `/`.
+    pub const fn ratio(name: &'static str) -> Self {
+        Self::new(StatKind::Ratio, name)
+    }
+
+    /// Create a statistic that accumulates time.
+    ///
+    /// * `name` - Category and title separated by `/`.
+    pub const fn timer(name: &'static str) -> Self {
+        Self::new(StatKind::Timer, name)
+    }
+
+    /// Increment a counter.
+    pub fn inc(&'static self) {
+        self.add(1);
+    }
+
+    /// Add to a counter or memory statistic.
+    ///
+    /// * `v` - The value to add.
+    pub fn add(&'static self, v: i64) {
+        debug_assert!(self.kind == StatKind::Counter || self.kind == StatKind::Memory);
+        self.update(|values| add(&values[0], v));
+    }
+
+    /// Report a value for a distribution.
+    ///
+    /// * `v` - The value.
+    pub fn report(&'static self, v: i64) {
+        debug_assert!(self.kind == StatKind::Distribution);
+        self.update(|values| report(values, v));
+    }
+
+    /// Add to the numerator and denominator of a percentage or ratio.
+    ///
+    /// * `num`   - Value to add to the numerator.
+    /// * `denom` - Value to add to the denominator.
+    pub fn add_fraction(&'static self, num: i64, denom: i64) {
+        debug_assert!(self.kind == StatKind::Percent || self.kind == StatKind::Ratio);
+        self.update(|values| {
+            add(&values[0], num);
+            add(&values[1], denom);
+        });
+    }
+
+    /// Start timing. The elapsed time is added to the statistic when the
+    /// returned timer is dropped.
+    pub fn start(&'static self) -> StatTimer {
+        debug_assert!(self.kind == StatKind::Timer);
+        StatTimer {
+            stat: self,
+            start: if STATS_ENABLED {
+                Some(Instant::now())
+            } else {
+                None
+            },
+        }
+    }
+
+    /// Update the values of the current thread.
+    ///
+    /// * `f` - Updates the values of this statistic.
+    fn update<F: FnOnce(&[AtomicI64])>(&'static self, f: F) {
+        if !STATS_ENABLED {
+            return;
+        }
+
+        let offset = self.offset();
+        THREAD_STATS.with(|stats| f(&stats.values[offset..offset + self.kind.n_values()]));
+    }
+
+    /// Returns the index of the first value, registering the statistic on
+    /// first use.
+    fn offset(&'static self) -> usize {
+        let offset = self.offset.load(Ordering::Acquire);
+        if offset != UNREGISTERED {
+            offset
+        } else {
+            REGISTRY.lock().unwrap().register(self)
+        }
+    }
+}
+
+/// Adds elapsed time to a timer statistic when dropped.
+pub struct StatTimer {
+    /// The timer statistic.
+    stat: &'static Stat,
+
+    /// Start time. This is `None` when statistics are disabled.
+    start: Option<Instant>,
+}
+
+impl Drop for StatTimer {
+    /// Add the elapsed time in nanoseconds.
+    fn drop(&mut self) {
+        if let Some(start) = self.start {
+            let ns = start.elapsed().as_nanos() as i64;
+            self.stat.update(|values| add(&values[0], ns));
+        }
+    }
+}
+
+/// Values recorded by one thread. Only the owning thread writes them so
+/// updates don't need atomic read-modify-write operations; values are atomic
+/// so they can be read when printing statistics.
+struct ThreadStats {
+    /// The values of all statistics.
+    values: Vec<AtomicI64>,
+}
+
+impl ThreadStats {
+    /// Create zeroed values.
+    fn new() -> Self {
+        Self {
+            values: (0..MAX_STAT_VALUES).map(|_| AtomicI64::new(0)).collect(),
+        }
+    }
+
+    /// Create the values for the current thread and register them.
+    fn register() -> Arc<Self> {
+        let stats = Arc::new(Self::new());
+        REGISTRY.lock().unwrap().threads.push(stats.clone());
+        stats
+    }
+}
+
+/// The registered statistics and the values of all threads.
+#[derive(Default)]
+struct StatsRegistry {
+    /// Statistics that have been used.
+    stats: Vec<&'static Stat>,
+
+    /// Number of values assigned to statistics.
+    n_values: usize,
+
+    /// Values of all threads that recorded statistics.
+    threads: Vec<Arc<ThreadStats>>,
+}
+
+impl StatsRegistry {
+    /// Assign values to a statistic and return the index of the first one.
+    ///
+    /// * `stat` - The statistic.
+    fn register(&mut self, stat: &'static Stat) -> usize {
+        // Another thread may have registered it while waiting for the lock.
+        let offset = stat.offset.load(Ordering::Acquire);
+        if offset != UNREGISTERED {
+            return offset;
+        }
+
+        let offset = self.n_values;
+        assert!(
+            offset + stat.kind.n_values() <= MAX_STAT_VALUES,
+            "Too many statistics"
+        );
+        self.n_values += stat.kind.n_values();
+        self.stats.push(stat);
+        stat.offset.store(offset, Ordering::Release);
+        offset
+    }
+
+    /// Returns the values of a statistic combined over all threads.
+    ///
+    /// * `stat` - The statistic.
+    fn values(&self, stat: &Stat) -> [i64; 4] {
+        let offset = stat.offset.load(Ordering::Acquire);
+        let mut result = [0; 4];
+        for thread in self.threads.iter() {
+            let v: Vec<i64> = thread.values[offset..offset + stat.kind.n_values()]
+                .iter()
+                .map(|v| v.load(Ordering::Relaxed))
+                .collect();
+            match stat.kind {
+                StatKind::Distribution => {
+                    if v[1] > 0 {
+                        if result[1] == 0 {
+                            result[2] = v[2];
+                            result[3] = v[3];
+                        } else {
+                            result[2] = result[2].min(v[2]);
+                            result[3] = result[3].max(v[3]);
+                        }
+                        result[0] += v[0];
+                        result[1] += v[1];
+                    }
+                }
+                _ => {
+                    for (r, v) in result.iter_mut().zip(v.iter()) {
+                        *r += v;
+                    }
+                }
+            }
+        }
+        result
+    }
+
+    /// Print the statistics grouped by category. Nothing is printed if no
+    /// statistics were recorded.
+    ///
+    /// * `out` - Where to print the statistics.
+    fn print<W: Write>(&self, out: &mut W) -> std::io::Result<()> {
+        let mut categories: BTreeMap<&str, Vec<String>> = BTreeMap::new();
+        for stat in self.stats.iter() {
+            let v = self.values(stat);
+            let (category, title) = match stat.name.find('/') {
+                Some(i) => (&stat.name[..i], &stat.name[i + 1..]),
+                None => ("", stat.name),
+            };
+
+            let line = match stat.kind {
+                StatKind::Counter if v[0] != 0 => format!("{:<42}{:>24}", title, v[0]),
+                StatKind::Memory if v[0] != 0 => {
+                    format!("{:<42}{:>24}", title, format_bytes(v[0]))
+                }
+                StatKind::Distribution if v[1] != 0 => format!(
+                    "{:<42}{:>12.3} avg [range {} - {}]",
+                    title,
+                    v[0] as f64 / v[1] as f64,
+                    v[2],
+                    v[3]
+                ),
+                StatKind::Percent if v[1] != 0 => format!(
+                    "{:<42}{:>12} / {:>12} ({:.2}%)",
+                    title,
+                    v[0],
+                    v[1],
+                    100.0 * v[0] as f64 / v[1] as f64
+                ),
+                StatKind::Ratio if v[1] != 0 => format!(
+                    "{:<42}{:>12} / {:>12} ({:.2}x)",
+                    title,
+                    v[0],
+                    v[1],
+                    v[0] as f64 / v[1] as f64
+                ),
+                StatKind::Timer if v[0] != 0 => {
+                    format!("{:<42}{:>22.3} s", title, v[0] as f64 * 1e-9)
+                }
+                _ => continue,
+            };
+            categories
+                .entry(category)
+                .or_insert_with(Vec::new)
+                .push(line);
+        }
+
+        if categories.is_empty() {
+            return Ok(());
+        }
+
+        writeln!(out, "Statistics:")?;
+        for (category, lines) in categories.iter_mut() {
+            lines.sort();
+            writeln!(out, "  {}", category)?;
+            for line in lines.iter() {
+                writeln!(out, "    {}", line)?;
+            }
+        }
+        Ok(())
+    }
+}
+
+/// Add to a value only written by the current thread.
+///
+/// * `value` - The value.
+/// * `v`     - Amount to add.
+fn add(value: &AtomicI64, v: i64) {
+    value.store(value.load(Ordering::Relaxed) + v, Ordering::Relaxed);
+}
+
+/// Report a value for a distribution to the values of one thread. The values
+/// are the sum, count, minimum and maximum.
+///
+/// * `values` - The values of the distribution.
+/// * `v`      - The value.
+fn report(values: &[AtomicI64], v: i64) {
+    let count = values[1].load(Ordering::Relaxed);
+    add(&values[0], v);
+    add(&values[1], 1);
+    if count == 0 || v < values[2].load(Ordering::Relaxed) {
+        values[2].store(v, Ordering::Relaxed);
+    }
+    if count == 0 || v > values[3].load(Ordering::Relaxed) {
+        values[3].store(v, Ordering::Relaxed);
+    }
+}
+
+/// Print the statistics recorded by all threads grouped by category. Nothing
+/// is printed if no statistics were recorded.
+///
+/// * `out` - Where to print the statistics.
+pub fn print_stats<W: Write>(out: &mut W) -> std::io::Result<()> {
+    REGISTRY.lock().unwrap().print(out)
+}
+
+/// Reset all statistics to zero. This must not be called while other
+/// threads record statistics.
+pub fn clear_stats() {
+    let registry = REGISTRY.lock().unwrap();
+    for thread in registry.threads.iter() {
+        for value in thread.values.iter() {
+            value.store(0, Ordering::Relaxed);
+        }
+    }
+}
+
+/// Returns a number of bytes formatted with units.
+///
+/// * `bytes` - Number of bytes.
+fn format_bytes(bytes: i64) -> String {
+    let kb = bytes as f64 / 1024.0;
+    if kb.abs() < 1024.0 {
+        format!("{:.2} kB", kb)
+    } else if kb.abs() < 1024.0 * 1024.0 {
+        format!("{:.2} MiB", kb / 1024.0)
+    } else {
+        format!("{:.2} GiB", kb / (1024.0 * 1024.0))
+    }
+}
+
+// ----------------------------------------------------------------------------
+// Tests
+// ----------------------------------------------------------------------------
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    /// Returns a registry with two threads and the statistics registered.
+    ///
+    /// * `stats` - The statistics.
+    fn registry(stats: &[&'static Stat]) -> StatsRegistry {
+        let mut registry = StatsRegistry::default();
+        registry.threads = vec![Arc::new(ThreadStats::new()), Arc::new(ThreadStats::new())];
+        for stat in stats.iter() {
+            registry.register(stat);
+        }
+        registry
+    }
+
+    /// Returns the values of a statistic for one thread of a registry.
+    ///
+    /// * `registry` - The registry.
+    /// * `thread`   - Index of the thread.
+    /// * `stat`     - The statistic.
+    fn values<'a>(registry: &'a StatsRegistry, thread: usize, stat: &Stat) -> &'a [AtomicI64] {
+        let offset = stat.offset.load(Ordering::Acquire);
+        &registry.threads[thread].values[offset..offset + stat.kind.n_values()]
+    }
+
+    #[test]
+    fn distributions_merge_threads() {
+        static POSITIVE: Stat = Stat::distribution("Test/Positive");
+        static NEGATIVE: Stat = Stat::distribution("Test/Negative");
+        static ONE_THREAD: Stat = Stat::distribution("Test/One thread");
+        let registry = registry(&[&POSITIVE, &NEGATIVE, &ONE_THREAD]);
+
+        for &(thread, v) in [(0, 5), (0, 1), (1, 10)].iter() {
+            report(values(&registry, thread, &POSITIVE), v);
+        }
+        for &(thread, v) in [(0, -5), (1, -2), (1, -7)].iter() {
+            report(values(&registry, thread, &NEGATIVE), v);
+        }
+        report(values(&registry, 1, &ONE_THREAD), 3);
+
+        // A thread without values must not contribute a minimum or maximum
+        // of zero.
+        assert_eq!(registry.values(&POSITIVE), [16, 3, 1, 10]);
+        assert_eq!(registry.values(&NEGATIVE), [-14, 3, -7, -2]);
+        assert_eq!(registry.values(&ONE_THREAD), [3, 1, 3, 3]);
+    }
+
+    #[test]
+    fn fractions_sum_threads() {
+        static HITS: Stat = Stat::percent("Test/Hits");
+        let registry = registry(&[&HITS]);
+        for &(thread, num, denom) in [(0, 1, 4), (1, 2, 4)].iter() {
+            let v = values(&registry, thread, &HITS);
+            add(&v[0], num);
+            add(&v[1], denom);
+        }
+        assert_eq!(registry.values(&HITS)[..2], [3, 8]);
+    }
+
+    #[test]
+    fn print_groups_by_category() {
+        static TESTS: Stat = Stat::counter("Intersections/Tests");
+        static UNUSED: Stat = Stat::counter("Intersections/Unused");
+        static MEMORY: Stat = Stat::memory("Memory/Meshes");
+        static HITS: Stat = Stat::percent("Intersections/Hits");
+        static DEPTH: Stat = Stat::distribution("BVH/Depth");
+        let registry = registry(&[&TESTS, &UNUSED, &MEMORY, &HITS, &DEPTH]);
+
+        add(&values(&registry, 0, &TESTS)[0], 3);
+        add(&values(&registry, 1, &TESTS)[0], 4);
+        add(&values(&registry, 0, &MEMORY)[0], 2048);
+        let hits = values(&registry, 1, &HITS);
+        add(&hits[0], 1);
+        add(&hits[1], 4);
+        report(values(&registry, 0, &DEPTH), 2);
+        report(values(&registry, 1, &DEPTH), 4);
+
+        let mut out = vec![];
+        registry.print(&mut out).unwrap();
+        let expected = [
+            "Statistics:".to_string(),
+            "  BVH".to_string(),
+            format!("    {:<42}{:>12.3} avg [range 2 - 4]", "Depth", 3.0),
+            "  Intersections".to_string(),
+            format!("    {:<42}{:>12} / {:>12} (25.00%)", "Hits", 1, 4),
+            format!("    {:<42}{:>24}", "Tests", 7),
+            "  Memory".to_string(),
+            format!("    {:<42}{:>24}", "Meshes", "2.00 kB"),
+        ];
+        assert_eq!(String::from_utf8(out).unwrap(), expected.join("\n") + "\n");
+    }
+
+    #[test]
+    fn print_nothing_without_values() {
+        static UNUSED: Stat = Stat::counter("Test/Unused");
+        let mut out = vec![];
+        registry(&[&UNUSED]).print(&mut out).unwrap();
+        assert!(out.is_empty());
+    }
+
+    #[test]
+    fn format_bytes_uses_units() {
+        assert_eq!(format_bytes(512), "0.50 kB");
+        assert_eq!(format_bytes(3 * 1024 * 1024), "3.00 MiB");
+        assert_eq!(format_bytes(5 * 1024 * 1024 * 1024), "5.00 GiB");
+    }
+
+    #[cfg(not(feature = "stats"))]
+    #[test]
+    fn recording_does_nothing_when_disabled() {
+        static COUNT: Stat = Stat::counter("Test/Count");
+        static TIME: Stat = Stat::timer("Test/Time");
+        COUNT.inc();
+        drop(TIME.start());
+        assert_eq!(COUNT.offset.load(Ordering::Acquire), UNREGISTERED);
+        assert_eq!(TIME.offset.load(Ordering::Acquire), UNREGISTERED);
+    }
+}
@@ -0,0 +1,521 @@
+//! Ambient Occlusion Integrator
+
+#![allow(dead_code)]
+use crate::core::camera::*;
+use crate::core::geometry::*;
+use crate::core::integrator::*;
+use crate::core::material::*;
+use crate::core::paramset::*;
+use crate::core::pbrt::*;
+use crate::core::sampler::*;
+use crate::core::sampling::*;
+use crate::core::scene::*;
+use crate::core::spectrum::*;
+use std::sync::Arc;
+
+/// Implements an integrator that computes the fraction of the hemisphere
+/// above the first intersection of camera rays that is not occluded by other
+/// geometry.
+pub struct AOIntegrator {
+    /// The common sampler integrator data.
+    pub data: SamplerIntegratorData,
+
+    /// Sample directions with a cosine-weighted distribution.
+    pub cos_sample: bool,
+
+    /// Geometry further away than this doesn't occlude.
+    pub max_distance: Float,
+
+    /// Number of directions sampled at each intersection.
+    pub n_samples: usize,
+}
+
+impl AOIntegrator {
+    /// Create a new `AOIntegrator`.
+    ///
+    /// * `data`         - The common sampler integrator data.
+    /// * `cos_sample`   - Sample directions with a cosine-weighted
+    ///                    distribution.
+    /// * `max_distance` - Geometry further away than this doesn't occlude.
+    /// * `n_samples`    - Number of directions sampled at each intersection.
+    pub fn new(
+        data: SamplerIntegratorData,
+        cos_sample: bool,
+        max_distance: Float,
+        n_samples: usize,
+    ) -> Self {
+        Self {
+            data,
+            cos_sample,
+            max_distance,
+            n_samples,
+        }
+    }
+
+    /// Returns the ambient occlusion at the first intersection of a given ray
+    /// and whether the ray hits a surface.
+    ///
+    /// * `ray`     - The ray.
+    /// * `scene`   - The scene.
+    /// * `sampler` - The sampler.
+    fn occlusion(
+        &self,
+        ray: &Ray,
+        scene: &Scene,
+        sampler: &mut (dyn Sampler + Send + Sync),
+    ) -> (Spectrum, bool) {
+        let mut ray = ray.clone();
+        loop {
+            let mut isect = match scene.intersect(&mut ray) {
+                Some(isect) => isect,
+                None => return (Spectrum::new(0.0), false),
+            };
+
+            if let Some(primitive) = isect.primitive {
+                primitive.compute_scattering_functions(&mut isect, TransportMode::Radiance, true);
+            }
+
+            // Skip over surfaces without a BSDF that only mark medium
+            // boundaries.
+            if isect.bsdf.is_none() {
+                ray = isect.hit.spawn_ray(&ray.d);
+                continue;
+            }
+
+            // Compute coordinate frame based on the shading normal facing
+            // the ray origin.
+            let n = isect.shading.n.face_forward(&(-ray.d));
+            let n = Vector3f::from(n);
+            let mut s = Vector3f::default();
+            let mut t = Vector3f::default();
+            coordinate_system(&n, &mut s, &mut t);
+
+            // The shadow rays all start at the intersection so they are
+            // traced together as a packet.
+            let mut shadow_rays = Vec::with_capacity(self.n_samples);
+            let mut weights = Vec::with_capacity(self.n_samples);
+            for _ in 0..self.n_samples {
+                let u = sampler.get_2d();
+                let (wi, pdf) = if self.cos_sample {
+                    let wi = cosine_sample_hemisphere(&u);
+                    (wi, cosine_hemisphere_pdf(wi.z))
+                } else {
+                    (uniform_sample_hemisphere(&u), uniform_hemisphere_pdf())
+                };
+                if pdf == 0.0 {
+                    continue;
+                }
+
+                let wi = s * wi.x + t * wi.y + n * wi.z;
+                let mut shadow_ray = isect.hit.spawn_ray(&wi);
+                shadow_ray.t_max = self.max_distance;
+                shadow_rays.push(shadow_ray);
+                weights.push(wi.dot(&n) / (PI * pdf));
+            }
+
+            let occluded = scene.occluded_n(&shadow_rays);
+            let l = weights
+                .iter()
+                .zip(occluded.iter())
+                .filter(|(_, &occluded)| !occluded)
+                .map(|(w, _)| *w)
+                .sum::<Float>()
+                / self.n_samples as Float;
+            return (Spectrum::new(l), true);
+        }
+    }
+}
+
+impl SamplerIntegrator for AOIntegrator {
+    /// Returns the common sampler integrator data.
+    fn get_data(&self) -> &SamplerIntegratorData {
+        &self.data
+    }
+
+    /// Returns the incident radiance at the origin of a given ray.
+    ///
+    /// * `ray`     - The ray.
+    /// * `scene`   - The scene.
+    /// * `sampler` - The sampler.
+    /// * `depth`   - The number of ray bounces from the camera.
+    fn li(
+        &self,
+        ray: &mut Ray,
+        scene: &Scene,
+        sampler: &mut (dyn Sampler + Send + Sync),
+        _depth: usize,
+    ) -> Spectrum {
+        self.occlusion(ray, scene, sampler).0
+    }
+
+    /// Returns the incident radiance along a camera ray and whether the ray
+    /// hits a surface.
+    ///
+    /// * `ray`     - The camera ray.
+    /// * `scene`   - The scene.
+    /// * `sampler` - The sampler.
+    fn li_camera(
+        &self,
+        ray: &mut Ray,
+        scene: &Scene,
+        sampler: &mut (dyn Sampler + Send + Sync),
+    ) -> (Spectrum, bool) {
+        self.occlusion(ray, scene, sampler)
+    }
+}
+
+impl Integrator for AOIntegrator {
+    /// Render the scene.
+    ///
+    /// * `scene` - The scene.
+    fn render(&self, scene: Arc<Scene>) {
+        SamplerIntegrator::render(self, scene);
+    }
+}
+
+impl From<(&ParamSet, ArcSampler, ArcCamera)> for AOIntegrator {
+    /// Create an `AOIntegrator` from given parameter set, sampler and camera.
+    ///
+    /// * `p` - A tuple containing the parameter set, sampler and camera.
+    fn from(p: (&ParamSet, ArcSampler, ArcCamera)) -> Self {
+        let (params, sampler, camera) = p;
+
+        let pixel_bounds = get_pixel_bounds(params, &camera);
+        let cos_sample = params.find_one_bool("cossample", true);
+        let max_distance = params.find_one_float("maxdistance", INFINITY);
+        let n_samples = max(1, params.find_one_int("nsamples", 64)) as usize;
+        let progressive = ProgressiveOptions::from(params);
+
+        Self::new(
+            SamplerIntegratorData::new(camera, sampler, pixel_bounds, progressive),
+            cos_sample,
+            max_distance,
+            n_samples,
+        )
+    }
+}
+
+// ----------------------------------------------------------------------------
+// Tests
+// ----------------------------------------------------------------------------
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::accelerators::*;
+    use crate::cameras::*;
+    use crate::core::film::*;
+    use crate::core::filter::*;
+    use crate::core::image_io::*;
+    use crate::core::medium::*;
+    use crate::core::primitive::*;
+    use crate::core::primitives::*;
+    use crate::core::texture::*;
+    use crate::filters::*;
+    use crate::materials::*;
+    use crate::samplers::*;
+    use crate::shapes::*;
+    use crate::textures::*;
+
+    /// Returns a matte primitive for a shape placed with a translation.
+    fn primitive<F>(delta: Vector3f, shape: F) -> ArcPrimitive
+    where
+        F: Fn(ArcTransform, ArcTransform) -> ArcShape,
+    {
+        let o2w = Arc::new(Transform::translate(&delta));
+        let w2o = Arc::new(o2w.inverse());
+        let kd: ArcTexture<Spectrum> = Arc::new(ConstantTexture::new(Spectrum::new(0.5)));
+        let sigma: ArcTexture<Float> = Arc::new(ConstantTexture::new(0.0));
+        Arc::new(GeometricPrimitive::new(
+            shape(o2w, w2o),
+            Arc::new(MatteMaterial::new(kd, sigma, None)),
+            None,
+            MediumInterface::new(None, None),
+        ))
+    }
+
+    /// Returns a disk of the given radius facing -z at a height.
+    fn disk(z: Float, radius: Float) -> ArcPrimitive {
+        primitive(Vector3f::new(0.0, 0.0, z), |o2w, w2o| {
+            Arc::new(Disk::new(o2w, w2o, true, 0.0, radius, 0.0, 360.0))
+        })
+    }
+
+    /// Returns a scene made of the given primitives.
+    fn scene(prims: Vec<ArcPrimitive>) -> Arc<Scene> {
+        let accel = Arc::new(BVHAccel::new(&prims, 1, SplitMethod::SAH, 2));
+        Arc::new(Scene::new(accel, vec![]))
+    }
+
+    /// Returns a floor disk at z = 5 with a sphere of radius 0.5 resting on
+    /// it. The camera looks at the floor from z = 0.
+    fn sphere_on_floor() -> Arc<Scene> {
+        let sphere = primitive(Vector3f::new(0.0, 0.0, 4.5), |o2w, w2o| {
+            Arc::new(Sphere::new(o2w, w2o, false, 0.5, -0.5, 0.5, 360.0))
+        });
+        scene(vec![disk(5.0, 100.0), sphere])
+    }
+
+    /// Returns the radiance for a ray towards the floor from a random sampler.
+    fn li(integrator: &AOIntegrator, scene: &Scene, o: Point3f) -> Spectrum {
+        let mut sampler = RandomSampler::new(1, Some(7));
+        sampler.start_pixel(&Point2i::new(0, 0));
+        let mut ray = Ray::new(o, Vector3f::new(0.0, 0.0, 1.0), INFINITY, 0.0, None);
+        integrator.li(&mut ray, scene, &mut sampler, 0)
+    }
+
+    /// Returns an orthographic camera for an 8x8 image looking down the z
+    /// axis.
+    fn camera(filename: &str, alpha: bool) -> ArcCamera {
+        let mut film_params = ParamSet::new();
+        film_params.add_int("xresolution", &[8]);
+        film_params.add_int("yresolution", &[8]);
+        film_params.add_string("filename", &[String::from(filename)]);
+        film_params.add_bool("alpha", &[alpha]);
+        let filter: ArcFilter = Arc::new(BoxFilter::from(&ParamSet::new()));
+        let film = Arc::new(Film::from((&film_params, filter)));
+
+        let cam2world = Arc::new(Transform::default());
+        let animated = AnimatedTransform::new(cam2world.clone(), cam2world, 0.0, 1.0);
+        Arc::new(OrthographicCamera::from((
+            &ParamSet::new(),
+            &animated,
+            film,
+            None,
+        )))
+    }
+
+    /// Returns an `AOIntegrator` for an 8x8 image with an orthographic camera
+    /// looking at the floor.
+    fn integrator(filename: &str, params: &ParamSet) -> AOIntegrator {
+        let sampler: ArcSampler = Arc::new(RandomSampler::new(8, None));
+        AOIntegrator::from((params, sampler, camera(filename, false)))
+    }
+
+    /// An integrator that returns constant radiance along every ray like an
+    /// environment light.
+    struct Background {
+        data: SamplerIntegratorData,
+    }
+
+    impl SamplerIntegrator for Background {
+        fn get_data(&self) -> &SamplerIntegratorData {
+            &self.data
+        }
+
+        fn li(
+            &self,
+            _ray: &mut Ray,
+            _scene: &Scene,
+            _sampler: &mut (dyn Sampler + Send + Sync),
+            _depth: usize,
+        ) -> Spectrum {
+            Spectrum::new(2.0)
+        }
+    }
+
+    /// Renders a small disk in the center of the image with an alpha channel
+    /// and returns the image.
+    fn render_alpha<I: SamplerIntegrator>(name: &str, integrator: &I) -> RGBImage {
+        SamplerIntegrator::render(integrator, scene(vec![disk(5.0, 0.5)]));
+        let path = integrator
+            .get_data()
+            .camera
+            .get_data()
+            .film
+            .filename
+            .clone();
+        let image = read_image(&path).unwrap();
+        let _ = std::fs::remove_file(&path);
+        assert_eq!(image.pixels.len(), 64, "{}", name);
+        image
+    }
+
+    /// Returns a unique path in the temporary directory.
+    fn temp_path(name: &str) -> String {
+        let path = std::env::temp_dir().join(format!("ao_{}_{}.pfm", name, std::process::id()));
+        path.to_str().unwrap().to_owned()
+    }
+
+    /// Renders the occluded scene and returns the image.
+    fn render(name: &str, params: &ParamSet) -> Vec<Float> {
+        let path = temp_path(name);
+        let ao = integrator(&path, params);
+        Integrator::render(&ao, sphere_on_floor());
+
+        // Completed renders don't leave a checkpoint behind.
+        let film = ao.data.camera.get_data().film.clone();
+        assert!(!std::path::Path::new(&film.get_checkpoint_filename()).exists());
+
+        let image = read_image(&path).unwrap();
+        let _ = std::fs::remove_file(&path);
+        image.pixels.iter().map(|p| p[0]).collect()
+    }
+
+    #[test]
+    fn unoccluded_surface_is_white() {
+        let scene = scene(vec![disk(5.0, 100.0)]);
+        let o = Point3f::new(1.0, 2.0, 0.0);
+
+        let ao = integrator("unused.pfm", &ParamSet::new());
+        assert!((li(&ao, &scene, o)[0] - 1.0).abs() < 1e-4);
+
+        // Uniform sampling weights directions by their cosine.
+        let mut params = ParamSet::new();
+        params.add_bool("cossample", &[false]);
+        let ao = integrator("unused.pfm", &params);
+        let l = li(&ao, &scene, o)[0];
+        assert!(l > 0.0 && l < 2.0);
+    }
+
+    #[test]
+    fn occluded_surface_is_black() {
+        // A large disk behind the camera covers the hemisphere above the
+        // floor.
+        let scene = scene(vec![disk(5.0, 100.0), disk(-1.0, 1e5)]);
+        let o = Point3f::new(1.0, 2.0, 0.0);
+
+        let ao = integrator("unused.pfm", &ParamSet::new());
+        assert_eq!(li(&ao, &scene, o)[0], 0.0);
+
+        let mut params = ParamSet::new();
+        params.add_float("maxdistance", &[5.0]);
+        let ao = integrator("unused.pfm", &params);
+        assert!((li(&ao, &scene, o)[0] - 1.0).abs() < 1e-4);
+    }
+
+    #[test]
+    fn partially_occluded_surface() {
+        // A ring between radius 0.5 and 1 at distance 1 from the floor
+        // covers the directions with sin^2(theta) between 0.2 and 0.5, which
+        // is 30% of the cosine-weighted hemisphere. Camera rays pass through
+        // its hole, slightly off the center where the floor disk has no
+        // tangents.
+        let ring = primitive(Vector3f::new(0.0, 0.0, 4.0), |o2w, w2o| {
+            Arc::new(Disk::new(o2w, w2o, false, 0.0, 1.0, 0.5, 360.0))
+        });
+        let scene = scene(vec![disk(5.0, 100.0), ring]);
+        let o = Point3f::new(0.01, 0.01, 0.0);
+
+        let mut params = ParamSet::new();
+        params.add_int("nsamples", &[4096]);
+        let ao = integrator("unused.pfm", &params);
+        let l = li(&ao, &scene, o)[0];
+        assert!((l - 0.7).abs() < 0.03, "{}", l);
+    }
+
+    #[test]
+    fn misses_are_black() {
+        let scene = scene(vec![disk(5.0, 100.0)]);
+        let ao = integrator("unused.pfm", &ParamSet::new());
+        assert_eq!(li(&ao, &scene, Point3f::new(200.0, 0.0, 0.0))[0], 0.0);
+    }
+
+    #[test]
+    fn passes_continue_the_sample_sequence() {
+        let single = render("single", &ParamSet::new());
+        let mut params = ParamSet::new();
+        params.add_int("passsamples", &[3]);
+        let passes = render("passes", &params);
+
+        assert_eq!(single.len(), 64);
+        assert!(single.iter().any(|&v| v < 0.9));
+        for (a, b) in single.iter().zip(passes.iter()) {
+            assert!((a - b).abs() < 1e-5, "{} != {}", a, b);
+        }
+    }
+
+    #[test]
+    fn unfinished_renders_keep_a_checkpoint() {
+        let path = temp_path("unfinished");
+        let mut params = ParamSet::new();
+        params.add_int("passsamples", &[2]);
+        params.add_float("maxtime", &[1e-9]);
+        let ao = integrator(&path, &params);
+        Integrator::render(&ao, sphere_on_floor());
+
+        // The time budget runs out after the first pass.
+        let film = ao.data.camera.get_data().film.clone();
+        assert_eq!(film.read_checkpoint(8), Ok(2));
+        film.remove_checkpoint();
+        let _ = std::fs::remove_file(&path);
+    }
+
+    #[test]
+    fn adaptive_sampling_renders_converged_pixels() {
+        // Pixels that only see the floor converge after the minimum number
+        // of samples.
+        let mut params = ParamSet::new();
+        params.add_int("passsamples", &[2]);
+        params.add_float("maxerror", &[0.05]);
+        params.add_int("minsamples", &[2]);
+        let adaptive = render("adaptive", &params);
+        let single = render("reference", &ParamSet::new());
+        assert!(adaptive.iter().all(|v| *v >= 0.0 && *v <= 1.0 + 1e-5));
+        assert!(adaptive
+            .iter()
+            .zip(single.iter())
+            .any(|(a, b)| (a - b).abs() > 1e-5));
+    }
+
+    #[test]
+    fn camera_rays_report_hits() {
+        let scene = scene(vec![disk(5.0, 100.0)]);
+        let ao = integrator("unused.pfm", &ParamSet::new());
+        let mut sampler = RandomSampler::new(1, Some(7));
+        sampler.start_pixel(&Point2i::new(0, 0));
+
+        let d = Vector3f::new(0.0, 0.0, 1.0);
+        let mut ray = Ray::new(Point3f::new(1.0, 2.0, 0.0), d, INFINITY, 0.0, None);
+        let (l, hit) = ao.li_camera(&mut ray, &scene, &mut sampler);
+        assert!(hit);
+        assert!((l[0] - 1.0).abs() < 1e-4);
+
+        let mut ray = Ray::new(Point3f::new(200.0, 0.0, 0.0), d, INFINITY, 0.0, None);
+        let (l, hit) = ao.li_camera(&mut ray, &scene, &mut sampler);
+        assert!(!hit);
+        assert_eq!(l[0], 0.0);
+    }
+
+    #[test]
+    fn alpha_comes_from_camera_ray_hits() {
+        let path = std::env::temp_dir().join(format!("ao_alpha_{}.tif", std::process::id()));
+        let sampler: ArcSampler = Arc::new(RandomSampler::new(8, None));
+        let camera = camera(path.to_str().unwrap(), true);
+        let ao = AOIntegrator::from((&ParamSet::new(), sampler, camera));
+
+        let image = render_alpha("ao", &ao);
+        let alpha = image.alpha.unwrap();
+        assert_eq!(alpha[0], 0.0);
+        assert_eq!(image.pixels[0][0], 0.0);
+        let center = 3 * 8 + 3;
+        assert_eq!(alpha[center], 1.0);
+        assert!((image.pixels[center][0] - 1.0).abs() < 1e-4);
+    }
+
+    #[test]
+    fn alpha_excludes_radiance_of_misses() {
+        let path = std::env::temp_dir().join(format!("bg_alpha_{}.tif", std::process::id()));
+        let camera = camera(path.to_str().unwrap(), true);
+        let pixel_bounds = get_pixel_bounds(&ParamSet::new(), &camera);
+        let background = Background {
+            data: SamplerIntegratorData::new(
+                camera,
+                Arc::new(RandomSampler::new(8, None)),
+                pixel_bounds,
+                ProgressiveOptions::from(&ParamSet::new()),
+            ),
+        };
+
+        // Colors are premultiplied by alpha so partially covered pixels keep
+        // the radiance of the disk when divided by their alpha.
+        let image = render_alpha("background", &background);
+        let alpha = image.alpha.unwrap();
+        for (p, a) in image.pixels.iter().zip(alpha.iter()) {
+            assert!((p[0] - 2.0 * a).abs() < 1e-4, "{} {}", p[0], a);
+        }
+        assert_eq!(alpha[0], 0.0);
+        assert_eq!(alpha[3 * 8 + 3], 1.0);
+        assert!(alpha.iter().any(|a| *a > 0.0 && *a < 1.0));
+    }
+}
@@ -0,0 +1,7 @@
+//! Integrators
+
+#![allow(dead_code)]
+mod ao;
+
+// Re-export
+pub use ao::*;
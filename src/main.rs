@@ -1,4 +1,6 @@
 #![feature(iter_partition_in_place)] // Can be removed once stable
+#![feature(portable_simd)]
+#![cfg_attr(test, feature(test))]
 
 extern crate byteorder;
 extern crate clap;
@@ -7,6 +9,8 @@ extern crate log;
 extern crate env_logger;
 extern crate exr;
 extern crate float_cmp;
+#[cfg(feature = "mmap")]
+extern crate memmap2;
 #[macro_use]
 extern crate hexf;
 extern crate image;
@@ -19,12 +23,15 @@ extern crate pest;
 extern crate pest_derive;
 extern crate rand;
 extern crate rand_pcg;
+#[cfg(test)]
+extern crate test;
 extern crate typed_arena;
 
 mod accelerators;
 mod cameras;
 mod core;
 mod filters;
+mod integrators;
 mod lights;
 mod materials;
 mod samplers;
@@ -42,18 +49,49 @@ fn main() {
     // Load the program options.
     let options = OPTIONS.clone();
 
+    // Limit the number of threads used for rendering tiles.
+    if let Err(err) = rayon::ThreadPoolBuilder::new()
+        .num_threads(options.n_threads)
+        .build_global()
+    {
+        warn!("Unable to configure thread pool. {}", err);
+    }
+
     // Initialize PBRT API.
     let mut api = Api::new();
     api.pbrt_init();
 
     // Process scene description.
-    for path in options.paths.iter() {
-        let parser = PbrtFileParser::new(path);
-        match parser.parse(&mut api) {
-            Ok(_) => (),
-            Err(err) => error!("{}", err),
+    if options.paths.is_empty() {
+        // Parse scene from standard input.
+        let parser = PbrtFileParser::from_source_name("<stdin>");
+        let result = parser.parse_reader(&mut std::io::stdin().lock(), &mut api);
+        report_parse_result(result, options.strict);
+    } else {
+        // Parse scene from input files.
+        for path in options.paths.iter() {
+            let parser = PbrtFileParser::new(path);
+            report_parse_result(parser.parse(&mut api), options.strict);
         }
     }
 
+    api.pbrt_end_of_input();
     api.pbrt_cleanup();
+    if let Some(err) = api.take_error() {
+        error!("{}", err);
+        std::process::exit(1);
+    }
+}
+
+/// Logs the error from parsing a scene description and exits in strict mode.
+///
+/// * `result` - Result of parsing the scene description.
+/// * `strict` - Exit on error.
+fn report_parse_result(result: Result<(), ParseError>, strict: bool) {
+    if let Err(err) = result {
+        error!("{}", err);
+        if strict {
+            std::process::exit(1);
+        }
+    }
 }
@@ -7,6 +7,11 @@ use crate::core::rng::*;
 use crate::core::sampler::*;
 use std::sync::Arc;
 
+/// Number of random values reserved for each sample of a pixel. Samples start
+/// at fixed offsets in the pixel's sequence so that rendering can continue at
+/// any sample number.
+const SAMPLE_STRIDE: u64 = 1 << 16;
+
 /// Implements a sampler that uses a PRNG to generate uniformly random samples.
 pub struct RandomSampler {
     /// The common sampler data.
@@ -14,6 +19,13 @@ pub struct RandomSampler {
 
     /// The random number generator.
     pub rng: RNG,
+
+    /// Seed combined with the pixel coordinates to start the sequence of
+    /// each pixel.
+    seed: u64,
+
+    /// Seed of the current pixel's sequence.
+    pixel_seed: u64,
 }
 
 impl RandomSampler {
@@ -22,14 +34,24 @@ impl RandomSampler {
     /// * `samples_per_pixel` - Number of samples to generate for each pixel.
     /// * `seed`              - Optional seed for the random number generator.
     pub fn new(samples_per_pixel: usize, seed: Option<u64>) -> Self {
+        let seed = seed.unwrap_or(0);
         Self {
             data: SamplerData::new(samples_per_pixel),
-            rng: match seed {
-                Some(s) => RNG::new(s),
-                None => RNG::default(),
-            },
+            rng: RNG::new(seed),
+            seed,
+            pixel_seed: seed,
         }
     }
+
+    /// Move the random number generator to the start of a sample in the
+    /// current pixel.
+    ///
+    /// * `sample_num` - The sample number.
+    fn seek_sample(&mut self, sample_num: usize) {
+        // Sequence offset 0 is used for the sample arrays.
+        self.rng = RNG::new(self.pixel_seed);
+        self.rng.advance((sample_num as u64 + 1) * SAMPLE_STRIDE);
+    }
 }
 
 impl Sampler for RandomSampler {
@@ -51,6 +73,12 @@ impl Sampler for RandomSampler {
     ///
     /// * `p` - The pixel.
     fn start_pixel(&mut self, p: &Point2i) {
+        self.pixel_seed = self
+            .seed
+            .wrapping_mul(0x9e3779b97f4a7c15)
+            .wrapping_add(((p.x as u32 as u64) << 32) | p.y as u32 as u64);
+        self.rng = RNG::new(self.pixel_seed);
+
         let n = self.data.sample_array_1d.len();
         for i in 0..n {
             for j in 0..self.data.sample_array_1d[i].len() {
@@ -67,6 +95,7 @@ impl Sampler for RandomSampler {
         }
 
         self.get_data().start_pixel(p);
+        self.seek_sample(0);
     }
 
     /// Returns the sample value for the next dimension of the current sample
@@ -80,6 +109,24 @@ impl Sampler for RandomSampler {
     fn get_2d(&mut self) -> Point2f {
         Point2f::new(self.rng.uniform(), self.rng.uniform())
     }
+
+    /// Reset the current sample dimension counter. Returns `true` if
+    /// `current_pixel_sample_index` < `samples_per_pixel`; otherwise `false`.
+    fn start_next_sample(&mut self) -> bool {
+        let more = self.data.start_next_sample();
+        self.seek_sample(self.data.current_pixel_sample_index);
+        more
+    }
+
+    /// Set the index of the sample in the current pixel to generate next.
+    /// Returns `true` if `current_pixel_sample_index` < `samples_per_pixel`;
+    /// otherwise `false`.
+    ///
+    /// * `sample_num` - The sample number.
+    fn set_sample_number(&mut self, sample_num: usize) -> bool {
+        self.seek_sample(sample_num);
+        self.data.set_sample_number(sample_num)
+    }
 }
 
 impl From<(&ParamSet, Bounds2i)> for RandomSampler {
@@ -92,3 +139,38 @@ impl From<(&ParamSet, Bounds2i)> for RandomSampler {
         Self::new(samples_per_pixel, None)
     }
 }
+
+// ----------------------------------------------------------------------------
+// Tests
+// ----------------------------------------------------------------------------
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn set_sample_number_continues_the_pixel_sequence() {
+        let p = Point2i::new(3, 5);
+        let mut sampler = RandomSampler::new(4, Some(1));
+        sampler.start_pixel(&p);
+        let mut samples = vec![];
+        loop {
+            samples.push((sampler.get_2d(), sampler.get_1d()));
+            if !sampler.start_next_sample() {
+                break;
+            }
+        }
+
+        // A fresh sampler with the same seed resumes at any sample.
+        for (i, sample) in samples.iter().enumerate().rev() {
+            let mut sampler = RandomSampler::new(4, Some(1));
+            sampler.start_pixel(&p);
+            assert!(sampler.set_sample_number(i));
+            assert_eq!((sampler.get_2d(), sampler.get_1d()), *sample);
+        }
+
+        // Other pixels get other samples.
+        sampler.start_pixel(&Point2i::new(5, 3));
+        assert_ne!(sampler.get_2d(), samples[0].0);
+    }
+}
@@ -0,0 +1,1002 @@
+//! Bilinear patches and bilinear patch meshes
+
+#![allow(dead_code)]
+use super::get_alpha_texture;
+use crate::core::geometry::*;
+use crate::core::paramset::*;
+use crate::core::pbrt::*;
+use crate::core::sampling::*;
+use crate::core::stats::*;
+use crate::core::texture::*;
+use std::collections::HashMap;
+use std::mem::size_of;
+use std::sync::Arc;
+
+/// Memory used by bilinear patch meshes.
+static BLP_MESH_BYTES: Stat = Stat::memory("Memory/Bilinear patch meshes");
+
+/// Number of bilinear patches and bilinear patch meshes.
+static PATCHES_PER_MESH: Stat = Stat::ratio("Scene/Patches per bilinear patch mesh");
+
+/// Number of ray-bilinear patch tests and hits.
+static RAY_BLP_TESTS: Stat = Stat::percent("Intersections/Ray-bilinear patch intersection tests");
+
+/// Bilinear patch mesh.
+#[derive(Clone)]
+pub struct BilinearPatchMesh {
+    /// Common shape data.
+    pub data: ShapeData,
+
+    /// The number of patches.
+    pub num_patches: usize,
+
+    /// Vertex indices. For the ith patch, its four vertex positions are
+    /// p[vertex_indices[4 * i]] to p[vertex_indices[4 * i + 3]] ordered
+    /// `p00, p10, p01, p11`.
+    pub vertex_indices: Vec<usize>,
+
+    /// Vertex positions in world space.
+    pub p: Vec<Point3f>,
+
+    /// Vertex normals. This will be empty if there are none.
+    pub n: Vec<Normal3f>,
+
+    /// Paramteric uv-coordinates per vertex. This will be empty if there are none.
+    pub uv: Vec<Point2f>,
+
+    /// Optional alpha mask texture, which can be used to cut away parts of
+    /// patch surfaces.
+    pub alpha_mask: Option<ArcTexture<Float>>,
+
+    /// Optional shadow alpha mask texture.
+    pub shadow_alpha_mask: Option<ArcTexture<Float>>,
+
+    /// Face indices.
+    pub face_indices: Vec<usize>,
+}
+
+impl BilinearPatchMesh {
+    /// Create a new bilinear patch mesh.
+    ///
+    /// * `object_to_world`     - The object to world transfomation.
+    /// * `reverse_orientation` - Indicates whether their surface normal directions
+    ///                           should be reversed from the default
+    /// * `vertex_indices`      - Vertex indices for patches. For the ith patch,
+    ///                           its four vertex positions are p[vertex_indices[4 * i]]
+    ///                           to p[vertex_indices[4 * i + 3]].
+    /// * `p`                   - Vertex positions.
+    /// * `n`                   - Vertex normals.
+    /// * `uv`                  - Paramteric uv-coordinates.
+    /// * `alpha_mask`          - Optional alpha mask texture, which can be used to
+    ///                           cut away parts of patch surfaces
+    /// * `shadow_alpha_mask`   - Optional shadow alpha mask texture.
+    /// * `face_indices`        - Face indices.
+    pub fn new(
+        object_to_world: ArcTransform,
+        reverse_orientation: bool,
+        vertex_indices: Vec<usize>,
+        p: Vec<Point3f>,
+        n: Vec<Normal3f>,
+        uv: Vec<Point2f>,
+        alpha_mask: Option<ArcTexture<Float>>,
+        shadow_alpha_mask: Option<ArcTexture<Float>>,
+        face_indices: Vec<usize>,
+    ) -> Self {
+        assert!(vertex_indices.len() % 4 == 0);
+        let num_patches = vertex_indices.len() / 4;
+
+        // Transform mesh vertices and normals to world space.
+        let tp = p.iter().map(|v| object_to_world.transform_point(&v));
+        let tn = n.iter().map(|v| object_to_world.transform_normal(&v));
+
+        Self {
+            num_patches,
+            vertex_indices,
+            p: tp.collect(),
+            n: tn.collect(),
+            uv,
+            alpha_mask,
+            shadow_alpha_mask,
+            face_indices,
+            data: ShapeData::new(object_to_world.clone(), None, reverse_orientation),
+        }
+    }
+
+    /// Returns the list of patches referencing a mesh.
+    ///
+    /// * `mesh`                - The bilinear patch mesh.
+    /// * `object_to_world`     - The object to world transfomation.
+    /// * `world_to_object`     - The world to object transfomation.
+    /// * `reverse_orientation` - Indicates whether their surface normal directions
+    ///                           should be reversed from the default
+    pub fn patches(
+        mesh: Arc<Self>,
+        object_to_world: ArcTransform,
+        world_to_object: ArcTransform,
+        reverse_orientation: bool,
+    ) -> Vec<ArcShape> {
+        PATCHES_PER_MESH.add_fraction(mesh.num_patches as i64, 1);
+        BLP_MESH_BYTES.add(mesh.memory_size() as i64);
+
+        (0..mesh.num_patches)
+            .map(|i| {
+                let patch = BilinearPatch::new(
+                    object_to_world.clone(),
+                    world_to_object.clone(),
+                    reverse_orientation,
+                    mesh.clone(),
+                    i,
+                );
+                Arc::new(patch) as ArcShape
+            })
+            .collect()
+    }
+
+    /// Returns the number of bytes used by the mesh.
+    pub fn memory_size(&self) -> usize {
+        size_of::<Self>()
+            + self.vertex_indices.len() * size_of::<usize>()
+            + self.p.len() * size_of::<Point3f>()
+            + self.n.len() * size_of::<Normal3f>()
+            + self.uv.len() * size_of::<Point2f>()
+            + self.face_indices.len() * size_of::<usize>()
+    }
+
+    /// Create a bilinear patch mesh from given parameter set, object to world
+    /// transform, world to object transform and whether or not surface normal
+    /// orientation is reversed.
+    ///
+    /// NOTE: Because we return a set of patches as `Vec<Arc<Shape>>` we cannot
+    /// implement this as `From` trait :(
+    ///
+    /// * `p`              - A tuple containing the parameter set, object to
+    ///                      world transform, world to object transform and
+    ///                      whether or not surface normal orientation is reversed.
+    /// * `float_textures` - Float textures.
+    pub fn from_props(
+        p: (&ParamSet, ArcTransform, ArcTransform, bool),
+        float_textures: &HashMap<String, ArcTexture<Float>>,
+    ) -> Vec<ArcShape> {
+        let (params, o2w, w2o, reverse_orientation) = p;
+
+        let p = params.find_point3f("P");
+        let npi = p.len();
+        if npi == 0 {
+            error!("Vertex positions 'P' not provided with bilinear patch mesh shape");
+            return vec![];
+        }
+
+        let mut vi: Vec<usize> = params
+            .find_int("indices")
+            .iter()
+            .map(|i| *i as usize)
+            .collect();
+        if vi.len() == 0 {
+            if npi == 4 {
+                // A single patch doesn't need indices.
+                vi = vec![0, 1, 2, 3];
+            } else {
+                error!("Vertex indices 'indices' not provided with bilinear patch mesh shape");
+                return vec![];
+            }
+        } else if vi.len() % 4 != 0 {
+            error!(
+                "Number of vertex indices {} not a multiple of 4 for bilinear patch mesh. Discarding {} excess.",
+                vi.len(),
+                vi.len() % 4
+            );
+            vi.truncate(vi.len() - vi.len() % 4);
+        }
+        let nvi = vi.len();
+
+        for i in 0..nvi {
+            if vi[i] >= npi {
+                error!(
+                    "bilinearmesh has out-of-bounds vertex index {} ({} 'P' values were given",
+                    vi[i], npi
+                );
+                return vec![];
+            }
+        }
+
+        let mut uvs = params.find_point2f("uv");
+        if uvs.len() > 0 && uvs.len() != npi {
+            error!("Number of 'uv' for bilinear patch mesh must match 'P'. Discarding.");
+            uvs = vec![];
+        }
+
+        let mut n = params.find_normal3f("N");
+        if n.len() > 0 && n.len() != npi {
+            error!("Number of 'N' for bilinear patch mesh must match 'P'. Discarding.");
+            n = vec![];
+        }
+
+        let mut face_indices: Vec<usize> = params
+            .find_int("faceIndices")
+            .iter()
+            .map(|i| *i as usize)
+            .collect();
+        let nfi = face_indices.len();
+        if nfi > 0 && nfi != nvi / 4 {
+            error!(
+                "Number of face indices, {}, doesn't match number of patches, {}",
+                nfi,
+                nvi / 4
+            );
+            face_indices = vec![];
+        }
+
+        let alpha_tex = get_alpha_texture(params, float_textures, "alpha");
+        let shadow_alpha_tex = get_alpha_texture(params, float_textures, "shadowalpha");
+
+        let mesh = Self::new(
+            o2w.clone(),
+            reverse_orientation,
+            vi,
+            p,
+            n,
+            uvs,
+            Some(alpha_tex),
+            Some(shadow_alpha_tex),
+            face_indices,
+        );
+
+        Self::patches(Arc::new(mesh), o2w, w2o, reverse_orientation)
+    }
+}
+
+/// Bilinear patch. The surface is `p(u, v) = lerp(u, lerp(v, p00, p01),
+/// lerp(v, p10, p11))` for `(u, v)` in `[0, 1]^2`.
+#[derive(Clone)]
+pub struct BilinearPatch {
+    /// Common shape data.
+    pub data: ShapeData,
+
+    /// The mesh.
+    pub mesh: Arc<BilinearPatchMesh>,
+
+    /// The index of the first vertex index of the patch in the mesh's
+    /// `vertex_indices`. The other three are at v + 1 to v + 3.
+    pub v: usize,
+
+    /// Surface area. Computed once since it has no closed form unless the
+    /// patch is a rectangle.
+    area: Float,
+}
+
+impl BilinearPatch {
+    /// Create a new bilinear patch.
+    ///
+    /// * `object_to_world`     - The object to world transfomation.
+    /// * `world_to_object`     - The world to object transfomation.
+    /// * `reverse_orientation` - Indicates whether their surface normal directions
+    ///                           should be reversed from the default
+    /// * `mesh`                - The bilinear patch mesh.
+    /// * `patch_index`         - The index of the patch.
+    pub fn new(
+        object_to_world: ArcTransform,
+        world_to_object: ArcTransform,
+        reverse_orientation: bool,
+        mesh: Arc<BilinearPatchMesh>,
+        patch_index: usize,
+    ) -> Self {
+        let mut patch = Self {
+            mesh: mesh.clone(),
+            v: 4 * patch_index,
+            data: ShapeData::new(
+                object_to_world.clone(),
+                Some(world_to_object.clone()),
+                reverse_orientation,
+            ),
+            area: 0.0,
+        };
+        patch.area = patch.compute_area();
+        patch
+    }
+
+    /// Returns the index of the ith vertex of the patch in the mesh.
+    ///
+    /// * `i` - The patch vertex (0 to 3 for `p00, p10, p01, p11`).
+    fn vertex(&self, i: usize) -> usize {
+        self.mesh.vertex_indices[self.v + i]
+    }
+
+    /// Returns the vertex positions `p00, p10, p01, p11` of the patch.
+    fn positions(&self) -> [Point3f; 4] {
+        [
+            self.mesh.p[self.vertex(0)],
+            self.mesh.p[self.vertex(1)],
+            self.mesh.p[self.vertex(2)],
+            self.mesh.p[self.vertex(3)],
+        ]
+    }
+
+    /// Returns the uv-coordinates for the patch. If there are no uv
+    /// coordinates, then default ones at the corners of `[0, 1]^2` are
+    /// returned.
+    fn get_uvs(&self) -> [Point2f; 4] {
+        if self.mesh.uv.len() > 0 {
+            [
+                self.mesh.uv[self.vertex(0)],
+                self.mesh.uv[self.vertex(1)],
+                self.mesh.uv[self.vertex(2)],
+                self.mesh.uv[self.vertex(3)],
+            ]
+        } else {
+            [
+                Point2f::new(0.0, 0.0),
+                Point2f::new(1.0, 0.0),
+                Point2f::new(0.0, 1.0),
+                Point2f::new(1.0, 1.0),
+            ]
+        }
+    }
+
+    /// Returns `true` if the patch is a planar rectangle. Rectangles have
+    /// uniform area density in `(u, v)`.
+    pub fn is_rectangle(&self) -> bool {
+        let [p00, p10, p01, p11] = self.positions();
+        if p00 == p01 || p01 == p11 || p11 == p10 || p10 == p00 {
+            return false;
+        }
+
+        // Check if the vertices are coplanar.
+        let n = (p10 - p00).cross(&(p01 - p00)).normalize();
+        if (p11 - p00).normalize().dot(&n).abs() > 1e-5 {
+            return false;
+        }
+
+        // Planar vertices form a rectangle if they are equally far from
+        // their center.
+        let center = (p00 + p01 + p10 + p11) * 0.25;
+        let d2 = [
+            (p00 - center).length_squared(),
+            (p01 - center).length_squared(),
+            (p10 - center).length_squared(),
+            (p11 - center).length_squared(),
+        ];
+        d2[1..].iter().all(|d| (d - d2[0]).abs() / d2[0] <= 1e-4)
+    }
+
+    /// Returns the surface area. Rectangles are computed exactly and other
+    /// patches integrate `|dp/du x dp/dv|` with 4x4 point Gauss-Legendre
+    /// quadrature, which is accurate since the integrand is smooth.
+    fn compute_area(&self) -> Float {
+        let [p00, p10, p01, _] = self.positions();
+        if self.is_rectangle() {
+            return (p10 - p00).length() * (p01 - p00).length();
+        }
+
+        // Nodes and weights on [0, 1].
+        const NODES: [Float; 4] = [0.069431844, 0.33000948, 0.66999052, 0.930568156];
+        const WEIGHTS: [Float; 4] = [0.17392742, 0.32607258, 0.32607258, 0.17392742];
+        let mut area = 0.0;
+        for i in 0..4 {
+            for j in 0..4 {
+                let (_, dpdu, dpdv) = self.evaluate(&Point2f::new(NODES[i], NODES[j]));
+                area += WEIGHTS[i] * WEIGHTS[j] * dpdu.cross(&dpdv).length();
+            }
+        }
+        area
+    }
+
+    /// Returns the lengths of `dp/du x dp/dv` at the corners of the patch,
+    /// which are used to sample points approximately uniformly by area.
+    fn corner_weights(&self) -> [Float; 4] {
+        let [p00, p10, p01, p11] = self.positions();
+        [
+            (p10 - p00).cross(&(p01 - p00)).length(),
+            (p10 - p00).cross(&(p11 - p10)).length(),
+            (p01 - p00).cross(&(p11 - p01)).length(),
+            (p11 - p10).cross(&(p11 - p01)).length(),
+        ]
+    }
+
+    /// Returns the point and partial derivatives `dp/du` and `dp/dv` at the
+    /// given patch parameters.
+    ///
+    /// * `uv` - Patch parameters.
+    fn evaluate(&self, uv: &Point2f) -> (Point3f, Vector3f, Vector3f) {
+        let [p00, p10, p01, p11] = self.positions();
+        let pu0 = lerp(uv[1], p00, p01);
+        let pu1 = lerp(uv[1], p10, p11);
+        let p = lerp(uv[0], pu0, pu1);
+        let dpdu = pu1 - pu0;
+        let dpdv = lerp(uv[0], p01, p11) - lerp(uv[0], p00, p10);
+        (p, dpdu, dpdv)
+    }
+
+    /// Returns the conservative floating point error of points on the patch.
+    fn p_error(&self) -> Vector3f {
+        let p_abs_sum = self
+            .positions()
+            .iter()
+            .fold(Vector3f::default(), |s, p| s + Vector3f::from(*p).abs());
+        gamma(6) * p_abs_sum
+    }
+
+    /// Returns the patch parameters `(u, v)` and parametric distance of the
+    /// closest intersection of a ray with the patch.
+    ///
+    /// * `r` - The ray.
+    fn intersect_uv(&self, r: &Ray) -> Option<(Point2f, Float)> {
+        let [p00, p10, p01, p11] = self.positions();
+        let o = Vector3f::from(r.o);
+
+        // Find quadratic coefficients for the distance from the ray to
+        // u iso-lines.
+        let a = (p10 - p00).cross(&(p01 - p11)).dot(&r.d);
+        let c = (p00 - r.o).cross(&r.d).dot(&(p01 - p00));
+        let b = (p10 - r.o).cross(&r.d).dot(&(p11 - p10)) - (a + c);
+
+        // Solve quadratic for patch u intersections.
+        let (u1, u2) = solve_quadratic(a, b, c)?;
+
+        // Find epsilon to ensure that candidate t is greater than zero.
+        let eps = gamma(10)
+            * (o.abs().max_component()
+                + r.d.abs().max_component()
+                + Vector3f::from(p00).abs().max_component()
+                + Vector3f::from(p10).abs().max_component()
+                + Vector3f::from(p01).abs().max_component()
+                + Vector3f::from(p11).abs().max_component());
+
+        // Compute v and t for each u intersection and keep the closest.
+        let mut hit: Option<(Point2f, Float)> = None;
+        for (i, &u) in [u1, u2].iter().enumerate() {
+            if u < 0.0 || u > 1.0 || (i == 1 && u2 == u1) {
+                continue;
+            }
+
+            let uo = lerp(u, p00, p10);
+            let ud = lerp(u, p01, p11) - uo;
+            let delta_o = uo - r.o;
+            let perp = r.d.cross(&ud);
+            let p2 = perp.length_squared();
+
+            // Determinants of the matrices with columns `delta_o, d, perp`
+            // and `delta_o, ud, perp` are the numerators of v and t.
+            let v = delta_o.dot(&r.d.cross(&perp));
+            let t = delta_o.dot(&ud.cross(&perp));
+            if v < 0.0 || v > p2 || t <= p2 * eps {
+                continue;
+            }
+
+            let t = t / p2;
+            if t < r.t_max && hit.map_or(true, |(_, t_hit)| t < t_hit) {
+                hit = Some((Point2f::new(u, v / p2), t));
+            }
+        }
+        hit
+    }
+
+    /// Returns the surface interaction at the given patch parameters.
+    ///
+    /// * `uv`   - Patch parameters.
+    /// * `wo`   - The outgoing direction.
+    /// * `time` - Time of the interaction.
+    fn interaction<'a>(&self, uv: &Point2f, wo: Vector3f, time: Float) -> SurfaceInteraction<'a> {
+        let [p00, p10, p01, p11] = self.positions();
+        let (p, mut dpdu, mut dpdv) = self.evaluate(uv);
+
+        // Compute (s, t) texture coordinates and partial derivatives of
+        // (u, v) with respect to them.
+        let mut st = *uv;
+        let (mut duds, mut dudt, mut dvds, mut dvdt) = (1.0, 0.0, 0.0, 1.0);
+        if self.mesh.uv.len() > 0 {
+            let [uv00, uv10, uv01, uv11] = self.get_uvs();
+            st = lerp(uv[0], lerp(uv[1], uv00, uv01), lerp(uv[1], uv10, uv11));
+
+            let dstdu = lerp(uv[1], uv10, uv11) - lerp(uv[1], uv00, uv01);
+            let dstdv = lerp(uv[0], uv01, uv11) - lerp(uv[0], uv00, uv10);
+            let inv = |x: Float| if x.abs() < 1e-8 { 0.0 } else { 1.0 / x };
+            duds = inv(dstdu[0]);
+            dvds = inv(dstdv[0]);
+            dudt = inv(dstdu[1]);
+            dvdt = inv(dstdv[1]);
+
+            // Update dpdu and dpdv to be with respect to (s, t) keeping the
+            // orientation of the patch.
+            let dpds = dpdu * duds + dpdv * dvds;
+            let mut dpdt = dpdu * dudt + dpdv * dvdt;
+            if dpds.cross(&dpdt).length_squared() > 0.0 {
+                if dpdu.cross(&dpdv).dot(&dpds.cross(&dpdt)) < 0.0 {
+                    dpdt = -dpdt;
+                }
+                dpdu = dpds;
+                dpdv = dpdt;
+            }
+        }
+
+        // Compute dndu and dndv from the fundamental forms. Bilinear patches
+        // only have a mixed second derivative.
+        let d2pduv = (p00 - p01) + (p11 - p10);
+        let e1 = dpdu.dot(&dpdu);
+        let f1 = dpdu.dot(&dpdv);
+        let g1 = dpdv.dot(&dpdv);
+        let n = dpdu.cross(&dpdv).normalize();
+        let f2 = n.dot(&d2pduv);
+        let egf2 = e1 * g1 - f1 * f1;
+        let inv_egf2 = if egf2 == 0.0 { 0.0 } else { 1.0 / egf2 };
+        let dndu = Normal3f::from((f2 * f1) * inv_egf2 * dpdu + (-f2 * e1) * inv_egf2 * dpdv);
+        let dndv = Normal3f::from((-f2 * g1) * inv_egf2 * dpdu + (f2 * f1) * inv_egf2 * dpdv);
+        let (dndu, dndv) = (dndu * duds + dndv * dvds, dndu * dudt + dndv * dvdt);
+
+        let mut isect = SurfaceInteraction::new(
+            p,
+            self.p_error(),
+            st,
+            wo,
+            dpdu,
+            dpdv,
+            dndu,
+            dndv,
+            time,
+            Some(Arc::new(self.clone())),
+        );
+
+        if self.mesh.n.len() > 0 {
+            // Interpolate shading normals.
+            let [n00, n10, n01, n11] = [
+                self.mesh.n[self.vertex(0)],
+                self.mesh.n[self.vertex(1)],
+                self.mesh.n[self.vertex(2)],
+                self.mesh.n[self.vertex(3)],
+            ];
+            let ns = lerp(uv[0], lerp(uv[1], n00, n01), lerp(uv[1], n10, n11));
+            if ns.length_squared() > 0.0 {
+                let ns = ns.normalize();
+
+                // Compute shading tangents perpendicular to ns.
+                let mut ss = isect.dpdu.normalize();
+                let mut ts = ss.cross(&ns.into());
+                if ts.length_squared() > 0.0 {
+                    ts = ts.normalize();
+                    ss = ts.cross(&ns.into());
+                } else {
+                    coordinate_system(&ns.into(), &mut ss, &mut ts);
+                }
+                if self.data.reverse_orientation {
+                    ts = -ts;
+                }
+
+                let dndu = lerp(uv[1], n10, n11) - lerp(uv[1], n00, n01);
+                let dndv = lerp(uv[0], n01, n11) - lerp(uv[0], n00, n10);
+                let (dndu, dndv) = (dndu * duds + dndv * dvds, dndu * dudt + dndv * dvdt);
+                isect.set_shading_geometry(ss, ts, dndu, dndv, true);
+            }
+        }
+
+        isect
+    }
+
+    /// Returns `true` if the alpha mask cuts away the patch at an
+    /// intersection.
+    ///
+    /// * `uv` - Patch parameters of the intersection.
+    /// * `r`  - The ray.
+    fn alpha_masked(&self, uv: &Point2f, r: &Ray) -> bool {
+        match self.mesh.alpha_mask.as_ref() {
+            Some(alpha_mask) => alpha_mask.evaluate(&self.interaction(uv, -r.d, r.time)) == 0.0,
+            None => false,
+        }
+    }
+
+    /// Sample a point on the surface approximately uniformly by area. Returns
+    /// the point and the PDF with respect to surface area or `None` if the
+    /// patch is degenerate at the sampled point.
+    ///
+    /// * `u` - The random sample point.
+    pub fn sample(&self, u: &Point2f) -> Option<(Hit, Float)> {
+        // Rectangles are uniform in (u, v); other patches sample (u, v)
+        // proportional to the area at the corners.
+        let (uv, pdf) = if self.is_rectangle() {
+            (*u, 1.0)
+        } else {
+            let w = self.corner_weights();
+            let uv = sample_bilinear(u, &w);
+            (uv, bilinear_pdf(&uv, &w))
+        };
+
+        let (p, dpdu, dpdv) = self.evaluate(&uv);
+        let area = dpdu.cross(&dpdv).length();
+        if area == 0.0 {
+            return None;
+        }
+
+        let mut n = Normal3f::from(dpdu.cross(&dpdv).normalize());
+        if self.mesh.n.len() > 0 {
+            let [n00, n10, n01, n11] = [
+                self.mesh.n[self.vertex(0)],
+                self.mesh.n[self.vertex(1)],
+                self.mesh.n[self.vertex(2)],
+                self.mesh.n[self.vertex(3)],
+            ];
+            let ns = lerp(uv[0], lerp(uv[1], n00, n01), lerp(uv[1], n10, n11));
+            n = n.face_forward(&ns.into());
+        } else if self.data.reverse_orientation ^ self.data.transform_swaps_handedness {
+            n = -n;
+        }
+
+        let hit = Hit::new(p, 0.0, self.p_error(), Vector3f::default(), n, None);
+        Some((hit, pdf / area))
+    }
+
+    /// Returns the PDF with respect to surface area of sampling the point at
+    /// the given patch parameters with `sample()`.
+    ///
+    /// * `uv` - Patch parameters.
+    pub fn pdf(&self, uv: &Point2f) -> Float {
+        let (_, dpdu, dpdv) = self.evaluate(uv);
+        let area = dpdu.cross(&dpdv).length();
+        if area == 0.0 {
+            return 0.0;
+        }
+
+        let pdf = if self.is_rectangle() {
+            1.0
+        } else {
+            bilinear_pdf(uv, &self.corner_weights())
+        };
+        pdf / area
+    }
+}
+
+impl Shape for BilinearPatch {
+    /// Returns the underlying shape data.
+    fn get_data(&self) -> ShapeData {
+        self.data.clone()
+    }
+
+    /// Returns a bounding box in the shapes object space.
+    fn object_bound(&self) -> Bounds3f {
+        // We can unwrap safely because the factory methods guarantee world_to_object
+        // is passed.
+        let world_to_object = self.data.world_to_object.clone().unwrap();
+        self.positions().iter().fold(Bounds3f::empty(), |b, p| {
+            b.union(&world_to_object.transform_point(p))
+        })
+    }
+
+    /// Returns a bounding box in the world space.
+    fn world_bound(&self) -> Bounds3f {
+        self.positions()
+            .iter()
+            .fold(Bounds3f::empty(), |b, p| b.union(p))
+    }
+
+    /// Returns geometric details if a ray intersects the shape intersection.
+    /// If there is no intersection, `None` is returned.
+    ///
+    /// * `r`                  - The ray.
+    /// * `test_alpha_texture` - Perform alpha texture tests.
+    fn intersect<'a>(&self, r: &Ray, test_alpha_texture: bool) -> Option<Intersection<'a>> {
+        RAY_BLP_TESTS.add_fraction(0, 1);
+
+        let (uv, t) = self.intersect_uv(r)?;
+        if test_alpha_texture && self.alpha_masked(&uv, r) {
+            return None;
+        }
+
+        RAY_BLP_TESTS.add_fraction(1, 0);
+        Some(Intersection::new(t, self.interaction(&uv, -r.d, r.time)))
+    }
+
+    /// Returns `true` if a ray-shape intersection succeeds; otherwise `false`.
+    ///
+    /// * `r`                  - The ray.
+    /// * `test_alpha_texture` - Perform alpha texture tests.
+    fn intersect_p(&self, r: &Ray, test_alpha_texture: bool) -> bool {
+        RAY_BLP_TESTS.add_fraction(0, 1);
+
+        match self.intersect_uv(r) {
+            Some((uv, _)) if !(test_alpha_texture && self.alpha_masked(&uv, r)) => {
+                RAY_BLP_TESTS.add_fraction(1, 0);
+                true
+            }
+            _ => false,
+        }
+    }
+
+    /// Returns the surface area of the shape in object space.
+    fn area(&self) -> Float {
+        self.area
+    }
+}
+
+/// Solve the quadratic equation a * x ^ 2  + b * x + c = 0 and return the
+/// roots in increasing order. Unlike `Quadratic::solve()` this handles
+/// `a == 0`, which is the case for planar patches.
+///
+/// * `a` - Coefficient of x ^ 2 term.
+/// * `b` - Coefficient of x term.
+/// * `c` - Coefficient of constant term.
+fn solve_quadratic(a: Float, b: Float, c: Float) -> Option<(Float, Float)> {
+    if a == 0.0 {
+        if b == 0.0 {
+            return None;
+        }
+        let t = -c / b;
+        return Some((t, t));
+    }
+
+    // Find quadratic discriminant in double precision.
+    let discrim = b as f64 * b as f64 - 4.0 * a as f64 * c as f64;
+    if discrim < 0.0 {
+        return None;
+    }
+    let root_discrim = discrim.sqrt();
+
+    // Compute quadratic roots.
+    let q = if b < 0.0 {
+        -0.5 * (b as f64 - root_discrim)
+    } else {
+        -0.5 * (b as f64 + root_discrim)
+    };
+    let t0 = (q / a as f64) as Float;
+    let t1 = if q != 0.0 {
+        (c as f64 / q) as Float
+    } else {
+        t0
+    };
+    if t0 > t1 {
+        Some((t1, t0))
+    } else {
+        Some((t0, t1))
+    }
+}
+
+// ----------------------------------------------------------------------------
+// Tests
+// ----------------------------------------------------------------------------
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    /// Returns a patch with the given vertices.
+    ///
+    /// * `p` - Vertices `p00, p10, p01, p11`.
+    fn patch(p: [Point3f; 4]) -> BilinearPatch {
+        let identity = Arc::new(Transform::default());
+        let mesh = BilinearPatchMesh::new(
+            identity.clone(),
+            false,
+            vec![0, 1, 2, 3],
+            p.to_vec(),
+            vec![],
+            vec![],
+            None,
+            None,
+            vec![],
+        );
+        BilinearPatch::new(identity.clone(), identity, false, Arc::new(mesh), 0)
+    }
+
+    /// Returns a 2x3 rectangle tilted out of the coordinate planes.
+    fn rectangle() -> BilinearPatch {
+        let t = Transform::rotate_axis(30.0, &Vector3f::new(1.0, 2.0, 3.0).normalize());
+        let p = |x: Float, y: Float| t.transform_point(&Point3f::new(x, y, 1.0));
+        patch([p(0.0, 0.0), p(2.0, 0.0), p(0.0, 3.0), p(2.0, 3.0)])
+    }
+
+    /// Returns a parallelogram, for which the quadratic for u is linear.
+    fn parallelogram() -> BilinearPatch {
+        patch([
+            Point3f::new(0.0, 0.0, 0.0),
+            Point3f::new(2.0, 0.5, 0.0),
+            Point3f::new(0.5, 1.0, 1.0),
+            Point3f::new(2.5, 1.5, 1.0),
+        ])
+    }
+
+    /// Returns the saddle `z = x * y` over the unit square.
+    fn saddle() -> BilinearPatch {
+        patch([
+            Point3f::new(0.0, 0.0, 0.0),
+            Point3f::new(1.0, 0.0, 0.0),
+            Point3f::new(0.0, 1.0, 0.0),
+            Point3f::new(1.0, 1.0, 1.0),
+        ])
+    }
+
+    /// Returns a grid of `n` x `n` points in (0, 1)^2.
+    fn grid(n: usize) -> Vec<Point2f> {
+        (0..n * n)
+            .map(|i| {
+                Point2f::new(
+                    ((i % n) as Float + 0.5) / n as Float,
+                    ((i / n) as Float + 0.5) / n as Float,
+                )
+            })
+            .collect()
+    }
+
+    /// Asserts that a ray towards the point at `uv` hits the patch there.
+    ///
+    /// * `patch` - The patch.
+    /// * `uv`    - Patch parameters of the point.
+    /// * `d`     - Ray direction.
+    fn assert_hits(patch: &BilinearPatch, uv: &Point2f, d: Vector3f) {
+        let (p, _, _) = patch.evaluate(uv);
+        let r = Ray::new(p - d * 2.0, d, INFINITY, 0.0, None);
+        let (hit_uv, t) = patch.intersect_uv(&r).expect("missed the patch");
+        assert!((hit_uv - *uv).length() < 1e-3, "{:?} != {:?}", hit_uv, uv);
+        assert!((t - 2.0).abs() < 1e-3, "{} != 2", t);
+    }
+
+    #[test]
+    fn planar_patches_have_a_linear_quadratic() {
+        let patch = parallelogram();
+        let [p00, p10, p01, p11] = patch.positions();
+        let d = Vector3f::new(0.3, -0.4, 1.0);
+        assert_eq!((p10 - p00).cross(&(p01 - p11)).dot(&d), 0.0);
+
+        for uv in grid(8).iter() {
+            assert_hits(&patch, uv, d);
+            assert_hits(&rectangle(), uv, Vector3f::new(-0.2, 0.1, -1.0));
+        }
+    }
+
+    #[test]
+    fn twisted_patches_return_the_closest_hit() {
+        let patch = saddle();
+
+        // Vertical rays cross the saddle once.
+        for uv in grid(8).iter() {
+            assert_hits(&patch, uv, Vector3f::new(0.0, 0.0, -1.0));
+        }
+
+        // The ray y = 1 - x, z = 0.25 - dz crosses the saddle z = x * y
+        // where (x - 0.5)^2 = dz and the first crossing is the closest.
+        let r = Ray::new(
+            Point3f::new(0.0, 1.0, 0.24),
+            Vector3f::new(1.0, -1.0, 0.0),
+            INFINITY,
+            0.0,
+            None,
+        );
+        let (uv, t) = patch.intersect_uv(&r).unwrap();
+        assert!((t - 0.4).abs() < 1e-4, "{}", t);
+        assert!((uv - Point2f::new(0.4, 0.6)).length() < 1e-4, "{:?}", uv);
+
+        // A ray starting between the crossings hits the second one.
+        let r = Ray::new(
+            Point3f::new(0.5, 0.5, 0.24),
+            Vector3f::new(1.0, -1.0, 0.0),
+            INFINITY,
+            0.0,
+            None,
+        );
+        let (uv, t) = patch.intersect_uv(&r).unwrap();
+        assert!((t - 0.1).abs() < 1e-4, "{}", t);
+        assert!((uv - Point2f::new(0.6, 0.4)).length() < 1e-4, "{:?}", uv);
+    }
+
+    #[test]
+    fn grazing_rays() {
+        let patch = saddle();
+
+        // Rays just above the saddle's highest point along y = 1 - x miss.
+        let r = Ray::new(
+            Point3f::new(0.0, 1.0, 0.26),
+            Vector3f::new(1.0, -1.0, 0.0),
+            INFINITY,
+            0.0,
+            None,
+        );
+        assert!(patch.intersect_uv(&r).is_none());
+
+        // A ray touching the saddle hits at the tangent point if at all.
+        let r = Ray::new(
+            Point3f::new(0.0, 1.0, 0.25),
+            Vector3f::new(1.0, -1.0, 0.0),
+            INFINITY,
+            0.0,
+            None,
+        );
+        if let Some((uv, t)) = patch.intersect_uv(&r) {
+            assert!((uv - Point2f::new(0.5, 0.5)).length() < 1e-2, "{:?}", uv);
+            assert!((t - 0.5).abs() < 1e-2, "{}", t);
+        }
+
+        // Rays parallel to a planar patch miss it.
+        let patch = parallelogram();
+        let [p00, p10, _, _] = patch.positions();
+        let d = p10 - p00;
+        let r = Ray::new(
+            p00 - d + Vector3f::new(0.0, 0.0, 0.01),
+            d,
+            INFINITY,
+            0.0,
+            None,
+        );
+        assert!(patch.intersect_uv(&r).is_none());
+        let r = Ray::new(
+            p00 - d + Vector3f::new(0.0, 0.0, -0.01),
+            d,
+            INFINITY,
+            0.0,
+            None,
+        );
+        assert!(patch.intersect_uv(&r).is_none());
+    }
+
+    #[test]
+    fn area_of_rectangles_is_exact() {
+        let patch = rectangle();
+        assert!(patch.is_rectangle());
+        assert!((patch.area() - 6.0).abs() < 1e-5, "{}", patch.area());
+
+        // Parallelograms aren't rectangles but have a constant integrand.
+        let patch = parallelogram();
+        assert!(!patch.is_rectangle());
+        let [p00, p10, p01, _] = patch.positions();
+        let area = (p10 - p00).cross(&(p01 - p00)).length();
+        assert!((patch.area() - area).abs() < 1e-5 * area);
+    }
+
+    #[test]
+    fn area_of_twisted_patches() {
+        let patch = saddle();
+        let area: Float = grid(256)
+            .iter()
+            .map(|uv| {
+                let (_, dpdu, dpdv) = patch.evaluate(uv);
+                dpdu.cross(&dpdv).length()
+            })
+            .sum::<Float>()
+            / (256.0 * 256.0);
+        assert!(
+            (patch.area() - area).abs() < 1e-4,
+            "{} != {}",
+            patch.area(),
+            area
+        );
+    }
+
+    #[test]
+    fn sample_matches_pdf() {
+        for patch in [rectangle(), parallelogram(), saddle()].iter() {
+            let w = patch.corner_weights();
+            let mut inv_pdf_sum = 0.0;
+            let samples = grid(64);
+            for u in samples.iter() {
+                let (hit, pdf) = patch.sample(u).unwrap();
+                let uv = if patch.is_rectangle() {
+                    *u
+                } else {
+                    sample_bilinear(u, &w)
+                };
+                let (p, _, _) = patch.evaluate(&uv);
+                assert!((hit.p - p).length() < 1e-5);
+                assert!((pdf - patch.pdf(&uv)).abs() <= 1e-5 * pdf);
+                inv_pdf_sum += 1.0 / pdf;
+            }
+
+            // The expected value of 1 / pdf is the area.
+            let area = inv_pdf_sum / samples.len() as Float;
+            assert!(
+                (area - patch.area()).abs() < 1e-3 * patch.area(),
+                "{}",
+                area
+            );
+        }
+    }
+
+    #[test]
+    fn pdf_integrates_to_one() {
+        for patch in [rectangle(), parallelogram(), saddle()].iter() {
+            let n = 256;
+            let integral: Float = grid(n)
+                .iter()
+                .map(|uv| {
+                    let (_, dpdu, dpdv) = patch.evaluate(uv);
+                    patch.pdf(uv) * dpdu.cross(&dpdv).length()
+                })
+                .sum::<Float>()
+                / (n * n) as Float;
+            assert!((integral - 1.0).abs() < 1e-3, "{}", integral);
+        }
+    }
+}
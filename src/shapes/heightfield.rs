@@ -0,0 +1,567 @@
+//! Heightfields
+
+#![allow(dead_code)]
+use super::{Triangle, TriangleMesh};
+use crate::core::geometry::*;
+use crate::core::paramset::*;
+use crate::core::pbrt::*;
+use crate::core::stats::*;
+use std::sync::Arc;
+
+/// Number of ray-heightfield tests and hits.
+static RAY_HEIGHTFIELD_TESTS: Stat =
+    Stat::percent("Intersections/Ray-heightfield intersection tests");
+
+/// Padding of the cell range in object space so that rounding in the
+/// transform doesn't skip neighbouring cells.
+const XY_EPSILON: Float = 1e-4;
+
+/// Heightfield over the unit square in the xy-plane of object space with
+/// `nu * nv` height samples. Each grid cell is split into two triangles.
+/// Rays walk the cells they pass over with a 2D-DDA and only test the
+/// triangles of cells whose height range they overlap.
+#[derive(Clone)]
+pub struct Heightfield {
+    /// Common shape data.
+    pub data: ShapeData,
+
+    /// Number of samples in the x-direction.
+    pub nu: usize,
+
+    /// Number of samples in the y-direction.
+    pub nv: usize,
+
+    /// Heights in object space. The height at (x, y) is `z[y * nu + x]`.
+    pub z: Vec<Float>,
+
+    /// World space triangles. The two triangles of cell (x, y) are at
+    /// `2 * (y * (nu - 1) + x)` and the one after it.
+    pub mesh: Arc<TriangleMesh>,
+
+    /// Bounds in object space.
+    bounds: Bounds3f,
+}
+
+impl Heightfield {
+    /// Create a new heightfield.
+    ///
+    /// * `object_to_world`     - The object to world transfomation.
+    /// * `world_to_object`     - The world to object transfomation.
+    /// * `reverse_orientation` - Indicates whether their surface normal directions
+    ///                           should be reversed from the default.
+    /// * `nu`                  - Number of samples in the x-direction (at least 2).
+    /// * `nv`                  - Number of samples in the y-direction (at least 2).
+    /// * `z`                   - The `nu * nv` heights.
+    pub fn new(
+        object_to_world: ArcTransform,
+        world_to_object: ArcTransform,
+        reverse_orientation: bool,
+        nu: usize,
+        nv: usize,
+        z: Vec<Float>,
+    ) -> Self {
+        assert!(nu > 1 && nv > 1 && z.len() == nu * nv);
+
+        // Vertex (x, y) is at (x / (nu - 1), y / (nv - 1)) in both the xy-plane
+        // and uv-space.
+        let mut p = Vec::with_capacity(nu * nv);
+        let mut uv = Vec::with_capacity(nu * nv);
+        for y in 0..nv {
+            for x in 0..nu {
+                let u = x as Float / (nu - 1) as Float;
+                let v = y as Float / (nv - 1) as Float;
+                p.push(Point3f::new(u, v, z[y * nu + x]));
+                uv.push(Point2f::new(u, v));
+            }
+        }
+
+        let mut indices = Vec::with_capacity(6 * (nu - 1) * (nv - 1));
+        let vertex = |x: usize, y: usize| y * nu + x;
+        for y in 0..nv - 1 {
+            for x in 0..nu - 1 {
+                indices.extend_from_slice(&[
+                    vertex(x, y),
+                    vertex(x + 1, y),
+                    vertex(x + 1, y + 1),
+                    vertex(x, y),
+                    vertex(x + 1, y + 1),
+                    vertex(x, y + 1),
+                ]);
+            }
+        }
+
+        let mesh = TriangleMesh::new(
+            object_to_world.clone(),
+            reverse_orientation,
+            indices,
+            p,
+            vec![],
+            vec![],
+            uv,
+            None,
+            None,
+            vec![],
+        );
+
+        let (z_min, z_max) = z.iter().fold((INFINITY, -INFINITY), |(lo, hi), &h| {
+            (min(lo, h), max(hi, h))
+        });
+
+        Self {
+            data: ShapeData::new(
+                object_to_world.clone(),
+                Some(world_to_object.clone()),
+                reverse_orientation,
+            ),
+            nu,
+            nv,
+            z,
+            mesh: Arc::new(mesh),
+            bounds: Bounds3f::new(Point3f::new(0.0, 0.0, z_min), Point3f::new(1.0, 1.0, z_max)),
+        }
+    }
+
+    /// Create a heightfield from given parameter set, object to world transform,
+    /// world to object transform and whether or not surface normal orientation
+    /// is reversed. Returns an empty list if the parameters are invalid.
+    ///
+    /// * `p` - A tuple containing the parameter set, object to world transform,
+    ///         world to object transform and whether or not surface normal
+    ///         orientation is reversed.
+    pub fn from_props(p: (&ParamSet, ArcTransform, ArcTransform, bool)) -> Vec<ArcShape> {
+        let (params, o2w, w2o, reverse_orientation) = p;
+
+        let nu = params.find_one_int("nu", -1);
+        let nv = params.find_one_int("nv", -1);
+        let z = params.find_float("Pz");
+        if nu < 2 || nv < 2 {
+            error!("Heightfield requires 'nu' and 'nv' of at least 2.");
+            return vec![];
+        }
+        if z.len() != (nu * nv) as usize {
+            error!(
+                "Heightfield has {} 'Pz' values; expected nu * nv = {}.",
+                z.len(),
+                nu * nv
+            );
+            return vec![];
+        }
+
+        vec![Arc::new(Self::new(
+            o2w.clone(),
+            w2o.clone(),
+            reverse_orientation,
+            nu as usize,
+            nv as usize,
+            z,
+        ))]
+    }
+
+    /// Returns the triangle at the given index in the mesh.
+    ///
+    /// * `index` - The triangle index.
+    fn triangle(&self, index: usize) -> Triangle {
+        Triangle::new(
+            self.data.object_to_world.clone(),
+            self.data.world_to_object.clone().unwrap(),
+            self.data.reverse_orientation,
+            self.mesh.clone(),
+            index,
+        )
+    }
+
+    /// Returns the minimum and maximum height of a cell.
+    ///
+    /// * `x` - The cell index in the x-direction.
+    /// * `y` - The cell index in the y-direction.
+    fn cell_heights(&self, x: usize, y: usize) -> (Float, Float) {
+        let i = y * self.nu + x;
+        let h = [
+            self.z[i],
+            self.z[i + 1],
+            self.z[i + self.nu],
+            self.z[i + self.nu + 1],
+        ];
+        (
+            min(min(h[0], h[1]), min(h[2], h[3])),
+            max(max(h[0], h[1]), max(h[2], h[3])),
+        )
+    }
+
+    /// Walks the cells a ray passes over in front to back order and calls
+    /// `visit` with the cell index and the ray parameter where the ray leaves
+    /// the cell. Cells the ray passes within rounding distance of are visited
+    /// with the same exit parameter. Traversal stops after a step in which
+    /// `visit` returned `true`.
+    ///
+    /// * `r`     - The ray in world space.
+    /// * `visit` - The function to call for each cell the ray may hit.
+    fn traverse<F>(&self, r: &Ray, mut visit: F)
+    where
+        F: FnMut(usize, Float) -> bool,
+    {
+        // Transform the ray to object space without offsetting its origin so
+        // ray parameters are the same in both spaces.
+        let world_to_object = self.data.world_to_object.clone().unwrap();
+        let o = world_to_object.transform_point(&r.o);
+        let d = world_to_object.transform_vector(&r.d);
+        let ray = Ray::new(o, d, r.t_max, r.time, None);
+
+        // Pad the bounds and height ranges so that rounding in the transform
+        // doesn't skip cells the world space triangles are hit in.
+        let eps = 1e-4 * max(1.0, max(abs(self.bounds.p_min.z), abs(self.bounds.p_max.z)));
+        let (t0, t1) = match self.bounds.expand(eps).intersect_p(&ray) {
+            Some(t) => t,
+            None => return,
+        };
+
+        let n_cells = [self.nu - 1, self.nv - 1];
+        let p = ray.at(t0);
+        let mut cell = [0_isize; 2];
+        let mut next_crossing = [INFINITY; 2];
+        let mut delta_t = [0.0; 2];
+        let mut step = [0_isize; 2];
+        let mut out = [0_isize; 2];
+        for axis in 0..2 {
+            let n = n_cells[axis] as Float;
+            let c = clamp(
+                (p[axis] * n).floor() as isize,
+                0,
+                n_cells[axis] as isize - 1,
+            );
+            cell[axis] = c;
+            if d[axis] > 0.0 {
+                next_crossing[axis] = t0 + ((c + 1) as Float / n - p[axis]) / d[axis];
+                delta_t[axis] = 1.0 / (n * d[axis]);
+                step[axis] = 1;
+                out[axis] = n_cells[axis] as isize;
+            } else if d[axis] < 0.0 {
+                next_crossing[axis] = t0 + (c as Float / n - p[axis]) / d[axis];
+                delta_t[axis] = -1.0 / (n * d[axis]);
+                step[axis] = -1;
+                out[axis] = -1;
+            }
+        }
+
+        let mut t_enter = t0;
+        loop {
+            let axis = if next_crossing[0] < next_crossing[1] {
+                0
+            } else {
+                1
+            };
+            let t_exit = min(next_crossing[axis], t1);
+
+            // Visit the cells the padded segment of the ray overlaps so that
+            // rays along grid lines find hits in the neighbouring cells too.
+            let mut lo = [0_usize; 2];
+            let mut hi = [0_usize; 2];
+            for a in 0..2 {
+                let (p0, p1) = (o[a] + d[a] * t_enter, o[a] + d[a] * t_exit);
+                let n = n_cells[a] as Float;
+                let last = n_cells[a] as isize - 1;
+                lo[a] = clamp(((min(p0, p1) - XY_EPSILON) * n).floor() as isize, 0, last) as usize;
+                hi[a] = clamp(((max(p0, p1) + XY_EPSILON) * n).floor() as isize, 0, last) as usize;
+            }
+
+            // Skip cells whose height range the ray doesn't overlap.
+            let (z0, z1) = (o.z + d.z * t_enter, o.z + d.z * t_exit);
+            let mut done = false;
+            for y in lo[1]..=hi[1] {
+                for x in lo[0]..=hi[0] {
+                    let (h_min, h_max) = self.cell_heights(x, y);
+                    if min(z0, z1) <= h_max + eps && max(z0, z1) >= h_min - eps {
+                        done |= visit(y * n_cells[0] + x, t_exit);
+                    }
+                }
+            }
+            if done {
+                return;
+            }
+
+            // Step to the next cell.
+            if next_crossing[axis] >= t1 {
+                return;
+            }
+            cell[axis] += step[axis];
+            if cell[axis] == out[axis] {
+                return;
+            }
+            t_enter = next_crossing[axis];
+            next_crossing[axis] += delta_t[axis];
+        }
+    }
+}
+
+impl Shape for Heightfield {
+    /// Returns the underlying shape data.
+    fn get_data(&self) -> ShapeData {
+        self.data.clone()
+    }
+
+    /// Returns a bounding box in the shapes object space.
+    fn object_bound(&self) -> Bounds3f {
+        self.bounds
+    }
+
+    /// Returns a bounding box in the world space.
+    fn world_bound(&self) -> Bounds3f {
+        self.mesh
+            .p
+            .iter()
+            .fold(Bounds3f::empty(), |b, p| b.union(p))
+    }
+
+    /// Returns geometric details if a ray intersects the shape intersection.
+    /// If there is no intersection, `None` is returned.
+    ///
+    /// * `r`                  - The ray.
+    /// * `test_alpha_texture` - Perform alpha texture tests.
+    fn intersect<'a>(&self, r: &Ray, test_alpha_texture: bool) -> Option<Intersection<'a>> {
+        RAY_HEIGHTFIELD_TESTS.add_fraction(0, 1);
+
+        let mut ray = r.clone();
+        let mut hit: Option<Intersection<'a>> = None;
+        self.traverse(r, |cell, t_exit| {
+            for i in 0..2 {
+                if let Some(isect) = self
+                    .triangle(2 * cell + i)
+                    .intersect(&ray, test_alpha_texture)
+                {
+                    ray.t_max = isect.t;
+                    hit = Some(isect);
+                }
+            }
+
+            // Cells are visited front to back so a hit inside this cell is
+            // the closest.
+            hit.is_some() && ray.t_max <= t_exit
+        });
+
+        if hit.is_some() {
+            RAY_HEIGHTFIELD_TESTS.add_fraction(1, 0);
+        }
+        hit
+    }
+
+    /// Returns `true` if a ray-shape intersection succeeds; otherwise `false`.
+    ///
+    /// * `r`                  - The ray.
+    /// * `test_alpha_texture` - Perform alpha texture tests.
+    fn intersect_p(&self, r: &Ray, test_alpha_texture: bool) -> bool {
+        RAY_HEIGHTFIELD_TESTS.add_fraction(0, 1);
+
+        let mut hit = false;
+        self.traverse(r, |cell, _t_exit| {
+            hit = hit
+                || (0..2).any(|i| {
+                    self.triangle(2 * cell + i)
+                        .intersect_p(r, test_alpha_texture)
+                });
+            hit
+        });
+
+        if hit {
+            RAY_HEIGHTFIELD_TESTS.add_fraction(1, 0);
+        }
+        hit
+    }
+
+    /// Returns the surface area of the triangles in world space.
+    fn area(&self) -> Float {
+        (0..self.mesh.num_triangles)
+            .map(|i| self.triangle(i).area())
+            .sum()
+    }
+}
+
+// ----------------------------------------------------------------------------
+// Tests
+// ----------------------------------------------------------------------------
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use rand::{Rng, SeedableRng};
+    use rand_pcg::Pcg32;
+
+    /// Number of samples in the x-direction.
+    const NU: usize = 9;
+
+    /// Number of samples in the y-direction.
+    const NV: usize = 7;
+
+    /// Returns a heightfield with random heights in [0, 1] placed in world
+    /// space with a non-uniform scale and a rotation about z.
+    fn heightfield() -> Heightfield {
+        let mut rng = Pcg32::seed_from_u64(1);
+        let z: Vec<Float> = (0..NU * NV).map(|_| rng.gen_range(0.0..1.0)).collect();
+        let o2w = Arc::new(
+            Transform::translate(&Vector3f::new(1.0, -2.0, 3.0))
+                * Transform::rotate_z(30.0)
+                * Transform::scale(4.0, 3.0, 0.5),
+        );
+        let w2o = Arc::new(o2w.inverse());
+        Heightfield::new(o2w, w2o, false, NU, NV, z)
+    }
+
+    /// Returns a ray from and towards points given in object space.
+    ///
+    /// * `hf` - The heightfield.
+    /// * `o`  - Ray origin in object space.
+    /// * `d`  - Ray direction in object space.
+    fn ray(hf: &Heightfield, o: Point3f, d: Vector3f) -> Ray {
+        let o2w = &hf.data.object_to_world;
+        Ray::new(
+            o2w.transform_point(&o),
+            o2w.transform_vector(&d),
+            INFINITY,
+            0.0,
+            None,
+        )
+    }
+
+    /// Returns the closest hit and whether any triangle is hit by testing
+    /// every triangle.
+    ///
+    /// * `hf` - The heightfield.
+    /// * `r`  - The ray.
+    fn brute_force(hf: &Heightfield, r: &Ray) -> (Option<Float>, bool) {
+        let mut ray = r.clone();
+        let mut t_hit = None;
+        for i in 0..hf.mesh.num_triangles {
+            if let Some(isect) = hf.triangle(i).intersect(&ray, false) {
+                ray.t_max = isect.t;
+                t_hit = Some(isect.t);
+            }
+        }
+        let any = (0..hf.mesh.num_triangles).any(|i| hf.triangle(i).intersect_p(r, false));
+        (t_hit, any)
+    }
+
+    /// Asserts that the DDA finds the same hits as brute force and returns
+    /// whether the ray hit.
+    ///
+    /// * `hf` - The heightfield.
+    /// * `r`  - The ray.
+    fn assert_matches_brute_force(hf: &Heightfield, r: &Ray) -> bool {
+        let (t_hit, any) = brute_force(hf, r);
+        let t = hf.intersect(r, false).map(|isect| isect.t);
+        match (t, t_hit) {
+            (Some(t), Some(t_hit)) => assert!(
+                (t - t_hit).abs() <= 1e-5 * t_hit,
+                "{:?}: {} != {}",
+                r,
+                t,
+                t_hit
+            ),
+            _ => assert_eq!(t, t_hit, "{:?}", r),
+        }
+        assert_eq!(hf.intersect_p(r, false), any, "{:?}", r);
+        any
+    }
+
+    #[test]
+    fn random_rays_match_brute_force() {
+        let hf = heightfield();
+        let mut rng = Pcg32::seed_from_u64(2);
+        let mut n_hits = 0;
+        for _ in 0..2000 {
+            let o = Point3f::new(
+                rng.gen_range(-0.5..1.5),
+                rng.gen_range(-0.5..1.5),
+                rng.gen_range(-1.0..2.0),
+            );
+            let d = Vector3f::new(
+                rng.gen_range(-1.0..1.0),
+                rng.gen_range(-1.0..1.0),
+                rng.gen_range(-1.0..1.0),
+            );
+            if assert_matches_brute_force(&hf, &ray(&hf, o, d)) {
+                n_hits += 1;
+            }
+        }
+        assert!(n_hits > 200);
+    }
+
+    #[test]
+    fn axis_parallel_rays_match_brute_force() {
+        let hf = heightfield();
+        let mut rng = Pcg32::seed_from_u64(3);
+        for _ in 0..500 {
+            let c = rng.gen_range(0.0..1.0);
+            let z = rng.gen_range(-0.2..1.2);
+            let dz = rng.gen_range(-0.5..0.5);
+            for &(o, d) in [
+                (Point3f::new(-0.5, c, z), Vector3f::new(1.0, 0.0, dz)),
+                (Point3f::new(1.5, c, z), Vector3f::new(-1.0, 0.0, dz)),
+                (Point3f::new(c, -0.5, z), Vector3f::new(0.0, 1.0, dz)),
+                (Point3f::new(c, 1.5, z), Vector3f::new(0.0, -1.0, dz)),
+            ]
+            .iter()
+            {
+                assert_matches_brute_force(&hf, &ray(&hf, o, d));
+            }
+        }
+    }
+
+    #[test]
+    fn vertical_rays_match_brute_force() {
+        let hf = heightfield();
+        let mut rng = Pcg32::seed_from_u64(4);
+        let mut n_hits = 0;
+        for i in 0..500 {
+            // Every other ray is on a grid line.
+            let mut x = rng.gen_range(0.0..1.0);
+            let y = rng.gen_range(0.0..1.0);
+            if i % 2 == 0 {
+                x = (i % NU) as Float / (NU - 1) as Float;
+            }
+            for &(z, dz) in [(2.0, -1.0), (-1.0, 1.0)].iter() {
+                let r = ray(&hf, Point3f::new(x, y, z), Vector3f::new(0.0, 0.0, dz));
+                if assert_matches_brute_force(&hf, &r) {
+                    n_hits += 1;
+                }
+            }
+        }
+        assert!(n_hits > 900);
+    }
+
+    #[test]
+    fn corner_grazing_rays_match_brute_force() {
+        let hf = heightfield();
+        let (nx, ny) = ((NU - 1) as Float, (NV - 1) as Float);
+        for x in 0..NU {
+            for y in 0..NV {
+                // Rays through the grid vertex at (x, y) along the cell
+                // diagonals and along the grid lines.
+                let p = Point3f::new(x as Float / nx, y as Float / ny, 0.5);
+                for &(dx, dy) in [
+                    (1.0 / nx, 1.0 / ny),
+                    (1.0 / nx, -1.0 / ny),
+                    (1.0, 0.0),
+                    (0.0, 1.0),
+                ]
+                .iter()
+                {
+                    for &dz in [-0.3, 0.0, 0.3].iter() {
+                        let d = Vector3f::new(dx, dy, dz);
+                        assert_matches_brute_force(&hf, &ray(&hf, p - d * 3.0, d));
+                        assert_matches_brute_force(&hf, &ray(&hf, p + d * 3.0, -d));
+                    }
+                }
+            }
+        }
+    }
+
+    #[test]
+    fn area_is_in_world_space() {
+        // A flat heightfield scaled by 4 x 3 has area 12.
+        let hf = {
+            let o2w = Arc::new(Transform::scale(4.0, 3.0, 0.5));
+            let w2o = Arc::new(o2w.inverse());
+            Heightfield::new(o2w, w2o, false, NU, NV, vec![0.25; NU * NV])
+        };
+        assert!((hf.area() - 12.0).abs() < 1e-4, "{}", hf.area());
+    }
+}
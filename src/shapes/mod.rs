@@ -1,22 +1,29 @@
 //! Geometry
 
+mod bilinear;
 mod cone;
 mod curve;
 mod cylinder;
 mod disk;
+mod heightfield;
 mod hyperboloid;
 mod loopsubdiv;
+mod nurbs;
 mod paraboloid;
+mod plymesh;
 mod sphere;
 mod triangle;
 
 // Re-export
+pub use bilinear::*;
 pub use cone::*;
 pub use curve::*;
 pub use cylinder::*;
 pub use disk::*;
+pub use heightfield::*;
 pub use hyperboloid::*;
 pub use loopsubdiv::*;
+pub use nurbs::*;
 pub use paraboloid::*;
 pub use sphere::*;
 pub use triangle::*;
@@ -0,0 +1,778 @@
+//! NURBS surfaces
+
+#![allow(dead_code)]
+use super::{get_alpha_texture, TriangleMesh};
+use crate::core::geometry::*;
+use crate::core::paramset::*;
+use crate::core::pbrt::*;
+use crate::core::texture::*;
+use std::collections::HashMap;
+
+/// Maximum distance between the surface and its tessellation relative to the
+/// diagonal of the control point bounds.
+const MAX_CHORD_ERROR: Float = 1e-3;
+
+/// Maximum number of times a knot span is halved during tessellation.
+const MAX_SPAN_DEPTH: usize = 8;
+
+/// Maximum number of vertices in the tessellation of a surface. Knots are
+/// always kept so surfaces with more knot spans than this can exceed it.
+const MAX_VERTICES: usize = 1 << 20;
+
+/// Control point in homogeneous coordinates `(w * x, w * y, w * z, w)`.
+type Homogeneous = [Float; 4];
+
+/// Non-uniform rational B-spline surface. The surface is evaluated with de
+/// Boor's algorithm and tessellated into triangles for rendering.
+#[derive(Clone)]
+pub struct Nurbs {
+    /// Number of control points in the u-direction.
+    pub nu: usize,
+
+    /// Number of control points in the v-direction.
+    pub nv: usize,
+
+    /// Order (degree + 1) in the u-direction.
+    pub u_order: usize,
+
+    /// Order (degree + 1) in the v-direction.
+    pub v_order: usize,
+
+    /// Knot vector in the u-direction with `nu + u_order` values.
+    pub u_knots: Vec<Float>,
+
+    /// Knot vector in the v-direction with `nv + v_order` values.
+    pub v_knots: Vec<Float>,
+
+    /// Parametric range in the u-direction.
+    pub u_range: [Float; 2],
+
+    /// Parametric range in the v-direction.
+    pub v_range: [Float; 2],
+
+    /// Control points. The control point (i, j) is `pw[j * nu + i]`.
+    pub pw: Vec<Homogeneous>,
+}
+
+impl Nurbs {
+    /// Returns the position and partial derivatives of the surface at the
+    /// given parametric coordinates.
+    ///
+    /// * `u` - The u-coordinate.
+    /// * `v` - The v-coordinate.
+    pub fn evaluate(&self, u: Float, v: Float) -> (Point3f, Vector3f, Vector3f) {
+        let (nu, nv) = (self.nu, self.nv);
+
+        // Evaluate the v-curves through the control point columns that
+        // influence u, then the u-curve through the resulting points.
+        let u_first = knot_offset(&self.u_knots, self.u_order, nu, u) + 1 - self.u_order;
+        let iso: Vec<Homogeneous> = (0..self.u_order)
+            .map(|i| {
+                let column = |j: usize| self.pw[j * nu + u_first + i];
+                evaluate_curve(self.v_order, &self.v_knots, nv, v, column).0
+            })
+            .collect();
+        let (p, dpdu) = evaluate_curve(self.u_order, &self.u_knots, nu, u, |i| iso[i - u_first]);
+
+        // Do the same in the other order for the v derivative.
+        let v_first = knot_offset(&self.v_knots, self.v_order, nv, v) + 1 - self.v_order;
+        let iso: Vec<Homogeneous> = (0..self.v_order)
+            .map(|j| {
+                let row = |i: usize| self.pw[(v_first + j) * nu + i];
+                evaluate_curve(self.u_order, &self.u_knots, nu, u, row).0
+            })
+            .collect();
+        let (_, dpdv) = evaluate_curve(self.v_order, &self.v_knots, nv, v, |j| iso[j - v_first]);
+
+        (project(&p), dpdu, dpdv)
+    }
+
+    /// Returns the surface normal at the given parametric coordinates. Where
+    /// the partial derivatives are degenerate, e.g. at poles, the normal is
+    /// taken from a point slightly towards the center of the surface.
+    ///
+    /// * `u` - The u-coordinate.
+    /// * `v` - The v-coordinate.
+    fn normal(&self, u: Float, v: Float) -> Normal3f {
+        // Treat the cross product as degenerate when it is at the level of
+        // rounding errors in the derivatives.
+        let cross = |dpdu: Vector3f, dpdv: Vector3f| {
+            let n = dpdu.cross(&dpdv);
+            let scale = max(dpdu.length_squared(), dpdv.length_squared());
+            if n.length_squared() > 1e-10 * scale * scale {
+                Some(Normal3f::from(n.normalize()))
+            } else {
+                None
+            }
+        };
+
+        let (_, dpdu, dpdv) = self.evaluate(u, v);
+        if let Some(n) = cross(dpdu, dpdv) {
+            return n;
+        }
+
+        let u_mid = 0.5 * (self.u_range[0] + self.u_range[1]);
+        let v_mid = 0.5 * (self.v_range[0] + self.v_range[1]);
+        let (_, dpdu, dpdv) = self.evaluate(lerp(1e-3, u, u_mid), lerp(1e-3, v, v_mid));
+        cross(dpdu, dpdv).unwrap_or_default()
+    }
+
+    /// Returns the parametric coordinates along one direction at which the
+    /// surface is tessellated. Each knot span inside the range is halved
+    /// until the surface along the given lines of the other direction stays
+    /// within `max_error` of the chords between the coordinates or the span
+    /// has used its share of `max_points`.
+    ///
+    /// * `knots`      - The knot vector of the direction.
+    /// * `range`      - The parametric range of the direction.
+    /// * `lines`      - Coordinates in the other direction to test along.
+    /// * `max_error`  - Maximum distance between surface and chords.
+    /// * `max_points` - Maximum number of coordinates to return unless there
+    ///                  are more knots.
+    /// * `point`      - Evaluates the surface at a coordinate in this
+    ///                  direction and one of `lines`.
+    fn breakpoints<F>(
+        knots: &[Float],
+        range: [Float; 2],
+        lines: &[Float],
+        max_error: Float,
+        max_points: usize,
+        point: F,
+    ) -> Vec<Float>
+    where
+        F: Fn(Float, Float) -> Point3f,
+    {
+        let flat = |a: Float, b: Float| {
+            lines.iter().all(|&l| {
+                let (pa, pb) = (point(a, l), point(b, l));
+                [0.25, 0.5, 0.75].iter().all(|&f| {
+                    let chord = lerp(f, pa, pb);
+                    (point(lerp(f, a, b), l) - chord).length() <= max_error
+                })
+            })
+        };
+
+        // Subdivide the knot spans independently since the surface is only
+        // smooth inside them. Each span gets an equal share of the segments.
+        let spans = knot_spans(knots, range);
+        let max_segments = max(1, max_points.saturating_sub(1) / (spans.len() - 1));
+        let mut result = vec![range[0]];
+        for span in spans.windows(2) {
+            let mut stack = vec![(span[1], 0)];
+            let mut a = span[0];
+            let mut segments = 0;
+            while let Some((b, depth)) = stack.pop() {
+                // Splitting adds one segment to the finished and pending ones.
+                let split = segments + stack.len() + 2;
+                if depth < MAX_SPAN_DEPTH && split <= max_segments && !flat(a, b) {
+                    let mid = 0.5 * (a + b);
+                    stack.push((b, depth + 1));
+                    stack.push((mid, depth + 1));
+                } else {
+                    result.push(b);
+                    a = b;
+                    segments += 1;
+                }
+            }
+        }
+        result
+    }
+
+    /// Returns the u- and v-coordinates of the tessellation vertices. Their
+    /// product is at most `MAX_VERTICES` unless there are more knots.
+    fn tessellation_coordinates(&self) -> (Vec<Float>, Vec<Float>) {
+        let bounds = self
+            .pw
+            .iter()
+            .fold(Bounds3f::empty(), |b, p| b.union(&project(p)));
+        let max_error = MAX_CHORD_ERROR * bounds.diagonal().length();
+
+        // Choose the u-coordinates along a few v lines per knot span and then
+        // the v-coordinates along all the chosen u lines with the rest of the
+        // vertex budget.
+        let v_lines: Vec<Float> = knot_spans(&self.v_knots, self.v_range)
+            .windows(2)
+            .flat_map(|w| (0..4).map(move |i| lerp(i as Float / 4.0, w[0], w[1])))
+            .chain(std::iter::once(self.v_range[1]))
+            .collect();
+        let max_us = (MAX_VERTICES as Float).sqrt() as usize;
+        let us = Self::breakpoints(
+            &self.u_knots,
+            self.u_range,
+            &v_lines,
+            max_error,
+            max_us,
+            |u, v| self.evaluate(u, v).0,
+        );
+        let vs = Self::breakpoints(
+            &self.v_knots,
+            self.v_range,
+            &us,
+            max_error,
+            MAX_VERTICES / us.len(),
+            |v, u| self.evaluate(u, v).0,
+        );
+        (us, vs)
+    }
+
+    /// Tessellates the surface into a triangle mesh with the analytic normals
+    /// and tangents and the (u, v) coordinates of the surface at its vertices.
+    ///
+    /// * `object_to_world`     - The object to world transfomation.
+    /// * `world_to_object`     - The world to object transfomation.
+    /// * `reverse_orientation` - Indicates whether their surface normal directions
+    ///                           should be reversed from the default.
+    /// * `alpha_mask`          - Optional alpha mask texture.
+    /// * `shadow_alpha_mask`   - Optional shadow alpha mask texture.
+    pub fn tessellate(
+        &self,
+        object_to_world: ArcTransform,
+        world_to_object: ArcTransform,
+        reverse_orientation: bool,
+        alpha_mask: Option<ArcTexture<Float>>,
+        shadow_alpha_mask: Option<ArcTexture<Float>>,
+    ) -> Vec<ArcShape> {
+        let (us, vs) = self.tessellation_coordinates();
+        let (n_u, n_v) = (us.len(), vs.len());
+        let mut p = Vec::with_capacity(n_u * n_v);
+        let mut n = Vec::with_capacity(n_u * n_v);
+        let mut s = Vec::with_capacity(n_u * n_v);
+        let mut uv = Vec::with_capacity(n_u * n_v);
+        for &v in vs.iter() {
+            for &u in us.iter() {
+                let (pt, dpdu, _) = self.evaluate(u, v);
+                p.push(pt);
+                n.push(self.normal(u, v));
+                s.push(dpdu);
+                uv.push(Point2f::new(u, v));
+            }
+        }
+
+        let mut indices = Vec::with_capacity(6 * (n_u - 1) * (n_v - 1));
+        let vertex = |i: usize, j: usize| j * n_u + i;
+        for j in 0..n_v - 1 {
+            for i in 0..n_u - 1 {
+                indices.extend_from_slice(&[
+                    vertex(i, j),
+                    vertex(i + 1, j),
+                    vertex(i + 1, j + 1),
+                    vertex(i, j),
+                    vertex(i + 1, j + 1),
+                    vertex(i, j + 1),
+                ]);
+            }
+        }
+
+        TriangleMesh::create(
+            object_to_world,
+            world_to_object,
+            reverse_orientation,
+            indices,
+            p,
+            n,
+            s,
+            uv,
+            alpha_mask,
+            shadow_alpha_mask,
+            vec![],
+        )
+    }
+
+    /// Create a tessellated NURBS surface from given parameter set, object to
+    /// world transform, world to object transform and whether or not surface
+    /// normal orientation is reversed.
+    ///
+    /// * `p`              - A tuple containing the parameter set, object to
+    ///                      world transform, world to object transform and
+    ///                      whether or not surface normal orientation is reversed.
+    /// * `float_textures` - Float textures.
+    pub fn from_props(
+        p: (&ParamSet, ArcTransform, ArcTransform, bool),
+        float_textures: &HashMap<String, ArcTexture<Float>>,
+    ) -> Vec<ArcShape> {
+        let (params, o2w, w2o, reverse_orientation) = p;
+
+        let nu = params.find_one_int("nu", -1);
+        let nv = params.find_one_int("nv", -1);
+        let u_order = params.find_one_int("uorder", -1);
+        let v_order = params.find_one_int("vorder", -1);
+        if u_order < 2 || v_order < 2 || nu < u_order || nv < v_order {
+            error!(
+                "NURBS shape requires 'uorder' and 'vorder' of at least 2 and at least as many 'nu' and 'nv' control points."
+            );
+            return vec![];
+        }
+        let (nu, nv, u_order, v_order) =
+            (nu as usize, nv as usize, u_order as usize, v_order as usize);
+
+        let u_knots = params.find_float("uknots");
+        if u_knots.len() != nu + u_order {
+            error!(
+                "Number of knots in u knot vector {} doesn't match sum of number of u control points {} and u order {}.",
+                u_knots.len(),
+                nu,
+                u_order
+            );
+            return vec![];
+        }
+        let v_knots = params.find_float("vknots");
+        if v_knots.len() != nv + v_order {
+            error!(
+                "Number of knots in v knot vector {} doesn't match sum of number of v control points {} and v order {}.",
+                v_knots.len(),
+                nv,
+                v_order
+            );
+            return vec![];
+        }
+        let decreasing = |k: &[Float]| k.windows(2).any(|w| w[1] < w[0]);
+        if decreasing(&u_knots) || decreasing(&v_knots) {
+            error!("NURBS knot vectors must be non-decreasing.");
+            return vec![];
+        }
+
+        // Clamp the parametric ranges to where the surface is defined.
+        let range = |knots: &[Float], order: usize, n: usize, lo: &str, hi: &str| {
+            let (k0, k1) = (knots[order - 1], knots[n]);
+            let r0 = clamp(params.find_one_float(lo, k0), k0, k1);
+            let r1 = clamp(params.find_one_float(hi, k1), k0, k1);
+            [r0, r1]
+        };
+        let u_range = range(&u_knots, u_order, nu, "u0", "u1");
+        let v_range = range(&v_knots, v_order, nv, "v0", "v1");
+        if !(u_range[0] < u_range[1] && v_range[0] < v_range[1]) {
+            error!("NURBS shape has an empty parametric range.");
+            return vec![];
+        }
+
+        let p = params.find_point3f("P");
+        let pw: Vec<Homogeneous> = if p.len() > 0 {
+            p.iter().map(|p| [p.x, p.y, p.z, 1.0]).collect()
+        } else {
+            let pw = params.find_float("Pw");
+            if pw.len() == 0 {
+                error!("Must provide control points via \"P\" or \"Pw\" parameter to NURBS shape.");
+                return vec![];
+            }
+            if pw.len() % 4 != 0 {
+                error!("Number of \"Pw\" control points provided to NURBS shape must be multiple of four");
+                return vec![];
+            }
+            if pw.chunks(4).any(|c| c[3] <= 0.0) {
+                error!("NURBS control point weights in \"Pw\" must be positive.");
+                return vec![];
+            }
+            pw.chunks(4).map(|c| [c[0], c[1], c[2], c[3]]).collect()
+        };
+        if pw.len() != nu * nv {
+            error!(
+                "NURBS shape was expecting {}x{}={} control points, was given {}",
+                nu,
+                nv,
+                nu * nv,
+                pw.len()
+            );
+            return vec![];
+        }
+
+        let nurbs = Self {
+            nu,
+            nv,
+            u_order,
+            v_order,
+            u_knots,
+            v_knots,
+            u_range,
+            v_range,
+            pw,
+        };
+
+        let alpha_tex = get_alpha_texture(params, float_textures, "alpha");
+        let shadow_alpha_tex = get_alpha_texture(params, float_textures, "shadowalpha");
+
+        nurbs.tessellate(
+            o2w,
+            w2o,
+            reverse_orientation,
+            Some(alpha_tex),
+            Some(shadow_alpha_tex),
+        )
+    }
+}
+
+/// Returns the distinct knots inside a parametric range together with the
+/// ends of the range in increasing order.
+///
+/// * `knots` - The knot vector.
+/// * `range` - The parametric range.
+fn knot_spans(knots: &[Float], range: [Float; 2]) -> Vec<Float> {
+    let mut spans = vec![range[0]];
+    spans.extend(knots.iter().filter(|&&k| k > range[0] && k < range[1]));
+    spans.push(range[1]);
+    spans.dedup();
+    spans
+}
+
+/// Returns the index of the first knot of the non-empty knot span containing
+/// `t`. Values on a knot belong to the span before it.
+///
+/// * `knots` - The knot vector.
+/// * `order` - The order of the curve.
+/// * `np`    - The number of control points.
+/// * `t`     - The parametric coordinate.
+fn knot_offset(knots: &[Float], order: usize, np: usize, t: Float) -> usize {
+    let mut offset = order - 1;
+    while offset + 1 < np && (t > knots[offset + 1] || knots[offset + 1] == knots[offset]) {
+        offset += 1;
+    }
+    offset
+}
+
+/// Evaluates a rational B-spline curve with de Boor's algorithm. Returns the
+/// point in homogeneous coordinates and the derivative of the projected
+/// point.
+///
+/// * `order` - The order of the curve.
+/// * `knots` - The knot vector.
+/// * `np`    - The number of control points.
+/// * `t`     - The parametric coordinate.
+/// * `cp`    - Returns the control point with a given index.
+fn evaluate_curve<F>(
+    order: usize,
+    knots: &[Float],
+    np: usize,
+    t: Float,
+    cp: F,
+) -> (Homogeneous, Vector3f)
+where
+    F: Fn(usize) -> Homogeneous,
+{
+    let offset = knot_offset(knots, order, np, t);
+    let knots = &knots[offset + 1 - order..];
+    let first = offset + 1 - order;
+    let mut work: Vec<Homogeneous> = (0..order).map(|i| cp(first + i)).collect();
+
+    let blend = |a: &Homogeneous, b: &Homogeneous, alpha: Float| {
+        [
+            alpha * a[0] + (1.0 - alpha) * b[0],
+            alpha * a[1] + (1.0 - alpha) * b[1],
+            alpha * a[2] + (1.0 - alpha) * b[2],
+            alpha * a[3] + (1.0 - alpha) * b[3],
+        ]
+    };
+
+    // Reduce to the two points of the last level, whose difference gives
+    // the derivative.
+    let k = order - 1;
+    for level in 0..order - 2 {
+        for j in 0..order - 1 - level {
+            let (k0, k1) = (knots[j + level + 1], knots[j + k + 1]);
+            let alpha = (k1 - t) / (k1 - k0);
+            work[j] = blend(&work[j], &work[j + 1], alpha);
+        }
+    }
+
+    let (k0, k1) = (knots[k], knots[k + 1]);
+    let alpha = (k1 - t) / (k1 - k0);
+    let p = blend(&work[0], &work[1], alpha);
+
+    let factor = k as Float / (k1 - k0);
+    let dp: Vec<Float> = (0..4).map(|i| (work[1][i] - work[0][i]) * factor).collect();
+    let w2 = p[3] * p[3];
+    let deriv = Vector3f::new(
+        dp[0] / p[3] - p[0] * dp[3] / w2,
+        dp[1] / p[3] - p[1] * dp[3] / w2,
+        dp[2] / p[3] - p[2] * dp[3] / w2,
+    );
+    (p, deriv)
+}
+
+/// Projects a homogeneous point to 3D.
+///
+/// * `p` - The point.
+fn project(p: &Homogeneous) -> Point3f {
+    Point3f::new(p[0] / p[3], p[1] / p[3], p[2] / p[3])
+}
+
+// ----------------------------------------------------------------------------
+// Tests
+// ----------------------------------------------------------------------------
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use rand::{Rng, SeedableRng};
+    use rand_pcg::Pcg32;
+    use std::sync::Arc;
+
+    /// Returns the B-spline basis function `i` of the given order with the
+    /// Cox-de Boor recursion and its derivative.
+    ///
+    /// * `knots` - The knot vector.
+    /// * `i`     - The index of the basis function.
+    /// * `order` - The order of the basis function.
+    /// * `t`     - The parametric coordinate, not on a knot.
+    fn basis(knots: &[Float], i: usize, order: usize, t: Float) -> (Float, Float) {
+        if order == 1 {
+            let b = if knots[i] <= t && t < knots[i + 1] {
+                1.0
+            } else {
+                0.0
+            };
+            return (b, 0.0);
+        }
+        let k = (order - 1) as Float;
+        let ratio = |a: Float, b: Float| if b == 0.0 { 0.0 } else { a / b };
+        let (n0, _) = basis(knots, i, order - 1, t);
+        let (n1, _) = basis(knots, i + 1, order - 1, t);
+        let (d0, d1) = (
+            knots[i + order - 1] - knots[i],
+            knots[i + order] - knots[i + 1],
+        );
+        let b = ratio(t - knots[i], d0) * n0 + ratio(knots[i + order] - t, d1) * n1;
+        let db = k * (ratio(n0, d0) - ratio(n1, d1));
+        (b, db)
+    }
+
+    /// Evaluates a rational B-spline curve as the weighted sum of its basis
+    /// functions and returns the point and its derivative.
+    ///
+    /// * `order` - The order of the curve.
+    /// * `knots` - The knot vector.
+    /// * `cp`    - Control points in homogeneous coordinates.
+    /// * `t`     - The parametric coordinate, not on a knot.
+    fn reference(
+        order: usize,
+        knots: &[Float],
+        cp: &[Homogeneous],
+        t: Float,
+    ) -> (Point3f, Vector3f) {
+        let mut a = [0.0; 4];
+        let mut da = [0.0; 4];
+        for (i, p) in cp.iter().enumerate() {
+            let (b, db) = basis(knots, i, order, t);
+            for c in 0..4 {
+                a[c] += b * p[c];
+                da[c] += db * p[c];
+            }
+        }
+        let w2 = a[3] * a[3];
+        let d = Vector3f::new(
+            (da[0] * a[3] - a[0] * da[3]) / w2,
+            (da[1] * a[3] - a[1] * da[3]) / w2,
+            (da[2] * a[3] - a[2] * da[3]) / w2,
+        );
+        (project(&a), d)
+    }
+
+    /// Returns random control points with weights in [0.5, 2].
+    ///
+    /// * `n`    - Number of control points.
+    /// * `seed` - Random number generator seed.
+    fn control_points(n: usize, seed: u64) -> Vec<Homogeneous> {
+        let mut rng = Pcg32::seed_from_u64(seed);
+        (0..n)
+            .map(|_| {
+                let w = rng.gen_range(0.5..2.0);
+                [
+                    w * rng.gen_range(-1.0..1.0),
+                    w * rng.gen_range(-1.0..1.0),
+                    w * rng.gen_range(-1.0..1.0),
+                    w,
+                ]
+            })
+            .collect()
+    }
+
+    /// Asserts that two points or vectors are within `eps` of each other.
+    fn assert_close(a: Vector3f, b: Vector3f, eps: Float) {
+        assert!(
+            (a - b).length() <= eps * max(1.0, b.length()),
+            "{:?} != {:?}",
+            a,
+            b
+        );
+    }
+
+    /// Returns a bicubic surface over [0, 1]^2 with a bump in the middle.
+    fn bump() -> Nurbs {
+        let knots = vec![0.0, 0.0, 0.0, 0.0, 0.5, 1.0, 1.0, 1.0, 1.0];
+        let pw = (0..25)
+            .map(|k| {
+                let (i, j) = (k % 5, k / 5);
+                let z = if (1..4).contains(&i) && (1..4).contains(&j) {
+                    1.0
+                } else {
+                    0.0
+                };
+                [i as Float / 4.0, j as Float / 4.0, z, 1.0]
+            })
+            .collect();
+        Nurbs {
+            nu: 5,
+            nv: 5,
+            u_order: 4,
+            v_order: 4,
+            u_knots: knots.clone(),
+            v_knots: knots,
+            u_range: [0.0, 1.0],
+            v_range: [0.0, 1.0],
+            pw,
+        }
+    }
+
+    #[test]
+    fn cubic_bezier_matches_bernstein() {
+        let cp = [
+            [0.0, 0.0, 0.0, 1.0],
+            [1.0, 2.0, 0.0, 1.0],
+            [3.0, 2.0, 1.0, 1.0],
+            [4.0, 0.0, -1.0, 1.0],
+        ];
+        let knots = [0.0, 0.0, 0.0, 0.0, 1.0, 1.0, 1.0, 1.0];
+        let v = |p: &Homogeneous| Vector3f::new(p[0], p[1], p[2]);
+        for s in 0..=10 {
+            let t = s as Float / 10.0;
+            let b3 = [
+                (1.0 - t).powi(3),
+                3.0 * t * (1.0 - t).powi(2),
+                3.0 * t * t * (1.0 - t),
+                t.powi(3),
+            ];
+            let b2 = [(1.0 - t).powi(2), 2.0 * t * (1.0 - t), t * t];
+            let p = (0..4).fold(Vector3f::default(), |p, i| p + v(&cp[i]) * b3[i]);
+            let d = (0..3).fold(Vector3f::default(), |d, i| {
+                d + (v(&cp[i + 1]) - v(&cp[i])) * (3.0 * b2[i])
+            });
+
+            let (q, dq) = evaluate_curve(4, &knots, 4, t, |i| cp[i]);
+            let q = project(&q);
+            assert_close(Vector3f::new(q.x, q.y, q.z), p, 1e-5);
+            assert_close(dq, d, 1e-5);
+        }
+    }
+
+    #[test]
+    fn rational_curves_match_cox_de_boor() {
+        let mut rng = Pcg32::seed_from_u64(1);
+        // Curves with a double and a triple interior knot.
+        for (seed, &(order, knots)) in [
+            (3, &[0.0, 0.0, 0.0, 1.0, 2.0, 2.0, 3.0, 3.0, 3.0][..]),
+            (
+                4,
+                &[0.0, 0.0, 0.0, 0.0, 1.0, 1.0, 1.0, 2.0, 3.0, 3.0, 3.0, 3.0][..],
+            ),
+        ]
+        .iter()
+        .enumerate()
+        {
+            let np = knots.len() - order;
+            let cp = control_points(np, seed as u64);
+            for _ in 0..200 {
+                let t = rng.gen_range(0.0..3.0);
+                if knots.contains(&t) {
+                    continue;
+                }
+                let (p, d) = reference(order, knots, &cp, t);
+                let (q, dq) = evaluate_curve(order, knots, np, t, |i| cp[i]);
+                let q = project(&q);
+                assert_close(
+                    Vector3f::new(q.x, q.y, q.z),
+                    Vector3f::new(p.x, p.y, p.z),
+                    1e-4,
+                );
+                assert_close(dq, d, 1e-3);
+            }
+        }
+    }
+
+    #[test]
+    fn knot_offset_skips_empty_spans() {
+        let knots = [0.0, 0.0, 0.0, 1.0, 2.0, 2.0, 3.0, 3.0, 3.0];
+        for &(t, offset) in [
+            (0.0, 2),
+            (0.5, 2),
+            (1.0, 2),
+            (1.5, 3),
+            (2.0, 3),
+            (2.5, 5),
+            (3.0, 5),
+        ]
+        .iter()
+        {
+            assert_eq!(knot_offset(&knots, 3, 6, t), offset, "t = {}", t);
+        }
+    }
+
+    #[test]
+    fn flat_patches_have_two_triangles() {
+        let nurbs = Nurbs {
+            nu: 2,
+            nv: 2,
+            u_order: 2,
+            v_order: 2,
+            u_knots: vec![0.0, 0.0, 1.0, 1.0],
+            v_knots: vec![0.0, 0.0, 1.0, 1.0],
+            u_range: [0.0, 1.0],
+            v_range: [0.0, 1.0],
+            pw: vec![
+                [0.0, 0.0, 0.0, 1.0],
+                [1.0, 0.0, 0.0, 1.0],
+                [0.0, 1.0, 0.0, 1.0],
+                [1.0, 1.0, 0.0, 1.0],
+            ],
+        };
+        assert_eq!(
+            nurbs.tessellation_coordinates(),
+            (vec![0.0, 1.0], vec![0.0, 1.0])
+        );
+        let identity = Arc::new(Transform::default());
+        assert_eq!(
+            nurbs
+                .tessellate(identity.clone(), identity, false, None, None)
+                .len(),
+            2
+        );
+    }
+
+    #[test]
+    fn tessellation_vertex_count() {
+        let nurbs = bump();
+        let (us, vs) = nurbs.tessellation_coordinates();
+        assert!(us.len() > 3 && vs.len() > 3);
+        assert!(us.len() * vs.len() <= MAX_VERTICES);
+        for c in [&us, &vs].iter() {
+            assert!(c.windows(2).all(|w| w[0] < w[1]));
+            assert!(c.contains(&0.0) && c.contains(&0.5) && c.contains(&1.0));
+        }
+
+        let identity = Arc::new(Transform::default());
+        let shapes = nurbs.tessellate(identity.clone(), identity, false, None, None);
+        assert_eq!(shapes.len(), 2 * (us.len() - 1) * (vs.len() - 1));
+    }
+
+    #[test]
+    fn breakpoints_are_capped() {
+        // A curve that is never flat enough with a zero error.
+        let knots = [0.0, 0.0, 1.0, 2.0, 3.0, 3.0];
+        let point = |u: Float, _v: Float| Point3f::new(u, u * u, 0.0);
+        let breakpoints =
+            |max_points| Nurbs::breakpoints(&knots, [0.0, 3.0], &[0.0], 0.0, max_points, point);
+
+        assert_eq!(breakpoints(usize::MAX).len(), 1 + 3 * (1 << MAX_SPAN_DEPTH));
+        for &max_points in [2, 4, 17, 100].iter() {
+            let us = breakpoints(max_points);
+            assert!(
+                us.len() <= max(max_points, 4),
+                "{} > {}",
+                us.len(),
+                max_points
+            );
+            assert!(us.windows(2).all(|w| w[0] < w[1]));
+            for k in [0.0, 1.0, 2.0, 3.0].iter() {
+                assert!(us.contains(k));
+            }
+        }
+        assert_eq!(breakpoints(100).len(), 100);
+    }
+}
@@ -0,0 +1,279 @@
+//! PLY Triangle Meshes
+
+#![allow(dead_code)]
+use super::{compute_normals, get_alpha_texture, TriangleMesh};
+use crate::core::geometry::*;
+use crate::core::paramset::*;
+use crate::core::pbrt::*;
+use crate::core::ply::*;
+use crate::core::texture::*;
+use std::collections::HashMap;
+
+/// Maximum number of passes used to refine a mesh before displacement.
+/// Each pass splits edges in half so this bounds the refinement for very
+/// small edge lengths.
+const MAX_REFINEMENT_PASSES: usize = 16;
+
+impl TriangleMesh {
+    /// Create a triangle mesh from a PLY file given by the parameter set,
+    /// object to world transform, world to object transform and whether or
+    /// not surface normal orientation is reversed.
+    ///
+    /// * `p`              - A tuple containing the parameter set, object to
+    ///                      world transform, world to object transform and
+    ///                      whether or not surface normal orientation is reversed.
+    /// * `float_textures` - Float textures.
+    pub fn from_ply_props(
+        p: (&ParamSet, ArcTransform, ArcTransform, bool),
+        float_textures: &HashMap<String, ArcTexture<Float>>,
+    ) -> Vec<ArcShape> {
+        let (params, o2w, w2o, reverse_orientation) = p;
+
+        let filename = params.find_one_filename("filename", String::new());
+        if filename.is_empty() {
+            error!("PLY file 'filename' not provided with plymesh shape.");
+            return vec![];
+        }
+
+        let mut mesh = match read_ply(&filename) {
+            Ok(mesh) => mesh,
+            Err(err) => {
+                error!("{}", err);
+                return vec![];
+            }
+        };
+        if mesh.indices.is_empty() {
+            error!("PLY file {} has no triangles or quads.", filename);
+            return vec![];
+        }
+
+        let displacement_tex_name = params.find_one_texture("displacement", String::new());
+        if !displacement_tex_name.is_empty() {
+            match float_textures.get(&displacement_tex_name) {
+                Some(displacement) => {
+                    let edge_length = params.find_one_float("edgelength", 1.0);
+                    displace(&mut mesh, displacement, edge_length, &o2w);
+                }
+                None => error!(
+                    "Couldn't find float texture '{}' for 'displacement' parameter.",
+                    displacement_tex_name
+                ),
+            }
+        }
+
+        let alpha_tex = get_alpha_texture(params, float_textures, "alpha");
+        let shadow_alpha_tex = get_alpha_texture(params, float_textures, "shadowalpha");
+
+        Self::create(
+            o2w.clone(),
+            w2o.clone(),
+            reverse_orientation,
+            mesh.indices,
+            mesh.p,
+            mesh.n,
+            vec![],
+            mesh.uv,
+            Some(alpha_tex),
+            Some(shadow_alpha_tex),
+            mesh.face_indices,
+        )
+    }
+}
+
+/// Refine the mesh until no edge is longer than the given length in world
+/// space and then move each vertex along its normal by the displacement
+/// texture value. Normals are recomputed from the displaced mesh.
+///
+/// * `mesh`            - The mesh.
+/// * `displacement`    - Displacement texture.
+/// * `edge_length`     - Maximum edge length in world space.
+/// * `object_to_world` - The object to world transfomation.
+fn displace(
+    mesh: &mut PLYMesh,
+    displacement: &ArcTexture<Float>,
+    edge_length: Float,
+    object_to_world: &Transform,
+) {
+    if mesh.n.is_empty() {
+        mesh.n = compute_normals(&mesh.p, &mesh.indices);
+    }
+
+    if edge_length > 0.0 {
+        for _ in 0..MAX_REFINEMENT_PASSES {
+            if !refine(mesh, edge_length, object_to_world) {
+                break;
+            }
+        }
+    } else {
+        warn!(
+            "Ignoring non-positive 'edgelength' {} for displacement.",
+            edge_length
+        );
+    }
+
+    for i in 0..mesh.p.len() {
+        let n = mesh.n[i].normalize();
+        let uv = if mesh.uv.is_empty() {
+            Point2f::default()
+        } else {
+            mesh.uv[i]
+        };
+
+        let mut dpdu = Vector3f::default();
+        let mut dpdv = Vector3f::default();
+        coordinate_system(&Vector3f::from(n), &mut dpdu, &mut dpdv);
+
+        let si = SurfaceInteraction::new(
+            mesh.p[i],
+            Vector3f::default(),
+            uv,
+            Vector3f::default(),
+            dpdu,
+            dpdv,
+            Normal3f::default(),
+            Normal3f::default(),
+            0.0,
+            None,
+        );
+        let d = displacement.evaluate(&si);
+        mesh.p[i] += Vector3f::from(n) * d;
+    }
+
+    mesh.n = compute_normals(&mesh.p, &mesh.indices);
+}
+
+/// Split mesh edges longer than the given length in world space at their
+/// midpoints. Edges are shared by adjacent triangles so the refined mesh has
+/// no cracks. Returns `true` if any edge was split.
+///
+/// * `mesh`            - The mesh.
+/// * `edge_length`     - Maximum edge length in world space.
+/// * `object_to_world` - The object to world transfomation.
+fn refine(mesh: &mut PLYMesh, edge_length: Float, object_to_world: &Transform) -> bool {
+    let world_p: Vec<Point3f> = mesh
+        .p
+        .iter()
+        .map(|p| object_to_world.transform_point(p))
+        .collect();
+
+    // Midpoint vertex of each edge or `None` if the edge isn't split.
+    let mut midpoints: HashMap<(usize, usize), Option<usize>> = HashMap::new();
+    let mut midpoint = |mesh: &mut PLYMesh, a: usize, b: usize| -> Option<usize> {
+        let key = if a < b { (a, b) } else { (b, a) };
+        if let Some(m) = midpoints.get(&key) {
+            return *m;
+        }
+
+        let m = if world_p[a].distance(world_p[b]) > edge_length {
+            mesh.p.push((mesh.p[a] + mesh.p[b]) * 0.5);
+            let n = mesh.n[a] + mesh.n[b];
+            mesh.n.push(if n.length_squared() > 0.0 {
+                n.normalize()
+            } else {
+                mesh.n[a]
+            });
+            if !mesh.uv.is_empty() {
+                mesh.uv.push((mesh.uv[a] + mesh.uv[b]) * 0.5);
+            }
+            Some(mesh.p.len() - 1)
+        } else {
+            None
+        };
+        midpoints.insert(key, m);
+        m
+    };
+
+    let n_triangles = mesh.indices.len() / 3;
+    let mut indices: Vec<usize> = Vec::with_capacity(mesh.indices.len());
+    let mut face_indices: Vec<usize> = Vec::with_capacity(mesh.face_indices.len());
+    let mut split = false;
+
+    for t in 0..n_triangles {
+        let v = [
+            mesh.indices[3 * t],
+            mesh.indices[3 * t + 1],
+            mesh.indices[3 * t + 2],
+        ];
+        let m = [
+            midpoint(mesh, v[0], v[1]),
+            midpoint(mesh, v[1], v[2]),
+            midpoint(mesh, v[2], v[0]),
+        ];
+
+        // Rotate the triangle so that the split edges come first. Rotating
+        // the vertices keeps the winding order.
+        let n_split = m.iter().filter(|m| m.is_some()).count();
+        let rotation = match n_split {
+            1 => m.iter().position(|m| m.is_some()).unwrap(),
+            2 => (m.iter().position(|m| m.is_none()).unwrap() + 1) % 3,
+            _ => 0,
+        };
+        let (a, b, c) = (v[rotation], v[(rotation + 1) % 3], v[(rotation + 2) % 3]);
+        let (mab, mbc, mca) = (m[rotation], m[(rotation + 1) % 3], m[(rotation + 2) % 3]);
+
+        let new_triangles: Vec<[usize; 3]> = match (mab, mbc, mca) {
+            (None, None, None) => vec![[a, b, c]],
+            (Some(mab), None, None) => vec![[a, mab, c], [mab, b, c]],
+            (Some(mab), Some(mbc), None) => vec![[mab, b, mbc], [a, mab, mbc], [a, mbc, c]],
+            (Some(mab), Some(mbc), Some(mca)) => {
+                vec![[a, mab, mca], [mab, b, mbc], [mca, mbc, c], [mab, mbc, mca]]
+            }
+            _ => unreachable!(),
+        };
+
+        split |= n_split > 0;
+        for tri in new_triangles.iter() {
+            indices.extend_from_slice(tri);
+            if !mesh.face_indices.is_empty() {
+                face_indices.push(mesh.face_indices[t]);
+            }
+        }
+    }
+
+    mesh.indices = indices;
+    mesh.face_indices = face_indices;
+    split
+}
+
+// ----------------------------------------------------------------------------
+// Tests
+// ----------------------------------------------------------------------------
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::sync::Arc;
+
+    /// Returns the shapes created for a PLY mesh with the given filename.
+    fn shapes(filename: &str) -> Vec<ArcShape> {
+        let mut params = ParamSet::new();
+        params.add_string("filename", &[String::from(filename)]);
+        let identity = Arc::new(Transform::default());
+        TriangleMesh::from_ply_props(
+            (&params, identity.clone(), identity, false),
+            &HashMap::new(),
+        )
+    }
+
+    #[test]
+    fn creates_triangles_from_ply_files() {
+        let dir = std::env::temp_dir();
+        let path = dir.join(format!("plymesh_{}.ply", std::process::id()));
+        let data = "ply\nformat ascii 1.0\n\
+                    element vertex 4\nproperty float x\nproperty float y\nproperty float z\n\
+                    element face 1\nproperty list uchar int vertex_indices\nend_header\n\
+                    0 0 0\n1 0 0\n1 1 0\n0 1 0\n4 0 1 2 3\n";
+        std::fs::write(&path, data).unwrap();
+
+        // The filename is resolved to an absolute path.
+        let dotted = dir.join(".").join(path.file_name().unwrap());
+        let triangles = shapes(dotted.to_str().unwrap());
+        std::fs::remove_file(&path).unwrap();
+
+        assert_eq!(triangles.len(), 2);
+        let area: Float = triangles.iter().map(|t| t.area()).sum();
+        assert!((area - 1.0).abs() < 1e-6);
+
+        assert!(shapes(path.to_str().unwrap()).is_empty());
+    }
+}
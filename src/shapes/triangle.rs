@@ -4,12 +4,23 @@
 use crate::core::geometry::*;
 use crate::core::paramset::*;
 use crate::core::pbrt::*;
+use crate::core::sampling::*;
+use crate::core::stats::*;
 use crate::core::texture::*;
 use crate::textures::*;
 use std::collections::HashMap;
 use std::mem::size_of;
 use std::sync::Arc;
 
+/// Memory used by triangle meshes.
+static TRI_MESH_BYTES: Stat = Stat::memory("Memory/Triangle meshes");
+
+/// Number of triangles and triangle meshes.
+static TRIANGLES_PER_MESH: Stat = Stat::ratio("Scene/Triangles per triangle mesh");
+
+/// Number of ray-triangle tests and hits.
+static RAY_TRIANGLE_TESTS: Stat = Stat::percent("Intersections/Ray-triangle intersection tests");
+
 /// Triangle mesh
 #[derive(Clone)]
 pub struct TriangleMesh {
@@ -27,6 +38,14 @@ pub struct TriangleMesh {
     /// Vertex positions.
     pub p: Vec<Point3f>,
 
+    /// Additional keys of vertex positions for deforming meshes. Keys are
+    /// evenly spaced over `motion_times` with `p` being the first key. This
+    /// will be empty if the mesh doesn't deform.
+    pub p_motion: Vec<Vec<Point3f>>,
+
+    /// Times of the first and last key of vertex positions.
+    pub motion_times: [Float; 2],
+
     /// Vertex normals. This will be empty if there are none.
     pub n: Vec<Normal3f>,
 
@@ -77,8 +96,8 @@ impl TriangleMesh {
         shadow_alpha_mask: Option<ArcTexture<Float>>,
         face_indices: Vec<usize>,
     ) -> Self {
-        let num_triangles = vertex_indices.len() % 3;
-        assert!(num_triangles == 0);
+        assert!(vertex_indices.len() % 3 == 0);
+        let num_triangles = vertex_indices.len() / 3;
 
         // Transform mesh vertices to world space.
         let tp = p.iter().map(|v| object_to_world.transform_point(&v));
@@ -93,6 +112,8 @@ impl TriangleMesh {
             num_triangles,
             vertex_indices,
             p: tp.collect(),
+            p_motion: vec![],
+            motion_times: [0.0, 1.0],
             n: tn.collect(),
             s: ts.collect(),
             uv,
@@ -137,9 +158,6 @@ impl TriangleMesh {
         shadow_alpha_mask: Option<ArcTexture<Float>>,
         face_indices: Vec<usize>,
     ) -> Vec<ArcShape> {
-        let num_triangles = vertex_indices.len() % 3;
-        assert!(num_triangles == 0);
-
         let mesh = Self::new(
             object_to_world.clone(),
             reverse_orientation,
@@ -153,20 +171,141 @@ impl TriangleMesh {
             face_indices,
         );
 
-        let m = Arc::new(mesh);
-        let mut tris = Vec::<ArcShape>::with_capacity(num_triangles);
-        for i in 0..num_triangles {
-            let tri = Triangle::new(
-                object_to_world.clone(),
-                world_to_object.clone(),
-                reverse_orientation,
-                m.clone(),
-                i,
-            );
-            tris.push(Arc::new(tri));
+        Self::triangles(
+            Arc::new(mesh),
+            object_to_world,
+            world_to_object,
+            reverse_orientation,
+        )
+    }
+
+    /// Returns the list of triangles referencing a mesh.
+    ///
+    /// * `mesh`                - The triangle mesh.
+    /// * `object_to_world`     - The object to world transfomation.
+    /// * `world_to_object`     - The world to object transfomation.
+    /// * `reverse_orientation` - Indicates whether their surface normal directions
+    ///                           should be reversed from the default
+    pub fn triangles(
+        mesh: Arc<Self>,
+        object_to_world: ArcTransform,
+        world_to_object: ArcTransform,
+        reverse_orientation: bool,
+    ) -> Vec<ArcShape> {
+        TRIANGLES_PER_MESH.add_fraction(mesh.num_triangles as i64, 1);
+        TRI_MESH_BYTES.add(mesh.memory_size() as i64);
+
+        (0..mesh.num_triangles)
+            .map(|i| {
+                let tri = Triangle::new(
+                    object_to_world.clone(),
+                    world_to_object.clone(),
+                    reverse_orientation,
+                    mesh.clone(),
+                    i,
+                );
+                Arc::new(tri) as ArcShape
+            })
+            .collect()
+    }
+
+    /// Add keys of vertex positions to make a deforming mesh. The existing
+    /// positions are the first key and the remaining keys are evenly spaced
+    /// over the time period.
+    ///
+    /// *NOTE*: Only positions are animated; vertex normals and tangents are
+    /// those of the first key.
+    ///
+    /// * `object_to_world` - The object to world transfomation.
+    /// * `p_motion`        - Vertex positions of the additional keys one key
+    ///                       after another. The length must be a multiple of
+    ///                       the number of vertices.
+    /// * `start_time`      - Time of the first key.
+    /// * `end_time`        - Time of the last key.
+    pub fn set_motion(
+        &mut self,
+        object_to_world: &ArcTransform,
+        p_motion: &[Point3f],
+        start_time: Float,
+        end_time: Float,
+    ) {
+        assert!(!self.p.is_empty() && p_motion.len() % self.p.len() == 0);
+
+        self.p_motion = p_motion
+            .chunks_exact(self.p.len())
+            .map(|key| {
+                key.iter()
+                    .map(|v| object_to_world.transform_point(v))
+                    .collect()
+            })
+            .collect();
+        self.motion_times = [start_time, end_time];
+    }
+
+    /// Returns the number of bytes used by the mesh.
+    pub fn memory_size(&self) -> usize {
+        size_of::<Self>()
+            + self.vertex_indices.len() * size_of::<usize>()
+            + (self.p.len() + self.p_motion.iter().map(|p| p.len()).sum::<usize>())
+                * size_of::<Point3f>()
+            + self.n.len() * size_of::<Normal3f>()
+            + self.s.len() * size_of::<Vector3f>()
+            + self.uv.len() * size_of::<Point2f>()
+            + self.face_indices.len() * size_of::<usize>()
+    }
+
+    /// Returns `true` if the mesh has more than one key of vertex positions.
+    pub fn is_deforming(&self) -> bool {
+        !self.p_motion.is_empty()
+    }
+
+    /// Returns the vertex positions of a key.
+    ///
+    /// * `key` - The key (0 is `p`).
+    fn key(&self, key: usize) -> &[Point3f] {
+        if key == 0 {
+            &self.p
+        } else {
+            &self.p_motion[key - 1]
         }
+    }
 
-        tris
+    /// Returns the number of keys of vertex positions.
+    fn n_keys(&self) -> usize {
+        self.p_motion.len() + 1
+    }
+
+    /// Returns the time of a key.
+    ///
+    /// * `key` - The key.
+    fn key_time(&self, key: usize) -> Float {
+        let [start_time, end_time] = self.motion_times;
+        lerp(
+            key as Float / (self.n_keys() - 1) as Float,
+            start_time,
+            end_time,
+        )
+    }
+
+    /// Returns the position of a vertex at the given time by linearly
+    /// interpolating between keys. Times outside the key times are clamped.
+    ///
+    /// * `v`    - The vertex index.
+    /// * `time` - The time.
+    pub fn position(&self, v: usize, time: Float) -> Point3f {
+        if !self.is_deforming() {
+            return self.p[v];
+        }
+
+        let [start_time, end_time] = self.motion_times;
+        let n_segments = self.n_keys() - 1;
+        let u = if end_time > start_time {
+            clamp((time - start_time) / (end_time - start_time), 0.0, 1.0) * n_segments as Float
+        } else {
+            0.0
+        };
+        let k = min(u as usize, n_segments - 1);
+        lerp(u - k as Float, self.key(k)[v], self.key(k + 1)[v])
     }
 
     /// Create a triangel mesh from given parameter set, object to world transform,
@@ -176,13 +315,17 @@ impl TriangleMesh {
     /// NOTE: Because we return a set of curves as `Vec<Arc<Shape>>` we cannot
     /// implement this as `From` trait :(
     ///
-    /// * `p`              - A tuple containing the parameter set, object to
-    ///                      world transform, world to object transform and
-    ///                      whether or not surface normal orientation is reversed.
-    /// * `float_textures` - Float textures.
+    /// * `p`               - A tuple containing the parameter set, object to
+    ///                       world transform, world to object transform and
+    ///                       whether or not surface normal orientation is reversed.
+    /// * `float_textures`  - Float textures.
+    /// * `transform_start` - Time of the first key of vertex positions.
+    /// * `transform_end`   - Time of the last key of vertex positions.
     pub fn from_props(
         p: (&ParamSet, ArcTransform, ArcTransform, bool),
         float_textures: &HashMap<String, ArcTexture<Float>>,
+        transform_start: Float,
+        transform_end: Float,
     ) -> Vec<ArcShape> {
         let (params, o2w, w2o, reverse_orientation) = p;
 
@@ -267,6 +410,16 @@ impl TriangleMesh {
             }
         }
 
+        let mut p_motion = params.find_point3f("Pmotion");
+        if p_motion.len() % npi != 0 {
+            error!(
+                "Number of 'Pmotion' for triangle mesh, {}, must be a multiple of 'P', {}.",
+                p_motion.len(),
+                npi
+            );
+            p_motion = vec![];
+        }
+
         let mut face_indices: Vec<usize> = params
             .find_int("faceIndices")
             .iter()
@@ -282,45 +435,11 @@ impl TriangleMesh {
             face_indices = vec![];
         }
 
-        let alpha_tex_name = params.find_one_texture("alpha", String::from(""));
-        let alpha_tex = if alpha_tex_name.len() > 0 {
-            if let Some(tex) = float_textures.get(&alpha_tex_name) {
-                tex.clone()
-            } else {
-                warn!(
-                    "Couldn't find float texture '{}' for 'alpha' parameter. 
-                    Using float 'alpha' parameterer instead.",
-                    alpha_tex_name
-                );
-                let alpha = params.find_one_float("alpha", 1.0);
-                Arc::new(ConstantTexture::new(alpha))
-            }
-        } else {
-            let alpha = params.find_one_float("alpha", 1.0);
-            Arc::new(ConstantTexture::new(alpha))
-        };
-
-        let shadow_alpha_tex_name = params.find_one_texture("shadowalpha", String::from(""));
-        let shadow_alpha_tex = if shadow_alpha_tex_name.len() > 0 {
-            if let Some(tex) = float_textures.get(&shadow_alpha_tex_name) {
-                tex.clone()
-            } else {
-                warn!(
-                    "Couldn't find float texture '{}' for 'shadowalpha' 
-                    parameter.  Using float 'shadowalpha' parameterer instead.",
-                    alpha_tex_name
-                );
-                let alpha = params.find_one_float("shadowalpha", 1.0);
-                Arc::new(ConstantTexture::new(alpha))
-            }
-        } else {
-            let alpha = params.find_one_float("shadowalpha", 1.0);
-            Arc::new(ConstantTexture::new(alpha))
-        };
+        let alpha_tex = get_alpha_texture(params, float_textures, "alpha");
+        let shadow_alpha_tex = get_alpha_texture(params, float_textures, "shadowalpha");
 
-        Self::create(
+        let mut mesh = Self::new(
             o2w.clone(),
-            w2o.clone(),
             reverse_orientation,
             vi,
             p,
@@ -330,10 +449,64 @@ impl TriangleMesh {
             Some(alpha_tex),
             Some(shadow_alpha_tex),
             face_indices,
-        )
+        );
+        if !p_motion.is_empty() {
+            mesh.set_motion(&o2w, &p_motion, transform_start, transform_end);
+        }
+
+        Self::triangles(Arc::new(mesh), o2w, w2o, reverse_orientation)
     }
 }
 
+/// Returns an alpha mask texture for a mesh from a texture parameter or a
+/// float parameter if there is no texture.
+///
+/// * `params`         - The parameter set.
+/// * `float_textures` - Float textures.
+/// * `name`           - Parameter name (`alpha` or `shadowalpha`).
+pub fn get_alpha_texture(
+    params: &ParamSet,
+    float_textures: &HashMap<String, ArcTexture<Float>>,
+    name: &str,
+) -> ArcTexture<Float> {
+    let tex_name = params.find_one_texture(name, String::from(""));
+    if tex_name.len() > 0 {
+        if let Some(tex) = float_textures.get(&tex_name) {
+            return tex.clone();
+        }
+        warn!(
+            "Couldn't find float texture '{}' for '{}' parameter. Using float '{}' parameter instead.",
+            tex_name, name, name
+        );
+    }
+    let alpha = params.find_one_float(name, 1.0);
+    Arc::new(ConstantTexture::new(alpha))
+}
+
+/// Returns smooth vertex normals computed from the area weighted normals of
+/// adjacent triangles.
+///
+/// * `p`       - Vertex positions.
+/// * `indices` - Vertex indices for triangles.
+pub fn compute_normals(p: &[Point3f], indices: &[usize]) -> Vec<Normal3f> {
+    let mut n = vec![Normal3f::default(); p.len()];
+    for v in indices.chunks_exact(3) {
+        let face_n = Normal3f::from((p[v[1]] - p[v[0]]).cross(&(p[v[2]] - p[v[0]])));
+        for i in v.iter() {
+            n[*i] += face_n;
+        }
+    }
+    n.iter()
+        .map(|n| {
+            if n.length_squared() > 0.0 {
+                n.normalize()
+            } else {
+                *n
+            }
+        })
+        .collect()
+}
+
 /// Triangle.
 #[derive(Clone)]
 pub struct Triangle {
@@ -343,8 +516,8 @@ pub struct Triangle {
     /// The mesh.
     pub mesh: Arc<TriangleMesh>,
 
-    /// The index of the first vertex in the triangle. The other two are
-    /// calculated as v + 1 and v + 2.
+    /// The index of the first vertex index of the triangle in the mesh's
+    /// `vertex_indices`. The other two are at v + 1 and v + 2.
     pub v: usize,
 }
 
@@ -377,14 +550,87 @@ impl Triangle {
 }
 
 impl Triangle {
+    /// Returns the index of the ith vertex of the triangle in the mesh.
+    ///
+    /// * `i` - The triangle vertex (0, 1 or 2).
+    fn vertex(&self, i: usize) -> usize {
+        self.mesh.vertex_indices[self.v + i]
+    }
+
+    /// Returns the vertex positions of the triangle at the given time.
+    ///
+    /// * `time` - The time.
+    fn positions(&self, time: Float) -> [Point3f; 3] {
+        [
+            self.mesh.position(self.vertex(0), time),
+            self.mesh.position(self.vertex(1), time),
+            self.mesh.position(self.vertex(2), time),
+        ]
+    }
+
+    /// Returns a bounding box in world space of the triangle vertices in a
+    /// key of a deforming mesh.
+    ///
+    /// * `key` - The key.
+    fn key_bound(&self, key: usize) -> Bounds3f {
+        let p = self.mesh.key(key);
+        (0..3).fold(Bounds3f::empty(), |b, i| b.union(&p[self.vertex(i)]))
+    }
+
+    /// Returns the surface area of the triangle at the given time.
+    ///
+    /// * `time` - The time.
+    pub fn area_at(&self, time: Float) -> Float {
+        let [p0, p1, p2] = self.positions(time);
+        0.5 * (p1 - p0).cross(&(p2 - p0)).length()
+    }
+
+    /// Sample a point on the triangle uniformly by area at the given time.
+    /// Returns the point and the PDF with respect to surface area.
+    ///
+    /// * `u`    - The random sample point.
+    /// * `time` - The time.
+    pub fn sample(&self, u: &Point2f, time: Float) -> (Hit, Float) {
+        let [p0, p1, p2] = self.positions(time);
+        let b = uniform_sample_triangle(u);
+        let b2 = 1.0 - b[0] - b[1];
+        let p = b[0] * p0 + b[1] * p1 + b2 * p2;
+
+        // Compute surface normal for sampled point on triangle.
+        let mut n = Normal3f::from((p1 - p0).cross(&(p2 - p0)).normalize());
+        if self.mesh.n.len() > 0 {
+            let ns = b[0] * self.mesh.n[self.vertex(0)]
+                + b[1] * self.mesh.n[self.vertex(1)]
+                + b2 * self.mesh.n[self.vertex(2)];
+            n = n.face_forward(&ns.into());
+        } else if self.data.reverse_orientation ^ self.data.transform_swaps_handedness {
+            n = -n;
+        }
+
+        // Compute error bounds for sampled point on triangle.
+        let p_abs_sum = (b[0] * p0).abs() + (b[1] * p1).abs() + (b2 * p2).abs();
+        let p_error = gamma(6) * Vector3f::new(p_abs_sum.x, p_abs_sum.y, p_abs_sum.z);
+
+        let hit = Hit::new(p, time, p_error, Vector3f::default(), n, None);
+        (hit, self.pdf(time))
+    }
+
+    /// Returns the PDF with respect to surface area of sampling a point with
+    /// `sample()` at the given time.
+    ///
+    /// * `time` - The time.
+    pub fn pdf(&self, time: Float) -> Float {
+        1.0 / self.area_at(time)
+    }
+
     /// Returns the uv-coordinates for the triangle. If there are no uv
     /// coordinates, then default ones [(0,0), (1,0), (1,1)] are returned.
     fn get_uvs(&self) -> [Point2f; 3] {
         if self.mesh.uv.len() > 0 {
             [
-                self.mesh.uv[self.v],
-                self.mesh.uv[self.v + 1],
-                self.mesh.uv[self.v + 2],
+                self.mesh.uv[self.vertex(0)],
+                self.mesh.uv[self.vertex(1)],
+                self.mesh.uv[self.vertex(2)],
             ]
         } else {
             [
@@ -407,8 +653,11 @@ impl Shape for Triangle {
         // We can unwrap safely because the factory methods guarantee world_to_object
         // is passed. If it is constructed without that, then tough luck!
         let world_to_object = self.data.world_to_object.clone().unwrap();
-        (0..3).fold(Bounds3f::empty(), |b, i| {
-            b.union(&world_to_object.transform_point(&self.mesh.p[self.v + i]))
+        (0..self.mesh.n_keys()).fold(Bounds3f::empty(), |b, k| {
+            let p = self.mesh.key(k);
+            (0..3).fold(b, |b, i| {
+                b.union(&world_to_object.transform_point(&p[self.vertex(i)]))
+            })
         })
     }
 
@@ -417,7 +666,135 @@ impl Shape for Triangle {
     /// Default is to transform the object bounds with the object-to0world
     /// transformation. Override for tighter bounds implementation.
     fn world_bound(&self) -> Bounds3f {
-        (0..3).fold(Bounds3f::empty(), |b, i| b.union(&self.mesh.p[self.v + i]))
+        (0..self.mesh.n_keys()).fold(Bounds3f::empty(), |b, k| b.union(&self.key_bound(k)))
+    }
+
+    /// Returns bounding boxes in the world space at the start and end of a
+    /// time interval such that linearly interpolating them bounds the
+    /// triangle at any time in the interval.
+    ///
+    /// * `time0` - Start of the time interval.
+    /// * `time1` - End of the time interval.
+    fn motion_world_bounds(&self, time0: Float, time1: Float) -> (Bounds3f, Bounds3f) {
+        if !self.mesh.is_deforming() {
+            let bounds = self.world_bound();
+            return (bounds, bounds);
+        }
+
+        let bound = |p: [Point3f; 3]| p.iter().fold(Bounds3f::empty(), |b, v| b.union(v));
+        let mut b0 = bound(self.positions(time0));
+        let mut b1 = bound(self.positions(time1));
+        if time1 <= time0 {
+            let bounds = b0.union(&b1);
+            return (bounds, bounds);
+        }
+
+        // Vertices move linearly between keys so the bounds are only
+        // exceeded at keys inside the interval. Grow both bounds by the
+        // amount a key extends past the interpolated bounds.
+        for k in 0..self.mesh.n_keys() {
+            let time = self.mesh.key_time(k);
+            if time <= time0 || time >= time1 {
+                continue;
+            }
+
+            let t = (time - time0) / (time1 - time0);
+            let key_bounds = self.key_bound(k);
+            for axis in [Axis::X, Axis::Y, Axis::Z] {
+                let below = lerp(t, b0.p_min[axis], b1.p_min[axis]) - key_bounds.p_min[axis];
+                if below > 0.0 {
+                    b0.p_min[axis] -= below;
+                    b1.p_min[axis] -= below;
+                }
+                let above = key_bounds.p_max[axis] - lerp(t, b0.p_max[axis], b1.p_max[axis]);
+                if above > 0.0 {
+                    b0.p_max[axis] += above;
+                    b1.p_max[axis] += above;
+                }
+            }
+        }
+
+        (b0, b1)
+    }
+
+    /// Hash the world space vertex positions of every key and the key times.
+    /// Spatial splits clip the triangle itself so its bounds aren't enough.
+    ///
+    /// * `hasher` - The hasher.
+    fn hash_geometry(&self, hasher: &mut GeometryHasher) {
+        for k in 0..self.mesh.n_keys() {
+            let p = self.mesh.key(k);
+            for i in 0..3 {
+                hasher.write_point(&p[self.vertex(i)]);
+            }
+        }
+        if self.mesh.is_deforming() {
+            hasher.write_float(self.mesh.motion_times[0]);
+            hasher.write_float(self.mesh.motion_times[1]);
+        }
+    }
+
+    /// Returns a bounding box in the world space of the part of the triangle
+    /// inside the given bounds. The triangle is clipped against each plane
+    /// of the bounds.
+    ///
+    /// * `bounds` - The bounds to clip to.
+    fn clipped_world_bound(&self, bounds: &Bounds3f) -> Bounds3f {
+        // Deforming triangles sweep a volume so only clip their bounds.
+        if self.mesh.is_deforming() {
+            return self.world_bound().intersect(bounds);
+        }
+
+        // Clipping a triangle against 6 planes results in at most 9 vertices.
+        let mut polygon = [Point3f::default(); 9];
+        let mut n = 3;
+        for i in 0..3 {
+            polygon[i] = self.mesh.p[self.vertex(i)];
+        }
+
+        for axis in [Axis::X, Axis::Y, Axis::Z] {
+            for &(plane, keep_above) in
+                [(bounds.p_min[axis], true), (bounds.p_max[axis], false)].iter()
+            {
+                let inside = |p: &Point3f| {
+                    if keep_above {
+                        p[axis] >= plane
+                    } else {
+                        p[axis] <= plane
+                    }
+                };
+
+                // Keep the vertices inside the plane and add the points where
+                // edges cross it.
+                let mut clipped = [Point3f::default(); 9];
+                let mut n_clipped = 0;
+                for i in 0..n {
+                    let (a, b) = (polygon[i], polygon[(i + 1) % n]);
+                    if inside(&a) {
+                        clipped[n_clipped] = a;
+                        n_clipped += 1;
+                    }
+                    if inside(&a) != inside(&b) {
+                        let t = (plane - a[axis]) / (b[axis] - a[axis]);
+                        let mut p = a + (b - a) * t;
+                        p[axis] = plane;
+                        clipped[n_clipped] = p;
+                        n_clipped += 1;
+                    }
+                }
+
+                polygon = clipped;
+                n = n_clipped;
+                if n == 0 {
+                    return Bounds3f::empty();
+                }
+            }
+        }
+
+        polygon[..n]
+            .iter()
+            .fold(Bounds3f::empty(), |b, p| b.union(p))
+            .intersect(bounds)
     }
 
     /// Returns geometric details if a ray intersects the shape intersection.
@@ -426,10 +803,10 @@ impl Shape for Triangle {
     /// * `r`                  - The ray.
     /// * `test_alpha_texture` - Perform alpha texture tests.
     fn intersect<'a>(&self, r: &Ray, test_alpha_texture: bool) -> Option<Intersection<'a>> {
+        RAY_TRIANGLE_TESTS.add_fraction(0, 1);
+
         // Get triangle vertices in p0, p1, and p2
-        let p0 = self.mesh.p[self.v];
-        let p1 = self.mesh.p[self.v + 1];
-        let p2 = self.mesh.p[self.v + 2];
+        let [p0, p1, p2] = self.positions(r.time);
 
         // Perform ray-triangle intersection test.
 
@@ -620,9 +997,9 @@ impl Shape for Triangle {
             // Compute shading normal ns for triangle.
             let mut ns = isect.hit.n;
             if has_vertex_normals {
-                let ns2 = b0 * self.mesh.n[self.v]
-                    + b1 * self.mesh.n[self.v + 1]
-                    + b2 * self.mesh.n[self.v + 2];
+                let ns2 = b0 * self.mesh.n[self.vertex(0)]
+                    + b1 * self.mesh.n[self.vertex(1)]
+                    + b2 * self.mesh.n[self.vertex(2)];
                 if ns2.length_squared() > 0.0 {
                     ns = ns2.normalize();
                 }
@@ -631,9 +1008,9 @@ impl Shape for Triangle {
             // Compute shading tangent ss for triangle.
             let mut ss = isect.dpdu;
             if has_vertex_tangents {
-                let ss2 = b0 * self.mesh.s[self.v]
-                    + b1 * self.mesh.s[self.v + 1]
-                    + b2 * self.mesh.s[self.v + 2];
+                let ss2 = b0 * self.mesh.s[self.vertex(0)]
+                    + b1 * self.mesh.s[self.vertex(1)]
+                    + b2 * self.mesh.s[self.vertex(2)];
                 if ss2.length_squared() > 0.0 {
                     ss = ss2;
                 }
@@ -654,8 +1031,8 @@ impl Shape for Triangle {
                 // Compute deltas for triangle partial derivatives of normal
                 let duv02 = uv[0] - uv[2];
                 let duv12 = uv[1] - uv[2];
-                let dn1 = self.mesh.n[self.v] - self.mesh.n[self.v + 2];
-                let dn2 = self.mesh.n[self.v + 1] - self.mesh.n[self.v + 2];
+                let dn1 = self.mesh.n[self.vertex(0)] - self.mesh.n[self.vertex(2)];
+                let dn2 = self.mesh.n[self.vertex(1)] - self.mesh.n[self.vertex(2)];
 
                 let determinant = duv02[0] * duv12[1] - duv02[1] * duv12[0];
                 let degenerate_uv = determinant.abs() < 1e-8;
@@ -666,9 +1043,11 @@ impl Shape for Triangle {
                     // (rather than giving up) so that ray differentials for
                     // rays reflected from triangles with degenerate
                     // parameterizations are still reasonable.
-                    let dn = Vector3::from(self.mesh.n[self.v + 2] - self.mesh.n[self.v]).cross(
-                        &Vector3::from(self.mesh.n[self.v + 1] - self.mesh.n[self.v]),
-                    );
+                    let dn =
+                        Vector3::from(self.mesh.n[self.vertex(2)] - self.mesh.n[self.vertex(0)])
+                            .cross(&Vector3::from(
+                                self.mesh.n[self.vertex(1)] - self.mesh.n[self.vertex(0)],
+                            ));
                     if dn.length_squared() == 0.0 {
                         (Normal3f::default(), Normal3f::default())
                     } else {
@@ -695,6 +1074,7 @@ impl Shape for Triangle {
             isect.set_shading_geometry(ss, ts, dndu, dndv, true);
         }
 
+        RAY_TRIANGLE_TESTS.add_fraction(1, 0);
         Some(Intersection::new(t, isect))
     }
 
@@ -703,10 +1083,10 @@ impl Shape for Triangle {
     /// * `r`                  - The ray.
     /// * `test_alpha_texture` - Perform alpha texture tests.
     fn intersect_p(&self, r: &Ray, test_alpha_texture: bool) -> bool {
+        RAY_TRIANGLE_TESTS.add_fraction(0, 1);
+
         // Get triangle vertices in p0, p1, and p2
-        let p0 = self.mesh.p[self.v];
-        let p1 = self.mesh.p[self.v + 1];
-        let p2 = self.mesh.p[self.v + 2];
+        let [p0, p1, p2] = self.positions(r.time);
 
         // Perform ray-triangle intersection test.
 
@@ -863,14 +1243,159 @@ impl Shape for Triangle {
             }
         }
 
+        RAY_TRIANGLE_TESTS.add_fraction(1, 0);
         true
     }
 
-    /// Returns the surface area of the shape in object space.
+    /// Returns the surface area of the shape. The area of deforming triangles
+    /// is averaged over the time period.
     fn area(&self) -> Float {
-        let p0 = self.mesh.p[self.v];
-        let p1 = self.mesh.p[self.v + 1];
-        let p2 = self.mesh.p[self.v + 2];
-        0.5 * (p1 - p0).cross(&(p2 - p0)).length()
+        if !self.mesh.is_deforming() {
+            return self.area_at(self.mesh.motion_times[0]);
+        }
+
+        // Integrate each segment between keys with Simpson's rule, which is
+        // exact when the triangle only scales.
+        let n_segments = self.mesh.n_keys() - 1;
+        let sum: Float = (0..n_segments)
+            .map(|k| {
+                let (t0, t1) = (self.mesh.key_time(k), self.mesh.key_time(k + 1));
+                (self.area_at(t0) + 4.0 * self.area_at(0.5 * (t0 + t1)) + self.area_at(t1)) / 6.0
+            })
+            .sum();
+        sum / n_segments as Float
+    }
+}
+
+// ----------------------------------------------------------------------------
+// Tests
+// ----------------------------------------------------------------------------
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    /// Returns the triangle (0, 0, 0), (1, 0, 0), (0, 1, 0).
+    fn triangle() -> ArcShape {
+        let identity = Arc::new(Transform::default());
+        let p = vec![
+            Point3f::new(0.0, 0.0, 0.0),
+            Point3f::new(1.0, 0.0, 0.0),
+            Point3f::new(0.0, 1.0, 0.0),
+        ];
+        let mut shapes = TriangleMesh::create(
+            identity.clone(),
+            identity,
+            false,
+            vec![0, 1, 2],
+            p,
+            vec![],
+            vec![],
+            vec![],
+            None,
+            None,
+            vec![],
+        );
+        shapes.pop().unwrap()
+    }
+
+    /// Returns a triangle with legs of length 1 at time 0 that is scaled by
+    /// the given factors at the following keys evenly spaced over [0, 1].
+    fn deforming_triangle(scales: &[Float]) -> Triangle {
+        let identity = Arc::new(Transform::default());
+        let p = |s: Float| {
+            vec![
+                Point3f::new(0.0, 0.0, 0.0),
+                Point3f::new(s, 0.0, 0.0),
+                Point3f::new(0.0, s, 0.0),
+            ]
+        };
+        let mut mesh = TriangleMesh::new(
+            identity.clone(),
+            false,
+            vec![0, 1, 2],
+            p(1.0),
+            vec![],
+            vec![],
+            vec![],
+            None,
+            None,
+            vec![],
+        );
+        let p_motion: Vec<Point3f> = scales.iter().flat_map(|&s| p(s)).collect();
+        mesh.set_motion(&identity, &p_motion, 0.0, 1.0);
+        Triangle::new(identity.clone(), identity, false, Arc::new(mesh), 0)
+    }
+
+    fn bounds(p_min: (Float, Float, Float), p_max: (Float, Float, Float)) -> Bounds3f {
+        Bounds3f::new(
+            Point3f::new(p_min.0, p_min.1, p_min.2),
+            Point3f::new(p_max.0, p_max.1, p_max.2),
+        )
+    }
+
+    #[test]
+    fn clipped_world_bound_is_tighter_than_the_box() {
+        let tri = triangle();
+        let clip = bounds((0.5, 0.0, -1.0), (1.0, 1.0, 1.0));
+        let clipped = tri.clipped_world_bound(&clip);
+
+        // The hypotenuse is at y = 0.5 where it crosses x = 0.5.
+        assert!(clipped == bounds((0.5, 0.0, 0.0), (1.0, 0.5, 0.0)));
+        assert!(tri.world_bound().intersect(&clip) == bounds((0.5, 0.0, 0.0), (1.0, 1.0, 0.0)));
+    }
+
+    #[test]
+    fn clipped_world_bound_misses_the_triangle() {
+        // The box overlaps the triangle's bounds but not the triangle.
+        let clipped = triangle().clipped_world_bound(&bounds((0.6, 0.6, -1.0), (1.0, 1.0, 1.0)));
+        assert!(clipped.is_empty());
+
+        let clipped = triangle().clipped_world_bound(&bounds((0.0, 0.0, 0.5), (1.0, 1.0, 1.0)));
+        assert!(clipped.is_empty());
+    }
+
+    #[test]
+    fn clipped_world_bound_keeps_contained_triangles() {
+        let tri = triangle();
+        let clipped = tri.clipped_world_bound(&bounds((-1.0, -1.0, -1.0), (2.0, 2.0, 2.0)));
+        assert!(clipped == tri.world_bound());
+    }
+
+    #[test]
+    fn static_area() {
+        assert_eq!(triangle().area(), 0.5);
+    }
+
+    #[test]
+    fn deforming_area_is_averaged_over_time() {
+        // The area is 0.5 * (1 + 2t)^2 which averages to 13 / 6.
+        let tri = deforming_triangle(&[3.0]);
+        assert_eq!(tri.area_at(0.0), 0.5);
+        assert_eq!(tri.area_at(0.5), 2.0);
+        assert_eq!(tri.area_at(1.0), 4.5);
+        assert!((tri.area() - 13.0 / 6.0).abs() < 1e-5);
+
+        // Growing and shrinking back has the same average.
+        let tri = deforming_triangle(&[3.0, 1.0]);
+        assert_eq!(tri.area_at(0.5), 4.5);
+        assert!((tri.area() - 13.0 / 6.0).abs() < 1e-5);
+    }
+
+    #[test]
+    fn deforming_samples_are_on_the_triangle_at_their_time() {
+        let tri = deforming_triangle(&[3.0]);
+        for &(time, legs) in &[(0.0, 1.0), (0.5, 2.0), (1.0, 3.0)] {
+            for i in 0..16 {
+                let u = Point2f::new((i % 4) as Float / 4.0 + 0.1, (i / 4) as Float / 4.0 + 0.1);
+                let (hit, pdf) = tri.sample(&u, time);
+                assert_eq!(hit.time, time);
+                assert!(hit.p.x >= 0.0 && hit.p.y >= 0.0 && hit.p.z == 0.0);
+                assert!(hit.p.x + hit.p.y <= legs * (1.0 + 1e-5));
+                assert_eq!(hit.n.z.abs(), 1.0);
+                assert_eq!(pdf, 1.0 / tri.area_at(time));
+                assert_eq!(pdf, tri.pdf(time));
+            }
+        }
     }
 }
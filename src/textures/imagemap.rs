@@ -44,6 +44,7 @@ macro_rules! new_image_texture {
             /// * `wrap_mode`        - Image wrapping convention.
             /// * `scale`            - Scale for the texel values.
             /// * `gamma`            - Do gamma correction for the texel values.
+            /// * `alpha`            - Use the image's alpha channel as texel values.
             /// * `max_anisotropy`   - Used to clamp the ellipse eccentricity (EWA).
             ///                        Set to 0 if EWA is not being used.
             pub fn new(
@@ -53,6 +54,7 @@ macro_rules! new_image_texture {
                 wrap_mode: ImageWrap,
                 scale: Float,
                 gamma: bool,
+                alpha: bool,
                 max_anisotropy: Float,
             ) -> Self {
                 let tex_info = TexInfo::new(
@@ -61,6 +63,7 @@ macro_rules! new_image_texture {
                     wrap_mode,
                     scale,
                     gamma,
+                    alpha,
                     max_anisotropy,
                 );
                 let mipmap = match MIPMapCache::get(tex_info) {
@@ -148,7 +151,11 @@ macro_rules! from_params {
                 };
                 let scale = tp.find_float("scale", 1.0);
                 let path = tp.find_filename("filename", String::from(""));
-                let gamma = tp.find_bool("gamma", path.ends_with(".tga") || path.ends_with(".png"));
+
+                // Alpha values are linear; e.g. for a shape's `alpha` cutout.
+                let alpha = tp.find_bool("alpha", false);
+                let gamma = !alpha
+                    && tp.find_bool("gamma", path.ends_with(".tga") || path.ends_with(".png"));
                 Self::new(
                     map,
                     &path,
@@ -156,6 +163,7 @@ macro_rules! from_params {
                     wrap_mode,
                     scale,
                     gamma,
+                    alpha,
                     max_anisotropy,
                 )
             }